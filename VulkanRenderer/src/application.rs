@@ -5,7 +5,6 @@ mod rhi;
 mod scene;
 
 use std::{
-    cell::RefCell,
     collections::BTreeMap,
     ops::DerefMut,
     rc::Rc,
@@ -13,7 +12,7 @@ use std::{
     time::{Duration, SystemTime},
 };
 
-use asset_system::assets::AssetHandle;
+use asset_system::assets::StrongAssetHandle;
 use egui_winit_vulkano::{
     egui,
     egui::{Color32, Sense, Stroke, StrokeKind, Ui, Vec2, epaint, epaint::PathShape},
@@ -37,8 +36,10 @@ use crate::{
     application::{
         assets::{
             AssetManager::AssetManager,
+            asset_error::AssetError,
             asset_traits::{
-                CameraInterface, Index, RHIInterface, RHISceneInterface, RendererInterface, Vertex,
+                CameraInterface, CompactVertex, Index, Meshlet, RHIInterface, RHISceneInterface,
+                RendererInterface, SkinVertex, Vertex,
             },
             material::Material,
         },
@@ -60,13 +61,13 @@ pub struct Application {
     /// Renderer to render the scene
     renderer: Option<Rc<VKRenderer>>,
     /// Stores all assets
-    asset_manager: Arc<RefCell<AssetManager>>,
+    asset_manager: Arc<AssetManager>,
     /// Rendering proxy of the scene
     rhi_scene_proxy: Option<VKScene>,
     /// Game version of the scene
     scene: Scene,
-    /// Global fallback material
-    fallback_material: AssetHandle<Material>,
+    /// Global fallback material. The strong handle keeps it loaded although no model uses it.
+    fallback_material: StrongAssetHandle<Material>,
     /// Input mapping
     input: InputMap<InputAction>,
     /// System to measure and print timings
@@ -78,12 +79,27 @@ pub struct Application {
 impl Application {
     pub fn new() -> Self {
         let asset_manager = AssetManager::new();
-        let fallback_material = asset_manager.borrow_mut().add_material(
-            "FallbackMaterial",
-            "Materials/basicMaterials",
-            "FallbackMaterial",
-        );
-        let scene = Self::scene(asset_manager.borrow_mut().deref_mut());
+        let fallback_material = {
+            let handle = asset_manager
+                .add_material(
+                    "FallbackMaterial",
+                    "Materials/basicMaterials",
+                    "FallbackMaterial",
+                )
+                .expect("The fallback material is required");
+            asset_manager.strong(&handle).unwrap()
+        };
+        // A glTF, OBJ or PLY file passed on the command line replaces the generated test scene
+        let scene = match std::env::args().nth(1) {
+            Some(path) => Self::import_scene(&asset_manager, &path).unwrap_or_else(|error| {
+                println!("Failed to import scene {}: {}", path, error);
+                Self::scene(&asset_manager)
+            }),
+            None => Self::scene(&asset_manager),
+        };
+        if let Err(error) = asset_manager.save_registry() {
+            println!("{}", error);
+        }
         Self {
             renderer: None,
             rhi_scene_proxy: None,
@@ -96,7 +112,19 @@ impl Application {
         }
     }
 
-    fn scene(asset_manager: &mut AssetManager) -> Scene {
+    fn import_scene(asset_manager: &AssetManager, path: &str) -> Result<Scene, AssetError> {
+        // Imported materials become instances of the metallic roughness material
+        let material = asset_manager.add_material(
+            "SceneMaterial",
+            "Materials/pbrMaterials",
+            "MetallicRoughnessMaterial",
+        )?;
+        let material_instance =
+            asset_manager.add_material_instance("SceneMaterialInst", material)?;
+        asset_manager.import_scene(path, material_instance)
+    }
+
+    fn scene(asset_manager: &AssetManager) -> Scene {
         // The total number of materials in the scene
         let num_materials = 1000;
 
@@ -104,13 +132,13 @@ impl Application {
         let num_instances = 200000 / num_materials;
 
         let mut scene = Scene::new();
-        // We have 2 meshes, a sphere and the blender monkey
-        let sphere = asset_manager.add_mesh("TestMesh1", "resources/assets/meshes/sphere.glb");
+        // We have 2 meshes, a sphere and the blender monkey. They are shown as cubes until they are loaded.
+        // The sphere is requested three times to make it more common, but it is only imported once.
         let meshes = [
-            sphere.clone(),
-            sphere.clone(),
-            sphere,
-            asset_manager.add_mesh("TestMesh2", "resources/assets/meshes/Suzanne.glb"),
+            asset_manager.request_mesh("TestMesh1", "resources/assets/meshes/sphere.glb"),
+            asset_manager.request_mesh("TestMesh1", "resources/assets/meshes/sphere.glb"),
+            asset_manager.request_mesh("TestMesh1", "resources/assets/meshes/sphere.glb"),
+            asset_manager.request_mesh("TestMesh2", "resources/assets/meshes/Suzanne.glb"),
         ];
 
         use rand::prelude::*;
@@ -121,13 +149,22 @@ impl Application {
 
         // Randomly spawn instances
         for i in 0..num_materials {
-            let material = asset_manager.add_material(
-                format!("TestMat_{}", i).as_str(),
-                "Materials/basicMaterials",
-                "SingleColorUnlitMaterial",
-            );
-            let material_instance = asset_manager
-                .add_material_instance(format!("TestMatInst_{}", i).as_str(), material);
+            let material_instance = match asset_manager
+                .add_material(
+                    format!("TestMat_{}", i).as_str(),
+                    "Materials/basicMaterials",
+                    "SingleColorUnlitMaterial",
+                )
+                .and_then(|material| {
+                    asset_manager
+                        .add_material_instance(format!("TestMatInst_{}", i).as_str(), material)
+                }) {
+                Ok(material_instance) => material_instance,
+                Err(error) => {
+                    println!("{}, skipping its models", error);
+                    continue;
+                }
+            };
 
             for j in 0..num_instances {
                 let transform = Transform {
@@ -145,12 +182,15 @@ impl Application {
                     ..Transform::default()
                 };
                 let mesh = &meshes[rng.random::<u32>() as usize % meshes.len()];
-                scene.models.push(asset_manager.add_model(
+                match asset_manager.add_model(
                     format!("TestModel_{}_{}", i, j).as_str(),
                     transform,
                     mesh.clone(),
                     material_instance.clone(),
-                ));
+                ) {
+                    Ok(model) => scene.models.push(model),
+                    Err(error) => println!("{}", error),
+                }
             }
         }
         scene.camera.transform.location = Vec3::new(0., 0., 2.);
@@ -183,9 +223,12 @@ impl Application {
     }
 
     fn update_scene_proxy(&mut self, rhi: &VKRHI) {
+        let unloaded = self.asset_manager.drain_unloaded();
+        rhi.resource_manager_mut().remove_unloaded_proxies(unloaded);
+
         // TODO: This is super hacky
         rhi.resource_manager_mut()
-            .create_material(self.fallback_material.clone());
+            .create_material(self.fallback_material.handle().clone());
 
         // TODO: This should be more lazy
         self.rhi_scene_proxy = Some(VKScene::create(
@@ -195,6 +238,17 @@ impl Application {
         ));
     }
 
+    /// Applies finished background loads and reloads assets whose files changed on disk,
+    /// then updates their render proxies in one batch. Proxies of unloaded assets are removed.
+    fn update_assets(&mut self) {
+        let mut changes = self.asset_manager.poll_loads();
+        changes.append(self.asset_manager.poll_file_changes());
+        changes.append(self.asset_manager.poll_edits());
+        let renderer = self.renderer.as_ref().unwrap();
+        renderer.reload_assets(&changes);
+        renderer.remove_unloaded_proxies();
+    }
+
     // TODO: This should just be update_scene_proxy but that one is not optimized
     fn update_scene_proxy_camera(&mut self, rhi: &VKRHI) {
         self.rhi_scene_proxy
@@ -203,14 +257,50 @@ impl Application {
             .set_camera(self.scene.camera.rhi(rhi));
     }
 
+    /// Samples the animations of the skinned models and hands their poses to the scene proxy
+    fn update_scene_proxy_poses(&mut self) {
+        let asset_manager = &self.asset_manager;
+        let scene_proxy = self.rhi_scene_proxy.as_mut().unwrap();
+        for index in &self.scene.skinned_models {
+            if let Some(matrices) = self
+                .scene
+                .models
+                .get(*index)
+                .and_then(|model| asset_manager.get(model))
+                .and_then(|model| model.joint_matrices(asset_manager))
+            {
+                scene_proxy.set_joint_matrices(*index, matrices);
+            }
+        }
+    }
+
+    /// Hands the transforms of the animated models to the scene proxy
+    fn update_scene_proxy_transforms(&mut self) {
+        let asset_manager = &self.asset_manager;
+        let scene_proxy = self.rhi_scene_proxy.as_mut().unwrap();
+        for animation in &self.scene.animations {
+            if let Some(model) = self
+                .scene
+                .models
+                .get(animation.model)
+                .and_then(|model| asset_manager.get(model))
+            {
+                scene_proxy.set_transform(animation.model, model.transform.matrix());
+            }
+        }
+    }
+
     fn update_aspect_ratio(&mut self, x: u32, y: u32) {
         self.scene.camera.aspect = x as f32 / y as f32;
     }
 
-    /// Game loop tick function. This is responsible for camera updates
+    /// Game loop tick function. This is responsible for camera updates and advances the animations of the models
     fn tick(&mut self, delta_time: f32) {
         use InputAction::*;
 
+        // Only the animated models are locked, the RHI can read all other assets meanwhile
+        self.scene.animate(delta_time, &self.asset_manager);
+
         let mouse_move = self.input.dir(MouseLeft, MouseRight, MouseUp, MouseDown);
         let cam_move = self.input.dir(Forward, Back, Right, Left);
         let scroll = self.input.axis(ScrollUp, ScrollDown);
@@ -293,12 +383,7 @@ impl Application {
                     ));
                     ui.label(format!(
                         "Materials in Scene:\t {}",
-                        self.asset_manager
-                            .borrow()
-                            .resource_manager()
-                            .get_iter::<Material>()
-                            .unwrap()
-                            .count()
+                        self.asset_manager.count::<Material>()
                     ));
                     ui.label(format!(
                         "Visible Materials:\t {}",
@@ -322,6 +407,24 @@ impl Application {
                             / (renderer.swapchain_extent()[0] * renderer.swapchain_extent()[1])
                                 as f32
                     ));
+                    ui.label(format!(
+                        "Drawn Clusters:\t {}",
+                        renderer.scene_statistics().drawn_clusters
+                    ));
+                    if renderer.scene_statistics().dropped_clusters > 0 {
+                        ui.colored_label(
+                            Color32::RED,
+                            format!(
+                                "Dropped Clusters:\t {}",
+                                renderer.scene_statistics().dropped_clusters
+                            ),
+                        );
+                    }
+                    ui.label(format!(
+                        "Resident Textures:\t {:.1}MiB",
+                        renderer.scene_statistics().resident_texture_bytes as f32
+                            / (1 << 20) as f32
+                    ));
 
                     self.time_measurement
                         .paint_graph_to_gui(&AppEvent::Render, ui);
@@ -331,6 +434,18 @@ impl Application {
                     ui.heading("Render Settings");
                     ui.label("Post Process:");
                     renderer.post_process_settings().draw_gui(ui);
+                    ui.label("Level of Detail:");
+                    renderer.lod_settings().draw_gui(ui);
+                    ui.label("Texture Streaming:");
+                    renderer.streaming_settings().draw_gui(ui);
+                    ui.label("Texture Filtering:");
+                    renderer.filtering_settings().draw_gui(ui);
+
+                    if !self.scene.animations.is_empty() {
+                        ui.add_space(10f32);
+                        ui.heading("Animations");
+                        self.scene.draw_gui(ui);
+                    }
                 });
         });
     }
@@ -346,7 +461,8 @@ impl ApplicationHandler<AppEvent> for Application {
         // Initialize RHI
         let rhi = VKRHI::new(event_loop, self.asset_manager.clone());
 
-        // Allocate global vertex and index buffers
+        // Allocate global vertex, index, meshlet and skin buffers. Meshes use the full vertex format unless
+        // they opt into the compact one. Skinned vertices of every instance are written to the full vertex buffer.
         rhi.resource_manager_mut().allocate_shared_buffer::<Vertex>(
             1000000,
             BufferUsage::VERTEX_BUFFER
@@ -354,6 +470,13 @@ impl ApplicationHandler<AppEvent> for Application {
                 | BufferUsage::STORAGE_BUFFER
                 | BufferUsage::SHADER_DEVICE_ADDRESS,
         );
+        rhi.resource_manager_mut()
+            .allocate_shared_buffer::<CompactVertex>(
+                2000000,
+                BufferUsage::TRANSFER_DST
+                    | BufferUsage::STORAGE_BUFFER
+                    | BufferUsage::SHADER_DEVICE_ADDRESS,
+            );
         rhi.resource_manager_mut().allocate_shared_buffer::<Index>(
             1000000,
             BufferUsage::INDEX_BUFFER
@@ -361,6 +484,20 @@ impl ApplicationHandler<AppEvent> for Application {
                 | BufferUsage::STORAGE_BUFFER
                 | BufferUsage::SHADER_DEVICE_ADDRESS,
         );
+        rhi.resource_manager_mut()
+            .allocate_shared_buffer::<Meshlet>(
+                100000,
+                BufferUsage::TRANSFER_DST
+                    | BufferUsage::STORAGE_BUFFER
+                    | BufferUsage::SHADER_DEVICE_ADDRESS,
+            );
+        rhi.resource_manager_mut()
+            .allocate_shared_buffer::<SkinVertex>(
+                100000,
+                BufferUsage::TRANSFER_DST
+                    | BufferUsage::STORAGE_BUFFER
+                    | BufferUsage::SHADER_DEVICE_ADDRESS,
+            );
         self.update_scene_proxy(rhi.as_ref());
 
         // Initialize renderer and time measurement system
@@ -420,7 +557,10 @@ impl ApplicationHandler<AppEvent> for Application {
             WindowEvent::CloseRequested => event_loop.exit(),
             // Render the scene
             WindowEvent::RedrawRequested => {
+                self.update_assets();
                 self.update_scene_proxy_camera(self.renderer.clone().unwrap().rhi());
+                self.update_scene_proxy_poses();
+                self.update_scene_proxy_transforms();
                 self.draw_gui();
                 self.renderer
                     .as_ref()
@@ -1,6 +1,19 @@
 pub mod AssetManager;
+pub mod animation_clip;
+pub mod asset_error;
 pub mod asset_traits;
+pub mod block_compression;
+pub mod compact_vertex;
+pub mod compressed_image;
+pub mod cooked_mesh;
+pub mod cube_texture;
 pub mod material;
 pub mod material_instance;
 pub mod mesh;
+pub mod mesh_lod;
+pub mod mesh_processing;
+pub mod mesh_source;
+pub mod meshlet;
+pub mod sampler;
+pub mod skeleton;
 pub mod texture;
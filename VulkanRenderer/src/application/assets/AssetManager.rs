@@ -1,75 +1,1473 @@
-use std::{cell::RefCell, marker::PhantomData, path::Path, sync::Arc};
+use std::{
+    any::TypeId,
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, RwLock, RwLockReadGuard,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    time::Duration,
+};
 
-use asset_system::{assets::AssetHandle, resource_management::ResourceManager};
+use asset_system::{
+    assets::{Asset, AssetHandle, LoadState, StrongAssetHandle, WeakAssetHandle},
+    dependencies::DependencyGraph,
+    loader::WorkerPool,
+    registry::{AssetId, AssetRecord, AssetRegistry, RegistryError},
+    resource_management::{
+        Resource, ResourceId, ResourceMut, ResourceRef, ResourceView, SharedResourceManager,
+    },
+    watcher::FileWatcher,
+};
+use glam::Mat4;
 
 use crate::application::{
-    assets::{material::Material, material_instance::MaterialInstance, mesh::Mesh},
-    scene::{model::Model, transform::Transform},
+    assets::{
+        animation_clip::AnimationClip,
+        asset_error::{AssetError, FallbackPolicy},
+        asset_traits::{
+            MaterialInstanceInterface, MaterialInterface, MaterialParameters, MeshInterface,
+            VertexFormat,
+        },
+        cube_texture::CubeTexture,
+        material::Material,
+        material_instance::{MaterialInstance, TextureSlot},
+        mesh::Mesh,
+        mesh_source::{MaterialDescription, MeshSource, PrimitiveIndex},
+        sampler::{Sampler, SamplerDesc},
+        skeleton::Skeleton,
+        texture::{Texture, TextureSettings},
+    },
+    scene::{
+        Scene,
+        animation::ModelAnimation,
+        model::{AnimationLayer, Model},
+        transform::Transform,
+    },
 };
 
+/// Manifest that stores the stable IDs of all persistent assets
+const REGISTRY_PATH: &str = "resources/assets/asset_registry.ron";
+
+/// Root of all assets that are watched for changes
+pub const ASSET_DIRECTORY: &str = "resources/assets";
+/// Search path of the Slang compiler. Material modules are relative to it.
+pub const SHADER_DIRECTORY: &str = "resources/assets/materials/shaders";
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+const MESH_TYPE: &str = "Mesh";
+const TEXTURE_TYPE: &str = "Texture";
+const CUBE_TEXTURE_TYPE: &str = "CubeTexture";
+const MATERIAL_TYPE: &str = "Material";
+const MATERIAL_INSTANCE_TYPE: &str = "MaterialInstance";
+const SAMPLER_TYPE: &str = "Sampler";
+
+/// Mesh setting that selects the compact vertex format. Meshes without it use the full format.
+const VERTEX_FORMAT_SETTING: &str = "vertex_format";
+const COMPACT_VERTEX_FORMAT: &str = "compact";
+/// Material instance setting with the stable ID of the environment map
+const ENVIRONMENT_TEXTURE_SETTING: &str = "environment_texture";
+/// Material instance setting with the stable ID of the shared sampler
+const SAMPLER_SETTING: &str = "sampler";
+
+/// Every asset type and every table has its own lock, so that the manager can be shared without an outer lock.
+/// Locks are only held for a single lookup or change, except for the guards returned by [`Self::get`],
+/// [`Self::get_mut`] and [`Self::view`]. While such a guard exists, the assets of its type must not be changed
+/// through the manager on the same thread.
 pub struct AssetManager {
-    resource_manager: ResourceManager,
+    resources: SharedResourceManager,
+    /// Persistent mapping of stable IDs to the sources of the assets
+    registry: RwLock<AssetRegistry>,
+    lookup: RwLock<AssetLookup>,
+    /// Which assets reference each other and how many handles keep them alive
+    dependencies: RwLock<DependencyGraph>,
+    /// Assets that were removed since the RHI last synchronized its proxies
+    unloaded: Mutex<Vec<ResourceId>>,
+    /// Samplers that were edited since the renderer last refreshed the material instances using them
+    edited_samplers: Mutex<Vec<AssetHandle<Sampler>>>,
+    /// Sources that are being imported. Importers of the same source wait for each other, see [`Self::find_or_import`].
+    importing: Mutex<HashMap<SourceKey, Arc<Mutex<()>>>>,
+    /// Detects changed source files for hot reloading
+    watcher: Mutex<FileWatcher>,
+    /// Imports requested assets in the background
+    workers: WorkerPool,
+    finished_sender: Sender<FinishedLoad>,
+    // Receivers cannot be shared between threads, the lock only makes the manager Sync
+    finished_loads: Mutex<Receiver<FinishedLoad>>,
+    pending_loads: AtomicUsize,
+    fallback_policy: RwLock<FallbackPolicy>,
+    /// Whether the textures of imported materials are block compressed on the CPU
+    compress_textures: AtomicBool,
+}
+
+/// Ways to find the loaded assets
+#[derive(Default)]
+struct AssetLookup {
+    /// Stable IDs of the currently loaded assets
+    loaded_assets: HashMap<AssetId, ResourceId>,
+    asset_ids: HashMap<ResourceId, AssetId>,
+    /// Loaded assets by type and name. If several assets share a name, the newest one is found.
+    names: HashMap<(TypeId, String), ResourceId>,
+    /// Loaded assets by type, canonical source path and registry item
+    sources: HashMap<SourceKey, ResourceId>,
+    /// Keys of each asset in `names` and `sources`, so that they can be removed when it is unloaded
+    lookup_keys: HashMap<ResourceId, LookupKeys>,
+}
+
+// The manager is shared with the RHI and importer threads, so this must keep compiling
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<AssetManager>();
+};
+
+type SourceKey = (TypeId, PathBuf, String);
+
+struct LookupKeys {
+    name: (TypeId, String),
+    source: Option<SourceKey>,
+}
+
+/// Result of a background import, addressed to the placeholder it replaces
+enum FinishedLoad {
+    Mesh(ResourceId, Result<Mesh, AssetError>),
+    Texture(ResourceId, Result<Texture, AssetError>),
+}
+
+/// Assets whose data was replaced in place, either because their source files changed
+/// or because a background load finished. Handles stay valid.
+#[derive(Default)]
+pub struct AssetChanges {
+    pub meshes: Vec<AssetHandle<Mesh>>,
+    pub textures: Vec<AssetHandle<Texture>>,
+    /// Materials whose shader code changed and that need to be recompiled
+    pub materials: Vec<AssetHandle<Material>>,
+    /// Samplers whose description changed, the material instances sharing them need new sampler indices
+    pub samplers: Vec<AssetHandle<Sampler>>,
+}
+
+impl AssetChanges {
+    pub fn is_empty(&self) -> bool {
+        self.meshes.is_empty()
+            && self.textures.is_empty()
+            && self.materials.is_empty()
+            && self.samplers.is_empty()
+    }
+
+    pub fn append(&mut self, mut other: AssetChanges) {
+        self.meshes.append(&mut other.meshes);
+        self.textures.append(&mut other.textures);
+        self.materials.append(&mut other.materials);
+        self.samplers.append(&mut other.samplers);
+    }
 }
 
 impl AssetManager {
-    pub fn new() -> Arc<RefCell<AssetManager>> {
-        Arc::new(RefCell::new(Self {
-            resource_manager: ResourceManager::new(),
-        }))
+    /// The manager is shared between the application, the RHI and importer threads
+    pub fn new() -> Arc<AssetManager> {
+        let (finished_sender, finished_loads) = mpsc::channel();
+        Arc::new(Self {
+            resources: SharedResourceManager::new()
+                .with_type::<Mesh>()
+                .with_type::<Texture>()
+                .with_type::<CubeTexture>()
+                .with_type::<Material>()
+                .with_type::<MaterialInstance>()
+                .with_type::<Sampler>()
+                .with_type::<Model>()
+                .with_type::<Skeleton>()
+                .with_type::<AnimationClip>(),
+            registry: RwLock::new(AssetRegistry::load(REGISTRY_PATH).unwrap_or_else(|error| {
+                println!(
+                    "{}. {} is ignored and assets start with new IDs.",
+                    error, REGISTRY_PATH
+                );
+                AssetRegistry::new(REGISTRY_PATH)
+            })),
+            lookup: RwLock::new(AssetLookup::default()),
+            dependencies: RwLock::new(DependencyGraph::new()),
+            unloaded: Mutex::new(Vec::new()),
+            edited_samplers: Mutex::new(Vec::new()),
+            importing: Mutex::new(HashMap::new()),
+            watcher: Mutex::new(FileWatcher::new(ASSET_DIRECTORY, WATCH_INTERVAL)),
+            workers: WorkerPool::with_available_parallelism(),
+            finished_sender,
+            finished_loads: Mutex::new(finished_loads),
+            pending_loads: AtomicUsize::new(0),
+            fallback_policy: RwLock::new(FallbackPolicy::default()),
+            compress_textures: AtomicBool::new(false),
+        })
+    }
+
+    /// Adds the asset and records its dependencies.
+    /// Collectable assets are unloaded as soon as the last asset or strong handle referencing them is gone.
+    fn insert<T: Asset + 'static>(&self, asset: T, collectable: bool) -> AssetHandle<T> {
+        let key = (TypeId::of::<T>(), asset.name().clone());
+        let dependencies = asset.dependencies();
+        let id = self.resources.add(asset);
+        self.dependencies
+            .write()
+            .unwrap()
+            .insert(id, dependencies, collectable);
+        let mut lookup = self.lookup.write().unwrap();
+        lookup.names.insert(key.clone(), id);
+        lookup.lookup_keys.insert(
+            id,
+            LookupKeys {
+                name: key,
+                source: None,
+            },
+        );
+        AssetHandle::new(id)
+    }
+
+    /// Makes the asset findable through its source file and its item within it
+    fn index_source<T: Asset + 'static>(&self, handle: &AssetHandle<T>, path: &Path, item: &str) {
+        let key = (TypeId::of::<T>(), canonical_path(path), item.to_string());
+        let mut lookup = self.lookup.write().unwrap();
+        lookup.sources.insert(key.clone(), handle.id);
+        if let Some(keys) = lookup.lookup_keys.get_mut(&handle.id) {
+            keys.source = Some(key);
+        }
+    }
+
+    /// Returns the loaded asset of type T from the source, or imports it if there is none.
+    /// The import must index its asset with [`Self::index_source`]. Importers of the same source are serialized,
+    /// so that concurrent importers wait for the first one and find its asset instead of importing the file again.
+    /// Different sources are imported in parallel.
+    fn find_or_import<T: Asset + 'static, E>(
+        &self,
+        path: &Path,
+        item: &str,
+        import: impl FnOnce() -> Result<AssetHandle<T>, E>,
+    ) -> Result<AssetHandle<T>, E> {
+        let key = (TypeId::of::<T>(), canonical_path(path), item.to_string());
+        let source = self
+            .importing
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let result = {
+            let _importing = source.lock().unwrap();
+            let loaded = self.lookup.read().unwrap().sources.get(&key).copied();
+            match loaded {
+                Some(id) => Ok(AssetHandle::new(id)),
+                None => import(),
+            }
+        };
+        // Handles to the lock are only taken while the map is locked, so nobody else waits if this is the last one
+        let mut importing = self.importing.lock().unwrap();
+        if Arc::strong_count(&source) == 2 {
+            importing.remove(&key);
+        }
+        result
+    }
+
+    pub fn fallback_policy(&self) -> FallbackPolicy {
+        *self.fallback_policy.read().unwrap()
+    }
+
+    pub fn set_fallback_policy(&self, policy: FallbackPolicy) {
+        *self.fallback_policy.write().unwrap() = policy;
+    }
+
+    pub fn compress_textures(&self) -> bool {
+        self.compress_textures.load(Ordering::Relaxed)
+    }
+
+    /// Applies to textures of materials imported afterwards that are not registered yet
+    pub fn set_compress_textures(&self, compress: bool) {
+        self.compress_textures.store(compress, Ordering::Relaxed);
+    }
+
+    /// Applies the fallback policy to a failed import
+    fn substitute<T>(
+        &self,
+        name: &str,
+        error: AssetError,
+        fallback: impl FnOnce(String, &AssetError) -> T,
+    ) -> Result<T, AssetError> {
+        match self.fallback_policy() {
+            FallbackPolicy::Fail => Err(error),
+            FallbackPolicy::Substitute => {
+                println!("{}. {} is replaced by the fallback asset.", error, name);
+                Ok(fallback(name.into(), &error))
+            }
+        }
+    }
+
+    /// Imports the first primitive of a glTF, OBJ or PLY file. The importer is selected by the file extension.
+    /// If a mesh from the same file is already loaded, its handle is returned instead.
+    /// Depending on the fallback policy, a failed import results in the error mesh or an error.
+    pub fn add_mesh(
+        &self,
+        name: &str,
+        path: impl AsRef<Path>,
+    ) -> Result<AssetHandle<Mesh>, AssetError> {
+        let path = path.as_ref();
+        self.find_or_import(path, "", || {
+            let handle =
+                self.insert_mesh(name, &mut MeshSource::new(path), PrimitiveIndex::default())?;
+            self.index_source(&handle, path, "");
+            Ok(handle)
+        })
+    }
+
+    /// Imports a single primitive of a mesh file. If a mesh with the same name is already loaded,
+    /// its handle is returned instead.
+    pub fn add_mesh_primitive(
+        &self,
+        name: &str,
+        source: &mut MeshSource,
+        index: PrimitiveIndex,
+    ) -> Result<AssetHandle<Mesh>, AssetError> {
+        if let Some(handle) = self.find(name) {
+            return Ok(handle);
+        }
+        self.insert_mesh(name, source, index)
+    }
+
+    fn insert_mesh(
+        &self,
+        name: &str,
+        source: &mut MeshSource,
+        index: PrimitiveIndex,
+    ) -> Result<AssetHandle<Mesh>, AssetError> {
+        let settings = self.mesh_settings(source.path(), index);
+        let mesh = match Mesh::load_primitive(name.into(), source, index) {
+            Ok(mesh) => mesh,
+            Err(error) => self.substitute(name, error, Mesh::error)?,
+        }
+        .with_vertex_format(vertex_format(&settings));
+        // Substituted meshes are registered as well, so that fixing the file reloads them
+        let asset_id = self.registry.write().unwrap().register(
+            MESH_TYPE,
+            name,
+            Some(source.path()),
+            &index.item(),
+            settings,
+        );
+        let handle = self.insert(mesh, true);
+        self.track(asset_id, &handle);
+        Ok(handle)
+    }
+
+    /// Imports the default scene of a glTF file, or all primitives of an OBJ or PLY file.
+    /// Every primitive becomes a mesh and every node instance of a primitive becomes a model
+    /// with the world transform of its node. Primitives with a material in the file, like OBJ faces
+    /// with an MTL material, get an instance of the base material of `material`. All others use `material`.
+    /// The first perspective camera in the hierarchy becomes the camera of the scene.
+    /// Models of skinned nodes get the skeleton of the skin and play the first animation that moves it.
+    pub fn import_scene(
+        &self,
+        path: impl AsRef<Path>,
+        material: AssetHandle<MaterialInstance>,
+    ) -> Result<Scene, AssetError> {
+        let path = path.as_ref();
+        let mut source = MeshSource::new(path);
+        let nodes = source.scene_nodes()?;
+
+        let mut scene = Scene::new();
+        let mut camera = None;
+        for node in nodes {
+            if camera.is_none() {
+                camera = node.camera;
+            }
+            let Some((mesh_index, primitive_count)) = node.mesh else {
+                continue;
+            };
+            let transform = Transform::from_matrix(node.world);
+            let skin = match node.skin {
+                Some(skin) => {
+                    Some(self.add_skin(path, &mut source, skin, &node.name, node.world)?)
+                }
+                None => None,
+            };
+            // Skinned meshes are moved by their joints, the transform of their node does not apply
+            let animation = match (node.index, &skin) {
+                (Some(index), None) => source.node_animation(index)?,
+                _ => None,
+            };
+            for primitive in 0..primitive_count {
+                // Primitives are named after their position in the file, names in the file are not unique
+                let mesh_name = format!(
+                    "{}#Mesh{}.Primitive{}",
+                    path.display(),
+                    mesh_index,
+                    primitive
+                );
+                let index = PrimitiveIndex {
+                    mesh: mesh_index,
+                    primitive,
+                };
+                let mesh = self.add_mesh_primitive(&mesh_name, &mut source, index)?;
+                let material = match source.material(index)? {
+                    Some(description) => {
+                        self.add_described_material(path, &description, &material)?
+                    }
+                    None => material.clone(),
+                };
+                let model_name = format!("{}.Primitive{}", node.name, primitive);
+                let model = self.add_model(&model_name, transform, mesh, material)?;
+                if let Some((skeleton, clips)) = &skin
+                    && let Some(mut skinned) = self.get_mut(&model)
+                {
+                    skinned.skeleton = Some(skeleton.clone());
+                    skinned.animations = clips
+                        .first()
+                        .cloned()
+                        .map(AnimationLayer::new)
+                        .into_iter()
+                        .collect();
+                    // The models stay locked while the guard exists
+                    drop(skinned);
+                    self.update_dependencies(&model);
+                    scene.skinned_models.push(scene.models.len());
+                }
+                if let Some(animation) = &animation {
+                    scene.animations.push(ModelAnimation::new(
+                        format!("{} ({})", model_name, animation.name),
+                        scene.models.len(),
+                        animation.parent,
+                        animation.rest,
+                        animation.tracks.clone(),
+                    ));
+                }
+                scene.models.push(model);
+            }
+        }
+        if let Some(camera) = camera {
+            scene.camera = camera;
+        }
+        Ok(scene)
+    }
+
+    /// Imports the skeleton of a skinned node and the animations of its skin.
+    /// The joints of a glTF skin are placed in the scene independently of the node,
+    /// so every node gets its own skeleton relative to its transform. Clips are shared by all nodes of a skin.
+    fn add_skin(
+        &self,
+        path: &Path,
+        source: &mut MeshSource,
+        skin: usize,
+        node: &str,
+        world: Mat4,
+    ) -> Result<(AssetHandle<Skeleton>, Vec<AssetHandle<AnimationClip>>), AssetError> {
+        let description = source.skin(skin)?;
+        let skeleton_name = format!("{}#Skin{}.{}", path.display(), skin, node);
+        let skeleton = match self.find(&skeleton_name) {
+            Some(handle) => handle,
+            None => self.insert(
+                Skeleton::new(
+                    skeleton_name,
+                    description.joints,
+                    world.inverse() * description.root_transform,
+                ),
+                true,
+            ),
+        };
+        let clips = description
+            .animations
+            .into_iter()
+            .map(|(animation, channels)| {
+                let clip_name = format!("{}#Skin{}.{}", path.display(), skin, animation);
+                self.find(&clip_name)
+                    .unwrap_or_else(|| self.insert(AnimationClip::new(clip_name, channels), true))
+            })
+            .collect();
+        Ok((skeleton, clips))
+    }
+
+    /// Creates an instance of the material behind `template` with the parameters from a source file.
+    /// Instances are shared by all primitives of the file that use the same material.
+    fn add_described_material(
+        &self,
+        path: &Path,
+        description: &MaterialDescription,
+        template: &AssetHandle<MaterialInstance>,
+    ) -> Result<AssetHandle<MaterialInstance>, AssetError> {
+        let name = format!("{}#{}", path.display(), description.name);
+        if let Some(handle) = self.find(&name) {
+            return Ok(handle);
+        }
+        let material = self
+            .get(template)
+            .ok_or(AssetError::InvalidHandle {
+                asset_type: MATERIAL_INSTANCE_TYPE,
+            })?
+            .material();
+        let mut instance = MaterialInstance::new(name, material)
+            .with_base_color(description.base_color)
+            .with_metallic_roughness(description.metallic, description.roughness)
+            .with_emissive(description.emissive)
+            .with_normal_scale(description.normal_scale)
+            .with_occlusion_strength(description.occlusion_strength);
+        for (slot, texture_path) in &description.textures {
+            let texture = self.add_texture(
+                &texture_path.to_string_lossy(),
+                texture_path,
+                slot.texture_settings(self.compress_textures()),
+            )?;
+            instance = instance.with_texture(*slot, texture);
+        }
+        self.add_configured_material_instance(instance)
+    }
+
+    /// Decodes the texture. If a texture from the same file with the same color space and usage is already loaded,
+    /// its handle is returned instead.
+    /// Depending on the fallback policy, a failed import results in the checkerboard texture or an error.
+    /// Colors are sampled from sRGB textures, normals and other data from linear ones.
+    /// The usage selects the block format if the texture is compressed on import.
+    pub fn add_texture(
+        &self,
+        name: &str,
+        path: impl AsRef<Path>,
+        settings: TextureSettings,
+    ) -> Result<AssetHandle<Texture>, AssetError> {
+        let path = path.as_ref();
+        let settings = self.texture_settings(path, settings);
+        self.find_or_import(path, &settings.item(), || {
+            let texture = match Texture::new(path, name.into(), settings) {
+                Ok(texture) => texture,
+                Err(error) => self.substitute(name, error, Texture::error)?,
+            };
+            let asset_id = self.registry.write().unwrap().register(
+                TEXTURE_TYPE,
+                name,
+                Some(path),
+                &settings.item(),
+                settings.to_settings(),
+            );
+            let handle = self.insert(texture, true);
+            self.track(asset_id, &handle);
+            self.index_source(&handle, path, &settings.item());
+            Ok(handle)
+        })
+    }
+
+    /// Projects an equirectangular .hdr or .exr panorama onto a cube.
+    /// If an environment map from the same file is already loaded, its handle is returned instead.
+    /// Depending on the fallback policy, a failed import results in a black cube or an error.
+    pub fn add_cube_texture(
+        &self,
+        name: &str,
+        path: impl AsRef<Path>,
+    ) -> Result<AssetHandle<CubeTexture>, AssetError> {
+        let path = path.as_ref();
+        self.find_or_import(path, "", || {
+            let texture = match CubeTexture::new(path, name.into()) {
+                Ok(texture) => texture,
+                Err(error) => self.substitute(name, error, CubeTexture::error)?,
+            };
+            let asset_id = self.registry.write().unwrap().register(
+                CUBE_TEXTURE_TYPE,
+                name,
+                Some(path),
+                "",
+                BTreeMap::new(),
+            );
+            let handle = self.insert(texture, true);
+            self.track(asset_id, &handle);
+            self.index_source(&handle, path, "");
+            Ok(handle)
+        })
+    }
+
+    /// Imports the mesh on a worker thread. Until it is ready, the handle refers to a placeholder cube.
+    /// If a mesh from the same file is already loaded or loading, its handle is returned instead.
+    pub fn request_mesh(&self, name: &str, path: impl AsRef<Path>) -> AssetHandle<Mesh> {
+        let path = path.as_ref();
+        let Ok(handle) = self.find_or_import::<_, Infallible>(path, "", || {
+            let settings = self.mesh_settings(path, PrimitiveIndex::default());
+            let format = vertex_format(&settings);
+            let asset_id =
+                self.registry
+                    .write()
+                    .unwrap()
+                    .register(MESH_TYPE, name, Some(path), "", settings);
+            let handle = self.insert(
+                Mesh::placeholder(name.into()).with_vertex_format(format),
+                true,
+            );
+            self.track(asset_id, &handle);
+            self.index_source(&handle, path, "");
+
+            let (id, name, path) = (handle.id, name.to_string(), path.to_path_buf());
+            self.spawn_load(move || {
+                FinishedLoad::Mesh(
+                    id,
+                    Mesh::load(name, &path).map(|mesh| mesh.with_vertex_format(format)),
+                )
+            });
+            Ok(handle)
+        });
+        handle
+    }
+
+    /// Decodes the texture on a worker thread. Until it is ready, the handle refers to a placeholder texture.
+    /// If a texture from the same file with the same color space and usage is already loaded or loading,
+    /// its handle is returned instead.
+    pub fn request_texture(
+        &self,
+        name: &str,
+        path: impl AsRef<Path>,
+        settings: TextureSettings,
+    ) -> AssetHandle<Texture> {
+        let path = path.as_ref();
+        let settings = self.texture_settings(path, settings);
+        let Ok(handle) = self.find_or_import::<_, Infallible>(path, &settings.item(), || {
+            let asset_id = self.registry.write().unwrap().register(
+                TEXTURE_TYPE,
+                name,
+                Some(path),
+                &settings.item(),
+                settings.to_settings(),
+            );
+            let handle = self.insert(Texture::placeholder(name.into(), settings), true);
+            self.track(asset_id, &handle);
+            self.index_source(&handle, path, &settings.item());
+
+            let (id, name, path) = (handle.id, name.to_string(), path.to_path_buf());
+            self.spawn_load(move || FinishedLoad::Texture(id, Texture::new(&path, name, settings)));
+            Ok(handle)
+        });
+        handle
     }
 
-    pub fn add_mesh(&mut self, name: &str, path: impl AsRef<Path>) -> AssetHandle<Mesh> {
-        AssetHandle::<Mesh> {
-            uuid: self.resource_manager.add(Mesh::new(name.into(), path)),
-            _phantom: PhantomData,
+    fn spawn_load(&self, load: impl FnOnce() -> FinishedLoad + Send + 'static) {
+        let sender = self.finished_sender.clone();
+        self.pending_loads.fetch_add(1, Ordering::Relaxed);
+        self.workers.execute(move || {
+            // The manager may be gone already, in which case the result is simply dropped
+            let _ = sender.send(load());
+        });
+    }
+
+    /// Moves the results of finished background loads into their placeholders.
+    /// Depending on the fallback policy, failed assets get the fallback data or keep the placeholder.
+    /// All assets whose data changed are returned together, so that their GPU data can be uploaded in one batch.
+    pub fn poll_loads(&self) -> AssetChanges {
+        let mut changes = AssetChanges::default();
+        let finished = self
+            .finished_loads
+            .lock()
+            .unwrap()
+            .try_iter()
+            .collect::<Vec<_>>();
+        self.pending_loads
+            .fetch_sub(finished.len(), Ordering::Relaxed);
+        for load in finished {
+            match load {
+                FinishedLoad::Mesh(id, result) => {
+                    if self.finish_load(id, result, Mesh::error) {
+                        changes.meshes.push(AssetHandle::new(id));
+                    }
+                }
+                FinishedLoad::Texture(id, result) => {
+                    if self.finish_load(id, result, Texture::error) {
+                        changes.textures.push(AssetHandle::new(id));
+                    }
+                }
+            }
+        }
+        changes
+    }
+
+    /// Returns false if the data of the asset did not change or the asset was removed while it was loading
+    fn finish_load<T: Asset + 'static>(
+        &self,
+        id: ResourceId,
+        result: Result<T, AssetError>,
+        fallback: impl FnOnce(String, &AssetError) -> T,
+    ) -> bool {
+        let Some(name) = self
+            .resources
+            .get::<T>(id)
+            .map(|asset| asset.name().clone())
+        else {
+            return false;
+        };
+        match result.or_else(|error| self.substitute(&name, error, fallback)) {
+            Ok(asset) => self.replace(id, asset),
+            Err(error) => {
+                println!("{}", error);
+                if let Some(mut asset) = self.resources.get_mut::<T>(id) {
+                    asset
+                        .asset_metadata_mut()
+                        .set_load_state(LoadState::Failed(error.to_string()));
+                }
+                false
+            }
         }
     }
 
+    /// Number of requested assets whose background load has not been polled yet
+    pub fn pending_loads(&self) -> usize {
+        self.pending_loads.load(Ordering::Relaxed)
+    }
+
+    /// Fails if the Slang module of the material does not exist
     pub fn add_material(
-        &mut self,
+        &self,
         name: &str,
         module: &str,
         material_type: &str,
-    ) -> AssetHandle<Material> {
-        AssetHandle::<Material> {
-            uuid: self.resource_manager.add(Material::new(
-                name.into(),
-                module.into(),
-                material_type.into(),
-            )),
-            _phantom: PhantomData,
+    ) -> Result<AssetHandle<Material>, AssetError> {
+        let module_path = Path::new(SHADER_DIRECTORY)
+            .join(module)
+            .with_extension("slang");
+        if !module_path.is_file() {
+            return Err(AssetError::MissingShaderModule {
+                module: module.into(),
+                path: module_path,
+            });
         }
+        let settings = BTreeMap::from([
+            ("module".to_string(), module.to_string()),
+            ("material".to_string(), material_type.to_string()),
+        ]);
+        let asset_id =
+            self.registry
+                .write()
+                .unwrap()
+                .register(MATERIAL_TYPE, name, None, name, settings);
+        let handle = self.insert(
+            Material::new(name.into(), module.into(), material_type.into()),
+            true,
+        );
+        self.track(asset_id, &handle);
+        Ok(handle)
     }
 
+    /// Fails if the material is not loaded
     pub fn add_material_instance(
-        &mut self,
+        &self,
         name: &str,
         material: AssetHandle<Material>,
-    ) -> AssetHandle<MaterialInstance> {
-        AssetHandle::<MaterialInstance> {
-            uuid: self
-                .resource_manager
-                .add(MaterialInstance::new(name.into(), material)),
-            _phantom: PhantomData,
+    ) -> Result<AssetHandle<MaterialInstance>, AssetError> {
+        self.add_configured_material_instance(MaterialInstance::new(name.into(), material))
+    }
+
+    /// Adds an instance with its parameters already set.
+    /// Fails if its material or one of its textures is not loaded.
+    pub fn add_configured_material_instance(
+        &self,
+        instance: MaterialInstance,
+    ) -> Result<AssetHandle<MaterialInstance>, AssetError> {
+        let material = instance.material();
+        self.check_handle(&material, MATERIAL_TYPE)?;
+        // Material instances reference their material and texture by their stable IDs
+        let mut settings = BTreeMap::new();
+        if let Some(material_id) = self.asset_id(&material) {
+            settings.insert("material".to_string(), material_id.to_string());
+        }
+        // Only parameters that differ from the defaults are stored
+        let defaults = MaterialParameters::default();
+        let parameters = instance.parameters();
+        let mut insert_floats = |key: &str, values: &[f32], default: &[f32]| {
+            if values != default {
+                let values = values.iter().map(f32::to_string).collect::<Vec<_>>();
+                settings.insert(key.to_string(), values.join(" "));
+            }
+        };
+        insert_floats("base_color", &parameters.base_color, &defaults.base_color);
+        insert_floats("metallic", &[parameters.metallic], &[defaults.metallic]);
+        insert_floats("roughness", &[parameters.roughness], &[defaults.roughness]);
+        insert_floats("emissive", &parameters.emissive, &defaults.emissive);
+        insert_floats(
+            "normal_scale",
+            &[parameters.normal_scale],
+            &[defaults.normal_scale],
+        );
+        insert_floats(
+            "occlusion_strength",
+            &[parameters.occlusion_strength],
+            &[defaults.occlusion_strength],
+        );
+        for slot in TextureSlot::ALL {
+            let Some(texture) = instance.texture(slot) else {
+                continue;
+            };
+            self.check_handle(&texture, TEXTURE_TYPE)?;
+            if let Some(texture_id) = self.asset_id(&texture) {
+                settings.insert(slot.setting().to_string(), texture_id.to_string());
+            }
+        }
+        if let Some(texture) = instance.environment_texture() {
+            self.check_handle(&texture, CUBE_TEXTURE_TYPE)?;
+            if let Some(texture_id) = self.asset_id(&texture) {
+                settings.insert(
+                    ENVIRONMENT_TEXTURE_SETTING.to_string(),
+                    texture_id.to_string(),
+                );
+            }
+        }
+        if let Some(sampler) = instance.sampler() {
+            self.check_handle(&sampler, SAMPLER_TYPE)?;
+            if let Some(sampler_id) = self.asset_id(&sampler) {
+                settings.insert(SAMPLER_SETTING.to_string(), sampler_id.to_string());
+            }
         }
+        let asset_id = self.registry.write().unwrap().register(
+            MATERIAL_INSTANCE_TYPE,
+            instance.name(),
+            None,
+            instance.name(),
+            settings,
+        );
+        let handle = self.insert(instance, true);
+        self.track(asset_id, &handle);
+        Ok(handle)
     }
 
+    /// Adds a sampler that material instances share through [`MaterialInstance::with_sampler`]
+    pub fn add_sampler(&self, name: &str, desc: SamplerDesc) -> AssetHandle<Sampler> {
+        let asset_id = self.registry.write().unwrap().register(
+            SAMPLER_TYPE,
+            name,
+            None,
+            name,
+            desc.to_settings(),
+        );
+        let handle = self.insert(Sampler::new(name.into(), desc), true);
+        self.track(asset_id, &handle);
+        handle
+    }
+
+    /// Changes how all material instances sharing the sampler sample their textures.
+    /// The renderer picks up the change through [`Self::poll_edits`].
+    pub fn set_sampler(
+        &self,
+        handle: &AssetHandle<Sampler>,
+        desc: SamplerDesc,
+    ) -> Result<(), AssetError> {
+        self.check_handle(handle, SAMPLER_TYPE)?;
+        let name = {
+            let mut sampler = self.get_mut(handle).unwrap();
+            sampler.set_desc(desc);
+            sampler.name().clone()
+        };
+        // Registering the same item again only updates the stored settings
+        self.registry.write().unwrap().register(
+            SAMPLER_TYPE,
+            &name,
+            None,
+            &name,
+            desc.to_settings(),
+        );
+        self.edited_samplers.lock().unwrap().push(handle.clone());
+        Ok(())
+    }
+
+    /// Returns the assets that were edited through the manager since the last call
+    pub fn poll_edits(&self) -> AssetChanges {
+        AssetChanges {
+            samplers: std::mem::take(&mut self.edited_samplers.lock().unwrap()),
+            ..Default::default()
+        }
+    }
+
+    /// Fails if the mesh or the material instance is not loaded
     pub fn add_model(
-        &mut self,
+        &self,
         name: &str,
         transform: Transform,
         mesh: AssetHandle<Mesh>,
         material: AssetHandle<MaterialInstance>,
-    ) -> AssetHandle<Model> {
-        AssetHandle::<Model> {
-            uuid: self
-                .resource_manager
-                .add(Model::new(name.into(), transform, mesh, material)),
-            _phantom: PhantomData,
+    ) -> Result<AssetHandle<Model>, AssetError> {
+        self.check_handle(&mesh, MESH_TYPE)?;
+        self.check_handle(&material, MATERIAL_INSTANCE_TYPE)?;
+        // Models are owned by the scene and are only removed explicitly
+        Ok(self.insert(Model::new(name.into(), transform, mesh, material), false))
+    }
+
+    fn check_handle<T: Asset + 'static>(
+        &self,
+        handle: &AssetHandle<T>,
+        asset_type: &'static str,
+    ) -> Result<(), AssetError> {
+        if self.resources.contains::<T>(handle.id) {
+            Ok(())
+        } else {
+            Err(AssetError::InvalidHandle { asset_type })
+        }
+    }
+
+    /// Removes the asset and returns it. Returns None if the handle is stale.
+    /// Dependencies that are not referenced anymore are unloaded as well.
+    /// The asset stays in the registry and can be loaded again through its stable ID.
+    pub fn take<T: Asset + 'static>(&self, handle: &AssetHandle<T>) -> Option<T> {
+        let asset = self.resources.take(handle.id)?;
+        self.forget(handle.id);
+        Some(asset)
+    }
+
+    /// Drops all bookkeeping of a removed asset and unloads dependencies that became unused
+    /// Returns the number of dependencies that were unloaded with the asset
+    fn forget(&self, id: ResourceId) -> usize {
+        {
+            let mut lookup = self.lookup.write().unwrap();
+            if let Some(asset_id) = lookup.asset_ids.remove(&id) {
+                lookup.loaded_assets.remove(&asset_id);
+            }
+            if let Some(keys) = lookup.lookup_keys.remove(&id) {
+                // A newer asset with the same name may have taken over the entry
+                if lookup.names.get(&keys.name) == Some(&id) {
+                    lookup.names.remove(&keys.name);
+                }
+                if let Some(source) = keys.source {
+                    lookup.sources.remove(&source);
+                }
+            }
+        }
+        self.unloaded.lock().unwrap().push(id);
+
+        let dependencies = self.dependencies.write().unwrap().remove(id);
+        self.unload_unused(dependencies)
+    }
+
+    /// Unloads the candidates that are not referenced anymore, following their dependencies.
+    /// Returns the number of unloaded assets.
+    fn unload_unused(&self, candidates: Vec<ResourceId>) -> usize {
+        let mut unloaded = 0;
+        for candidate in candidates {
+            let unused = self.dependencies.read().unwrap().is_unused(candidate);
+            if unused && self.resources.remove_any(candidate) {
+                unloaded += 1 + self.forget(candidate);
+            }
+        }
+        unloaded
+    }
+
+    /// Returns the assets that were unloaded since the last call, so that their RHI proxies can be released
+    pub fn drain_unloaded(&self) -> Vec<ResourceId> {
+        std::mem::take(&mut self.unloaded.lock().unwrap())
+    }
+
+    /// Returns a handle that keeps the asset loaded while it exists
+    pub fn strong<T: Asset + 'static>(
+        &self,
+        handle: &AssetHandle<T>,
+    ) -> Option<StrongAssetHandle<T>> {
+        self.dependencies.read().unwrap().strong(handle)
+    }
+
+    /// Returns a handle that can be upgraded as long as the asset is loaded
+    pub fn weak<T: Asset + 'static>(&self, handle: &AssetHandle<T>) -> Option<WeakAssetHandle<T>> {
+        self.dependencies.read().unwrap().weak(handle)
+    }
+
+    /// Number of strong handles to the asset. Assets that use it are listed by `users`.
+    pub fn strong_count<T: Asset + 'static>(&self, handle: &AssetHandle<T>) -> usize {
+        self.dependencies.read().unwrap().strong_count(handle.id)
+    }
+
+    pub fn weak_count<T: Asset + 'static>(&self, handle: &AssetHandle<T>) -> usize {
+        self.dependencies.read().unwrap().weak_count(handle.id)
+    }
+
+    /// All assets that hold a handle to the given asset
+    pub fn users<T: Asset + 'static>(&self, handle: &AssetHandle<T>) -> Vec<ResourceId> {
+        self.dependencies
+            .read()
+            .unwrap()
+            .dependents(handle.id)
+            .collect()
+    }
+
+    /// All assets of type U that hold a handle to the given asset
+    pub fn users_of_type<T: Asset + 'static, U: Asset + 'static>(
+        &self,
+        handle: &AssetHandle<T>,
+    ) -> Vec<AssetHandle<U>> {
+        self.users(handle)
+            .into_iter()
+            .filter(|id| self.resources.contains::<U>(*id))
+            .map(AssetHandle::new)
+            .collect()
+    }
+
+    /// Re-reads the dependencies of an asset after its handles were changed through [`Self::get_mut`]
+    pub fn update_dependencies<T: Asset + 'static>(&self, handle: &AssetHandle<T>) {
+        let Some(dependencies) = self.get(handle).map(|asset| asset.dependencies()) else {
+            return;
+        };
+        let previous = self
+            .dependencies
+            .write()
+            .unwrap()
+            .set_dependencies(handle.id, dependencies);
+        self.unload_unused(previous);
+    }
+
+    /// Removes the asset. All handles to it become invalid.
+    pub fn remove<T: Asset + 'static>(&self, handle: &AssetHandle<T>) -> bool {
+        self.take(handle).is_some()
+    }
+
+    fn track<T: Asset>(&self, asset_id: AssetId, handle: &AssetHandle<T>) {
+        // Adding an asset under an already registered name replaces the previous mapping
+        let mut lookup = self.lookup.write().unwrap();
+        if let Some(previous) = lookup.loaded_assets.insert(asset_id, handle.id) {
+            lookup.asset_ids.remove(&previous);
+        }
+        lookup.asset_ids.insert(handle.id, asset_id);
+    }
+
+    /// Returns the loaded asset of type T with the given name
+    pub fn find<T: Asset + 'static>(&self, name: &str) -> Option<AssetHandle<T>> {
+        self.lookup
+            .read()
+            .unwrap()
+            .names
+            .get(&(TypeId::of::<T>(), name.to_string()))
+            .map(|id| AssetHandle::new(*id))
+    }
+
+    /// Returns the loaded asset of type T that was imported from the given file as the given registry item,
+    /// e.g. a texture with the item of its settings. Assets of whole files have an empty item.
+    pub fn find_by_path<T: Asset + 'static>(
+        &self,
+        path: impl AsRef<Path>,
+        item: &str,
+    ) -> Option<AssetHandle<T>> {
+        self.lookup
+            .read()
+            .unwrap()
+            .sources
+            .get(&(
+                TypeId::of::<T>(),
+                canonical_path(path.as_ref()),
+                item.to_string(),
+            ))
+            .map(|id| AssetHandle::new(*id))
+    }
+
+    /// Returns the stable ID of a registered asset
+    pub fn asset_id<T: Asset + 'static>(&self, handle: &AssetHandle<T>) -> Option<AssetId> {
+        self.lookup
+            .read()
+            .unwrap()
+            .asset_ids
+            .get(&handle.id)
+            .copied()
+    }
+
+    /// Resolves a stable ID to a handle. Assets that are registered but not loaded are imported from their record.
+    /// Returns None if the ID is unknown or refers to an asset of another type.
+    pub fn resolve<T: Asset + 'static>(&self, asset_id: AssetId) -> Option<AssetHandle<T>> {
+        if self.loaded(asset_id).is_none() {
+            let record = self.registry.read().unwrap().get(asset_id)?.clone();
+            self.load_record(&record)?;
         }
+        let id = self.loaded(asset_id)?;
+        self.resources
+            .contains::<T>(id)
+            .then(|| AssetHandle::new(id))
+    }
+
+    fn loaded(&self, asset_id: AssetId) -> Option<ResourceId> {
+        self.lookup
+            .read()
+            .unwrap()
+            .loaded_assets
+            .get(&asset_id)
+            .copied()
+    }
+
+    fn load_record(&self, record: &AssetRecord) -> Option<()> {
+        match record.asset_type.as_str() {
+            MESH_TYPE => {
+                let source = record.source.as_ref()?;
+                let index = PrimitiveIndex::from_settings(&record.settings);
+                if index == PrimitiveIndex::default() {
+                    self.add_mesh(&record.name, source).ok()?;
+                } else {
+                    self.add_mesh_primitive(&record.name, &mut MeshSource::new(source), index)
+                        .ok()?;
+                }
+            }
+            TEXTURE_TYPE => {
+                self.add_texture(
+                    &record.name,
+                    record.source.as_ref()?,
+                    TextureSettings::from_settings(&record.settings),
+                )
+                .ok()?;
+            }
+            CUBE_TEXTURE_TYPE => {
+                self.add_cube_texture(&record.name, record.source.as_ref()?)
+                    .ok()?;
+            }
+            MATERIAL_TYPE => {
+                self.add_material(
+                    &record.name,
+                    record.settings.get("module")?,
+                    record.settings.get("material")?,
+                )
+                .ok()?;
+            }
+            SAMPLER_TYPE => {
+                let desc = SamplerDesc::from_settings(&record.settings).unwrap_or_else(|| {
+                    println!(
+                        "Sampler {} has malformed settings and is replaced by the default sampler.",
+                        record.name
+                    );
+                    SamplerDesc::default()
+                });
+                // The record keeps its settings, so that it can be fixed by hand
+                let handle = self.insert(Sampler::new(record.name.clone(), desc), true);
+                self.track(record.id, &handle);
+            }
+            MATERIAL_INSTANCE_TYPE => {
+                let material_id = record.settings.get("material")?.parse().ok()?;
+                let material = self.resolve::<Material>(material_id)?;
+                let mut instance = MaterialInstance::new(record.name.clone(), material);
+                // Missing settings keep their defaults, malformed ones fail the record
+                let floats = |key: &str| match record.settings.get(key) {
+                    Some(values) => values
+                        .split(' ')
+                        .map(|value| value.parse().ok())
+                        .collect::<Option<Vec<f32>>>()
+                        .map(Some),
+                    None => Some(None),
+                };
+                if let Some(color) = floats("base_color")? {
+                    instance.base_color = color.try_into().ok()?;
+                }
+                if let Some([metallic]) = floats("metallic")?.as_deref() {
+                    instance.metallic = *metallic;
+                }
+                if let Some([roughness]) = floats("roughness")?.as_deref() {
+                    instance.roughness = *roughness;
+                }
+                if let Some(emissive) = floats("emissive")? {
+                    instance.emissive = emissive.try_into().ok()?;
+                }
+                if let Some([normal_scale]) = floats("normal_scale")?.as_deref() {
+                    instance.normal_scale = *normal_scale;
+                }
+                if let Some([occlusion_strength]) = floats("occlusion_strength")?.as_deref() {
+                    instance.occlusion_strength = *occlusion_strength;
+                }
+                for slot in TextureSlot::ALL {
+                    if let Some(texture_id) = record.settings.get(slot.setting()) {
+                        let texture = self.resolve::<Texture>(texture_id.parse().ok()?)?;
+                        instance = instance.with_texture(slot, texture);
+                    }
+                }
+                if let Some(texture_id) = record.settings.get(ENVIRONMENT_TEXTURE_SETTING) {
+                    let texture = self.resolve::<CubeTexture>(texture_id.parse().ok()?)?;
+                    instance = instance.with_environment_texture(texture);
+                }
+                if let Some(sampler_id) = record.settings.get(SAMPLER_SETTING) {
+                    match sampler_id
+                        .parse()
+                        .ok()
+                        .and_then(|sampler_id| self.resolve::<Sampler>(sampler_id))
+                    {
+                        Some(sampler) => instance = instance.with_sampler(sampler),
+                        None => println!(
+                            "Sampler {} of {} cannot be loaded. The instance uses the default sampler.",
+                            sampler_id, record.name
+                        ),
+                    }
+                }
+                self.add_configured_material_instance(instance).ok()?;
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// Re-imports meshes and textures whose source files changed and collects materials that need recompilation.
+    /// If an import fails, the previous version of the asset is kept.
+    pub fn poll_file_changes(&self) -> AssetChanges {
+        let mut changes = AssetChanges::default();
+        let changed = self.watcher.lock().unwrap().poll();
+        for path in changed {
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "slang")
+            {
+                self.collect_changed_materials(&path, &mut changes.materials);
+                continue;
+            }
+
+            let records = self
+                .registry
+                .read()
+                .unwrap()
+                .records()
+                .filter(|record| {
+                    record
+                        .source
+                        .as_deref()
+                        .is_some_and(|source| same_file(source, &path))
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            // All primitives of a changed mesh file are imported from one parsed source
+            let mut mesh_source = MeshSource::new(&path);
+            for record in records {
+                let Some(id) = self.loaded(record.id) else {
+                    continue;
+                };
+                match record.asset_type.as_str() {
+                    MESH_TYPE => match Mesh::load_primitive(
+                        record.name.clone(),
+                        &mut mesh_source,
+                        PrimitiveIndex::from_settings(&record.settings),
+                    ) {
+                        Ok(mesh) => {
+                            let mesh = mesh.with_vertex_format(vertex_format(&record.settings));
+                            if self.replace(id, mesh) {
+                                changes.meshes.push(AssetHandle::new(id));
+                            }
+                        }
+                        Err(error) => println!("{}, keeping the previous version", error),
+                    },
+                    TEXTURE_TYPE => match Texture::new(
+                        &path,
+                        record.name.clone(),
+                        TextureSettings::from_settings(&record.settings),
+                    ) {
+                        Ok(texture) => {
+                            if self.replace(id, texture) {
+                                changes.textures.push(AssetHandle::new(id));
+                            }
+                        }
+                        Err(error) => println!("{}, keeping the previous version", error),
+                    },
+                    _ => {}
+                }
+            }
+        }
+        changes
+    }
+
+    /// Materials are affected if their module is the changed file.
+    /// Any other shader file may be included by every material, so all of them are recompiled.
+    fn collect_changed_materials(&self, path: &Path, materials: &mut Vec<AssetHandle<Material>>) {
+        let module = path
+            .strip_prefix(SHADER_DIRECTORY)
+            .ok()
+            .map(|module| module.with_extension(""))
+            .map(|module| module.to_string_lossy().replace('\\', "/"));
+        let Some(all) = self.view::<Material>() else {
+            return;
+        };
+        let matching = all
+            .iter()
+            .filter(|(_, material)| module.as_deref() == Some(material.module()))
+            .map(|(id, _)| AssetHandle::new(id))
+            .collect::<Vec<_>>();
+        let affected = if matching.is_empty() {
+            all.iter().map(|(id, _)| AssetHandle::new(id)).collect()
+        } else {
+            matching
+        };
+        for handle in affected {
+            if !materials.contains(&handle) {
+                materials.push(handle);
+            }
+        }
+    }
+
+    /// Swaps the data of a loaded asset while keeping its id
+    fn replace<T: Asset + 'static>(&self, id: ResourceId, mut asset: T) -> bool {
+        let Some(mut current) = self.resources.get_mut::<T>(id) else {
+            return false;
+        };
+        asset.set_uuid(id.uuid);
+        *current = asset;
+        true
+    }
+
+    /// Selects the layout of the vertices of a mesh on the GPU and keeps the choice in the registry.
+    /// The returned changes contain the mesh if its GPU data has to be uploaded again.
+    pub fn set_vertex_format(
+        &self,
+        mesh: &AssetHandle<Mesh>,
+        format: VertexFormat,
+    ) -> AssetChanges {
+        let mut changes = AssetChanges::default();
+        {
+            let Some(mut asset) = self.get_mut(mesh) else {
+                return changes;
+            };
+            if asset.vertex_format() == format {
+                return changes;
+            }
+            asset.set_vertex_format(format);
+        }
+        changes.meshes.push(AssetHandle::new(mesh.id));
+
+        let Some(asset_id) = self.asset_id(mesh) else {
+            return changes;
+        };
+        let mut registry = self.registry.write().unwrap();
+        if let Some(mut record) = registry.get(asset_id).cloned() {
+            set_vertex_format_setting(&mut record.settings, format);
+            registry.register(
+                MESH_TYPE,
+                &record.name,
+                record.source.as_deref(),
+                &record.item,
+                record.settings,
+            );
+        }
+        changes
+    }
+
+    /// Settings of a texture record. A compression choice that is already registered for the file
+    /// with the same color space and usage is kept.
+    fn texture_settings(&self, path: &Path, mut settings: TextureSettings) -> TextureSettings {
+        let registry = self.registry.read().unwrap();
+        if let Some(record) = registry
+            .find(TEXTURE_TYPE, Some(path), &settings.item())
+            .and_then(|id| registry.get(id))
+        {
+            settings.compress = TextureSettings::from_settings(&record.settings).compress;
+        }
+        settings
+    }
+
+    /// Settings of a mesh record. A vertex format that is already registered for the primitive is kept.
+    fn mesh_settings(&self, path: &Path, index: PrimitiveIndex) -> BTreeMap<String, String> {
+        let mut settings = index.to_settings();
+        let registry = self.registry.read().unwrap();
+        let registered = registry
+            .find(MESH_TYPE, Some(path), &index.item())
+            .and_then(|id| registry.get(id))
+            .map(|record| vertex_format(&record.settings))
+            .unwrap_or_default();
+        set_vertex_format_setting(&mut settings, registered);
+        settings
+    }
+
+    pub fn registry(&self) -> RwLockReadGuard<'_, AssetRegistry> {
+        self.registry.read().unwrap()
+    }
+
+    /// Writes newly registered assets to the manifest
+    pub fn save_registry(&self) -> Result<(), RegistryError> {
+        self.registry.write().unwrap().save()
+    }
+
+    /// Assets of type T cannot be changed while the guard exists
+    pub fn get<T: Asset + 'static>(&self, handle: &AssetHandle<T>) -> Option<ResourceRef<'_, T>> {
+        self.resources.get(handle.id)
+    }
+
+    /// Assets of type T cannot be read by other threads while the guard exists
+    pub fn get_mut<T: Asset + 'static>(
+        &self,
+        handle: &AssetHandle<T>,
+    ) -> Option<ResourceMut<'_, T>> {
+        self.resources.get_mut(handle.id)
+    }
+
+    /// Locks all assets of type T for reading. Their ids can be turned into handles with [`AssetHandle::new`].
+    pub fn view<T: Asset + 'static>(&self) -> Option<ResourceView<'_, T>> {
+        self.resources.view()
+    }
+
+    pub fn count<T: Asset + 'static>(&self) -> usize {
+        self.resources.count::<T>()
+    }
+}
+
+/// Paths that cannot be resolved, e.g. because the file is missing, are used as they are
+fn canonical_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn same_file(left: &Path, right: &Path) -> bool {
+    left == right
+        || matches!(
+            (fs::canonicalize(left), fs::canonicalize(right)),
+            (Ok(left), Ok(right)) if left == right
+        )
+}
+
+fn vertex_format(settings: &BTreeMap<String, String>) -> VertexFormat {
+    match settings.get(VERTEX_FORMAT_SETTING).map(String::as_str) {
+        Some(COMPACT_VERTEX_FORMAT) => VertexFormat::Compact,
+        _ => VertexFormat::Full,
+    }
+}
+
+/// The full format is the default and stored without a setting
+fn set_vertex_format_setting(settings: &mut BTreeMap<String, String>, format: VertexFormat) {
+    match format {
+        VertexFormat::Full => {
+            settings.remove(VERTEX_FORMAT_SETTING);
+        }
+        VertexFormat::Compact => {
+            settings.insert(
+                VERTEX_FORMAT_SETTING.to_string(),
+                COMPACT_VERTEX_FORMAT.to_string(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaced_meshes_are_unloaded_once() {
+        let manager = AssetManager::new();
+        let material = manager.insert(
+            Material::new("Material".into(), "module".into(), "material".into()),
+            true,
+        );
+        let instance = manager.insert(MaterialInstance::new("Instance".into(), material), true);
+        let first = manager.insert(Mesh::placeholder("First".into()), true);
+        let second = manager.insert(Mesh::placeholder("Second".into()), true);
+        let model = manager
+            .add_model("Model", Transform::default(), first.clone(), instance)
+            .unwrap();
+        assert!(manager.drain_unloaded().is_empty());
+
+        manager.get_mut(&model).unwrap().mesh = second.clone();
+        manager.update_dependencies(&model);
+        assert!(manager.get(&first).is_none());
+        assert!(manager.get(&second).is_some());
+        assert_eq!(manager.drain_unloaded(), vec![first.id]);
+        assert!(manager.drain_unloaded().is_empty());
     }
 
-    pub fn resource_manager(&self) -> &ResourceManager {
-        &self.resource_manager
+    #[test]
+    fn concurrent_imports_of_one_file_share_the_texture() {
+        let path = std::env::temp_dir().join(format!("{}_shared.png", std::process::id()));
+        image::RgbaImage::from_pixel(64, 64, image::Rgba([255, 0, 0, 255]))
+            .save(&path)
+            .unwrap();
+        let manager = AssetManager::new();
+        let handles = std::thread::scope(|scope| {
+            let imports = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        manager
+                            .add_texture("Shared", &path, TextureSettings::default())
+                            .unwrap()
+                    })
+                })
+                .collect::<Vec<_>>();
+            imports
+                .into_iter()
+                .map(|import| import.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        let _ = fs::remove_file(&path);
+        assert!(handles.iter().all(|handle| *handle == handles[0]));
+        assert_eq!(manager.resources.count::<Texture>(), 1);
     }
 }
@@ -0,0 +1,316 @@
+use std::ops::{Add, Mul};
+
+use asset_system::{Asset, assets::AssetMetadata};
+use glam::{Quat, Vec3};
+
+use crate::application::{assets::skeleton::Pose, scene::transform::Transform};
+
+/// How values between two keyframes are computed, as defined by glTF
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Interpolation {
+    /// The value of a keyframe holds until the next one
+    Step,
+    Linear,
+    /// Hermite spline. Every keyframe stores its in tangent, its value and its out tangent.
+    CubicSpline,
+}
+
+/// Values that can be animated with keyframes
+pub trait Keyframe: Copy + Add<Output = Self> + Mul<f32, Output = Self> {
+    fn interpolate(self, other: Self, t: f32) -> Self;
+
+    /// Brings a point of a spline back to a valid value
+    fn renormalize(self) -> Self {
+        self
+    }
+}
+
+impl Keyframe for Vec3 {
+    fn interpolate(self, other: Self, t: f32) -> Self {
+        self.lerp(other, t)
+    }
+}
+
+impl Keyframe for Quat {
+    /// Takes the shorter way around
+    fn interpolate(self, other: Self, t: f32) -> Self {
+        self.slerp(other, t)
+    }
+
+    fn renormalize(self) -> Self {
+        self.normalize()
+    }
+}
+
+/// Values of one property over time
+#[derive(Clone, PartialEq, Debug)]
+pub struct Keyframes<T: Keyframe> {
+    /// Increasing, in seconds
+    times: Vec<f32>,
+    values: Vec<T>,
+    interpolation: Interpolation,
+}
+
+impl<T: Keyframe> Keyframes<T> {
+    /// Returns None if there are no keyframes or the number of values does not match the times.
+    /// Cubic splines need three values per keyframe, the other interpolations one.
+    pub fn new(times: Vec<f32>, values: Vec<T>, interpolation: Interpolation) -> Option<Self> {
+        let values_per_keyframe = match interpolation {
+            Interpolation::CubicSpline => 3,
+            Interpolation::Step | Interpolation::Linear => 1,
+        };
+        (!times.is_empty() && values.len() == times.len() * values_per_keyframe).then_some(Self {
+            times,
+            values,
+            interpolation,
+        })
+    }
+
+    /// Time of the last keyframe
+    pub fn duration(&self) -> f32 {
+        *self.times.last().unwrap()
+    }
+
+    fn value(&self, keyframe: usize) -> T {
+        match self.interpolation {
+            Interpolation::CubicSpline => self.values[keyframe * 3 + 1],
+            Interpolation::Step | Interpolation::Linear => self.values[keyframe],
+        }
+    }
+
+    /// The first and the last value hold before and after the keyframes
+    pub fn sample(&self, time: f32) -> T {
+        let next = self.times.partition_point(|keyframe| *keyframe <= time);
+        if next == 0 {
+            return self.value(0);
+        }
+        if next == self.times.len() {
+            return self.value(next - 1);
+        }
+        let previous = next - 1;
+        let span = self.times[next] - self.times[previous];
+        let t = (time - self.times[previous]) / span;
+        match self.interpolation {
+            Interpolation::Step => self.value(previous),
+            Interpolation::Linear => self.value(previous).interpolate(self.value(next), t),
+            Interpolation::CubicSpline => {
+                let (t2, t3) = (t * t, t * t * t);
+                // Tangents are per second, the span scales them to the interval
+                let out_tangent = self.values[previous * 3 + 2] * span;
+                let in_tangent = self.values[next * 3] * span;
+                (self.value(previous) * (2. * t3 - 3. * t2 + 1.)
+                    + out_tangent * (t3 - 2. * t2 + t)
+                    + self.value(next) * (3. * t2 - 2. * t3)
+                    + in_tangent * (t3 - t2))
+                    .renormalize()
+            }
+        }
+    }
+}
+
+/// Property of a transform that a channel animates
+#[derive(Clone, PartialEq, Debug)]
+pub enum ChannelKeyframes {
+    Translation(Keyframes<Vec3>),
+    Rotation(Keyframes<Quat>),
+    Scale(Keyframes<Vec3>),
+}
+
+impl ChannelKeyframes {
+    pub fn duration(&self) -> f32 {
+        match self {
+            ChannelKeyframes::Translation(keyframes) | ChannelKeyframes::Scale(keyframes) => {
+                keyframes.duration()
+            }
+            ChannelKeyframes::Rotation(keyframes) => keyframes.duration(),
+        }
+    }
+
+    /// Overwrites the animated property, the others are kept
+    pub fn apply(&self, time: f32, transform: &mut Transform) {
+        match self {
+            ChannelKeyframes::Translation(keyframes) => transform.location = keyframes.sample(time),
+            ChannelKeyframes::Rotation(keyframes) => transform.rotation = keyframes.sample(time),
+            ChannelKeyframes::Scale(keyframes) => transform.scale = keyframes.sample(time),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Channel {
+    /// Index into the joints of the skeleton
+    pub joint: usize,
+    pub keyframes: ChannelKeyframes,
+}
+
+/// Animation of the joints of a skeleton
+#[derive(Asset)]
+pub struct AnimationClip {
+    asset_metadata: AssetMetadata,
+    // Channels are imported from the source file and not edited field by field
+    #[asset(skip)]
+    channels: Vec<Channel>,
+    /// Time of the last keyframe of all channels in seconds
+    #[asset(skip)]
+    duration: f32,
+}
+
+impl AnimationClip {
+    pub fn new(name: String, channels: Vec<Channel>) -> Self {
+        let duration = channels
+            .iter()
+            .map(|channel| channel.keyframes.duration())
+            .fold(0., f32::max);
+        Self {
+            asset_metadata: AssetMetadata::new(name),
+            channels,
+            duration,
+        }
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    pub fn channels(&self) -> &[Channel] {
+        &self.channels
+    }
+
+    /// Overwrites the joints the clip animates, the others keep their transforms.
+    /// Channels of joints the pose does not have are ignored.
+    pub fn sample(&self, time: f32, pose: &mut Pose) {
+        for channel in &self.channels {
+            if let Some(joint) = pose.joints.get_mut(channel.joint) {
+                channel.keyframes.apply(time, joint);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!(a.abs_diff_eq(b, 1e-5), "{} != {}", a, b);
+    }
+
+    fn ramp(interpolation: Interpolation) -> Keyframes<Vec3> {
+        Keyframes::new(
+            vec![1., 3.],
+            vec![Vec3::ZERO, Vec3::new(2., 4., 6.)],
+            interpolation,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn values_hold_outside_of_the_keyframes() {
+        let keyframes = ramp(Interpolation::Linear);
+        assert_close(keyframes.sample(0.), Vec3::ZERO);
+        assert_close(keyframes.sample(5.), Vec3::new(2., 4., 6.));
+        assert_eq!(keyframes.duration(), 3.);
+    }
+
+    #[test]
+    fn linear_and_step_interpolation() {
+        assert_close(
+            ramp(Interpolation::Linear).sample(1.5),
+            Vec3::new(0.5, 1., 1.5),
+        );
+        assert_close(ramp(Interpolation::Step).sample(2.9), Vec3::ZERO);
+        assert_close(ramp(Interpolation::Step).sample(3.), Vec3::new(2., 4., 6.));
+    }
+
+    #[test]
+    fn cubic_splines_hit_the_keyframes_and_follow_the_tangents() {
+        // A straight line with matching tangents stays a straight line
+        let slope = Vec3::new(1., 2., 3.);
+        let keyframes = Keyframes::new(
+            vec![1., 3.],
+            vec![slope, Vec3::ZERO, slope, slope, slope * 2., slope],
+            Interpolation::CubicSpline,
+        )
+        .unwrap();
+        assert_close(keyframes.sample(1.), Vec3::ZERO);
+        assert_close(keyframes.sample(2.5), slope * 1.5);
+        assert_close(keyframes.sample(3.), slope * 2.);
+
+        // Zero tangents ease in and out
+        let eased = Keyframes::new(
+            vec![0., 1.],
+            vec![
+                Vec3::ZERO,
+                Vec3::ZERO,
+                Vec3::ZERO,
+                Vec3::ZERO,
+                Vec3::ONE,
+                Vec3::ZERO,
+            ],
+            Interpolation::CubicSpline,
+        )
+        .unwrap();
+        assert_close(eased.sample(0.5), Vec3::splat(0.5));
+        assert!(eased.sample(0.1).x < 0.1);
+    }
+
+    #[test]
+    fn mismatched_values_are_rejected() {
+        assert!(
+            Keyframes::new(
+                vec![0., 1.],
+                vec![Vec3::ZERO; 2],
+                Interpolation::CubicSpline
+            )
+            .is_none()
+        );
+        assert!(Keyframes::<Vec3>::new(vec![], vec![], Interpolation::Linear).is_none());
+    }
+
+    #[test]
+    fn rotations_stay_normalized_and_take_the_short_way() {
+        let start = Quat::from_rotation_y(0.1);
+        // The same rotation as 0.3 about Y, but in the other hemisphere of the quaternions
+        let end = -Quat::from_rotation_y(0.3);
+        let linear = Keyframes::new(vec![0., 1.], vec![start, end], Interpolation::Linear).unwrap();
+        assert!(linear.sample(0.5).angle_between(Quat::from_rotation_y(0.2)) < 1e-4);
+
+        let zero = Quat::from_xyzw(0., 0., 0., 0.);
+        let spline = Keyframes::new(
+            vec![0., 1.],
+            vec![zero, start, zero, zero, Quat::from_rotation_y(0.3), zero],
+            Interpolation::CubicSpline,
+        )
+        .unwrap();
+        assert!((spline.sample(0.4).length() - 1.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn clips_only_overwrite_animated_properties() {
+        let clip = AnimationClip::new(
+            "Clip".into(),
+            vec![
+                Channel {
+                    joint: 1,
+                    keyframes: ChannelKeyframes::Translation(ramp(Interpolation::Linear)),
+                },
+                // Joints the pose does not have are skipped
+                Channel {
+                    joint: 5,
+                    keyframes: ChannelKeyframes::Scale(ramp(Interpolation::Linear)),
+                },
+            ],
+        );
+        assert_eq!(clip.duration(), 3.);
+
+        let rotation = Quat::from_rotation_x(1.);
+        let mut pose = Pose {
+            joints: vec![Transform::default(); 2],
+        };
+        pose.joints[1].rotation = rotation;
+        clip.sample(2., &mut pose);
+        assert_close(pose.joints[0].location, Vec3::ZERO);
+        assert_close(pose.joints[1].location, Vec3::new(1., 2., 3.));
+        assert_eq!(pose.joints[1].rotation, rotation);
+    }
+}
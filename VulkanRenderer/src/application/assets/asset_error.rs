@@ -0,0 +1,153 @@
+use std::{
+    fmt::{Display, Formatter},
+    io,
+    path::PathBuf,
+};
+
+use image::ImageError;
+
+#[derive(Debug)]
+pub enum AssetError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    /// The file extension does not belong to a supported mesh format
+    UnsupportedFormat {
+        path: PathBuf,
+    },
+    Gltf {
+        path: PathBuf,
+        error: gltf::Error,
+    },
+    /// The glTF file does not contain the requested mesh primitive
+    MissingPrimitive {
+        path: PathBuf,
+    },
+    /// The glTF file contains no scene to import
+    MissingScene {
+        path: PathBuf,
+    },
+    /// The glTF file does not contain the requested skin
+    MissingSkin {
+        path: PathBuf,
+    },
+    /// The primitive lacks a vertex attribute the renderer needs
+    MissingAttribute {
+        path: PathBuf,
+        attribute: &'static str,
+    },
+    /// An OBJ, MTL, PLY, DDS or KTX2 file is malformed
+    Parse {
+        path: PathBuf,
+        message: String,
+    },
+    Image {
+        path: PathBuf,
+        error: ImageError,
+    },
+    /// A DDS or KTX2 file stores its texels in a format other than the supported block formats
+    UnsupportedTextureFormat {
+        path: PathBuf,
+        format: String,
+    },
+    /// The texels of an image cannot be encoded into the block format
+    UnsupportedEncoding {
+        path: PathBuf,
+        format: String,
+    },
+    /// The Slang module of a material does not exist
+    MissingShaderModule {
+        module: String,
+        path: PathBuf,
+    },
+    /// A handle passed to the asset manager refers to an asset that is not loaded
+    InvalidHandle {
+        asset_type: &'static str,
+    },
+}
+
+impl Display for AssetError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssetError::Io { path, error } => {
+                write!(f, "Failed to read {}: {}", path.display(), error)
+            }
+            AssetError::UnsupportedFormat { path } => {
+                write!(f, "{} is not in a supported mesh format", path.display())
+            }
+            AssetError::Gltf { path, error } => {
+                write!(
+                    f,
+                    "Failed to import glTF file {}: {}",
+                    path.display(),
+                    error
+                )
+            }
+            AssetError::MissingPrimitive { path } => {
+                write!(f, "{} does not contain a mesh primitive", path.display())
+            }
+            AssetError::MissingScene { path } => {
+                write!(f, "{} does not contain a scene", path.display())
+            }
+            AssetError::MissingSkin { path } => {
+                write!(f, "{} does not contain the requested skin", path.display())
+            }
+            AssetError::MissingAttribute { path, attribute } => {
+                write!(f, "A primitive in {} has no {}", path.display(), attribute)
+            }
+            AssetError::Parse { path, message } => {
+                write!(f, "Failed to parse {}: {}", path.display(), message)
+            }
+            AssetError::Image { path, error } => {
+                write!(f, "Failed to decode image {}: {}", path.display(), error)
+            }
+            AssetError::UnsupportedTextureFormat { path, format } => {
+                write!(
+                    f,
+                    "{} stores its texels as {}, which is not supported",
+                    path.display(),
+                    format
+                )
+            }
+            AssetError::UnsupportedEncoding { path, format } => {
+                write!(f, "{} cannot be encoded as {}", path.display(), format)
+            }
+            AssetError::MissingShaderModule { module, path } => write!(
+                f,
+                "Shader module {} does not exist at {}",
+                module,
+                path.display()
+            ),
+            AssetError::InvalidHandle { asset_type } => {
+                write!(
+                    f,
+                    "The {} handle does not refer to a loaded asset",
+                    asset_type
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for AssetError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AssetError::Io { error, .. } => Some(error),
+            AssetError::Gltf { error, .. } => Some(error),
+            AssetError::Image { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// What the asset manager does when a mesh or texture cannot be imported
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum FallbackPolicy {
+    /// Log the cause and substitute the built-in error mesh or checkerboard texture.
+    /// The asset is marked as failed, but callers still get a usable handle.
+    #[default]
+    Substitute,
+    /// Return the error to the caller. Background loads keep their placeholder.
+    Fail,
+}
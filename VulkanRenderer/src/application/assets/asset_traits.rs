@@ -1,4 +1,7 @@
-use std::cell::{Ref, RefMut};
+use std::{
+    cell::{Ref, RefMut},
+    path::Path,
+};
 
 use asset_system::{
     assets::{Asset, AssetHandle},
@@ -8,6 +11,7 @@ use glam::{Mat4, Vec3};
 use vulkano::{buffer::BufferContents, pipeline::graphics::vertex_input};
 
 use crate::application::{
+    assets::{material_instance::TextureSlot, sampler::Sampler},
     rhi::rhi_assets::{RHIHandle, RHIResourceManager, vulkan_camera::VKCamera},
     scene::transform::Transform,
 };
@@ -29,6 +33,130 @@ pub struct Vertex {
     pub texture_coordinates: [f32; 2],
 }
 
+/// Layout in which the vertices of a mesh are stored on the GPU
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum VertexFormat {
+    /// Vertex, with the full precision of the imported data
+    #[default]
+    Full,
+    /// CompactVertex, less than half the size of Vertex. Meshes opt in where the precision suffices.
+    Compact,
+}
+
+/// How the texels of a texture are encoded
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum ColorSpace {
+    /// Colors, decoded to linear values when sampled
+    #[default]
+    Srgb,
+    /// Data such as normals, roughness or occlusion, sampled as stored
+    Linear,
+}
+
+/// What the texels of a texture hold, which selects the block format it is compressed to
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum TextureUsage {
+    /// RGBA colors or packed data in several channels
+    #[default]
+    Color,
+    /// Tangent space normals, of which only x and y are stored
+    NormalMap,
+    /// Data in the red channel, such as occlusion
+    SingleChannel,
+}
+
+impl TextureUsage {
+    pub fn block_format(self) -> BlockFormat {
+        match self {
+            TextureUsage::Color => BlockFormat::Bc7,
+            TextureUsage::NormalMap => BlockFormat::Bc5,
+            TextureUsage::SingleChannel => BlockFormat::Bc4,
+        }
+    }
+}
+
+/// Channel layout of uncompressed texels
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum PixelFormat {
+    #[default]
+    Rgba8,
+    /// Unsigned normalized 16 bit channels
+    Rgba16,
+    Rgba32Float,
+}
+
+impl PixelFormat {
+    /// Size of a texel in bytes
+    pub fn texel_size(self) -> usize {
+        match self {
+            PixelFormat::Rgba8 => 4,
+            PixelFormat::Rgba16 => 8,
+            PixelFormat::Rgba32Float => 16,
+        }
+    }
+}
+
+/// Block compressed encoding of texels. Every block holds 4x4 texels.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BlockFormat {
+    /// RGB with one bit alpha
+    Bc1,
+    /// BC1 colors with explicit 4 bit alpha
+    Bc2,
+    /// BC1 colors with interpolated alpha
+    Bc3,
+    /// Single channel
+    Bc4,
+    /// Two channels, each encoded like BC4
+    Bc5,
+    /// Unsigned half float RGB
+    Bc6hUfloat,
+    /// Signed half float RGB
+    Bc6hSfloat,
+    /// High quality RGBA
+    Bc7,
+}
+
+impl BlockFormat {
+    /// Size of a block in bytes
+    pub fn block_size(self) -> usize {
+        match self {
+            BlockFormat::Bc1 | BlockFormat::Bc4 => 8,
+            _ => 16,
+        }
+    }
+
+    /// Size in bytes of a mip level with the given extent in texels
+    pub fn level_size(self, width: u32, height: u32) -> usize {
+        width.div_ceil(4) as usize * height.div_ceil(4) as usize * self.block_size()
+    }
+}
+
+/// Quantized version of Vertex
+#[derive(BufferContents, Copy, Clone, PartialEq, Debug)]
+#[repr(C)]
+pub struct CompactVertex {
+    /// Position within the bounding box of the mesh, 0 is the minimum and 65535 the maximum corner
+    pub position: [u16; 3],
+    /// The highest bit is reserved for the handedness of the bitangent, which Vertex does not store yet
+    pub tangent_sign: u16,
+    /// Octahedral encoded unit vectors
+    pub normal: [i16; 2],
+    pub tangent: [i16; 2],
+    /// Half floats
+    pub texture_coordinates: [u16; 2],
+}
+
+/// Joints that deform a vertex of a skinned mesh, stored next to its Vertex
+#[derive(BufferContents, Copy, Clone, PartialEq, Debug)]
+#[repr(C)]
+pub struct SkinVertex {
+    /// Indices into the joints of the skin
+    pub joints: [u32; 4],
+    /// Sum up to one
+    pub weights: [f32; 4],
+}
+
 #[derive(BufferContents, Copy, Clone, vertex_input::Vertex)]
 #[repr(C)]
 pub struct Instance {
@@ -43,6 +171,61 @@ pub struct Index {
     pub index: u32,
 }
 
+/// Range of the indices of a mesh that draws one level of detail. All levels share the vertices.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct MeshLod {
+    /// Relative to the first index of the mesh
+    pub first_index: u32,
+    pub index_count: u32,
+    /// Object space distance by which the level deviates from the full resolution mesh
+    pub error: f32,
+    /// Range of the meshlets of the level, relative to the first meshlet of the mesh
+    pub first_meshlet: u32,
+    pub meshlet_count: u32,
+}
+
+/// Per-instance parameters of the metallic roughness model, uploaded for the materials to read
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct MaterialParameters {
+    /// Linear RGBA
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Linear RGB radiance
+    pub emissive: [f32; 3],
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+}
+
+impl Default for MaterialParameters {
+    /// A white, rough dielectric
+    fn default() -> Self {
+        Self {
+            base_color: [1.; 4],
+            metallic: 0.,
+            roughness: 1.,
+            emissive: [0.; 3],
+            normal_scale: 1.,
+            occlusion_strength: 1.,
+        }
+    }
+}
+
+/// Cluster of the triangles of one level of detail, the unit in which meshes are culled and drawn
+#[derive(BufferContents, Copy, Clone, PartialEq, Debug)]
+#[repr(C)]
+pub struct Meshlet {
+    /// Relative to the first triangle of the level of detail
+    pub first_triangle: u32,
+    pub triangle_count: u32,
+    /// Bounding sphere in object space
+    pub center: [f32; 3],
+    pub radius: f32,
+    /// All triangles face away from viewers that see the axis at an angle whose cosine is above the cutoff
+    pub cone_axis: [f32; 3],
+    pub cone_cutoff: f32,
+}
+
 pub trait RHIInterface {
     type MeshType: RHIMeshInterface;
     type TextureType: RHITextureInterface;
@@ -78,6 +261,15 @@ pub trait RHIResource: Resource {
 pub trait MeshInterface: Asset {
     fn vertices(&self) -> &[Vertex];
     fn indices(&self) -> &[Index];
+    /// Levels of detail, starting with the full resolution
+    fn lods(&self) -> &[MeshLod];
+    /// Meshlets of all levels of detail, the triangles of each are consecutive in the indices
+    fn meshlets(&self) -> &[Meshlet];
+    /// Minimum and maximum corner of the bounding box
+    fn bounds(&self) -> ([f32; 3], [f32; 3]);
+    fn vertex_format(&self) -> VertexFormat;
+    /// Joints and weights of each vertex. None if the mesh is not skinned.
+    fn skin(&self) -> Option<&[SkinVertex]>;
     /*fn rhi<RHIType: RHIMeshInterface>(&self, rhi: &RHIType::RHI) -> RHIType {
         RHIType::create(self, rhi)
     }*/
@@ -93,10 +285,25 @@ pub trait RHIMeshInterface: RHIResource {
 }
 
 pub trait TextureInterface: Asset {
+    /// File the texture was imported from, empty for generated textures
+    fn path(&self) -> &Path;
+
     fn pixels(&self) -> &[u8];
 
     fn size(&self) -> [u32; 3];
 
+    fn color_space(&self) -> ColorSpace;
+
+    /// Layout of the texels if the texture is not block compressed
+    fn pixel_format(&self) -> PixelFormat;
+
+    /// None for uncompressed texels
+    fn block_format(&self) -> Option<BlockFormat>;
+
+    /// Texels of the stored mip levels, largest first.
+    /// Uncompressed textures only store the top level, the others are generated on upload.
+    fn mips(&self) -> Vec<&[u8]>;
+
     /*fn rhi<RHIType: RHITextureInterface>(&self, rhi: &RHIType::RHI) -> RHIType {
         RHIType::create(self, rhi)
     }*/
@@ -111,6 +318,24 @@ pub trait RHITextureInterface: RHIResource {
     ) -> Self;
 }
 
+pub trait CubeTextureInterface: Asset {
+    /// Width and height of every face in texels
+    fn face_size(&self) -> u32;
+
+    /// Half float RGBA texels of every mip level, largest first.
+    /// Each level holds the six faces in the order of the layers of a cube image: +X, -X, +Y, -Y, +Z, -Z.
+    fn mips(&self) -> Vec<&[u8]>;
+}
+
+pub trait RHICubeTextureInterface: RHIResource {
+    type RHI: RHIInterface;
+    fn create<T: CubeTextureInterface>(
+        source: &T,
+        rhi: &Self::RHI,
+        resource_manager: &mut RHIResourceManager,
+    ) -> Self;
+}
+
 pub trait ModelInterface: Asset {
     /*fn rhi<RHIType: RHIModelInterface>(&self, rhi: &RHIType::RHI) -> RHIType {
         RHIType::create(self, rhi)
@@ -199,12 +424,20 @@ pub trait RHIMaterialInterface: RHIResource {
 
 pub trait MaterialInstanceInterface: Asset {
     type MaterialType: MaterialInterface + 'static;
+    type TextureType: TextureInterface + 'static;
+    type CubeTextureType: CubeTextureInterface + 'static;
 
     /*fn rhi<RHIType: RHIMaterialInstanceInterface>(&self, rhi: &RHIType::RHI) -> RHIType {
         RHIType::create(self, rhi)
     }*/
 
     fn material(&self) -> AssetHandle<Self::MaterialType>;
+    fn parameters(&self) -> MaterialParameters;
+    fn texture(&self, slot: TextureSlot) -> Option<AssetHandle<Self::TextureType>>;
+    /// Shared sampler of the textures of the instance, None for the default sampler
+    fn sampler(&self) -> Option<AssetHandle<Sampler>>;
+    /// Surroundings that the material shows or is lit by
+    fn environment_texture(&self) -> Option<AssetHandle<Self::CubeTextureType>>;
 }
 
 pub trait RHIMaterialInstanceInterface: RHIResource {
@@ -0,0 +1,430 @@
+use std::array;
+
+use glam::{Mat4, Vec4};
+use image::{Rgba, RgbaImage};
+
+use crate::application::assets::asset_traits::BlockFormat;
+
+/// Interpolation weights of the 16 colors of a BC7 mode 6 block, in 64ths of the second endpoint
+const BC7_WEIGHTS: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Encodes a mip level into BC4, BC5 or BC7, other formats return None.
+/// BC4 keeps the red channel, BC5 red and green. BC7 blocks are all written in mode 6.
+pub fn encode(format: BlockFormat, image: &RgbaImage) -> Option<Vec<u8>> {
+    let encode_block: fn(&[[u8; 4]; 16], &mut Vec<u8>) = match format {
+        BlockFormat::Bc4 => |texels, output| output.extend(encode_bc4_block(channel(texels, 0))),
+        BlockFormat::Bc5 => |texels, output| {
+            output.extend(encode_bc4_block(channel(texels, 0)));
+            output.extend(encode_bc4_block(channel(texels, 1)));
+        },
+        BlockFormat::Bc7 => |texels, output| output.extend(encode_bc7_block(texels)),
+        _ => return None,
+    };
+    let mut output = Vec::with_capacity(format.level_size(image.width(), image.height()));
+    for block_y in 0..image.height().div_ceil(4) {
+        for block_x in 0..image.width().div_ceil(4) {
+            encode_block(&read_block(image, block_x, block_y), &mut output);
+        }
+    }
+    Some(output)
+}
+
+/// Decodes a mip level into RGBA texels, for devices that cannot sample the block format.
+/// Returns None for BC6H, BC7 blocks in other modes than 6 and data that is too short.
+pub fn decode(format: BlockFormat, data: &[u8], width: u32, height: u32) -> Option<RgbaImage> {
+    let size = format.level_size(width, height);
+    if data.len() < size {
+        return None;
+    }
+    let blocks_x = width.div_ceil(4);
+    let mut image = RgbaImage::new(width, height);
+    for (index, block) in data[..size].chunks_exact(format.block_size()).enumerate() {
+        let texels = match format {
+            BlockFormat::Bc1 => decode_bc1_block(block, true),
+            BlockFormat::Bc2 => {
+                let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+                let mut texels = decode_bc1_block(&block[8..], false);
+                for (i, texel) in texels.iter_mut().enumerate() {
+                    texel[3] = ((alpha >> (4 * i)) & 0xF) as u8 * 17;
+                }
+                texels
+            }
+            BlockFormat::Bc3 => {
+                let alpha = decode_bc4_block(&block[..8]);
+                let mut texels = decode_bc1_block(&block[8..], false);
+                for (texel, alpha) in texels.iter_mut().zip(alpha) {
+                    texel[3] = alpha;
+                }
+                texels
+            }
+            BlockFormat::Bc4 => decode_bc4_block(block).map(|red| [red, 0, 0, 255]),
+            BlockFormat::Bc5 => {
+                let red = decode_bc4_block(&block[..8]);
+                let green = decode_bc4_block(&block[8..]);
+                array::from_fn(|i| [red[i], green[i], 0, 255])
+            }
+            BlockFormat::Bc6hUfloat | BlockFormat::Bc6hSfloat => return None,
+            BlockFormat::Bc7 => decode_bc7_block(block)?,
+        };
+        let (block_x, block_y) = (index as u32 % blocks_x, index as u32 / blocks_x);
+        for (i, texel) in texels.into_iter().enumerate() {
+            let x = block_x * 4 + i as u32 % 4;
+            let y = block_y * 4 + i as u32 / 4;
+            if x < width && y < height {
+                image.put_pixel(x, y, Rgba(texel));
+            }
+        }
+    }
+    Some(image)
+}
+
+/// Texels of a block in row order. Blocks at the border repeat the last row and column of the image.
+fn read_block(image: &RgbaImage, block_x: u32, block_y: u32) -> [[u8; 4]; 16] {
+    array::from_fn(|i| {
+        let x = (block_x * 4 + i as u32 % 4).min(image.width() - 1);
+        let y = (block_y * 4 + i as u32 / 4).min(image.height() - 1);
+        image.get_pixel(x, y).0
+    })
+}
+
+fn channel(texels: &[[u8; 4]; 16], channel: usize) -> [u8; 16] {
+    texels.map(|texel| texel[channel])
+}
+
+/// Colors of a BC1 block. BC2 and BC3 always use four colors, BC1 switches to three colors and
+/// transparent black if the first endpoint is not larger than the second.
+fn decode_bc1_block(block: &[u8], punch_through: bool) -> [[u8; 4]; 16] {
+    let color0 = u16::from_le_bytes([block[0], block[1]]);
+    let color1 = u16::from_le_bytes([block[2], block[3]]);
+    let (start, end) = (expand_565(color0), expand_565(color1));
+    let palette = if color0 > color1 || !punch_through {
+        [
+            start,
+            end,
+            interpolate(start, end, 1, 3),
+            interpolate(start, end, 2, 3),
+        ]
+    } else {
+        [start, end, interpolate(start, end, 1, 2), [0, 0, 0, 0]]
+    };
+    let indices = u32::from_le_bytes(block[4..8].try_into().unwrap());
+    array::from_fn(|i| palette[(indices >> (2 * i)) as usize & 3])
+}
+
+fn expand_565(color: u16) -> [u8; 4] {
+    let red = (color >> 11) & 0x1F;
+    let green = (color >> 5) & 0x3F;
+    let blue = color & 0x1F;
+    [
+        (red << 3 | red >> 2) as u8,
+        (green << 2 | green >> 4) as u8,
+        (blue << 3 | blue >> 2) as u8,
+        255,
+    ]
+}
+
+/// Color at numerator / denominator of the way from start to end, rounded
+fn interpolate(start: [u8; 4], end: [u8; 4], numerator: u32, denominator: u32) -> [u8; 4] {
+    array::from_fn(|c| {
+        (((denominator - numerator) * start[c] as u32
+            + numerator * end[c] as u32
+            + denominator / 2)
+            / denominator) as u8
+    })
+}
+
+/// Values a BC4 block can hold. With the first endpoint larger there are six interpolated values,
+/// otherwise four plus 0 and 255.
+fn bc4_palette(first: u8, second: u8) -> [u8; 8] {
+    let (start, end) = (first as u32, second as u32);
+    array::from_fn(|i| {
+        let i = i as u32;
+        match i {
+            0 => first,
+            1 => second,
+            _ if first > second => (((8 - i) * start + (i - 1) * end + 3) / 7) as u8,
+            6 => 0,
+            7 => 255,
+            _ => (((6 - i) * start + (i - 1) * end + 2) / 5) as u8,
+        }
+    })
+}
+
+fn encode_bc4_block(values: [u8; 16]) -> [u8; 8] {
+    let min = *values.iter().min().unwrap();
+    let max = *values.iter().max().unwrap();
+    // Equal endpoints select the palette with 0 and 255, whose first entry still holds the value
+    let palette = bc4_palette(max, min);
+    let mut indices = 0u64;
+    for (i, value) in values.into_iter().enumerate() {
+        let index = (0..8)
+            .min_by_key(|index| palette[*index].abs_diff(value))
+            .unwrap();
+        indices |= (index as u64) << (3 * i);
+    }
+    let mut block = [0; 8];
+    block[0] = max;
+    block[1] = min;
+    block[2..].copy_from_slice(&indices.to_le_bytes()[..6]);
+    block
+}
+
+fn decode_bc4_block(block: &[u8]) -> [u8; 16] {
+    let palette = bc4_palette(block[0], block[1]);
+    let mut index_bytes = [0; 8];
+    index_bytes[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(index_bytes);
+    array::from_fn(|i| palette[(indices >> (3 * i)) as usize & 7])
+}
+
+fn bc7_interpolate(start: [u32; 4], end: [u32; 4], index: u32) -> [u8; 4] {
+    let weight = BC7_WEIGHTS[index as usize];
+    array::from_fn(|c| (((64 - weight) * start[c] + weight * end[c] + 32) >> 6) as u8)
+}
+
+fn decode_bc7_block(block: &[u8]) -> Option<[[u8; 4]; 16]> {
+    let bits = u128::from_le_bytes(block.try_into().ok()?);
+    // Mode 6 starts with six zero bits followed by a one
+    if bits & 0x7F != 0x40 {
+        return None;
+    }
+    let read = |offset: u32, count: u32| ((bits >> offset) & ((1 << count) - 1)) as u32;
+    let endpoint =
+        |offset: u32, p_bit: u32| array::from_fn(|c| read(offset + 14 * c as u32, 7) << 1 | p_bit);
+    let start = endpoint(7, read(63, 1));
+    let end = endpoint(14, read(64, 1));
+    Some(array::from_fn(|i| {
+        // The index of the first texel lacks its highest bit
+        let index = if i == 0 {
+            read(65, 3)
+        } else {
+            read(64 + 4 * i as u32, 4)
+        };
+        bc7_interpolate(start, end, index)
+    }))
+}
+
+/// Mode 6 block with quantized endpoints and the indices that fit the texels best
+struct Bc7Block {
+    /// 7 bit RGBA
+    endpoints: [[u32; 4]; 2],
+    /// Lowest bit of all channels of each endpoint
+    p_bits: [u32; 2],
+    indices: [u32; 16],
+    /// Sum of the squared differences to the texels
+    error: u32,
+}
+
+impl Bc7Block {
+    /// Quantizes the endpoints with the p-bits that result in the smallest error
+    fn fit(start: Vec4, end: Vec4, texels: &[[u8; 4]; 16]) -> Self {
+        let quantize = |endpoint: Vec4, p_bit: u32| -> [u32; 4] {
+            array::from_fn(|c| ((endpoint[c] - p_bit as f32) / 2.).round().clamp(0., 127.) as u32)
+        };
+        (0..4)
+            .map(|p_bits| {
+                let p_bits = [p_bits & 1, p_bits >> 1];
+                let endpoints = [quantize(start, p_bits[0]), quantize(end, p_bits[1])];
+                let [first, second] = Self::expand(endpoints, p_bits);
+                let palette: [[u8; 4]; 16] =
+                    array::from_fn(|index| bc7_interpolate(first, second, index as u32));
+                let mut indices = [0; 16];
+                let mut error = 0;
+                for (texel, index) in texels.iter().zip(&mut indices) {
+                    let (best, best_error) = palette
+                        .iter()
+                        .map(|color| {
+                            (0..4)
+                                .map(|c| (color[c].abs_diff(texel[c]) as u32).pow(2))
+                                .sum::<u32>()
+                        })
+                        .enumerate()
+                        .min_by_key(|(_, error)| *error)
+                        .unwrap();
+                    *index = best as u32;
+                    error += best_error;
+                }
+                Self {
+                    endpoints,
+                    p_bits,
+                    indices,
+                    error,
+                }
+            })
+            .min_by_key(|block| block.error)
+            .unwrap()
+    }
+
+    fn expand(endpoints: [[u32; 4]; 2], p_bits: [u32; 2]) -> [[u32; 4]; 2] {
+        [0, 1].map(|i| endpoints[i].map(|value| value << 1 | p_bits[i]))
+    }
+
+    fn pack(mut self) -> [u8; 16] {
+        // The index of the first texel is stored without its highest bit, so it must be below 8
+        if self.indices[0] >= 8 {
+            self.endpoints.swap(0, 1);
+            self.p_bits.swap(0, 1);
+            self.indices = self.indices.map(|index| 15 - index);
+        }
+        let mut bits = 0x40u128;
+        for c in 0..4 {
+            bits |= (self.endpoints[0][c] as u128) << (7 + 14 * c);
+            bits |= (self.endpoints[1][c] as u128) << (14 + 14 * c);
+        }
+        bits |= (self.p_bits[0] as u128) << 63 | (self.p_bits[1] as u128) << 64;
+        bits |= (self.indices[0] as u128) << 65;
+        for (i, index) in self.indices.iter().enumerate().skip(1) {
+            bits |= (*index as u128) << (64 + 4 * i);
+        }
+        bits.to_le_bytes()
+    }
+}
+
+fn encode_bc7_block(texels: &[[u8; 4]; 16]) -> [u8; 16] {
+    let points = texels.map(|texel| Vec4::from_array(texel.map(f32::from)));
+    let (start, end) = principal_extent(&points);
+    let mut block = Bc7Block::fit(start, end, texels);
+    // The extent of the principal axis ignores how the texels are distributed along it
+    if let Some((start, end)) = least_squares_endpoints(&points, &block.indices) {
+        let refined = Bc7Block::fit(start, end, texels);
+        if refined.error < block.error {
+            block = refined;
+        }
+    }
+    block.pack()
+}
+
+/// Ends of the line through the texels along their principal axis
+fn principal_extent(points: &[Vec4; 16]) -> (Vec4, Vec4) {
+    let mean = points.iter().sum::<Vec4>() / 16.;
+    let covariance = points.iter().fold(Mat4::ZERO, |covariance, point| {
+        let offset = *point - mean;
+        covariance
+            + Mat4::from_cols(
+                offset * offset.x,
+                offset * offset.y,
+                offset * offset.z,
+                offset * offset.w,
+            )
+    });
+    // Power iteration converges to the eigenvector with the largest eigenvalue
+    let mut axis = Vec4::ONE;
+    for _ in 0..8 {
+        axis = (covariance * axis).normalize_or_zero();
+    }
+    let projections = points.iter().map(|point| (*point - mean).dot(axis));
+    let min = projections.clone().fold(f32::MAX, f32::min);
+    let max = projections.fold(f32::MIN, f32::max);
+    (
+        (mean + axis * min).clamp(Vec4::ZERO, Vec4::splat(255.)),
+        (mean + axis * max).clamp(Vec4::ZERO, Vec4::splat(255.)),
+    )
+}
+
+/// Endpoints that minimize the squared error for fixed indices
+fn least_squares_endpoints(points: &[Vec4; 16], indices: &[u32; 16]) -> Option<(Vec4, Vec4)> {
+    let (mut start_start, mut start_end, mut end_end) = (0., 0., 0.);
+    let (mut start_sum, mut end_sum) = (Vec4::ZERO, Vec4::ZERO);
+    for (point, index) in points.iter().zip(indices) {
+        let end_weight = BC7_WEIGHTS[*index as usize] as f32 / 64.;
+        let start_weight = 1. - end_weight;
+        start_start += start_weight * start_weight;
+        start_end += start_weight * end_weight;
+        end_end += end_weight * end_weight;
+        start_sum += *point * start_weight;
+        end_sum += *point * end_weight;
+    }
+    let determinant = start_start * end_end - start_end * start_end;
+    if determinant.abs() < 1e-6 {
+        return None;
+    }
+    let start = (start_sum * end_end - end_sum * start_end) / determinant;
+    let end = (end_sum * start_start - start_sum * start_end) / determinant;
+    Some((
+        start.clamp(Vec4::ZERO, Vec4::splat(255.)),
+        end.clamp(Vec4::ZERO, Vec4::splat(255.)),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn max_difference(first: &RgbaImage, second: &RgbaImage, channels: usize) -> u8 {
+        first
+            .pixels()
+            .zip(second.pixels())
+            .flat_map(|(a, b)| (0..channels).map(|c| a[c].abs_diff(b[c])))
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn bc4_and_bc5_keep_smooth_channels() {
+        let image = RgbaImage::from_fn(8, 8, |x, y| {
+            Rgba([(x * 8 + y * 2) as u8, (200 - x * 3 - y * 7) as u8, 77, 255])
+        });
+        let bc4 = encode(BlockFormat::Bc4, &image).unwrap();
+        assert_eq!(bc4.len(), BlockFormat::Bc4.level_size(8, 8));
+        let decoded = decode(BlockFormat::Bc4, &bc4, 8, 8).unwrap();
+        assert!(max_difference(&image, &decoded, 1) <= 3);
+
+        let bc5 = encode(BlockFormat::Bc5, &image).unwrap();
+        let decoded = decode(BlockFormat::Bc5, &bc5, 8, 8).unwrap();
+        assert!(max_difference(&image, &decoded, 2) <= 3);
+        // Blue is not stored
+        assert_eq!(decoded.get_pixel(3, 3).0[2..], [0, 255]);
+    }
+
+    #[test]
+    fn bc7_keeps_colors_along_a_line() {
+        let image = RgbaImage::from_fn(12, 8, |x, y| {
+            let t = x * 10 + y * 5;
+            Rgba([t as u8, (200 - t) as u8, 60, (255 - t / 2) as u8])
+        });
+        let encoded = encode(BlockFormat::Bc7, &image).unwrap();
+        assert_eq!(encoded.len(), 3 * 2 * 16);
+        let decoded = decode(BlockFormat::Bc7, &encoded, 12, 8).unwrap();
+        assert!(max_difference(&image, &decoded, 4) <= 4);
+
+        let flat = RgbaImage::from_pixel(4, 4, Rgba([200, 101, 50, 255]));
+        let encoded = encode(BlockFormat::Bc7, &flat).unwrap();
+        let decoded = decode(BlockFormat::Bc7, &encoded, 4, 4).unwrap();
+        assert!(max_difference(&flat, &decoded, 4) <= 1);
+    }
+
+    #[test]
+    fn partial_blocks_repeat_the_border() {
+        let image = RgbaImage::from_fn(6, 5, |x, y| Rgba([(x * 40) as u8, (y * 50) as u8, 0, 255]));
+        let encoded = encode(BlockFormat::Bc5, &image).unwrap();
+        assert_eq!(encoded.len(), 2 * 2 * 16);
+        let decoded = decode(BlockFormat::Bc5, &encoded, 6, 5).unwrap();
+        assert_eq!(decoded.dimensions(), (6, 5));
+        assert!(max_difference(&image, &decoded, 2) <= 8);
+    }
+
+    #[test]
+    fn bc1_switches_to_transparency_with_ordered_endpoints() {
+        // Red and blue endpoints, the first three texels use the indices 0, 1 and 2
+        let mut block = [0x00, 0xF8, 0x1F, 0x00, 0b10_01_00, 0, 0, 0];
+        let texels = decode_bc1_block(&block, true);
+        assert_eq!(texels[0], [255, 0, 0, 255]);
+        assert_eq!(texels[1], [0, 0, 255, 255]);
+        assert_eq!(texels[2], [170, 0, 85, 255]);
+
+        block[4] = 0b11;
+        block[..4].copy_from_slice(&[0x1F, 0x00, 0x00, 0xF8]);
+        assert_eq!(decode_bc1_block(&block, true)[0], [0, 0, 0, 0]);
+        assert_eq!(decode_bc1_block(&block, false)[0], [170, 0, 85, 255]);
+    }
+
+    #[test]
+    fn unsupported_formats_are_rejected() {
+        let image = RgbaImage::new(4, 4);
+        assert!(encode(BlockFormat::Bc1, &image).is_none());
+        assert!(decode(BlockFormat::Bc6hUfloat, &[0; 16], 4, 4).is_none());
+        assert!(decode(BlockFormat::Bc7, &[0; 8], 4, 4).is_none());
+        // Mode 0 blocks are not decoded
+        assert!(decode(BlockFormat::Bc7, &[1; 16], 4, 4).is_none());
+    }
+}
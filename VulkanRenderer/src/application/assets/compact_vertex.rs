@@ -0,0 +1,163 @@
+use glam::Vec3;
+
+use crate::application::assets::asset_traits::{CompactVertex, Vertex};
+
+const POSITION_STEPS: f32 = u16::MAX as f32;
+
+/// Offset and scale that turn quantized positions back into object space: offset + position * scale.
+/// Flat axes get a scale of zero, so that every position decodes to the offset.
+pub fn position_quantization((min, max): ([f32; 3], [f32; 3])) -> ([f32; 3], [f32; 3]) {
+    let extent = (Vec3::from(max) - Vec3::from(min)).max(Vec3::ZERO);
+    (min, (extent / POSITION_STEPS).into())
+}
+
+/// Quantizes the vertices against the bounding box of their mesh
+pub fn compress(vertices: &[Vertex], bounds: ([f32; 3], [f32; 3])) -> Vec<CompactVertex> {
+    let (offset, scale) = position_quantization(bounds);
+    let (offset, scale) = (Vec3::from(offset), Vec3::from(scale));
+    let inverse_scale = Vec3::select(scale.cmpgt(Vec3::ZERO), scale.recip(), Vec3::ZERO);
+
+    vertices
+        .iter()
+        .map(|vertex| {
+            let position = ((Vec3::from(vertex.position) - offset) * inverse_scale)
+                .round()
+                .clamp(Vec3::ZERO, Vec3::splat(POSITION_STEPS));
+            CompactVertex {
+                position: position.to_array().map(|axis| axis as u16),
+                tangent_sign: 0,
+                normal: octahedral(Vec3::from(vertex.normal)),
+                tangent: octahedral(Vec3::from(vertex.tangent)),
+                texture_coordinates: vertex.texture_coordinates.map(half),
+            }
+        })
+        .collect()
+}
+
+/// Projects the unit vector onto an octahedron and unfolds the lower half over the upper one.
+/// Both coordinates are stored as 16 bit signed normalized values.
+fn octahedral(vector: Vec3) -> [i16; 2] {
+    let length = vector.x.abs() + vector.y.abs() + vector.z.abs();
+    if length == 0. {
+        return [0, 0];
+    }
+    let vector = vector / length;
+    let (x, y) = if vector.z >= 0. {
+        (vector.x, vector.y)
+    } else {
+        (
+            (1. - vector.y.abs()) * vector.x.signum(),
+            (1. - vector.x.abs()) * vector.y.signum(),
+        )
+    };
+    [x, y].map(|value| (value.clamp(-1., 1.) * i16::MAX as f32).round() as i16)
+}
+
+/// Converts to the bits of the nearest half float. Values beyond the half range become infinite.
+fn half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // Infinity stays infinite, NaN stays NaN
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // Subnormal half, the implicit leading one becomes explicit
+        let shift = (14 - exponent) as u32;
+        if shift > 24 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let rounding = (mantissa >> (shift - 1)) & 1;
+        return sign | ((mantissa >> shift) + rounding) as u16;
+    }
+    // A carry out of the mantissa correctly increments the exponent
+    let rounding = (mantissa >> 12) & 1;
+    sign | ((((exponent as u32) << 10) | (mantissa >> 13)) + rounding) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mirrors the decoding in the shaders
+    fn decode_octahedral(encoded: [i16; 2]) -> Vec3 {
+        let [x, y] = encoded.map(|value| (value as f32 / i16::MAX as f32).max(-1.));
+        let mut vector = Vec3::new(x, y, 1. - x.abs() - y.abs());
+        let fold = (-vector.z).max(0.);
+        vector.x += if vector.x >= 0. { -fold } else { fold };
+        vector.y += if vector.y >= 0. { -fold } else { fold };
+        vector.normalize()
+    }
+
+    fn decode_half(bits: u16) -> f32 {
+        let sign = if bits & 0x8000 != 0 { -1. } else { 1. };
+        let exponent = ((bits >> 10) & 0x1f) as i32;
+        let mantissa = (bits & 0x3ff) as f32;
+        match exponent {
+            0 => sign * mantissa * 2f32.powi(-24),
+            0x1f => sign * f32::INFINITY,
+            _ => sign * (1. + mantissa / 1024.) * 2f32.powi(exponent - 15),
+        }
+    }
+
+    #[test]
+    fn positions_stay_within_half_a_step() {
+        let vertices = (0..100)
+            .map(|i| {
+                let t = i as f32 / 99.;
+                Vertex {
+                    position: [t * 10. - 3., (t * 7.).sin(), 2.],
+                    normal: [0., 0., 1.],
+                    tangent: [1., 0., 0.],
+                    texture_coordinates: [0., 0.],
+                }
+            })
+            .collect::<Vec<_>>();
+        let bounds = ([-3., -1., 2.], [7., 1., 2.]);
+        let (offset, scale) = position_quantization(bounds);
+        assert_eq!(scale[2], 0.);
+
+        for (vertex, compact) in vertices.iter().zip(compress(&vertices, bounds)) {
+            for axis in 0..3 {
+                let decoded = offset[axis] + compact.position[axis] as f32 * scale[axis];
+                assert!((decoded - vertex.position[axis]).abs() <= scale[axis] * 0.5 + 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn octahedral_vectors_keep_their_direction() {
+        for i in 0..1000 {
+            // Spiral over the whole sphere, including both poles
+            let z = 1. - 2. * i as f32 / 999.;
+            let angle = i as f32 * 2.399;
+            let radius = (1. - z * z).max(0.).sqrt();
+            let vector = Vec3::new(radius * angle.cos(), radius * angle.sin(), z);
+
+            let decoded = decode_octahedral(octahedral(vector));
+            assert!(decoded.dot(vector) > 0.99999, "{vector} became {decoded}");
+        }
+    }
+
+    #[test]
+    fn half_floats_round_to_nearest() {
+        for value in [0., 1., -2.5, 0.333, 1000.125, 65504., 1e-6, -0.] {
+            let decoded = decode_half(half(value));
+            // Half floats have 11 significant bits
+            assert!(
+                (decoded - value).abs() <= value.abs() * 2f32.powi(-11) + 2f32.powi(-25),
+                "{value} became {decoded}"
+            );
+        }
+        assert_eq!(half(1e6), 0x7c00);
+        assert_eq!(decode_half(half(0.5)), 0.5);
+    }
+}
@@ -0,0 +1,286 @@
+use std::path::Path;
+
+use ddsfile::{Dds, DxgiFormat};
+use image::DynamicImage;
+
+use crate::application::assets::{
+    asset_error::AssetError, asset_traits::BlockFormat, block_compression, texture,
+};
+
+/// Block compressed texels of a 2D texture with their mip chain
+#[derive(Clone, PartialEq, Debug)]
+pub struct CompressedImage {
+    pub format: BlockFormat,
+    pub width: u32,
+    pub height: u32,
+    /// Largest first
+    pub mips: Vec<Vec<u8>>,
+}
+
+impl CompressedImage {
+    /// Encodes the image and all its mips down to a single texel.
+    /// Returns None if there is no encoder for the format.
+    pub fn encode(image: &DynamicImage, format: BlockFormat) -> Option<Self> {
+        let (width, height) = (image.width(), image.height());
+        let mips = texture::mip_chain(&DynamicImage::ImageRgba8(image.to_rgba8()))
+            .iter()
+            .map(|level| block_compression::encode(format, level.as_rgba8()?))
+            .collect::<Option<_>>()?;
+        Some(Self {
+            format,
+            width,
+            height,
+            mips,
+        })
+    }
+
+    /// Reads the first layer of a DDS file with a BC format
+    pub fn from_dds(path: &Path, bytes: &[u8]) -> Result<Self, AssetError> {
+        let dds = Dds::read(bytes).map_err(|error| AssetError::Parse {
+            path: path.to_path_buf(),
+            message: error.to_string(),
+        })?;
+        let dxgi_format = dds.get_dxgi_format();
+        let format = dxgi_format
+            .and_then(Self::dxgi_block_format)
+            .ok_or_else(|| AssetError::UnsupportedTextureFormat {
+                path: path.to_path_buf(),
+                format: format!("{:?}", dxgi_format),
+            })?;
+        let data = dds.get_data(0).map_err(|error| AssetError::Parse {
+            path: path.to_path_buf(),
+            message: error.to_string(),
+        })?;
+        // Mips follow each other without padding
+        let (width, height) = (dds.get_width(), dds.get_height());
+        let mut offset = 0;
+        let mut levels = Vec::new();
+        for level in 0..dds.get_num_mipmap_levels().max(1) {
+            let size = format.level_size((width >> level).max(1), (height >> level).max(1));
+            levels.push(data.get(offset..offset + size).unwrap_or_default());
+            offset += size;
+        }
+        Self::from_levels(path, format, width, height, levels)
+    }
+
+    /// Reads the first layer of a KTX2 file with a BC format. Supercompressed files are not supported.
+    pub fn from_ktx2(path: &Path, bytes: &[u8]) -> Result<Self, AssetError> {
+        let reader = ktx2::Reader::new(bytes).map_err(|error| AssetError::Parse {
+            path: path.to_path_buf(),
+            message: error.to_string(),
+        })?;
+        let header = reader.header();
+        let unsupported = |format: String| AssetError::UnsupportedTextureFormat {
+            path: path.to_path_buf(),
+            format,
+        };
+        if let Some(scheme) = header.supercompression_scheme {
+            return Err(unsupported(format!("{:?} supercompression", scheme)));
+        }
+        let format = header
+            .format
+            .and_then(Self::ktx2_block_format)
+            .ok_or_else(|| unsupported(format!("{:?}", header.format)))?;
+        // Layers and faces of a level follow each other, the first one starts the level
+        let (width, height) = (header.pixel_width, header.pixel_height.max(1));
+        let levels = reader.levels().enumerate().map(|(level, data)| {
+            let size = format.level_size((width >> level).max(1), (height >> level).max(1));
+            data.data.get(..size).unwrap_or_default()
+        });
+        Self::from_levels(path, format, width, height, levels.collect())
+    }
+
+    /// Checks that every level holds all of its blocks
+    fn from_levels(
+        path: &Path,
+        format: BlockFormat,
+        width: u32,
+        height: u32,
+        levels: Vec<&[u8]>,
+    ) -> Result<Self, AssetError> {
+        if width == 0 || height == 0 {
+            return Err(AssetError::Parse {
+                path: path.to_path_buf(),
+                message: "the texture is empty".into(),
+            });
+        }
+        let mut mips = Vec::with_capacity(levels.len());
+        for (level, data) in levels.into_iter().enumerate() {
+            let size = format.level_size((width >> level).max(1), (height >> level).max(1));
+            if data.len() != size {
+                return Err(AssetError::Parse {
+                    path: path.to_path_buf(),
+                    message: format!("mip level {} is truncated", level),
+                });
+            }
+            mips.push(data.to_vec());
+        }
+        Ok(Self {
+            format,
+            width,
+            height,
+            mips,
+        })
+    }
+
+    fn dxgi_block_format(format: DxgiFormat) -> Option<BlockFormat> {
+        match format {
+            DxgiFormat::BC1_Typeless | DxgiFormat::BC1_UNorm | DxgiFormat::BC1_UNorm_sRGB => {
+                Some(BlockFormat::Bc1)
+            }
+            DxgiFormat::BC2_Typeless | DxgiFormat::BC2_UNorm | DxgiFormat::BC2_UNorm_sRGB => {
+                Some(BlockFormat::Bc2)
+            }
+            DxgiFormat::BC3_Typeless | DxgiFormat::BC3_UNorm | DxgiFormat::BC3_UNorm_sRGB => {
+                Some(BlockFormat::Bc3)
+            }
+            DxgiFormat::BC4_Typeless | DxgiFormat::BC4_UNorm => Some(BlockFormat::Bc4),
+            DxgiFormat::BC5_Typeless | DxgiFormat::BC5_UNorm => Some(BlockFormat::Bc5),
+            DxgiFormat::BC6H_Typeless | DxgiFormat::BC6H_UF16 => Some(BlockFormat::Bc6hUfloat),
+            DxgiFormat::BC6H_SF16 => Some(BlockFormat::Bc6hSfloat),
+            DxgiFormat::BC7_Typeless | DxgiFormat::BC7_UNorm | DxgiFormat::BC7_UNorm_sRGB => {
+                Some(BlockFormat::Bc7)
+            }
+            _ => None,
+        }
+    }
+
+    fn ktx2_block_format(format: ktx2::Format) -> Option<BlockFormat> {
+        match format {
+            ktx2::Format::BC1_RGB_UNORM_BLOCK
+            | ktx2::Format::BC1_RGB_SRGB_BLOCK
+            | ktx2::Format::BC1_RGBA_UNORM_BLOCK
+            | ktx2::Format::BC1_RGBA_SRGB_BLOCK => Some(BlockFormat::Bc1),
+            ktx2::Format::BC2_UNORM_BLOCK | ktx2::Format::BC2_SRGB_BLOCK => Some(BlockFormat::Bc2),
+            ktx2::Format::BC3_UNORM_BLOCK | ktx2::Format::BC3_SRGB_BLOCK => Some(BlockFormat::Bc3),
+            ktx2::Format::BC4_UNORM_BLOCK => Some(BlockFormat::Bc4),
+            ktx2::Format::BC5_UNORM_BLOCK => Some(BlockFormat::Bc5),
+            ktx2::Format::BC6H_UFLOAT_BLOCK => Some(BlockFormat::Bc6hUfloat),
+            ktx2::Format::BC6H_SFLOAT_BLOCK => Some(BlockFormat::Bc6hSfloat),
+            ktx2::Format::BC7_UNORM_BLOCK | ktx2::Format::BC7_SRGB_BLOCK => Some(BlockFormat::Bc7),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ddsfile::{AlphaMode, D3D10ResourceDimension, NewDxgiParams};
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+
+    fn path() -> &'static Path {
+        Path::new("test")
+    }
+
+    #[test]
+    fn encoding_builds_the_full_mip_chain() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(10, 4, Rgba([9, 8, 7, 255])));
+        let encoded = CompressedImage::encode(&image, BlockFormat::Bc4).unwrap();
+        let sizes = encoded.mips.iter().map(Vec::len).collect::<Vec<_>>();
+        // 10x4, 5x2, 2x1 and 1x1 texels
+        assert_eq!(sizes, [3 * 8, 2 * 8, 8, 8]);
+        assert!(CompressedImage::encode(&image, BlockFormat::Bc1).is_none());
+    }
+
+    #[test]
+    fn dds_mips_are_split_by_their_size() {
+        let mut dds = Dds::new_dxgi(NewDxgiParams {
+            height: 8,
+            width: 8,
+            depth: None,
+            format: DxgiFormat::BC7_UNorm_sRGB,
+            mipmap_levels: Some(4),
+            array_layers: None,
+            caps2: None,
+            is_cubemap: false,
+            resource_dimension: D3D10ResourceDimension::Texture2D,
+            alpha_mode: AlphaMode::Unknown,
+        })
+        .unwrap();
+        dds.data = (0..(4 + 1 + 1 + 1) * 16).map(|i| i as u8).collect();
+        let mut bytes = Vec::new();
+        dds.write(&mut bytes).unwrap();
+
+        let image = CompressedImage::from_dds(path(), &bytes).unwrap();
+        assert_eq!(image.format, BlockFormat::Bc7);
+        assert_eq!((image.width, image.height), (8, 8));
+        assert_eq!(image.mips.len(), 4);
+        assert_eq!(image.mips[0].len(), 64);
+        assert_eq!(image.mips[1][0], 64);
+        assert_eq!(image.mips[3][0], 96);
+
+        bytes.truncate(bytes.len() - 1);
+        assert!(matches!(
+            CompressedImage::from_dds(path(), &bytes),
+            Err(AssetError::Parse { .. })
+        ));
+    }
+
+    /// KTX2 file with the given vkFormat and mips of 8x4 texels
+    fn ktx2_file(vk_format: u32, supercompression: u32) -> Vec<u8> {
+        let levels: [&[u8]; 2] = [&[1; 32], &[2; 16]];
+        let level_index_end = 80 + 24 * levels.len();
+        // The data format descriptor is required, but not read
+        let dfd = [4u8, 0, 0, 0];
+        let mut data_offset = level_index_end + dfd.len();
+
+        let mut file = b"\xABKTX 20\xBB\r\n\x1A\n".to_vec();
+        for value in [
+            vk_format,
+            1,
+            8,
+            4,
+            0,
+            0,
+            1,
+            levels.len() as u32,
+            supercompression,
+        ] {
+            file.extend(value.to_le_bytes());
+        }
+        for value in [level_index_end as u32, dfd.len() as u32, 0, 0] {
+            file.extend(value.to_le_bytes());
+        }
+        file.extend([0u8; 16]);
+        for level in levels {
+            for value in [data_offset as u64, level.len() as u64, level.len() as u64] {
+                file.extend(value.to_le_bytes());
+            }
+            data_offset += level.len();
+        }
+        file.extend(dfd);
+        for level in levels {
+            file.extend(level);
+        }
+        file
+    }
+
+    #[test]
+    fn ktx2_levels_are_read_in_order() {
+        let bytes = ktx2_file(ktx2::Format::BC5_UNORM_BLOCK.value(), 0);
+        let image = CompressedImage::from_ktx2(path(), &bytes).unwrap();
+        assert_eq!(image.format, BlockFormat::Bc5);
+        assert_eq!(image.mips, [vec![1; 32], vec![2; 16]]);
+    }
+
+    #[test]
+    fn ktx2_without_block_format_is_unsupported() {
+        let unsupported = |bytes: Vec<u8>| {
+            matches!(
+                CompressedImage::from_ktx2(path(), &bytes),
+                Err(AssetError::UnsupportedTextureFormat { .. })
+            )
+        };
+        assert!(unsupported(ktx2_file(
+            ktx2::Format::R8G8B8A8_UNORM.value(),
+            0
+        )));
+        // Zstandard
+        assert!(unsupported(ktx2_file(
+            ktx2::Format::BC7_UNORM_BLOCK.value(),
+            2
+        )));
+    }
+}
@@ -0,0 +1,537 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use memmap2::{Mmap, MmapMut};
+
+use crate::application::assets::{
+    AssetManager::ASSET_DIRECTORY,
+    asset_traits::{Index, MeshLod, Meshlet, SkinVertex, Vertex},
+    mesh_source::PrimitiveIndex,
+};
+
+/// Cooked meshes mirror the layout of the asset directory below this folder
+const COOKED_DIRECTORY: &str = "resources/cooked";
+const COOKED_EXTENSION: &str = "mesh";
+
+const MAGIC: [u8; 4] = *b"VRMS";
+/// Must be bumped whenever the header layout or the processing of imported meshes changes
+const VERSION: u32 = 5;
+const HEADER_SIZE: usize = 84;
+/// Index range, error and meshlet range of a level of detail
+const LOD_SIZE: usize = 20;
+
+/// Header at the start of every cooked mesh. It is followed by the levels of detail, the meshlets, the vertices,
+/// the indices of all levels and, for skinned meshes, the skin of each vertex. All but the levels of detail are
+/// stored in the layout of their `BufferContents`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct CookedMeshHeader {
+    pub vertex_count: u64,
+    pub index_count: u64,
+    pub lod_count: u32,
+    pub meshlet_count: u32,
+    pub skinned: bool,
+    pub bounds_min: [f32; 3],
+    pub bounds_max: [f32; 3],
+    /// Hash of the source file the mesh was cooked from
+    pub source_hash: u64,
+}
+
+/// Memory-mapped cooked mesh. Meshlets, vertices and indices are read directly from the mapping.
+pub struct CookedMesh {
+    header: CookedMeshHeader,
+    lods: Vec<MeshLod>,
+    map: Mmap,
+}
+
+impl CookedMeshHeader {
+    fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+        let mut writer = ByteWriter::new(&mut bytes);
+        writer.write(&MAGIC);
+        writer.write(&VERSION.to_le_bytes());
+        // The element sizes detect layout changes of Vertex, Index, Meshlet and SkinVertex
+        writer.write(&(size_of::<Vertex>() as u32).to_le_bytes());
+        writer.write(&(size_of::<Index>() as u32).to_le_bytes());
+        writer.write(&(size_of::<Meshlet>() as u32).to_le_bytes());
+        writer.write(&(size_of::<SkinVertex>() as u32).to_le_bytes());
+        writer.write(&self.vertex_count.to_le_bytes());
+        writer.write(&self.index_count.to_le_bytes());
+        for value in self.bounds_min.iter().chain(&self.bounds_max) {
+            writer.write(&value.to_le_bytes());
+        }
+        writer.write(&self.source_hash.to_le_bytes());
+        writer.write(&self.lod_count.to_le_bytes());
+        writer.write(&self.meshlet_count.to_le_bytes());
+        writer.write(&(self.skinned as u32).to_le_bytes());
+        bytes
+    }
+
+    /// Returns None if the header was written by another version or for other vertex, index, meshlet and skin layouts
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut reader = ByteReader(bytes.get(..HEADER_SIZE)?);
+        let compatible = reader.read::<4>() == MAGIC
+            && reader.read_u32() == VERSION
+            && reader.read_u32() == size_of::<Vertex>() as u32
+            && reader.read_u32() == size_of::<Index>() as u32
+            && reader.read_u32() == size_of::<Meshlet>() as u32
+            && reader.read_u32() == size_of::<SkinVertex>() as u32;
+        if !compatible {
+            return None;
+        }
+        let vertex_count = reader.read_u64();
+        let index_count = reader.read_u64();
+        let bounds_min = [reader.read_f32(), reader.read_f32(), reader.read_f32()];
+        let bounds_max = [reader.read_f32(), reader.read_f32(), reader.read_f32()];
+        let source_hash = reader.read_u64();
+        Some(Self {
+            vertex_count,
+            index_count,
+            lod_count: reader.read_u32(),
+            meshlet_count: reader.read_u32(),
+            skinned: reader.read_u32() != 0,
+            bounds_min,
+            bounds_max,
+            source_hash,
+        })
+    }
+
+    fn lod_bytes(&self) -> usize {
+        self.lod_count as usize * LOD_SIZE
+    }
+
+    fn meshlet_bytes(&self) -> usize {
+        self.meshlet_count as usize * size_of::<Meshlet>()
+    }
+
+    fn vertex_bytes(&self) -> usize {
+        self.vertex_count as usize * size_of::<Vertex>()
+    }
+
+    fn index_bytes(&self) -> usize {
+        self.index_count as usize * size_of::<Index>()
+    }
+
+    fn skin_bytes(&self) -> usize {
+        if self.skinned {
+            self.vertex_count as usize * size_of::<SkinVertex>()
+        } else {
+            0
+        }
+    }
+}
+
+impl CookedMesh {
+    /// Returns where the cooked version of a primitive in a source file is stored
+    pub fn cooked_path(source: &Path, index: PrimitiveIndex) -> PathBuf {
+        let relative = source
+            .strip_prefix(ASSET_DIRECTORY)
+            .ok()
+            .or_else(|| source.file_name().map(Path::new))
+            .unwrap_or(source);
+        let mut file_name = OsString::from(relative.as_os_str());
+        file_name.push(format!(
+            ".{}.{}.{}",
+            index.mesh, index.primitive, COOKED_EXTENSION
+        ));
+        Path::new(COOKED_DIRECTORY).join(file_name)
+    }
+
+    /// Maps a cooked mesh. Returns None if it is missing, invalid or was cooked from another version of the source.
+    pub fn open(path: &Path, source_hash: u64) -> Option<Self> {
+        let file = File::open(path).ok()?;
+        // Cooked files are only replaced through renames, never modified in place
+        let map = unsafe { Mmap::map(&file) }.ok()?;
+        Self::from_map(map, source_hash)
+    }
+
+    fn from_map(map: Mmap, source_hash: u64) -> Option<Self> {
+        let header = CookedMeshHeader::from_bytes(&map)?;
+        let expected_size = HEADER_SIZE
+            + header.lod_bytes()
+            + header.meshlet_bytes()
+            + header.vertex_bytes()
+            + header.index_bytes()
+            + header.skin_bytes();
+        if header.source_hash != source_hash || map.len() != expected_size {
+            return None;
+        }
+        let mut reader = ByteReader(&map[HEADER_SIZE..HEADER_SIZE + header.lod_bytes()]);
+        let lods = (0..header.lod_count)
+            .map(|_| MeshLod {
+                first_index: reader.read_u32(),
+                index_count: reader.read_u32(),
+                error: reader.read_f32(),
+                first_meshlet: reader.read_u32(),
+                meshlet_count: reader.read_u32(),
+            })
+            .collect();
+        Some(Self { header, lods, map })
+    }
+
+    /// Writes the cooked mesh and maps it. The skin must have one entry per vertex.
+    pub fn write(
+        path: &Path,
+        source_hash: u64,
+        vertices: &[Vertex],
+        indices: &[Index],
+        lods: &[MeshLod],
+        meshlets: &[Meshlet],
+        skin: Option<&[SkinVertex]>,
+    ) -> io::Result<Self> {
+        let (bounds_min, bounds_max) = bounds(vertices);
+        let header = CookedMeshHeader {
+            vertex_count: vertices.len() as u64,
+            index_count: indices.len() as u64,
+            lod_count: lods.len() as u32,
+            meshlet_count: meshlets.len() as u32,
+            skinned: skin.is_some(),
+            bounds_min,
+            bounds_max,
+            source_hash,
+        };
+
+        let mut content = Vec::with_capacity(
+            HEADER_SIZE
+                + header.lod_bytes()
+                + header.meshlet_bytes()
+                + header.vertex_bytes()
+                + header.index_bytes()
+                + header.skin_bytes(),
+        );
+        content.extend_from_slice(&header.to_bytes());
+        for lod in lods {
+            content.extend_from_slice(&lod.first_index.to_le_bytes());
+            content.extend_from_slice(&lod.index_count.to_le_bytes());
+            content.extend_from_slice(&lod.error.to_le_bytes());
+            content.extend_from_slice(&lod.first_meshlet.to_le_bytes());
+            content.extend_from_slice(&lod.meshlet_count.to_le_bytes());
+        }
+        content.extend_from_slice(as_bytes(meshlets));
+        content.extend_from_slice(as_bytes(vertices));
+        content.extend_from_slice(as_bytes(indices));
+        if let Some(skin) = skin {
+            content.extend_from_slice(as_bytes(skin));
+        }
+
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        // Writing to a temporary file and renaming it keeps existing mappings of the old file intact
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, &content)?;
+        let read_back_error = || io::Error::other("The cooked mesh could not be read back");
+        // Windows refuses to replace a file that is still mapped, as when a mesh is reloaded while the previous
+        // version is alive. The mesh is then read from memory and cooked again the next time it is loaded.
+        if let Err(error) = fs::rename(&temporary, path) {
+            let _ = fs::remove_file(&temporary);
+            println!(
+                "Cannot replace the cooked mesh {}, keeping it in memory: {}",
+                path.display(),
+                error
+            );
+            let mut map = MmapMut::map_anon(content.len())?;
+            map.copy_from_slice(&content);
+            return Self::from_map(map.make_read_only()?, source_hash).ok_or_else(read_back_error);
+        }
+
+        Self::open(path, source_hash).ok_or_else(read_back_error)
+    }
+
+    pub fn header(&self) -> &CookedMeshHeader {
+        &self.header
+    }
+
+    pub fn lods(&self) -> &[MeshLod] {
+        &self.lods
+    }
+
+    pub fn meshlets(&self) -> &[Meshlet] {
+        let start = HEADER_SIZE + self.header.lod_bytes();
+        from_bytes(&self.map[start..start + self.header.meshlet_bytes()])
+    }
+
+    pub fn vertices(&self) -> &[Vertex] {
+        let start = HEADER_SIZE + self.header.lod_bytes() + self.header.meshlet_bytes();
+        from_bytes(&self.map[start..start + self.header.vertex_bytes()])
+    }
+
+    pub fn indices(&self) -> &[Index] {
+        let start = HEADER_SIZE
+            + self.header.lod_bytes()
+            + self.header.meshlet_bytes()
+            + self.header.vertex_bytes();
+        from_bytes(&self.map[start..start + self.header.index_bytes()])
+    }
+
+    pub fn skin(&self) -> Option<&[SkinVertex]> {
+        let start = HEADER_SIZE
+            + self.header.lod_bytes()
+            + self.header.meshlet_bytes()
+            + self.header.vertex_bytes()
+            + self.header.index_bytes();
+        self.header
+            .skinned
+            .then(|| from_bytes(&self.map[start..start + self.header.skin_bytes()]))
+    }
+}
+
+/// FNV-1a hash of the content of a source file and of the files it references, like buffers and material libraries.
+/// References that cannot be read are hashed as missing, so that creating them changes the hash as well.
+/// It only has to detect changes, not resist attacks.
+pub fn source_hash(content: &[u8], references: &[PathBuf]) -> u64 {
+    references
+        .iter()
+        .fold(fnv1a(0xcbf29ce484222325, content), |hash, reference| {
+            // The length separates the files, so that moving bytes from one file to the next changes the hash
+            match fs::read(reference) {
+                Ok(content) => fnv1a(fnv1a(hash, &(content.len() as u64).to_le_bytes()), &content),
+                Err(_) => fnv1a(hash, &u64::MAX.to_le_bytes()),
+            }
+        })
+}
+
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Minimum and maximum corner of the bounding box of the vertices
+pub fn bounds(vertices: &[Vertex]) -> ([f32; 3], [f32; 3]) {
+    if vertices.is_empty() {
+        return ([0.; 3], [0.; 3]);
+    }
+    vertices.iter().fold(
+        ([f32::MAX; 3], [f32::MIN; 3]),
+        |(mut min, mut max), vertex| {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(vertex.position[axis]);
+                max[axis] = max[axis].max(vertex.position[axis]);
+            }
+            (min, max)
+        },
+    )
+}
+
+/// Only used for Vertex, Index, Meshlet and SkinVertex, which are repr(C) and contain no padding
+fn as_bytes<T: Copy>(data: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(data.as_ptr().cast(), size_of_val(data)) }
+}
+
+/// Only used for Vertex, Index, Meshlet and SkinVertex. The mapping is page aligned and the header size keeps the data aligned.
+fn from_bytes<T: Copy>(bytes: &[u8]) -> &[T] {
+    assert_eq!(bytes.as_ptr().align_offset(align_of::<T>()), 0);
+    assert_eq!(bytes.len() % size_of::<T>(), 0);
+    unsafe { std::slice::from_raw_parts(bytes.as_ptr().cast(), bytes.len() / size_of::<T>()) }
+}
+
+struct ByteWriter<'a> {
+    bytes: &'a mut [u8],
+    position: usize,
+}
+
+impl<'a> ByteWriter<'a> {
+    fn new(bytes: &'a mut [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        self.bytes[self.position..self.position + data.len()].copy_from_slice(data);
+        self.position += data.len();
+    }
+}
+
+struct ByteReader<'a>(&'a [u8]);
+
+impl ByteReader<'_> {
+    fn read<const N: usize>(&mut self) -> [u8; N] {
+        let (value, rest) = self.0.split_first_chunk::<N>().unwrap();
+        self.0 = rest;
+        *value
+    }
+
+    fn read_u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.read())
+    }
+
+    fn read_u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.read())
+    }
+
+    fn read_f32(&mut self) -> f32 {
+        f32::from_le_bytes(self.read())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deletes the file when the test ends, even if it fails
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, content: &[u8]) -> Self {
+            let path = std::env::temp_dir().join(format!("{}_{}", std::process::id(), name));
+            fs::write(&path, content).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn vertex(position: [f32; 3]) -> Vertex {
+        Vertex {
+            position,
+            normal: [0., 0., 1.],
+            tangent: [1., 0., 0.],
+            texture_coordinates: [position[0], position[1]],
+        }
+    }
+
+    const SOURCE_HASH: u64 = 0x1234;
+
+    /// Writes a skinned square with two levels of detail
+    fn write_square(path: &Path) -> io::Result<CookedMesh> {
+        let vertices = [[0., 0., 0.], [1., 0., 0.], [1., 1., 0.], [0., 1., 0.]].map(vertex);
+        let indices = [0, 1, 2, 0, 2, 3, 0, 1, 2].map(|index| Index { index });
+        let lods = [
+            MeshLod {
+                first_index: 0,
+                index_count: 6,
+                error: 0.,
+                first_meshlet: 0,
+                meshlet_count: 1,
+            },
+            MeshLod {
+                first_index: 6,
+                index_count: 3,
+                error: 0.5,
+                first_meshlet: 1,
+                meshlet_count: 1,
+            },
+        ];
+        let meshlet = |first_triangle, triangle_count| Meshlet {
+            first_triangle,
+            triangle_count,
+            center: [0.5, 0.5, 0.],
+            radius: 0.75,
+            cone_axis: [0., 0., 1.],
+            cone_cutoff: 0.,
+        };
+        let skin = [0, 1, 1, 0].map(|joint| SkinVertex {
+            joints: [joint, 0, 0, 0],
+            weights: [1., 0., 0., 0.],
+        });
+        CookedMesh::write(
+            path,
+            SOURCE_HASH,
+            &vertices,
+            &indices,
+            &lods,
+            &[meshlet(0, 2), meshlet(0, 1)],
+            Some(&skin),
+        )
+    }
+
+    fn assert_square(mesh: &CookedMesh) {
+        assert_eq!(mesh.header().vertex_count, 4);
+        assert_eq!(mesh.header().bounds_max, [1., 1., 0.]);
+        assert_eq!(mesh.lods().len(), 2);
+        assert_eq!(mesh.lods()[1].error, 0.5);
+        assert_eq!(mesh.meshlets()[0].triangle_count, 2);
+        assert_eq!(mesh.meshlets()[1].triangle_count, 1);
+        assert_eq!(mesh.vertices()[2].position, [1., 1., 0.]);
+        assert_eq!(mesh.vertices()[3].texture_coordinates, [0., 1.]);
+        let indices = mesh
+            .indices()
+            .iter()
+            .map(|index| index.index)
+            .collect::<Vec<_>>();
+        assert_eq!(indices, [0, 1, 2, 0, 2, 3, 0, 1, 2]);
+        let joints = mesh
+            .skin()
+            .unwrap()
+            .iter()
+            .map(|vertex| vertex.joints[0])
+            .collect::<Vec<_>>();
+        assert_eq!(joints, [0, 1, 1, 0]);
+    }
+
+    #[test]
+    fn written_meshes_are_opened_again() {
+        let file = TempFile::new("round_trip.mesh", b"");
+        assert_square(&write_square(&file.0).unwrap());
+        assert_square(&CookedMesh::open(&file.0, SOURCE_HASH).unwrap());
+    }
+
+    #[test]
+    fn invalid_cooked_meshes_are_rejected() {
+        let file = TempFile::new("rejected.mesh", b"");
+        drop(write_square(&file.0).unwrap());
+        let content = fs::read(&file.0).unwrap();
+        assert!(CookedMesh::open(&file.0, SOURCE_HASH + 1).is_none());
+
+        let mut other_version = content.clone();
+        other_version[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        fs::write(&file.0, other_version).unwrap();
+        assert!(CookedMesh::open(&file.0, SOURCE_HASH).is_none());
+
+        for length in [content.len() - 1, HEADER_SIZE, 10] {
+            fs::write(&file.0, &content[..length]).unwrap();
+            assert!(
+                CookedMesh::open(&file.0, SOURCE_HASH).is_none(),
+                "{} bytes were accepted",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn meshes_that_cannot_replace_their_file_stay_in_memory() {
+        // Renaming onto a directory that is not empty fails on every platform
+        let path = std::env::temp_dir().join(format!("{}_occupied.mesh", std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+        let blocker = TempFile(path.join("blocker"));
+        fs::write(&blocker.0, b"").unwrap();
+        let mesh = write_square(&path);
+        drop(blocker);
+        fs::remove_dir(&path).unwrap();
+        assert_square(&mesh.unwrap());
+        assert!(!path.with_extension("tmp").exists());
+    }
+
+    #[test]
+    fn hashes_cover_the_referenced_files() {
+        let buffer = TempFile::new("hashed_buffer.bin", b"vertices");
+        let missing = std::env::temp_dir().join("missing_buffer.bin");
+        let content = b"{ \"buffers\": [] }";
+
+        let alone = source_hash(content, &[]);
+        let referenced = source_hash(content, std::slice::from_ref(&buffer.0));
+        assert_ne!(alone, referenced);
+        assert_ne!(
+            referenced,
+            source_hash(content, std::slice::from_ref(&missing))
+        );
+        assert_eq!(
+            referenced,
+            source_hash(content, std::slice::from_ref(&buffer.0))
+        );
+
+        fs::write(&buffer.0, b"changed vertices").unwrap();
+        assert_ne!(
+            referenced,
+            source_hash(content, std::slice::from_ref(&buffer.0))
+        );
+        assert_ne!(alone, source_hash(content, &[missing]));
+    }
+}
@@ -0,0 +1,254 @@
+use std::{f32::consts::PI, path::Path};
+
+use asset_system::{
+    assets::{Asset, AssetMetadata, LoadState},
+    resource_management::Resource,
+};
+use half::f16;
+use image::{DynamicImage, Rgba, Rgba32FImage};
+
+use crate::application::assets::{
+    asset_error::AssetError, asset_traits::CubeTextureInterface, texture,
+};
+
+/// Faces of a cube in the order of the layers of a cube image
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CubeFace {
+    PositiveX,
+    NegativeX,
+    PositiveY,
+    NegativeY,
+    PositiveZ,
+    NegativeZ,
+}
+
+impl CubeFace {
+    pub const ALL: [CubeFace; 6] = [
+        CubeFace::PositiveX,
+        CubeFace::NegativeX,
+        CubeFace::PositiveY,
+        CubeFace::NegativeY,
+        CubeFace::PositiveZ,
+        CubeFace::NegativeZ,
+    ];
+
+    /// Unnormalized direction through a point of the face.
+    /// The face coordinates go from -1 to 1 to the right and downwards, as they are sampled by Vulkan.
+    pub fn direction(self, u: f32, v: f32) -> [f32; 3] {
+        match self {
+            CubeFace::PositiveX => [1., -v, -u],
+            CubeFace::NegativeX => [-1., -v, u],
+            CubeFace::PositiveY => [u, 1., v],
+            CubeFace::NegativeY => [u, -1., -v],
+            CubeFace::PositiveZ => [u, -v, 1.],
+            CubeFace::NegativeZ => [-u, -v, -1.],
+        }
+    }
+}
+
+/// Environment map with float texels, sampled by direction
+pub struct CubeTexture {
+    face_size: u32,
+    /// Half float RGBA texels of every mip level, each holding all faces
+    mips: Vec<Vec<u8>>,
+    metadata: AssetMetadata,
+}
+
+impl CubeTexture {
+    /// Reads an equirectangular panorama, usually from a .hdr or .exr file, and projects it onto the cube
+    pub fn new(filepath: impl AsRef<Path>, name: String) -> Result<Self, AssetError> {
+        let image = texture::decode(filepath.as_ref())?;
+        Ok(Self::from_equirect(&image, name))
+    }
+
+    /// Every face covers a quarter of the width of the panorama, which keeps the resolution at the horizon.
+    /// The mips are generated for each face down to a single texel.
+    pub fn from_equirect(image: &DynamicImage, name: String) -> Self {
+        let face_size = (image.width() / 4).max(1);
+        let faces = equirect_to_faces(&image.to_rgba32f(), face_size);
+        Self::from_faces(faces, name)
+    }
+
+    /// Black cube with a single texel per face
+    pub fn placeholder(name: String) -> Self {
+        let faces = CubeFace::ALL.map(|_| Rgba32FImage::from_pixel(1, 1, Rgba([0., 0., 0., 1.])));
+        Self::from_faces(faces, name)
+    }
+
+    /// Black cube that replaces environment maps that failed to load. It is marked as failed with the cause.
+    pub fn error(name: String, cause: &AssetError) -> Self {
+        let mut texture = Self::placeholder(name);
+        texture
+            .metadata
+            .set_load_state(LoadState::Failed(cause.to_string()));
+        texture
+    }
+
+    fn from_faces(faces: [Rgba32FImage; 6], name: String) -> Self {
+        let face_size = faces[0].width();
+        let chains = faces.map(|face| texture::mip_chain(&DynamicImage::ImageRgba32F(face)));
+        let mips = (0..chains[0].len())
+            .map(|level| {
+                chains
+                    .iter()
+                    .flat_map(|chain| chain[level].as_rgba32f().unwrap().as_raw())
+                    .flat_map(|channel| f16::from_f32(*channel).to_ne_bytes())
+                    .collect()
+            })
+            .collect();
+        Self {
+            face_size,
+            mips,
+            metadata: AssetMetadata::new(name),
+        }
+    }
+}
+
+/// Projects the panorama onto the faces of a cube, in the order of CubeFace::ALL.
+/// The center of the panorama looks towards -Z and its top row towards +Y.
+pub fn equirect_to_faces(image: &Rgba32FImage, face_size: u32) -> [Rgba32FImage; 6] {
+    CubeFace::ALL.map(|face| {
+        Rgba32FImage::from_fn(face_size, face_size, |x, y| {
+            // Texel centers
+            let u = 2. * (x as f32 + 0.5) / face_size as f32 - 1.;
+            let v = 2. * (y as f32 + 0.5) / face_size as f32 - 1.;
+            sample_equirect(image, face.direction(u, v))
+        })
+    })
+}
+
+/// Bilinear sample of the panorama in the direction. It wraps around horizontally.
+fn sample_equirect(image: &Rgba32FImage, direction: [f32; 3]) -> Rgba<f32> {
+    let [x, y, z] = direction;
+    let length = (x * x + y * y + z * z).sqrt();
+    let longitude = 0.5 + x.atan2(-z) / (2. * PI);
+    let latitude = (y / length).clamp(-1., 1.).acos() / PI;
+
+    let (width, height) = (image.width(), image.height());
+    // Texel centers lie at half texels
+    let column = longitude * width as f32 - 0.5;
+    let row = (latitude * height as f32 - 0.5).clamp(0., (height - 1) as f32);
+    let (left, top) = (column.floor(), row.floor());
+    let (right_weight, bottom_weight) = (column - left, row - top);
+    let wrap = |column: f32| (column as i64).rem_euclid(width as i64) as u32;
+    let (left, right) = (wrap(left), wrap(left + 1.));
+    let (top, bottom) = (top as u32, (top as u32 + 1).min(height - 1));
+
+    let mut result = [0.; 4];
+    for (column, row, weight) in [
+        (left, top, (1. - right_weight) * (1. - bottom_weight)),
+        (right, top, right_weight * (1. - bottom_weight)),
+        (left, bottom, (1. - right_weight) * bottom_weight),
+        (right, bottom, right_weight * bottom_weight),
+    ] {
+        let texel = image.get_pixel(column, row);
+        for (channel, value) in result.iter_mut().zip(texel.0) {
+            *channel += value * weight;
+        }
+    }
+    Rgba(result)
+}
+
+impl Resource for CubeTexture {
+    fn set_uuid(&mut self, uuid: usize) {
+        self.metadata.uuid = uuid;
+    }
+}
+
+impl Asset for CubeTexture {
+    fn asset_metadata(&self) -> &AssetMetadata {
+        &self.metadata
+    }
+
+    fn asset_metadata_mut(&mut self) -> &mut AssetMetadata {
+        &mut self.metadata
+    }
+}
+
+impl CubeTextureInterface for CubeTexture {
+    fn face_size(&self) -> u32 {
+        self.face_size
+    }
+
+    fn mips(&self) -> Vec<&[u8]> {
+        self.mips.iter().map(Vec::as_slice).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn face_centers_point_along_their_axis() {
+        let axes = [
+            [1., 0., 0.],
+            [-1., 0., 0.],
+            [0., 1., 0.],
+            [0., -1., 0.],
+            [0., 0., 1.],
+            [0., 0., -1.],
+        ];
+        for (face, axis) in CubeFace::ALL.into_iter().zip(axes) {
+            assert_eq!(face.direction(0., 0.), axis);
+        }
+        // The top of the side faces is up, the top of the +Y face is towards -Z
+        assert_eq!(CubeFace::PositiveZ.direction(0., -1.), [0., 1., 1.]);
+        assert_eq!(CubeFace::PositiveY.direction(0., -1.), [0., 1., -1.]);
+    }
+
+    #[test]
+    fn panorama_rows_map_to_the_poles() {
+        // Sky in the upper half, ground in the lower half
+        let panorama = Rgba32FImage::from_fn(16, 8, |_, y| {
+            if y < 4 {
+                Rgba([1., 2., 3., 1.])
+            } else {
+                Rgba([0., 0., 0., 1.])
+            }
+        });
+        let faces = equirect_to_faces(&panorama, 4);
+        assert!(faces[2].pixels().all(|texel| texel.0 == [1., 2., 3., 1.]));
+        assert!(faces[3].pixels().all(|texel| texel.0 == [0., 0., 0., 1.]));
+        for face in [0, 1, 4, 5] {
+            assert_eq!(faces[face].get_pixel(1, 0).0, [1., 2., 3., 1.]);
+            assert_eq!(faces[face].get_pixel(2, 3).0, [0., 0., 0., 1.]);
+        }
+    }
+
+    #[test]
+    fn panorama_center_looks_towards_negative_z() {
+        let panorama = Rgba32FImage::from_fn(64, 32, |x, _| {
+            let value = if (24..40).contains(&x) { 1. } else { 0. };
+            Rgba([value, value, value, 1.])
+        });
+        assert_eq!(sample_equirect(&panorama, [0., 0., -1.]).0[0], 1.);
+        assert_eq!(sample_equirect(&panorama, [0., 0., 1.]).0[0], 0.);
+    }
+
+    #[test]
+    fn sampling_wraps_around_behind() {
+        let panorama = Rgba32FImage::from_fn(8, 4, |x, _| {
+            let value = if x == 0 { 1. } else { 0. };
+            Rgba([value, value, value, 1.])
+        });
+        // Halfway between the last and the first column
+        assert_eq!(sample_equirect(&panorama, [0., 0., 1.]).0[0], 0.5);
+    }
+
+    #[test]
+    fn mips_hold_all_faces_as_half_floats() {
+        let panorama =
+            DynamicImage::ImageRgba32F(Rgba32FImage::from_pixel(32, 16, Rgba([0.5, 4., 100., 1.])));
+        let cube = CubeTexture::from_equirect(&panorama, "sky".into());
+        assert_eq!(cube.face_size(), 8);
+        let sizes = cube.mips().iter().map(|mip| mip.len()).collect::<Vec<_>>();
+        // 8x8, 4x4, 2x2 and 1x1 texels of 8 bytes on six faces
+        assert_eq!(sizes, [6 * 64 * 8, 6 * 16 * 8, 6 * 4 * 8, 6 * 8]);
+        let last = cube.mips()[3]
+            .chunks_exact(2)
+            .map(|bytes| f16::from_ne_bytes([bytes[0], bytes[1]]).to_f32())
+            .collect::<Vec<_>>();
+        assert_eq!(last, [0.5, 4., 100., 1.].repeat(6));
+    }
+}
@@ -3,27 +3,224 @@ use asset_system::{
     assets::{AssetHandle, AssetMetadata},
 };
 
-use crate::application::assets::{asset_traits::MaterialInstanceInterface, material::Material};
+use crate::application::assets::{
+    asset_traits::{ColorSpace, MaterialInstanceInterface, MaterialParameters, TextureUsage},
+    cube_texture::CubeTexture,
+    material::Material,
+    sampler::Sampler,
+    texture::{Texture, TextureSettings},
+};
+
+/// Texture inputs of the metallic roughness model, named after their glTF counterparts
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TextureSlot {
+    BaseColor,
+    /// Roughness in the green and metalness in the blue channel
+    MetallicRoughness,
+    /// Tangent space normals
+    Normal,
+    /// Ambient occlusion in the red channel
+    Occlusion,
+    Emissive,
+}
+
+impl TextureSlot {
+    pub const ALL: [TextureSlot; 5] = [
+        TextureSlot::BaseColor,
+        TextureSlot::MetallicRoughness,
+        TextureSlot::Normal,
+        TextureSlot::Occlusion,
+        TextureSlot::Emissive,
+    ];
+
+    /// Key of the texture in the registry settings
+    pub fn setting(self) -> &'static str {
+        match self {
+            TextureSlot::BaseColor => "base_color_texture",
+            TextureSlot::MetallicRoughness => "metallic_roughness_texture",
+            TextureSlot::Normal => "normal_texture",
+            TextureSlot::Occlusion => "occlusion_texture",
+            TextureSlot::Emissive => "emissive_texture",
+        }
+    }
+
+    /// Colors are stored in sRGB, everything else holds linear data
+    pub fn color_space(self) -> ColorSpace {
+        match self {
+            TextureSlot::BaseColor | TextureSlot::Emissive => ColorSpace::Srgb,
+            TextureSlot::MetallicRoughness | TextureSlot::Normal | TextureSlot::Occlusion => {
+                ColorSpace::Linear
+            }
+        }
+    }
+
+    pub fn usage(self) -> TextureUsage {
+        match self {
+            TextureSlot::Normal => TextureUsage::NormalMap,
+            TextureSlot::Occlusion => TextureUsage::SingleChannel,
+            TextureSlot::BaseColor | TextureSlot::MetallicRoughness | TextureSlot::Emissive => {
+                TextureUsage::Color
+            }
+        }
+    }
+
+    /// Import settings of a texture in this slot
+    pub fn texture_settings(self, compress: bool) -> TextureSettings {
+        TextureSettings {
+            color_space: self.color_space(),
+            usage: self.usage(),
+            compress,
+        }
+    }
+}
 
 #[derive(Asset)]
 pub struct MaterialInstance {
     material: AssetHandle<Material>,
+    /// Linear RGBA, multiplied with the base color texture
+    #[asset(range = 0.0..1.0)]
+    pub base_color: [f32; 4],
+    #[asset(range = 0.0..1.0)]
+    pub metallic: f32,
+    /// Perceptual roughness, squared by the BRDF
+    #[asset(range = 0.0..1.0)]
+    pub roughness: f32,
+    /// Linear RGB radiance, multiplied with the emissive texture
+    pub emissive: [f32; 3],
+    /// Scales the X and Y of the normal texture
+    pub normal_scale: f32,
+    /// How much of the occlusion texture is applied
+    #[asset(range = 0.0..1.0)]
+    pub occlusion_strength: f32,
+    #[asset(handle)]
+    pub base_color_texture: Option<AssetHandle<Texture>>,
+    #[asset(handle)]
+    pub metallic_roughness_texture: Option<AssetHandle<Texture>>,
+    #[asset(handle)]
+    pub normal_texture: Option<AssetHandle<Texture>>,
+    #[asset(handle)]
+    pub occlusion_texture: Option<AssetHandle<Texture>>,
+    #[asset(handle)]
+    pub emissive_texture: Option<AssetHandle<Texture>>,
+    /// Shared sampler of all textures of the instance, the default sampler if there is none
+    #[asset(handle)]
+    pub sampler: Option<AssetHandle<Sampler>>,
+    /// Shown by sky materials and lights the surface in place of the default ambient light
+    #[asset(handle)]
+    pub environment_texture: Option<AssetHandle<CubeTexture>>,
     asset_metadata: AssetMetadata,
 }
 
 impl MaterialInstance {
+    /// A white, rough dielectric without textures
     pub fn new(name: String, material: AssetHandle<Material>) -> Self {
+        let defaults = MaterialParameters::default();
         Self {
             material,
+            base_color: defaults.base_color,
+            metallic: defaults.metallic,
+            roughness: defaults.roughness,
+            emissive: defaults.emissive,
+            normal_scale: defaults.normal_scale,
+            occlusion_strength: defaults.occlusion_strength,
+            base_color_texture: None,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            occlusion_texture: None,
+            emissive_texture: None,
+            sampler: None,
+            environment_texture: None,
             asset_metadata: AssetMetadata::new(name),
         }
     }
+
+    pub fn with_base_color(mut self, base_color: [f32; 4]) -> Self {
+        self.base_color = base_color;
+        self
+    }
+
+    pub fn with_metallic_roughness(mut self, metallic: f32, roughness: f32) -> Self {
+        self.metallic = metallic;
+        self.roughness = roughness;
+        self
+    }
+
+    pub fn with_emissive(mut self, emissive: [f32; 3]) -> Self {
+        self.emissive = emissive;
+        self
+    }
+
+    pub fn with_normal_scale(mut self, normal_scale: f32) -> Self {
+        self.normal_scale = normal_scale;
+        self
+    }
+
+    pub fn with_occlusion_strength(mut self, occlusion_strength: f32) -> Self {
+        self.occlusion_strength = occlusion_strength;
+        self
+    }
+
+    pub fn with_texture(mut self, slot: TextureSlot, texture: AssetHandle<Texture>) -> Self {
+        *self.texture_mut(slot) = Some(texture);
+        self
+    }
+
+    pub fn with_sampler(mut self, sampler: AssetHandle<Sampler>) -> Self {
+        self.sampler = Some(sampler);
+        self
+    }
+
+    pub fn with_environment_texture(mut self, texture: AssetHandle<CubeTexture>) -> Self {
+        self.environment_texture = Some(texture);
+        self
+    }
+
+    fn texture_mut(&mut self, slot: TextureSlot) -> &mut Option<AssetHandle<Texture>> {
+        match slot {
+            TextureSlot::BaseColor => &mut self.base_color_texture,
+            TextureSlot::MetallicRoughness => &mut self.metallic_roughness_texture,
+            TextureSlot::Normal => &mut self.normal_texture,
+            TextureSlot::Occlusion => &mut self.occlusion_texture,
+            TextureSlot::Emissive => &mut self.emissive_texture,
+        }
+    }
 }
 
 impl MaterialInstanceInterface for MaterialInstance {
     type MaterialType = Material;
+    type TextureType = Texture;
+    type CubeTextureType = CubeTexture;
 
     fn material(&self) -> AssetHandle<Self::MaterialType> {
         self.material.clone()
     }
+
+    fn parameters(&self) -> MaterialParameters {
+        MaterialParameters {
+            base_color: self.base_color,
+            metallic: self.metallic,
+            roughness: self.roughness,
+            emissive: self.emissive,
+            normal_scale: self.normal_scale,
+            occlusion_strength: self.occlusion_strength,
+        }
+    }
+
+    fn texture(&self, slot: TextureSlot) -> Option<AssetHandle<Texture>> {
+        match slot {
+            TextureSlot::BaseColor => self.base_color_texture.clone(),
+            TextureSlot::MetallicRoughness => self.metallic_roughness_texture.clone(),
+            TextureSlot::Normal => self.normal_texture.clone(),
+            TextureSlot::Occlusion => self.occlusion_texture.clone(),
+            TextureSlot::Emissive => self.emissive_texture.clone(),
+        }
+    }
+
+    fn sampler(&self) -> Option<AssetHandle<Sampler>> {
+        self.sampler.clone()
+    }
+
+    fn environment_texture(&self) -> Option<AssetHandle<CubeTexture>> {
+        self.environment_texture.clone()
+    }
 }
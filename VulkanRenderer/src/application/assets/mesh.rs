@@ -1,62 +1,257 @@
 use std::path::Path;
 
-use asset_system::{Asset, assets::AssetMetadata};
+use asset_system::{
+    Asset,
+    assets::{AssetMetadata, LoadState},
+};
+use glam::Vec3;
 
-use crate::application::assets::asset_traits::{Index, MeshInterface, Vertex};
+use crate::application::assets::{
+    asset_error::AssetError,
+    asset_traits::{Index, MeshInterface, MeshLod, Meshlet, SkinVertex, Vertex, VertexFormat},
+    cooked_mesh::{self, CookedMesh},
+    mesh_lod,
+    mesh_source::{MeshSource, PrimitiveIndex},
+    meshlet,
+};
 
 #[derive(Asset)]
 pub struct Mesh {
     asset_metadata: AssetMetadata,
+    /// Layout of the vertices on the GPU
+    vertex_format: VertexFormat,
 
-    vertices: Vec<Vertex>,
-    indices: Vec<Index>,
+    // Geometry is imported from the source file and not edited field by field
+    #[asset(skip)]
+    geometry: Geometry,
+}
+
+enum Geometry {
+    Imported {
+        vertices: Vec<Vertex>,
+        indices: Vec<Index>,
+        lods: Vec<MeshLod>,
+        meshlets: Vec<Meshlet>,
+        skin: Option<Vec<SkinVertex>>,
+    },
+    Cooked(CookedMesh),
 }
 
 impl Mesh {
-    pub fn new(name: String, path: impl AsRef<Path>) -> Self {
-        let (doc, buffers, _) = gltf::import(path).unwrap();
-        let mesh = doc.meshes().next().unwrap();
-        let prim = mesh.primitives().next().unwrap();
-        let read = prim.reader(|buffer| Some(&buffers[buffer.index()]));
-
-        let pos = read.read_positions().unwrap();
-        let normal = read.read_normals().unwrap();
-        let tangent = read.read_tangents().unwrap();
-        let uv = read.read_tex_coords(0).unwrap();
-
-        let vertices = pos
-            .zip(normal)
-            .zip(tangent)
-            .zip(uv.into_f32())
-            .map(|(((p, n), t), tex)| Vertex {
-                position: p,
-                normal: n,
-                tangent: *t.first_chunk().unwrap(),
-                texture_coordinates: tex,
-            })
-            .collect();
-
-        let indices = read
-            .read_indices()
-            .unwrap()
-            .into_u32()
-            .map(|index| Index { index })
-            .collect();
+    /// Loads the first primitive of a glTF, OBJ or PLY file
+    pub fn load(name: String, path: impl AsRef<Path>) -> Result<Self, AssetError> {
+        Self::load_primitive(name, &mut MeshSource::new(path), PrimitiveIndex::default())
+    }
+
+    /// Loads the cooked version of a primitive. If it is missing or outdated, the primitive is imported and cooked again.
+    pub fn load_primitive(
+        name: String,
+        source: &mut MeshSource,
+        index: PrimitiveIndex,
+    ) -> Result<Self, AssetError> {
+        let source_hash = source.hash()?;
+        let cooked_path = CookedMesh::cooked_path(source.path(), index);
+
+        let geometry = match CookedMesh::open(&cooked_path, source_hash) {
+            Some(cooked) => Geometry::Cooked(cooked),
+            None => {
+                let (vertices, indices, skin) = source.primitive(index)?;
+                let (mut indices, mut lods) = mesh_lod::generate_lods(&vertices, indices);
+                let meshlets = meshlet::build_meshlets(&vertices, &mut indices, &mut lods);
+                // A failed cook only costs the import time on the next launch
+                match CookedMesh::write(
+                    &cooked_path,
+                    source_hash,
+                    &vertices,
+                    &indices,
+                    &lods,
+                    &meshlets,
+                    skin.as_deref(),
+                ) {
+                    Ok(cooked) => Geometry::Cooked(cooked),
+                    Err(error) => {
+                        println!("Failed to cook mesh {}: {}", source.path().display(), error);
+                        Geometry::Imported {
+                            vertices,
+                            indices,
+                            lods,
+                            meshlets,
+                            skin,
+                        }
+                    }
+                }
+            }
+        };
+
+        Ok(Self {
+            geometry,
+            asset_metadata: AssetMetadata::new(name),
+            vertex_format: VertexFormat::default(),
+        })
+    }
+
+    pub fn with_vertex_format(mut self, vertex_format: VertexFormat) -> Self {
+        self.set_vertex_format(vertex_format);
+        self
+    }
+
+    /// Only takes effect when the mesh is uploaded to the GPU again
+    pub fn set_vertex_format(&mut self, vertex_format: VertexFormat) {
+        self.vertex_format = vertex_format;
+    }
+
+    /// Unit cube that is shown while the mesh is loaded in the background
+    pub fn placeholder(name: String) -> Self {
+        // Normal and tangent of each face, the bitangent is their cross product
+        const FACES: [([f32; 3], [f32; 3]); 6] = [
+            ([1., 0., 0.], [0., 0., -1.]),
+            ([-1., 0., 0.], [0., 0., 1.]),
+            ([0., 1., 0.], [1., 0., 0.]),
+            ([0., -1., 0.], [1., 0., 0.]),
+            ([0., 0., 1.], [1., 0., 0.]),
+            ([0., 0., -1.], [-1., 0., 0.]),
+        ];
+        const CORNERS: [[f32; 2]; 4] = [[0., 0.], [1., 0.], [1., 1.], [0., 1.]];
+
+        let mut vertices = Vec::with_capacity(FACES.len() * CORNERS.len());
+        let mut indices = Vec::with_capacity(FACES.len() * 6);
+        for (normal, tangent) in FACES {
+            let n = Vec3::from(normal);
+            let t = Vec3::from(tangent);
+            let b = n.cross(t);
+            let first = vertices.len() as u32;
+            for uv in CORNERS {
+                let position = (n + t * (uv[0] * 2. - 1.) + b * (uv[1] * 2. - 1.)) * 0.5;
+                vertices.push(Vertex {
+                    position: position.into(),
+                    normal,
+                    tangent,
+                    texture_coordinates: uv,
+                });
+            }
+            indices.extend([0, 1, 2, 0, 2, 3].map(|offset| Index {
+                index: first + offset,
+            }));
+        }
+
+        Self {
+            geometry: Geometry::full_resolution(vertices, indices),
+            asset_metadata: AssetMetadata::pending(name),
+            vertex_format: VertexFormat::default(),
+        }
+    }
+
+    /// Flat shaded octahedron that replaces meshes that failed to load. It is marked as failed with the cause.
+    pub fn error(name: String, cause: &AssetError) -> Self {
+        let mut vertices = Vec::with_capacity(24);
+        let mut indices = Vec::with_capacity(24);
+        for octant in 0..8 {
+            let sign = |bit: u32| if octant & bit == 0 { 1f32 } else { -1f32 };
+            let (x, y, z) = (sign(1), sign(2), sign(4));
+            // Keep the winding counter clockwise when seen from outside
+            let corners = if x * y * z > 0. {
+                [
+                    Vec3::new(x, 0., 0.),
+                    Vec3::new(0., y, 0.),
+                    Vec3::new(0., 0., z),
+                ]
+            } else {
+                [
+                    Vec3::new(x, 0., 0.),
+                    Vec3::new(0., 0., z),
+                    Vec3::new(0., y, 0.),
+                ]
+            };
+            let normal = Vec3::new(x, y, z).normalize();
+            let tangent = (corners[1] - corners[0]).normalize();
+            let first = vertices.len() as u32;
+            for (corner, uv) in corners.into_iter().zip([[0., 0.], [1., 0.], [0.5, 1.]]) {
+                vertices.push(Vertex {
+                    position: (corner * 0.5).into(),
+                    normal: normal.into(),
+                    tangent: tangent.into(),
+                    texture_coordinates: uv,
+                });
+            }
+            indices.extend((first..first + 3).map(|index| Index { index }));
+        }
 
+        let mut asset_metadata = AssetMetadata::new(name);
+        asset_metadata.set_load_state(LoadState::Failed(cause.to_string()));
         Self {
+            geometry: Geometry::full_resolution(vertices, indices),
+            asset_metadata,
+            vertex_format: VertexFormat::default(),
+        }
+    }
+}
+
+impl Geometry {
+    /// Imported geometry without simplified levels of detail
+    fn full_resolution(vertices: Vec<Vertex>, mut indices: Vec<Index>) -> Self {
+        let mut lods = vec![MeshLod {
+            first_index: 0,
+            index_count: indices.len() as u32,
+            error: 0.,
+            first_meshlet: 0,
+            meshlet_count: 0,
+        }];
+        let meshlets = meshlet::build_meshlets(&vertices, &mut indices, &mut lods);
+        Geometry::Imported {
             vertices,
             indices,
-            asset_metadata: AssetMetadata::new(name),
+            lods,
+            meshlets,
+            skin: None,
         }
     }
 }
 
 impl MeshInterface for Mesh {
     fn vertices(&self) -> &[Vertex] {
-        self.vertices.as_ref()
+        match &self.geometry {
+            Geometry::Imported { vertices, .. } => vertices,
+            // Read straight from the mapping into the staging buffers of the RHI
+            Geometry::Cooked(cooked) => cooked.vertices(),
+        }
     }
 
     fn indices(&self) -> &[Index] {
-        self.indices.as_ref()
+        match &self.geometry {
+            Geometry::Imported { indices, .. } => indices,
+            Geometry::Cooked(cooked) => cooked.indices(),
+        }
+    }
+
+    fn lods(&self) -> &[MeshLod] {
+        match &self.geometry {
+            Geometry::Imported { lods, .. } => lods,
+            Geometry::Cooked(cooked) => cooked.lods(),
+        }
+    }
+
+    fn meshlets(&self) -> &[Meshlet] {
+        match &self.geometry {
+            Geometry::Imported { meshlets, .. } => meshlets,
+            Geometry::Cooked(cooked) => cooked.meshlets(),
+        }
+    }
+
+    fn bounds(&self) -> ([f32; 3], [f32; 3]) {
+        match &self.geometry {
+            Geometry::Imported { vertices, .. } => cooked_mesh::bounds(vertices),
+            Geometry::Cooked(cooked) => (cooked.header().bounds_min, cooked.header().bounds_max),
+        }
+    }
+
+    fn vertex_format(&self) -> VertexFormat {
+        self.vertex_format
+    }
+
+    fn skin(&self) -> Option<&[SkinVertex]> {
+        match &self.geometry {
+            Geometry::Imported { skin, .. } => skin.as_deref(),
+            Geometry::Cooked(cooked) => cooked.skin(),
+        }
     }
 }
@@ -0,0 +1,432 @@
+use std::collections::HashMap;
+
+use glam::{DVec3, Vec3};
+
+use crate::application::assets::{
+    asset_traits::{Index, MeshLod, Vertex},
+    mesh_processing::{optimize_vertex_cache, position_key},
+};
+
+/// Upper bound of the levels of detail of a mesh, including the full resolution
+pub const MAX_LOD_COUNT: usize = 8;
+/// Levels with fewer triangles are not simplified any further
+const MIN_LOD_TRIANGLES: usize = 64;
+/// A level that removes less than this share of the triangles of the previous one is not worth its draw
+const MIN_LOD_REDUCTION: f32 = 0.15;
+
+/// Builds the levels of detail of a mesh and appends their indices to the indices of the full resolution.
+/// Each level is simplified from the previous one to about half of its triangles.
+pub fn generate_lods(vertices: &[Vertex], indices: Vec<Index>) -> (Vec<Index>, Vec<MeshLod>) {
+    // The meshlet ranges are filled in by the meshlet builder
+    let mut lods = vec![MeshLod {
+        first_index: 0,
+        index_count: indices.len() as u32,
+        error: 0.,
+        first_meshlet: 0,
+        meshlet_count: 0,
+    }];
+    let mut indices = indices;
+    let mut previous = 0..indices.len();
+    let mut error = 0.;
+
+    while lods.len() < MAX_LOD_COUNT && previous.len() / 3 >= MIN_LOD_TRIANGLES * 2 {
+        let source = &indices[previous.clone()];
+        let (mut simplified, simplification_error) = simplify(vertices, source, source.len() / 2);
+        if simplified.len() as f32 > source.len() as f32 * (1. - MIN_LOD_REDUCTION) {
+            break;
+        }
+        optimize_vertex_cache(&mut simplified, vertices.len());
+        // The deviations of consecutive levels add up in the worst case
+        error += simplification_error;
+        let first_index = indices.len();
+        lods.push(MeshLod {
+            first_index: first_index as u32,
+            index_count: simplified.len() as u32,
+            error,
+            first_meshlet: 0,
+            meshlet_count: 0,
+        });
+        indices.extend(simplified);
+        previous = first_index..indices.len();
+    }
+
+    (indices, lods)
+}
+
+/// Simplifies a triangle list with quadric error metrics until it has at most `target_index_count` indices
+/// or no edge can be collapsed anymore. Edges collapse onto one of their vertices, so the result indexes the
+/// unchanged vertices. Vertices on borders and attribute seams are locked, which keeps outlines and
+/// texture layouts intact.
+/// Returns the indices and the object space distance by which the result deviates from the input.
+pub fn simplify(
+    vertices: &[Vertex],
+    indices: &[Index],
+    target_index_count: usize,
+) -> (Vec<Index>, f32) {
+    let positions = vertices
+        .iter()
+        .map(|vertex| Vec3::from(vertex.position).as_dvec3())
+        .collect::<Vec<_>>();
+    let mut triangles = indices
+        .chunks_exact(3)
+        .map(|triangle| [triangle[0].index, triangle[1].index, triangle[2].index])
+        .collect::<Vec<_>>();
+    let locked = locked_vertices(vertices, &triangles);
+
+    let mut quadrics = vec![Quadric::default(); vertices.len()];
+    for triangle in &triangles {
+        let [a, b, c] = triangle.map(|vertex| positions[vertex as usize]);
+        let normal = (b - a).cross(c - a);
+        let area = normal.length() * 0.5;
+        if area == 0. {
+            continue;
+        }
+        let normal = normal / (area * 2.);
+        let plane = Quadric::plane(normal, -normal.dot(a), area);
+        for vertex in triangle {
+            quadrics[*vertex as usize].add(&plane);
+        }
+    }
+
+    let target_triangle_count = target_index_count / 3;
+    let mut max_error = 0f64;
+    while triangles.len() > target_triangle_count {
+        let adjacency = Adjacency::new(vertices.len(), &triangles);
+
+        // Each edge collapses in the direction with the lower error
+        let mut candidates = Vec::new();
+        for triangle in &triangles {
+            for corner in 0..3 {
+                let (a, b) = (triangle[corner], triangle[(corner + 1) % 3]);
+                if a > b {
+                    continue;
+                }
+                let best = [(a, b), (b, a)]
+                    .into_iter()
+                    .filter(|(from, _)| !locked[*from as usize])
+                    .map(|(from, to)| {
+                        let mut quadric = quadrics[from as usize];
+                        quadric.add(&quadrics[to as usize]);
+                        (quadric.error(positions[to as usize]), from, to)
+                    })
+                    .min_by(|left, right| left.0.total_cmp(&right.0));
+                candidates.extend(best);
+            }
+        }
+        candidates.sort_unstable_by(|left, right| left.0.total_cmp(&right.0));
+
+        // Collapses within a pass must not share triangles, so that their checks stay valid
+        let mut remap = (0..vertices.len() as u32).collect::<Vec<_>>();
+        let mut touched = vec![false; vertices.len()];
+        let mut removed_count = 0;
+        for (error, from, to) in candidates {
+            if triangles.len() - removed_count <= target_triangle_count {
+                break;
+            }
+            if touched[from as usize]
+                || touched[to as usize]
+                || !can_collapse(&positions, &triangles, &adjacency, from, to)
+            {
+                continue;
+            }
+            for triangle in adjacency.triangles(from) {
+                for vertex in triangles[*triangle as usize] {
+                    touched[vertex as usize] = true;
+                }
+            }
+            remap[from as usize] = to;
+            let quadric = quadrics[from as usize];
+            quadrics[to as usize].add(&quadric);
+            removed_count += adjacency
+                .triangles(from)
+                .iter()
+                .filter(|triangle| triangles[**triangle as usize].contains(&to))
+                .count();
+            max_error = max_error.max(error);
+        }
+        if removed_count == 0 {
+            break;
+        }
+
+        triangles = triangles
+            .into_iter()
+            .map(|triangle| triangle.map(|vertex| remap[vertex as usize]))
+            .filter(|[a, b, c]| a != b && b != c && c != a)
+            .collect();
+    }
+
+    let indices = triangles
+        .into_iter()
+        .flatten()
+        .map(|index| Index { index })
+        .collect();
+    (indices, max_error.sqrt() as f32)
+}
+
+/// Vertices that must not move: all vertices of a position that has several vertices, which is an attribute
+/// seam, and the vertices of edges with other than two triangles
+fn locked_vertices(vertices: &[Vertex], triangles: &[[u32; 3]]) -> Vec<bool> {
+    let mut position_counts = HashMap::with_capacity(vertices.len());
+    for vertex in vertices {
+        *position_counts.entry(position_key(vertex)).or_insert(0u32) += 1;
+    }
+    let mut locked = vertices
+        .iter()
+        .map(|vertex| position_counts[&position_key(vertex)] > 1)
+        .collect::<Vec<_>>();
+
+    let mut edge_counts = HashMap::with_capacity(triangles.len() * 3);
+    for triangle in triangles {
+        for corner in 0..3 {
+            let (a, b) = (triangle[corner], triangle[(corner + 1) % 3]);
+            *edge_counts.entry((a.min(b), a.max(b))).or_insert(0u32) += 1;
+        }
+    }
+    for ((a, b), count) in edge_counts {
+        if count != 2 {
+            locked[a as usize] = true;
+            locked[b as usize] = true;
+        }
+    }
+    locked
+}
+
+/// Rejects collapses that change the topology or flip a triangle
+fn can_collapse(
+    positions: &[DVec3],
+    triangles: &[[u32; 3]],
+    adjacency: &Adjacency,
+    from: u32,
+    to: u32,
+) -> bool {
+    // The vertices shared by both ends must be exactly the third corners of the collapsed triangles,
+    // otherwise the collapse would create non-manifold edges
+    let mut shared_count = 0;
+    let mut neighbors = Vec::new();
+    for triangle in adjacency.triangles(from) {
+        let triangle = triangles[*triangle as usize];
+        if triangle.contains(&to) {
+            shared_count += 1;
+        }
+        for vertex in triangle {
+            if vertex != from && vertex != to && !neighbors.contains(&vertex) {
+                neighbors.push(vertex);
+            }
+        }
+    }
+    let common_count = neighbors
+        .iter()
+        .filter(|vertex| {
+            adjacency
+                .triangles(to)
+                .iter()
+                .any(|triangle| triangles[*triangle as usize].contains(vertex))
+        })
+        .count();
+    if common_count != shared_count {
+        return false;
+    }
+
+    adjacency.triangles(from).iter().all(|triangle| {
+        let triangle = triangles[*triangle as usize];
+        if triangle.contains(&to) {
+            return true;
+        }
+        let corners = triangle.map(|vertex| positions[vertex as usize]);
+        let moved =
+            triangle.map(|vertex| positions[if vertex == from { to } else { vertex } as usize]);
+        let normal = (corners[1] - corners[0]).cross(corners[2] - corners[0]);
+        let moved_normal = (moved[1] - moved[0]).cross(moved[2] - moved[0]);
+        normal.dot(moved_normal) > 0.
+    })
+}
+
+/// Area weighted sum of squared distances to planes, stored as the symmetric matrix A, the vector b and the scalar c
+#[derive(Copy, Clone, Default)]
+struct Quadric {
+    a: [f64; 6],
+    b: DVec3,
+    c: f64,
+    weight: f64,
+}
+
+impl Quadric {
+    /// Plane of the points p with normal · p + distance = 0
+    fn plane(normal: DVec3, distance: f64, weight: f64) -> Self {
+        let DVec3 { x, y, z } = normal;
+        Self {
+            a: [x * x, x * y, x * z, y * y, y * z, z * z].map(|value| value * weight),
+            b: normal * distance * weight,
+            c: distance * distance * weight,
+            weight,
+        }
+    }
+
+    fn add(&mut self, other: &Self) {
+        for (value, other) in self.a.iter_mut().zip(other.a) {
+            *value += other;
+        }
+        self.b += other.b;
+        self.c += other.c;
+        self.weight += other.weight;
+    }
+
+    /// Mean squared distance of the point to the planes
+    fn error(&self, point: DVec3) -> f64 {
+        if self.weight == 0. {
+            return 0.;
+        }
+        let [xx, xy, xz, yy, yz, zz] = self.a;
+        let transformed = DVec3::new(
+            xx * point.x + xy * point.y + xz * point.z,
+            xy * point.x + yy * point.y + yz * point.z,
+            xz * point.x + yz * point.y + zz * point.z,
+        );
+        let error = point.dot(transformed) + 2. * self.b.dot(point) + self.c;
+        (error / self.weight).max(0.)
+    }
+}
+
+/// Triangles adjacent to each vertex, stored contiguously
+struct Adjacency {
+    offsets: Vec<usize>,
+    triangles: Vec<u32>,
+}
+
+impl Adjacency {
+    fn new(vertex_count: usize, triangles: &[[u32; 3]]) -> Self {
+        let mut offsets = vec![0; vertex_count + 1];
+        for triangle in triangles {
+            for vertex in triangle {
+                offsets[*vertex as usize + 1] += 1;
+            }
+        }
+        for vertex in 0..vertex_count {
+            offsets[vertex + 1] += offsets[vertex];
+        }
+        let mut filled = offsets.clone();
+        let mut adjacent = vec![0; offsets[vertex_count]];
+        for (index, triangle) in triangles.iter().enumerate() {
+            for vertex in triangle {
+                adjacent[filled[*vertex as usize]] = index as u32;
+                filled[*vertex as usize] += 1;
+            }
+        }
+        Self {
+            offsets,
+            triangles: adjacent,
+        }
+    }
+
+    fn triangles(&self, vertex: u32) -> &[u32] {
+        &self.triangles[self.offsets[vertex as usize]..self.offsets[vertex as usize + 1]]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Grid in the XY plane facing +Z, displaced along Z by the height function
+    fn grid(size: u32, height: impl Fn(f32, f32) -> f32) -> (Vec<Vertex>, Vec<Index>) {
+        let mut vertices = Vec::new();
+        for y in 0..=size {
+            for x in 0..=size {
+                let (u, v) = (x as f32 / size as f32, y as f32 / size as f32);
+                vertices.push(Vertex {
+                    position: [u, v, height(u, v)],
+                    normal: [0., 0., 1.],
+                    tangent: [1., 0., 0.],
+                    texture_coordinates: [u, v],
+                });
+            }
+        }
+        let mut indices = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                let corner = y * (size + 1) + x;
+                let above = corner + size + 1;
+                indices.extend([corner, corner + 1, above + 1, corner, above + 1, above]);
+            }
+        }
+        (
+            vertices,
+            indices.into_iter().map(|index| Index { index }).collect(),
+        )
+    }
+
+    fn normal(vertices: &[Vertex], triangle: &[Index]) -> Vec3 {
+        let [a, b, c] =
+            [0, 1, 2].map(|corner| Vec3::from(vertices[triangle[corner].index as usize].position));
+        (b - a).cross(c - a)
+    }
+
+    #[test]
+    fn flat_grid_simplifies_without_error() {
+        let (vertices, indices) = grid(16, |_, _| 0.);
+        let (simplified, error) = simplify(&vertices, &indices, 0);
+
+        assert!(simplified.len() < indices.len() / 4);
+        assert!(error < 1e-6);
+        // No triangle is flipped
+        for triangle in simplified.chunks_exact(3) {
+            assert!(normal(&vertices, triangle).z > 0.);
+        }
+        // The border is locked
+        for (index, vertex) in vertices.iter().enumerate() {
+            let on_border = vertex.position[..2]
+                .iter()
+                .any(|value| *value == 0. || *value == 1.);
+            if on_border {
+                assert!(
+                    simplified
+                        .iter()
+                        .any(|corner| corner.index as usize == index)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn curved_grid_stops_at_the_target() {
+        let (vertices, indices) = grid(32, |u, v| (u * 6.).sin() * (v * 6.).cos() * 0.2);
+        let (simplified, error) = simplify(&vertices, &indices, indices.len() / 2);
+
+        assert!(simplified.len() <= indices.len() / 2);
+        assert!(simplified.len() > indices.len() / 4);
+        assert!(error > 0. && error < 0.2);
+    }
+
+    #[test]
+    fn lods_halve_the_triangles_with_growing_error() {
+        let (vertices, indices) = grid(32, |u, v| (u * 6.).sin() * (v * 6.).cos() * 0.2);
+        let full_count = indices.len();
+        let (indices, lods) = generate_lods(&vertices, indices);
+
+        assert!(lods.len() > 2);
+        assert_eq!(lods[0].index_count as usize, full_count);
+        assert_eq!(lods[0].error, 0.);
+        for pair in lods.windows(2) {
+            assert_eq!(
+                pair[1].first_index,
+                pair[0].first_index + pair[0].index_count
+            );
+            assert!(pair[1].index_count < pair[0].index_count);
+            assert!(pair[1].error >= pair[0].error);
+        }
+        let last = lods.last().unwrap();
+        assert_eq!(
+            indices.len(),
+            (last.first_index + last.index_count) as usize
+        );
+    }
+
+    #[test]
+    fn small_meshes_have_a_single_lod() {
+        let (vertices, indices) = grid(4, |_, _| 0.);
+        let (lod_indices, lods) = generate_lods(&vertices, indices.clone());
+
+        assert_eq!(lods.len(), 1);
+        assert_eq!(lod_indices.len(), indices.len());
+    }
+}
@@ -0,0 +1,664 @@
+use std::{collections::HashMap, hash::Hash, path::Path};
+
+use glam::{Vec2, Vec3};
+
+use crate::application::assets::{
+    asset_error::AssetError,
+    asset_traits::{Index, SkinVertex, Vertex},
+};
+
+/// Size of the simulated post-transform cache. Larger than most real caches, which costs little on smaller ones.
+const CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum NormalMode {
+    /// Vertices at the same position share the area weighted average of the adjacent face normals
+    Smooth,
+    /// Every triangle gets its own vertices with the face normal, as the glTF specification requires
+    /// for primitives without normals
+    Flat,
+}
+
+/// Vertices, indices and skin of a primitive that is ready for upload
+pub type ProcessedGeometry = (Vec<Vertex>, Vec<Index>, Option<Vec<SkinVertex>>);
+
+/// Vertex data as read from a source file, before missing attributes are generated
+#[derive(Clone)]
+pub struct RawGeometry {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<Index>,
+    pub has_normals: bool,
+    pub has_uvs: bool,
+    pub has_tangents: bool,
+    /// Joints and weights of each vertex of skinned meshes
+    pub skin: Option<Vec<SkinVertex>>,
+}
+
+impl RawGeometry {
+    /// Generates the missing attributes, welds the vertices and orders the triangles for the vertex cache.
+    /// The skin is returned in the order of the vertices.
+    /// Fails if an index is out of range, which the processing below relies on.
+    pub fn finish(
+        self,
+        path: &Path,
+        normal_mode: NormalMode,
+    ) -> Result<ProcessedGeometry, AssetError> {
+        let error = |message| AssetError::Parse {
+            path: path.to_path_buf(),
+            message,
+        };
+        if let Some(index) = self
+            .indices
+            .iter()
+            .find(|index| index.index as usize >= self.vertices.len())
+        {
+            return Err(error(format!(
+                "Index {} is out of range for {} vertices",
+                index.index,
+                self.vertices.len()
+            )));
+        }
+        if let Some(skin) = &self.skin
+            && skin.len() != self.vertices.len()
+        {
+            return Err(error(format!(
+                "{} skin vertices do not match {} vertices",
+                skin.len(),
+                self.vertices.len()
+            )));
+        }
+        let Self {
+            mut vertices,
+            mut indices,
+            mut skin,
+            ..
+        } = self;
+        // Normals come first because the other two depend on them
+        if !self.has_normals {
+            // Flat normals give every triangle corner its own vertex, the skin has to follow
+            if normal_mode == NormalMode::Flat
+                && let Some(skin) = &mut skin
+            {
+                *skin = indices
+                    .chunks_exact(3)
+                    .flatten()
+                    .map(|index| skin[index.index as usize])
+                    .collect();
+            }
+            generate_normals(&mut vertices, &mut indices, normal_mode);
+        }
+        if !self.has_uvs {
+            generate_uvs(&mut vertices);
+        }
+        if !self.has_tangents {
+            generate_tangents(&mut vertices, &indices);
+        }
+        match &mut skin {
+            Some(skin) => weld_skinned(&mut vertices, skin, &mut indices),
+            None => weld(&mut vertices, &mut indices),
+        }
+        optimize_vertex_cache(&mut indices, vertices.len());
+        Ok((vertices, indices, skin))
+    }
+}
+
+/// Replaces the normals of all vertices. Flat normals split the vertices, so that no triangle shares them.
+pub fn generate_normals(vertices: &mut Vec<Vertex>, indices: &mut Vec<Index>, mode: NormalMode) {
+    match mode {
+        NormalMode::Smooth => {
+            let mut sums = HashMap::<[u32; 3], Vec3>::new();
+            for triangle in indices.chunks_exact(3) {
+                let normal = face_normal(vertices, triangle);
+                for index in triangle {
+                    let key = position_key(&vertices[index.index as usize]);
+                    *sums.entry(key).or_default() += normal;
+                }
+            }
+            for vertex in vertices.iter_mut() {
+                let sum = sums.get(&position_key(vertex)).copied().unwrap_or_default();
+                vertex.normal = sum.try_normalize().unwrap_or(Vec3::Z).into();
+            }
+        }
+        NormalMode::Flat => {
+            let mut flat = Vec::with_capacity(indices.len());
+            for triangle in indices.chunks_exact(3) {
+                let normal = face_normal(vertices, triangle)
+                    .try_normalize()
+                    .unwrap_or(Vec3::Z);
+                flat.extend(triangle.iter().map(|index| Vertex {
+                    normal: normal.into(),
+                    ..vertices[index.index as usize]
+                }));
+            }
+            *vertices = flat;
+            *indices = (0..vertices.len() as u32)
+                .map(|index| Index { index })
+                .collect();
+        }
+    }
+}
+
+/// Generates tangents in the spirit of MikkTSpace: the tangent of each triangle follows the U direction
+/// of its texture coordinates and is weighted by the angle of the corner at each vertex.
+/// The result is orthogonalized against the normal. The bitangent is cross(normal, tangent),
+/// because vertices do not store a handedness.
+pub fn generate_tangents(vertices: &mut [Vertex], indices: &[Index]) {
+    let mut sums = vec![Vec3::ZERO; vertices.len()];
+    for triangle in indices.chunks_exact(3) {
+        let corners = [0, 1, 2].map(|corner| &vertices[triangle[corner].index as usize]);
+        let positions = corners.map(|vertex| Vec3::from(vertex.position));
+        let uvs = corners.map(|vertex| Vec2::from(vertex.texture_coordinates));
+
+        let (edge1, edge2) = (positions[1] - positions[0], positions[2] - positions[0]);
+        let (uv1, uv2) = (uvs[1] - uvs[0], uvs[2] - uvs[0]);
+        let determinant = uv1.x * uv2.y - uv2.x * uv1.y;
+        if determinant.abs() <= f32::EPSILON {
+            // Triangles without a UV parameterization contribute nothing
+            continue;
+        }
+        let Some(tangent) = ((edge1 * uv2.y - edge2 * uv1.y) / determinant).try_normalize() else {
+            continue;
+        };
+
+        for corner in 0..3 {
+            let to_next = positions[(corner + 1) % 3] - positions[corner];
+            let to_previous = positions[(corner + 2) % 3] - positions[corner];
+            let angle = to_next.angle_between(to_previous);
+            if angle.is_finite() {
+                sums[triangle[corner].index as usize] += tangent * angle;
+            }
+        }
+    }
+
+    for (vertex, sum) in vertices.iter_mut().zip(sums) {
+        let normal = Vec3::from(vertex.normal);
+        let tangent = (sum - normal * normal.dot(sum))
+            .try_normalize()
+            .unwrap_or_else(|| normal.any_orthonormal_vector());
+        vertex.tangent = tangent.into();
+    }
+}
+
+/// Generates placeholder texture coordinates with a box projection. Each vertex is projected along the
+/// dominant axis of its normal and the result is scaled by the bounds, so that coordinates lie in [0, 1].
+pub fn generate_uvs(vertices: &mut [Vertex]) {
+    let (min, max) = vertices.iter().fold(
+        (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+        |(min, max), vertex| {
+            let position = Vec3::from(vertex.position);
+            (min.min(position), max.max(position))
+        },
+    );
+    let extent = (max - min).max_element();
+    let scale = if extent > 0. { 1. / extent } else { 0. };
+
+    for vertex in vertices {
+        let position = (Vec3::from(vertex.position) - min) * scale;
+        let normal = Vec3::from(vertex.normal).abs();
+        vertex.texture_coordinates = if normal.x >= normal.y && normal.x >= normal.z {
+            [position.z, position.y]
+        } else if normal.y >= normal.z {
+            [position.x, position.z]
+        } else {
+            [position.x, position.y]
+        };
+    }
+}
+
+/// Merges vertices whose attributes are bitwise identical and remaps the indices.
+/// Vertices keep the order of their first occurrence.
+pub fn weld(vertices: &mut Vec<Vertex>, indices: &mut [Index]) {
+    let kept = weld_by_key(vertices.iter().map(vertex_key), indices);
+    *vertices = kept.iter().map(|vertex| vertices[*vertex]).collect();
+}
+
+/// Like weld, but vertices are only merged if their joints and weights are identical as well
+pub fn weld_skinned(vertices: &mut Vec<Vertex>, skin: &mut Vec<SkinVertex>, indices: &mut [Index]) {
+    let keys = vertices
+        .iter()
+        .zip(skin.iter())
+        .map(|(vertex, skin)| (vertex_key(vertex), skin.joints, skin.weights.map(float_key)));
+    let kept = weld_by_key(keys, indices);
+    *vertices = kept.iter().map(|vertex| vertices[*vertex]).collect();
+    *skin = kept.iter().map(|vertex| skin[*vertex]).collect();
+}
+
+/// Remaps the indices to the first vertex with the same key. Returns the vertices that are kept.
+fn weld_by_key<K: Hash + Eq>(
+    keys: impl ExactSizeIterator<Item = K>,
+    indices: &mut [Index],
+) -> Vec<usize> {
+    let mut unique = HashMap::with_capacity(keys.len());
+    let mut kept = Vec::with_capacity(keys.len());
+    let remap = keys
+        .enumerate()
+        .map(|(vertex, key)| {
+            *unique.entry(key).or_insert_with(|| {
+                kept.push(vertex);
+                kept.len() as u32 - 1
+            })
+        })
+        .collect::<Vec<_>>();
+    for index in indices {
+        index.index = remap[index.index as usize];
+    }
+    kept
+}
+
+/// Reorders the triangles for the post-transform vertex cache with Tom Forsyth's linear-speed algorithm.
+/// The winding of each triangle is kept.
+pub fn optimize_vertex_cache(indices: &mut [Index], vertex_count: usize) {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return;
+    }
+
+    // Triangles adjacent to each vertex, stored contiguously
+    let mut remaining = vec![0u32; vertex_count];
+    for index in &indices[..triangle_count * 3] {
+        remaining[index.index as usize] += 1;
+    }
+    let mut offsets = Vec::with_capacity(vertex_count + 1);
+    offsets.push(0);
+    for count in &remaining {
+        offsets.push(offsets.last().unwrap() + *count as usize);
+    }
+    let mut adjacency = vec![0u32; offsets[vertex_count]];
+    let mut filled = offsets.clone();
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        for corner in corners {
+            let vertex = corner.index as usize;
+            adjacency[filled[vertex]] = triangle as u32;
+            filled[vertex] += 1;
+        }
+    }
+
+    let mut cache_positions = vec![None; vertex_count];
+    let mut vertex_scores = (0..vertex_count)
+        .map(|vertex| vertex_score(None, remaining[vertex]))
+        .collect::<Vec<_>>();
+    let triangle_score = |triangle: usize, vertex_scores: &[f32]| -> f32 {
+        indices[triangle * 3..triangle * 3 + 3]
+            .iter()
+            .map(|index| vertex_scores[index.index as usize])
+            .sum()
+    };
+    let mut triangle_scores = (0..triangle_count)
+        .map(|triangle| triangle_score(triangle, &vertex_scores))
+        .collect::<Vec<_>>();
+    let mut emitted = vec![false; triangle_count];
+
+    let mut output = Vec::with_capacity(triangle_count * 3);
+    let mut cache: Vec<u32> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut best = Some(
+        (0..triangle_count)
+            .max_by(|a, b| triangle_scores[*a].total_cmp(&triangle_scores[*b]))
+            .unwrap(),
+    );
+    // Triangles before the cursor are all emitted, it bounds the search when the cache yields no candidate
+    let mut cursor = 0;
+
+    while output.len() < triangle_count * 3 {
+        let triangle = match best {
+            Some(triangle) => triangle,
+            None => {
+                while emitted[cursor] {
+                    cursor += 1;
+                }
+                cursor
+            }
+        };
+        emitted[triangle] = true;
+        let corners = [0, 1, 2].map(|corner| indices[triangle * 3 + corner].index);
+        output.extend(corners.map(|index| Index { index }));
+
+        // The triangle's vertices move to the front, the others keep their order
+        for vertex in corners {
+            remaining[vertex as usize] -= 1;
+        }
+        let mut updated = Vec::with_capacity(cache.len() + 3);
+        for vertex in corners.iter().chain(&cache) {
+            if !updated.contains(vertex) {
+                updated.push(*vertex);
+            }
+        }
+        for (position, vertex) in updated.iter().enumerate() {
+            let position = (position < CACHE_SIZE).then_some(position);
+            cache_positions[*vertex as usize] = position;
+            vertex_scores[*vertex as usize] = vertex_score(position, remaining[*vertex as usize]);
+        }
+
+        // Only triangles around the touched vertices change their score
+        best = None;
+        let mut best_score = f32::MIN;
+        for vertex in &updated {
+            let vertex = *vertex as usize;
+            for &adjacent in &adjacency[offsets[vertex]..offsets[vertex + 1]] {
+                let adjacent = adjacent as usize;
+                if emitted[adjacent] {
+                    continue;
+                }
+                let score = triangle_score(adjacent, &vertex_scores);
+                triangle_scores[adjacent] = score;
+                if score > best_score {
+                    best_score = score;
+                    best = Some(adjacent);
+                }
+            }
+        }
+        updated.truncate(CACHE_SIZE);
+        cache = updated;
+    }
+
+    indices[..triangle_count * 3].copy_from_slice(&output);
+}
+
+fn vertex_score(cache_position: Option<usize>, remaining_triangles: u32) -> f32 {
+    if remaining_triangles == 0 {
+        return -1.;
+    }
+    let cache_score = match cache_position {
+        None => 0.,
+        // The vertices of the last triangle get a fixed score, so that the next triangle does not reuse all of them
+        Some(position) if position < 3 => LAST_TRIANGLE_SCORE,
+        Some(position) => {
+            let scaled = 1. - (position - 3) as f32 / (CACHE_SIZE - 3) as f32;
+            scaled.powf(CACHE_DECAY_POWER)
+        }
+    };
+    // Vertices with few remaining triangles are preferred, so that they can leave the cache
+    cache_score + VALENCE_BOOST_SCALE * (remaining_triangles as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+/// Unnormalized, so that its length weights the normal by the triangle area
+fn face_normal(vertices: &[Vertex], triangle: &[Index]) -> Vec3 {
+    let [a, b, c] =
+        [0, 1, 2].map(|corner| Vec3::from(vertices[triangle[corner].index as usize].position));
+    (b - a).cross(c - a)
+}
+
+/// Negative zero is treated as zero, so that both compare equal like the floats do
+fn float_key(value: f32) -> u32 {
+    if value == 0. { 0 } else { value.to_bits() }
+}
+
+/// Hashable key of the position, equal for vertices at the same position
+pub fn position_key(vertex: &Vertex) -> [u32; 3] {
+    vertex.position.map(float_key)
+}
+
+fn vertex_key(vertex: &Vertex) -> [u32; 11] {
+    let mut key = [0; 11];
+    let attributes = vertex
+        .position
+        .iter()
+        .chain(&vertex.normal)
+        .chain(&vertex.tangent)
+        .chain(&vertex.texture_coordinates);
+    for (key, value) in key.iter_mut().zip(attributes) {
+        *key = float_key(*value);
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(position: [f32; 3], texture_coordinates: [f32; 2]) -> Vertex {
+        Vertex {
+            position,
+            normal: [0.; 3],
+            tangent: [0.; 3],
+            texture_coordinates,
+        }
+    }
+
+    fn indices(values: &[u32]) -> Vec<Index> {
+        values.iter().map(|index| Index { index: *index }).collect()
+    }
+
+    fn values(indices: &[Index]) -> Vec<u32> {
+        indices.iter().map(|index| index.index).collect()
+    }
+
+    /// Grid in the XY plane facing +Z, with texture coordinates following X and Y
+    fn grid(size: u32) -> (Vec<Vertex>, Vec<Index>) {
+        let mut vertices = Vec::new();
+        for y in 0..=size {
+            for x in 0..=size {
+                let (u, v) = (x as f32 / size as f32, y as f32 / size as f32);
+                vertices.push(vertex([u, v, 0.], [u, v]));
+            }
+        }
+        let mut values = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                let corner = y * (size + 1) + x;
+                let above = corner + size + 1;
+                values.extend([corner, corner + 1, above + 1, corner, above + 1, above]);
+            }
+        }
+        (vertices, indices(&values))
+    }
+
+    /// Cube with separate vertices for each face
+    fn split_cube() -> (Vec<Vertex>, Vec<Index>) {
+        let mut vertices = Vec::new();
+        let mut values = Vec::new();
+        for axis in 0..3 {
+            for sign in [-1f32, 1.] {
+                let normal = Vec3::AXES[axis] * sign;
+                let u = Vec3::AXES[(axis + 1) % 3];
+                let v = normal.cross(u);
+                let first = vertices.len() as u32;
+                for (x, y) in [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)] {
+                    vertices.push(Vertex {
+                        normal: normal.into(),
+                        ..vertex((normal + u * x + v * y).into(), [x, y])
+                    });
+                }
+                values.extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+            }
+        }
+        (vertices, indices(&values))
+    }
+
+    fn assert_close(a: [f32; 3], b: Vec3) {
+        assert!(Vec3::from(a).abs_diff_eq(b, 1e-5), "{:?} != {}", a, b);
+    }
+
+    fn triangles(vertices: &[Vertex], indices: &[Index]) -> Vec<[[u32; 3]; 3]> {
+        let mut triangles = indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                let corners = [0, 1, 2]
+                    .map(|corner| position_key(&vertices[triangle[corner].index as usize]));
+                // Rotate the smallest corner to the front, which keeps the winding
+                let first = (0..3).min_by_key(|corner| corners[*corner]).unwrap();
+                [0, 1, 2].map(|corner| corners[(first + corner) % 3])
+            })
+            .collect::<Vec<_>>();
+        triangles.sort();
+        triangles
+    }
+
+    /// Average number of vertices transformed per triangle with a FIFO cache of the given size.
+    /// 3 is the worst case, well ordered meshes get close to 0.5.
+    fn average_cache_miss_ratio(indices: &[Index], cache_size: usize) -> f32 {
+        let triangle_count = indices.len() / 3;
+        if triangle_count == 0 {
+            return 0.;
+        }
+        let mut cache = std::collections::VecDeque::with_capacity(cache_size);
+        let mut misses = 0;
+        for index in &indices[..triangle_count * 3] {
+            if !cache.contains(&index.index) {
+                misses += 1;
+                if cache.len() == cache_size {
+                    cache.pop_front();
+                }
+                cache.push_back(index.index);
+            }
+        }
+        misses as f32 / triangle_count as f32
+    }
+
+    #[test]
+    fn flat_normals_split_vertices() {
+        let (mut vertices, mut indices) = split_cube();
+        weld(&mut vertices, &mut indices);
+        generate_normals(&mut vertices, &mut indices, NormalMode::Flat);
+        assert_eq!(vertices.len(), 36);
+        assert_eq!(values(&indices), (0..36).collect::<Vec<_>>());
+        for triangle in indices.chunks_exact(3) {
+            let normal = Vec3::from(vertices[triangle[0].index as usize].normal);
+            // Each face normal is an axis that points away from the center
+            assert_eq!(normal.abs().max_element(), 1.);
+            assert!(normal.dot(Vec3::from(vertices[triangle[1].index as usize].position)) > 0.);
+        }
+    }
+
+    #[test]
+    fn smooth_normals_are_shared_across_faces() {
+        let (mut vertices, mut indices) = split_cube();
+        generate_normals(&mut vertices, &mut indices, NormalMode::Smooth);
+        assert_eq!(vertices.len(), 24);
+        for vertex in &vertices {
+            // Every corner touches three faces of equal area
+            assert_close(vertex.normal, Vec3::from(vertex.position).normalize());
+        }
+    }
+
+    #[test]
+    fn tangents_follow_the_u_direction() {
+        let (mut vertices, mut indices) = grid(4);
+        generate_normals(&mut vertices, &mut indices, NormalMode::Smooth);
+        generate_tangents(&mut vertices, &indices);
+        for vertex in &vertices {
+            assert_close(vertex.normal, Vec3::Z);
+            assert_close(vertex.tangent, Vec3::X);
+        }
+    }
+
+    #[test]
+    fn tangents_without_uvs_are_orthonormal() {
+        let (mut vertices, mut indices) = split_cube();
+        vertices
+            .iter_mut()
+            .for_each(|vertex| vertex.texture_coordinates = [0.; 2]);
+        generate_normals(&mut vertices, &mut indices, NormalMode::Flat);
+        generate_tangents(&mut vertices, &indices);
+        for vertex in &vertices {
+            let (normal, tangent) = (Vec3::from(vertex.normal), Vec3::from(vertex.tangent));
+            assert!((tangent.length() - 1.).abs() < 1e-5);
+            assert!(normal.dot(tangent).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn generated_uvs_cover_the_unit_square() {
+        let (mut vertices, mut indices) = split_cube();
+        generate_normals(&mut vertices, &mut indices, NormalMode::Flat);
+        generate_uvs(&mut vertices);
+        for vertex in &vertices {
+            assert!(
+                vertex
+                    .texture_coordinates
+                    .iter()
+                    .all(|value| (0. ..=1.).contains(value))
+            );
+        }
+        // Faces on the +Z side are projected along Z and keep their X and Y layout
+        let front = vertices
+            .iter()
+            .find(|vertex| vertex.normal == [0., 0., 1.] && vertex.position[0] > 0.)
+            .unwrap();
+        assert_eq!(front.texture_coordinates[0], 1.);
+    }
+
+    #[test]
+    fn weld_merges_identical_vertices() {
+        let (vertices, indices) = grid(3);
+        let mut unwelded = indices
+            .iter()
+            .map(|index| vertices[index.index as usize])
+            .collect::<Vec<_>>();
+        let mut unwelded_indices = (0..unwelded.len() as u32)
+            .map(|index| Index { index })
+            .collect::<Vec<_>>();
+        weld(&mut unwelded, &mut unwelded_indices);
+        assert_eq!(unwelded.len(), vertices.len());
+        assert_eq!(
+            triangles(&unwelded, &unwelded_indices),
+            triangles(&vertices, &indices)
+        );
+
+        // Vertices that differ in any attribute stay separate
+        let mut cube = split_cube();
+        weld(&mut cube.0, &mut cube.1);
+        assert_eq!(cube.0.len(), 24);
+    }
+
+    #[test]
+    fn weld_keeps_vertices_with_different_weights_apart() {
+        let corner = vertex([0., 0., 0.], [0., 0.]);
+        let mut vertices = vec![corner; 3];
+        let bound = |weight| SkinVertex {
+            joints: [0, 1, 0, 0],
+            weights: [weight, 1. - weight, 0., 0.],
+        };
+        let mut skin = vec![bound(1.), bound(1.), bound(0.5)];
+        let mut indices = indices(&[0, 1, 2]);
+        weld_skinned(&mut vertices, &mut skin, &mut indices);
+        assert_eq!(vertices.len(), 2);
+        assert_eq!(skin, [bound(1.), bound(0.5)]);
+        assert_eq!(values(&indices), [0, 0, 1]);
+    }
+
+    #[test]
+    fn cache_optimization_keeps_triangles_and_reduces_misses() {
+        let (vertices, mut indices) = grid(32);
+        // Scatter the triangles with a fixed permutation
+        let triangle_count = indices.len() / 3;
+        let scattered = (0..triangle_count)
+            .map(|triangle| (triangle * 769) % triangle_count)
+            .flat_map(|triangle| indices[triangle * 3..triangle * 3 + 3].to_vec())
+            .collect::<Vec<_>>();
+        indices.copy_from_slice(&scattered);
+        let expected = triangles(&vertices, &indices);
+        let before = average_cache_miss_ratio(&indices, 16);
+
+        optimize_vertex_cache(&mut indices, vertices.len());
+        let after = average_cache_miss_ratio(&indices, 16);
+        assert_eq!(triangles(&vertices, &indices), expected);
+        assert!(after < before, "{} >= {}", after, before);
+        // A grid can be ordered close to one miss per two triangles
+        assert!(after < 0.8, "{}", after);
+    }
+
+    #[test]
+    fn out_of_range_indices_are_rejected() {
+        let (vertices, _) = grid(1);
+        let geometry = RawGeometry {
+            vertices,
+            indices: indices(&[0, 1, 4]),
+            has_normals: false,
+            has_uvs: true,
+            has_tangents: false,
+            skin: None,
+        };
+        let Err(error) = geometry.finish(Path::new("broken.obj"), NormalMode::Smooth) else {
+            panic!("the geometry was accepted");
+        };
+        assert!(
+            matches!(&error, AssetError::Parse { message, .. } if message.contains("Index 4")),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,787 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use glam::{Mat4, Quat, Vec3};
+use gltf::{
+    Document, Gltf,
+    animation::{self, util::ReadOutputs},
+    buffer,
+    camera::Projection,
+    mesh::Mode,
+};
+
+use crate::application::{
+    assets::{
+        animation_clip::{Channel, ChannelKeyframes, Interpolation, Keyframes},
+        asset_error::AssetError,
+        asset_traits::{Index, MaterialParameters, SkinVertex, Vertex},
+        cooked_mesh,
+        material_instance::TextureSlot,
+        mesh_processing::{NormalMode, ProcessedGeometry, RawGeometry},
+        skeleton::Joint,
+    },
+    scene::{camera::Camera, transform::Transform},
+};
+
+mod obj;
+mod ply;
+
+/// File formats meshes are imported from, selected by the file extension
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MeshFormat {
+    Gltf,
+    /// Wavefront OBJ with MTL materials
+    Obj,
+    /// Stanford PLY in ASCII or binary encoding
+    Ply,
+}
+
+impl MeshFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "glb" | "gltf" => Some(MeshFormat::Gltf),
+            "obj" => Some(MeshFormat::Obj),
+            "ply" => Some(MeshFormat::Ply),
+            _ => None,
+        }
+    }
+}
+
+/// Selects a primitive of a mesh in a source file.
+/// OBJ files contain a single mesh with one primitive per object, group and material, PLY files a single primitive.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct PrimitiveIndex {
+    pub mesh: usize,
+    pub primitive: usize,
+}
+
+impl PrimitiveIndex {
+    /// Reads the index from the settings of a registry record. Records without it refer to the first primitive.
+    pub fn from_settings(settings: &BTreeMap<String, String>) -> Self {
+        let read = |key: &str| {
+            settings
+                .get(key)
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0)
+        };
+        Self {
+            mesh: read("mesh"),
+            primitive: read("primitive"),
+        }
+    }
+
+    /// Identifies the primitive within its file in the registry. The first primitive stands for the whole file.
+    pub fn item(self) -> String {
+        if self == Self::default() {
+            return String::new();
+        }
+        format!("{}/{}", self.mesh, self.primitive)
+    }
+
+    /// The first primitive is stored without settings, which keeps records of single meshes unchanged
+    pub fn to_settings(self) -> BTreeMap<String, String> {
+        if self == Self::default() {
+            return BTreeMap::new();
+        }
+        BTreeMap::from([
+            ("mesh".to_string(), self.mesh.to_string()),
+            ("primitive".to_string(), self.primitive.to_string()),
+        ])
+    }
+}
+
+/// Material of a primitive as described by the source file, in terms of the glTF metallic roughness model
+#[derive(Clone, PartialEq, Debug)]
+pub struct MaterialDescription {
+    pub name: String,
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emissive: [f32; 3],
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    /// Texture files by the input they feed
+    pub textures: Vec<(TextureSlot, PathBuf)>,
+}
+
+impl MaterialDescription {
+    /// A white, rough dielectric without textures
+    pub fn new(name: String) -> Self {
+        let defaults = MaterialParameters::default();
+        Self {
+            name,
+            base_color: defaults.base_color,
+            metallic: defaults.metallic,
+            roughness: defaults.roughness,
+            emissive: defaults.emissive,
+            normal_scale: defaults.normal_scale,
+            occlusion_strength: defaults.occlusion_strength,
+            textures: Vec::new(),
+        }
+    }
+}
+
+/// Node of a scene with its transform resolved through the hierarchy
+pub struct SceneNode {
+    pub name: String,
+    /// Index of the glTF node, formats without a hierarchy have none
+    pub index: Option<usize>,
+    pub world: Mat4,
+    /// Index of the mesh and its number of primitives
+    pub mesh: Option<(usize, usize)>,
+    /// Only perspective cameras are imported
+    pub camera: Option<Camera>,
+    /// Index of the skin that deforms the mesh
+    pub skin: Option<usize>,
+}
+
+/// Joints of a skin, the transform of the nodes above its root joints and the animations that move them
+pub struct SkinDescription {
+    pub joints: Vec<Joint>,
+    /// World transform of the parent of the root joints
+    pub root_transform: Mat4,
+    /// Named after the animations in the file. Animations that move none of the joints are left out.
+    pub animations: Vec<(String, Vec<Channel>)>,
+}
+
+/// Keyframes of the translation, rotation and scale of a node, relative to its parent
+pub struct NodeAnimation {
+    pub name: String,
+    /// World transform of the parent of the node
+    pub parent: Mat4,
+    /// Local transform of the node in the file, used for properties without keyframes
+    pub rest: Transform,
+    pub tracks: Vec<ChannelKeyframes>,
+}
+
+enum Parsed {
+    Gltf(Document, Vec<buffer::Data>),
+    Obj(obj::ObjFile),
+    Ply(RawGeometry),
+}
+
+/// A mesh file that is hashed and parsed on first use.
+/// All primitives of a file share one source, so that it is read at most once.
+pub struct MeshSource {
+    path: PathBuf,
+    hash: Option<u64>,
+    parsed: Option<Parsed>,
+}
+
+impl MeshSource {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            hash: None,
+            parsed: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Hash of the content of the file and the files it references, used to validate cooked meshes
+    pub fn hash(&mut self) -> Result<u64, AssetError> {
+        if let Some(hash) = self.hash {
+            return Ok(hash);
+        }
+        let content = fs::read(&self.path).map_err(|error| AssetError::Io {
+            path: self.path.clone(),
+            error,
+        })?;
+        let hash = cooked_mesh::source_hash(&content, &self.referenced_files(&content));
+        self.hash = Some(hash);
+        Ok(hash)
+    }
+
+    /// External buffers and images of a glTF file and material libraries of an OBJ file.
+    /// Files that cannot be parsed reference nothing, their import reports the error.
+    fn referenced_files(&self, content: &[u8]) -> Vec<PathBuf> {
+        let directory = self.path.parent().unwrap_or(Path::new(""));
+        match MeshFormat::from_path(&self.path) {
+            Some(MeshFormat::Gltf) => {
+                let Ok(Gltf { document, .. }) = Gltf::from_slice(content) else {
+                    return Vec::new();
+                };
+                let buffers = document
+                    .buffers()
+                    .filter_map(|buffer| match buffer.source() {
+                        buffer::Source::Uri(uri) => Some(uri),
+                        buffer::Source::Bin => None,
+                    });
+                let images = document.images().filter_map(|image| match image.source() {
+                    gltf::image::Source::Uri { uri, .. } => Some(uri),
+                    gltf::image::Source::View { .. } => None,
+                });
+                // Embedded data changes the file itself
+                buffers
+                    .chain(images)
+                    .filter(|uri| !uri.starts_with("data:"))
+                    .map(|uri| directory.join(decode_uri(uri)))
+                    .collect()
+            }
+            Some(MeshFormat::Obj) => {
+                obj::material_libraries(directory, &String::from_utf8_lossy(content))
+            }
+            Some(MeshFormat::Ply) | None => Vec::new(),
+        }
+    }
+
+    /// Parses the file with the importer selected by its extension. Images are not decoded.
+    fn parse(&mut self) -> Result<&Parsed, AssetError> {
+        if self.parsed.is_none() {
+            let format =
+                MeshFormat::from_path(&self.path).ok_or_else(|| AssetError::UnsupportedFormat {
+                    path: self.path.clone(),
+                })?;
+            let parsed = match format {
+                MeshFormat::Gltf => {
+                    let gltf_error = |error| AssetError::Gltf {
+                        path: self.path.clone(),
+                        error,
+                    };
+                    let Gltf { document, blob } = Gltf::open(&self.path).map_err(gltf_error)?;
+                    let buffers = gltf::import_buffers(&document, self.path.parent(), blob)
+                        .map_err(gltf_error)?;
+                    Parsed::Gltf(document, buffers)
+                }
+                MeshFormat::Obj => Parsed::Obj(obj::parse(&self.path)?),
+                MeshFormat::Ply => Parsed::Ply(ply::parse(&self.path)?),
+            };
+            self.parsed = Some(parsed);
+        }
+        Ok(self.parsed.as_ref().unwrap())
+    }
+
+    /// Imports the vertex data of a primitive and its skin. Missing attributes are generated.
+    pub fn primitive(&mut self, index: PrimitiveIndex) -> Result<ProcessedGeometry, AssetError> {
+        let path = self.path.clone();
+        let missing = || AssetError::MissingPrimitive { path: path.clone() };
+        let geometry = match self.parse()? {
+            Parsed::Gltf(document, buffers) => {
+                let primitive = document
+                    .meshes()
+                    .nth(index.mesh)
+                    .and_then(|mesh| mesh.primitives().nth(index.primitive))
+                    .ok_or_else(missing)?;
+                // The glTF specification requires flat normals if a primitive has none
+                return read_gltf_primitive(&path, document, index.mesh, &primitive, buffers)?
+                    .finish(&path, NormalMode::Flat);
+            }
+            Parsed::Obj(file) => file
+                .primitives
+                .get(index.primitive)
+                .filter(|_| index.mesh == 0)
+                .map(|primitive| primitive.geometry.clone()),
+            Parsed::Ply(geometry) => (index == PrimitiveIndex::default()).then(|| geometry.clone()),
+        };
+        // Scans and CAD exports are usually smooth surfaces
+        geometry
+            .ok_or_else(missing)?
+            .finish(&path, NormalMode::Smooth)
+    }
+
+    /// Returns the material the file assigns to a primitive.
+    /// None if the file has no material for it or its materials are not imported.
+    pub fn material(
+        &mut self,
+        index: PrimitiveIndex,
+    ) -> Result<Option<MaterialDescription>, AssetError> {
+        let directory = self.path.parent().unwrap_or(Path::new("")).to_path_buf();
+        Ok(match self.parse()? {
+            Parsed::Gltf(document, _) => document
+                .meshes()
+                .nth(index.mesh)
+                .and_then(|mesh| mesh.primitives().nth(index.primitive))
+                .and_then(|primitive| {
+                    let material = primitive.material();
+                    // Primitives without a material use the default material of the specification
+                    material.index().map(|material_index| {
+                        read_gltf_material(&directory, material_index, &material)
+                    })
+                }),
+            Parsed::Obj(file) => file
+                .primitives
+                .get(index.primitive)
+                .and_then(|primitive| primitive.material.clone()),
+            Parsed::Ply(_) => None,
+        })
+    }
+
+    /// Flattens the node hierarchy of the default scene, or of the first scene if there is no default.
+    /// Formats without a hierarchy result in a single node with all primitives.
+    pub fn scene_nodes(&mut self) -> Result<Vec<SceneNode>, AssetError> {
+        let path = self.path.clone();
+        let single_node = |primitive_count| SceneNode {
+            name: path
+                .file_stem()
+                .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()),
+            index: None,
+            world: Mat4::IDENTITY,
+            mesh: Some((0, primitive_count)),
+            camera: None,
+            skin: None,
+        };
+        match self.parse()? {
+            Parsed::Gltf(document, _) => {
+                let scene = document
+                    .default_scene()
+                    .or_else(|| document.scenes().next())
+                    .ok_or_else(|| AssetError::MissingScene { path: path.clone() })?;
+                let mut nodes = Vec::new();
+                for node in scene.nodes() {
+                    flatten(node, Mat4::IDENTITY, &mut nodes);
+                }
+                Ok(nodes)
+            }
+            Parsed::Obj(file) => Ok(vec![single_node(file.primitives.len())]),
+            Parsed::Ply(_) => Ok(vec![single_node(1)]),
+        }
+    }
+
+    /// Imports a skin of a glTF file with the animations of its joints
+    pub fn skin(&mut self, index: usize) -> Result<SkinDescription, AssetError> {
+        let path = self.path.clone();
+        let Parsed::Gltf(document, buffers) = self.parse()? else {
+            return Err(AssetError::MissingSkin { path });
+        };
+        let skin = document
+            .skins()
+            .nth(index)
+            .ok_or_else(|| AssetError::MissingSkin { path: path.clone() })?;
+
+        let parents = node_parents(document);
+        let joint_nodes = skin.joints().map(|node| node.index()).collect::<Vec<_>>();
+        let joint_of = |node: usize| joint_nodes.iter().position(|joint| *joint == node);
+        let inverse_bind_matrices = skin
+            .reader(|buffer| Some(&buffers[buffer.index()]))
+            .read_inverse_bind_matrices()
+            .map(|matrices| {
+                matrices
+                    .map(|matrix| Mat4::from_cols_array_2d(&matrix))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let joints = skin
+            .joints()
+            .enumerate()
+            .map(|(joint, node)| {
+                let (location, rotation, scale) = node.transform().decomposed();
+                Joint {
+                    name: node
+                        .name()
+                        .map_or_else(|| format!("Node{}", node.index()), str::to_string),
+                    parent: parents[node.index()].and_then(joint_of),
+                    rest: Transform {
+                        location: location.into(),
+                        rotation: Quat::from_array(rotation),
+                        scale: scale.into(),
+                    },
+                    // Without inverse bind matrices, the joints are bound at the origin
+                    inverse_bind_matrix: inverse_bind_matrices
+                        .get(joint)
+                        .copied()
+                        .unwrap_or(Mat4::IDENTITY),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // All roots of a skin share the nodes above them in practice, so the first one places the skeleton
+        let root_transform = joint_nodes
+            .iter()
+            .find(|node| parents[**node].and_then(joint_of).is_none())
+            .and_then(|node| parents[*node])
+            .map_or(Mat4::IDENTITY, |parent| {
+                node_world(document, &parents, parent)
+            });
+
+        let animations = document
+            .animations()
+            .filter_map(|gltf_animation| {
+                let channels = gltf_animation
+                    .channels()
+                    .filter_map(|channel| {
+                        Some(Channel {
+                            joint: joint_of(channel.target().node().index())?,
+                            keyframes: read_gltf_channel(&channel, buffers)?,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                (!channels.is_empty()).then(|| {
+                    let name = gltf_animation.name().map_or_else(
+                        || format!("Animation{}", gltf_animation.index()),
+                        str::to_string,
+                    );
+                    (name, channels)
+                })
+            })
+            .collect();
+
+        Ok(SkinDescription {
+            joints,
+            root_transform,
+            animations,
+        })
+    }
+
+    /// Imports the first animation of a glTF file that moves the node.
+    /// Only channels that target the node itself are read, animated parents are not followed.
+    pub fn node_animation(&mut self, node: usize) -> Result<Option<NodeAnimation>, AssetError> {
+        let Parsed::Gltf(document, buffers) = self.parse()? else {
+            return Ok(None);
+        };
+        let Some(gltf_node) = document.nodes().nth(node) else {
+            return Ok(None);
+        };
+        let animation = document.animations().find_map(|gltf_animation| {
+            let tracks = gltf_animation
+                .channels()
+                .filter(|channel| channel.target().node().index() == node)
+                .filter_map(|channel| read_gltf_channel(&channel, buffers))
+                .collect::<Vec<_>>();
+            (!tracks.is_empty()).then(|| {
+                let name = gltf_animation.name().map_or_else(
+                    || format!("Animation{}", gltf_animation.index()),
+                    str::to_string,
+                );
+                (name, tracks)
+            })
+        });
+        let Some((name, tracks)) = animation else {
+            return Ok(None);
+        };
+
+        let parents = node_parents(document);
+        let (location, rotation, scale) = gltf_node.transform().decomposed();
+        Ok(Some(NodeAnimation {
+            name,
+            parent: parents[node].map_or(Mat4::IDENTITY, |parent| {
+                node_world(document, &parents, parent)
+            }),
+            rest: Transform {
+                location: location.into(),
+                rotation: Quat::from_array(rotation),
+                scale: scale.into(),
+            },
+            tracks,
+        }))
+    }
+}
+
+/// Strips and fans are converted to triangle lists, points and lines are rejected.
+/// Indices must refer to vertices and joints to joints of every skin the mesh is used with.
+fn read_gltf_primitive(
+    path: &Path,
+    document: &Document,
+    mesh: usize,
+    primitive: &gltf::Primitive,
+    buffers: &[buffer::Data],
+) -> Result<RawGeometry, AssetError> {
+    let error = |message: String| AssetError::Parse {
+        path: path.to_path_buf(),
+        message,
+    };
+    let read = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+    let mut vertices = read
+        .read_positions()
+        .ok_or_else(|| AssetError::MissingAttribute {
+            path: path.to_path_buf(),
+            attribute: "positions",
+        })?
+        .map(|position| Vertex {
+            position,
+            normal: [0.; 3],
+            tangent: [0.; 3],
+            texture_coordinates: [0.; 2],
+        })
+        .collect::<Vec<_>>();
+    let normals = read.read_normals();
+    let has_normals = normals.is_some();
+    for (vertex, normal) in vertices.iter_mut().zip(normals.into_iter().flatten()) {
+        vertex.normal = normal;
+    }
+    let uvs = read.read_tex_coords(0).map(|uvs| uvs.into_f32());
+    let has_uvs = uvs.is_some();
+    for (vertex, uv) in vertices.iter_mut().zip(uvs.into_iter().flatten()) {
+        vertex.texture_coordinates = uv;
+    }
+    let tangents = read.read_tangents();
+    let has_tangents = tangents.is_some();
+    for (vertex, tangent) in vertices.iter_mut().zip(tangents.into_iter().flatten()) {
+        vertex.tangent = *tangent.first_chunk().unwrap();
+    }
+    // Joints are only imported together with their weights
+    let skin = read
+        .read_joints(0)
+        .zip(read.read_weights(0))
+        .map(|(joints, weights)| {
+            joints
+                .into_u16()
+                .zip(weights.into_f32())
+                .map(|(joints, weights)| {
+                    // Exporters do not always normalize the weights
+                    let sum = weights.iter().sum::<f32>();
+                    SkinVertex {
+                        joints: joints.map(u32::from),
+                        weights: if sum > 0. {
+                            weights.map(|weight| weight / sum)
+                        } else {
+                            [1., 0., 0., 0.]
+                        },
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .filter(|skin| skin.len() == vertices.len());
+    let indices = match read.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        // Non-indexed primitives use every vertex once
+        None => (0..vertices.len() as u32).collect::<Vec<_>>(),
+    };
+    let indices = match primitive.mode() {
+        Mode::Triangles if indices.len() % 3 == 0 => indices,
+        Mode::Triangles => {
+            return Err(error(format!(
+                "{} indices do not form triangles",
+                indices.len()
+            )));
+        }
+        // Every other triangle of a strip is flipped to keep the winding
+        Mode::TriangleStrip => (0..indices.len().saturating_sub(2))
+            .flat_map(|triangle| {
+                let flip = triangle % 2;
+                [
+                    indices[triangle],
+                    indices[triangle + 1 + flip],
+                    indices[triangle + 2 - flip],
+                ]
+            })
+            .collect(),
+        Mode::TriangleFan => (1..indices.len().saturating_sub(1))
+            .flat_map(|triangle| [indices[triangle], indices[triangle + 1], indices[0]])
+            .collect(),
+        mode => return Err(error(format!("{:?} primitives are not supported", mode))),
+    };
+    let indices = indices.into_iter().map(|index| Index { index }).collect();
+    // The skinning reads the joint matrices of the skin the mesh is drawn with
+    let joint_count = document
+        .nodes()
+        .filter(|node| {
+            node.mesh()
+                .is_some_and(|node_mesh| node_mesh.index() == mesh)
+        })
+        .filter_map(|node| node.skin())
+        .map(|skin| skin.joints().count())
+        .min();
+    if let (Some(skin), Some(joint_count)) = (&skin, joint_count)
+        && let Some(joint) = skin
+            .iter()
+            .flat_map(|vertex| vertex.joints)
+            .find(|joint| *joint as usize >= joint_count)
+    {
+        return Err(error(format!(
+            "Joint {} is out of range for a skin with {} joints",
+            joint, joint_count
+        )));
+    }
+
+    Ok(RawGeometry {
+        vertices,
+        indices,
+        has_normals,
+        has_uvs,
+        has_tangents,
+        skin,
+    })
+}
+
+/// Keyframes of a channel that animates the translation, rotation or scale of a node.
+/// Morph target weights are not imported.
+fn read_gltf_channel(
+    channel: &animation::Channel,
+    buffers: &[buffer::Data],
+) -> Option<ChannelKeyframes> {
+    let read = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+    let times = read.read_inputs()?.collect::<Vec<_>>();
+    let interpolation = match channel.sampler().interpolation() {
+        animation::Interpolation::Step => Interpolation::Step,
+        animation::Interpolation::Linear => Interpolation::Linear,
+        animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+    };
+    match read.read_outputs()? {
+        ReadOutputs::Translations(values) => {
+            Keyframes::new(times, values.map(Vec3::from).collect(), interpolation)
+                .map(ChannelKeyframes::Translation)
+        }
+        ReadOutputs::Rotations(values) => Keyframes::new(
+            times,
+            values.into_f32().map(Quat::from_array).collect(),
+            interpolation,
+        )
+        .map(ChannelKeyframes::Rotation),
+        ReadOutputs::Scales(values) => {
+            Keyframes::new(times, values.map(Vec3::from).collect(), interpolation)
+                .map(ChannelKeyframes::Scale)
+        }
+        ReadOutputs::MorphTargetWeights(_) => None,
+    }
+}
+
+/// Parent of every node of the file
+fn node_parents(document: &Document) -> Vec<Option<usize>> {
+    let mut parents = vec![None; document.nodes().len()];
+    for node in document.nodes() {
+        for child in node.children() {
+            parents[child.index()] = Some(node.index());
+        }
+    }
+    parents
+}
+
+fn node_world(document: &Document, parents: &[Option<usize>], node: usize) -> Mat4 {
+    let local = Mat4::from_cols_array_2d(&document.nodes().nth(node).unwrap().transform().matrix());
+    match parents[node] {
+        Some(parent) => node_world(document, parents, parent) * local,
+        None => local,
+    }
+}
+
+/// Only images in separate files are referenced, embedded images and data URIs are skipped.
+/// Unnamed materials are named after their index.
+fn read_gltf_material(
+    directory: &Path,
+    index: usize,
+    material: &gltf::Material,
+) -> MaterialDescription {
+    let pbr = material.pbr_metallic_roughness();
+    let mut description = MaterialDescription::new(
+        material
+            .name()
+            .map_or_else(|| format!("Material{index}"), str::to_string),
+    );
+    description.base_color = pbr.base_color_factor();
+    description.metallic = pbr.metallic_factor();
+    description.roughness = pbr.roughness_factor();
+    description.emissive = material.emissive_factor();
+
+    let textures = [
+        (
+            TextureSlot::BaseColor,
+            pbr.base_color_texture().map(|info| info.texture()),
+        ),
+        (
+            TextureSlot::MetallicRoughness,
+            pbr.metallic_roughness_texture().map(|info| info.texture()),
+        ),
+        (
+            TextureSlot::Normal,
+            material.normal_texture().map(|normal| {
+                description.normal_scale = normal.scale();
+                normal.texture()
+            }),
+        ),
+        (
+            TextureSlot::Occlusion,
+            material.occlusion_texture().map(|occlusion| {
+                description.occlusion_strength = occlusion.strength();
+                occlusion.texture()
+            }),
+        ),
+        (
+            TextureSlot::Emissive,
+            material.emissive_texture().map(|info| info.texture()),
+        ),
+    ];
+    for (slot, texture) in textures {
+        if let Some(gltf::image::Source::Uri { uri, .. }) =
+            texture.map(|texture| texture.source().source())
+            && !uri.starts_with("data:")
+        {
+            description
+                .textures
+                .push((slot, directory.join(decode_uri(uri))));
+        }
+    }
+    description
+}
+
+fn flatten(node: gltf::Node, parent: Mat4, nodes: &mut Vec<SceneNode>) {
+    let world = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+    let camera = node.camera().and_then(|camera| match camera.projection() {
+        Projection::Perspective(perspective) => {
+            let default = Camera::default();
+            Some(Camera {
+                transform: Transform::from_matrix(world),
+                fov: perspective.yfov().to_degrees(),
+                aspect: perspective.aspect_ratio().unwrap_or(default.aspect),
+                near: perspective.znear(),
+                // Infinite projections are approximated by the default far plane
+                far: perspective.zfar().unwrap_or(default.far),
+                ..default
+            })
+        }
+        Projection::Orthographic(_) => None,
+    });
+    nodes.push(SceneNode {
+        name: node
+            .name()
+            .map_or_else(|| format!("Node{}", node.index()), str::to_string),
+        index: Some(node.index()),
+        world,
+        mesh: node
+            .mesh()
+            .map(|mesh| (mesh.index(), mesh.primitives().count())),
+        camera,
+        skin: node.skin().map(|skin| skin.index()),
+    });
+    for child in node.children() {
+        flatten(child, world, nodes);
+    }
+}
+
+/// Decodes the percent-encoded bytes of a relative URI, e.g., `my%20texture.png`
+fn decode_uri(uri: &str) -> PathBuf {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut position = 0;
+    while position < bytes.len() {
+        let escaped = bytes
+            .get(position + 1..position + 3)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[position], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                position += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                position += 1;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uris_are_percent_decoded() {
+        assert_eq!(decode_uri("my%20tex.png"), PathBuf::from("my tex.png"));
+        assert_eq!(
+            decode_uri("textures/%C3%A4.png"),
+            PathBuf::from("textures/ä.png")
+        );
+        // Incomplete escapes are kept as they are
+        assert_eq!(decode_uri("100%.png"), PathBuf::from("100%.png"));
+        assert_eq!(decode_uri("%+1.png"), PathBuf::from("%+1.png"));
+    }
+}
@@ -0,0 +1,443 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    str::SplitWhitespace,
+};
+
+use super::MaterialDescription;
+use crate::application::assets::{
+    asset_error::AssetError,
+    asset_traits::{Index, Vertex},
+    material_instance::TextureSlot,
+    mesh_processing::RawGeometry,
+};
+
+/// Faces are split into primitives by object, group and material.
+/// Faces of a group that is continued later in the file are added to its primitive.
+pub struct ObjFile {
+    pub primitives: Vec<ObjPrimitive>,
+}
+
+pub struct ObjPrimitive {
+    pub geometry: RawGeometry,
+    pub material: Option<MaterialDescription>,
+}
+
+/// Collects the faces of one group and material. Corners that reference the same attributes share a vertex.
+struct PrimitiveBuilder {
+    key: PrimitiveKey,
+    geometry: RawGeometry,
+    vertices: HashMap<Corner, u32>,
+}
+
+/// Object, group and material of the faces in a primitive. None until the file sets them.
+#[derive(Clone, PartialEq, Default)]
+struct PrimitiveKey {
+    object: Option<String>,
+    group: Option<String>,
+    material: Option<String>,
+}
+
+/// Attribute indices of a face corner, already resolved to zero based indices
+type Corner = (usize, Option<usize>, Option<usize>);
+
+pub fn parse(path: &Path) -> Result<ObjFile, AssetError> {
+    parse_content(path, &read_to_string(path)?)
+}
+
+/// Material libraries are read relative to `path`, which also identifies the file in errors
+fn parse_content(path: &Path, content: &str) -> Result<ObjFile, AssetError> {
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    let mut positions = Vec::new();
+    let mut texture_coordinates = Vec::new();
+    let mut normals = Vec::new();
+    let mut materials = Vec::new();
+    let mut builders: Vec<PrimitiveBuilder> = Vec::new();
+    let mut current = PrimitiveKey::default();
+
+    for (number, line) in content.lines().enumerate() {
+        let error = |message: &str| parse_error(path, number, message);
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        match keyword {
+            "v" => {
+                positions.push(floats::<3>(&mut tokens).ok_or_else(|| error("invalid position"))?)
+            }
+            "vn" => normals.push(floats::<3>(&mut tokens).ok_or_else(|| error("invalid normal"))?),
+            "vt" => {
+                let [u] =
+                    floats::<1>(&mut tokens).ok_or_else(|| error("invalid texture coordinate"))?;
+                let v = tokens
+                    .next()
+                    .and_then(|v| v.parse::<f32>().ok())
+                    .unwrap_or(0.);
+                // OBJ puts the origin at the bottom left, glTF and Vulkan at the top left
+                texture_coordinates.push([u, 1. - v]);
+            }
+            "f" => {
+                let counts = (positions.len(), texture_coordinates.len(), normals.len());
+                let corners = tokens
+                    .map(|token| corner(token, counts))
+                    .collect::<Option<Vec<_>>>()
+                    .filter(|corners| corners.len() >= 3)
+                    .ok_or_else(|| error("invalid face"))?;
+                let builder = match builders.iter().position(|builder| builder.key == current) {
+                    Some(builder) => &mut builders[builder],
+                    None => {
+                        builders.push(PrimitiveBuilder::new(current.clone()));
+                        builders.last_mut().unwrap()
+                    }
+                };
+                builder.add_polygon(&corners, &positions, &texture_coordinates, &normals);
+            }
+            "usemtl" => current.material = Some(rest(line, keyword)),
+            // A new object starts without a group. Statements without a name return to the default.
+            "o" => {
+                current.object = name(line, keyword);
+                current.group = None;
+            }
+            "g" => current.group = name(line, keyword),
+            "mtllib" => {
+                // Faces without their material are still useful, so a missing library is not an error
+                for library in library_paths(directory, line) {
+                    match parse_mtl(&library) {
+                        Ok(library) => materials.extend(library),
+                        Err(error) => println!("{}, using the default material", error),
+                    }
+                }
+            }
+            // Smoothing groups, lines and points do not affect the triangles
+            _ => {}
+        }
+    }
+
+    Ok(ObjFile {
+        primitives: builders
+            .into_iter()
+            .map(|builder| ObjPrimitive {
+                material: builder.key.material.and_then(|name| {
+                    materials
+                        .iter()
+                        .rev()
+                        .find(|material| material.name == name)
+                        .cloned()
+                }),
+                geometry: builder.geometry,
+            })
+            .collect(),
+    })
+}
+
+impl PrimitiveBuilder {
+    fn new(key: PrimitiveKey) -> Self {
+        Self {
+            key,
+            geometry: RawGeometry {
+                vertices: Vec::new(),
+                indices: Vec::new(),
+                has_normals: true,
+                has_uvs: true,
+                has_tangents: false,
+                skin: None,
+            },
+            vertices: HashMap::new(),
+        }
+    }
+
+    /// Triangulates the polygon as a fan around its first corner
+    fn add_polygon(
+        &mut self,
+        corners: &[Corner],
+        positions: &[[f32; 3]],
+        texture_coordinates: &[[f32; 2]],
+        normals: &[[f32; 3]],
+    ) {
+        let indices = corners
+            .iter()
+            .map(|corner| {
+                let (position, texture_coordinate, normal) = *corner;
+                // Attributes are only present if every corner of the primitive has them
+                self.geometry.has_uvs &= texture_coordinate.is_some();
+                self.geometry.has_normals &= normal.is_some();
+                *self.vertices.entry(*corner).or_insert_with(|| {
+                    self.geometry.vertices.push(Vertex {
+                        position: positions[position],
+                        normal: normal.map_or([0.; 3], |normal| normals[normal]),
+                        tangent: [0.; 3],
+                        texture_coordinates: texture_coordinate
+                            .map_or([0.; 2], |texture_coordinate| {
+                                texture_coordinates[texture_coordinate]
+                            }),
+                    });
+                    self.geometry.vertices.len() as u32 - 1
+                })
+            })
+            .collect::<Vec<_>>();
+        for corner in 1..indices.len() - 1 {
+            self.geometry.indices.extend(
+                [indices[0], indices[corner], indices[corner + 1]].map(|index| Index { index }),
+            );
+        }
+    }
+}
+
+/// Parses `v`, `v/vt`, `v//vn` or `v/vt/vn`. Negative indices count back from the last attribute.
+fn corner(
+    token: &str,
+    (positions, texture_coordinates, normals): (usize, usize, usize),
+) -> Option<Corner> {
+    let mut parts = token.split('/');
+    let position = resolve(parts.next()?, positions)?;
+    let texture_coordinate = match parts.next() {
+        Some("") | None => None,
+        Some(part) => Some(resolve(part, texture_coordinates)?),
+    };
+    let normal = match parts.next() {
+        Some("") | None => None,
+        Some(part) => Some(resolve(part, normals)?),
+    };
+    Some((position, texture_coordinate, normal))
+}
+
+fn resolve(part: &str, count: usize) -> Option<usize> {
+    let index = part.parse::<isize>().ok()?;
+    let index = if index < 0 {
+        count.checked_sub(index.unsigned_abs())?
+    } else {
+        (index as usize).checked_sub(1)?
+    };
+    (index < count).then_some(index)
+}
+
+/// Material libraries the file references, in the order they are read by [`parse`]
+pub fn material_libraries(directory: &Path, content: &str) -> Vec<PathBuf> {
+    content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .filter(|line| line.split_whitespace().next() == Some("mtllib"))
+        .flat_map(|line| library_paths(directory, line))
+        .collect()
+}
+
+/// A statement can name several libraries, which are relative to the OBJ file
+fn library_paths(directory: &Path, line: &str) -> Vec<PathBuf> {
+    line.split_whitespace()
+        .skip(1)
+        .map(|file| directory.join(file))
+        .collect()
+}
+
+/// Reads the diffuse color, opacity and diffuse texture of every material in the library
+fn parse_mtl(path: &Path) -> Result<Vec<MaterialDescription>, AssetError> {
+    let content = read_to_string(path)?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let mut materials: Vec<MaterialDescription> = Vec::new();
+
+    for (number, line) in content.lines().enumerate() {
+        let error = |message: &str| parse_error(path, number, message);
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        if keyword == "newmtl" {
+            materials.push(MaterialDescription::new(rest(line, keyword)));
+            continue;
+        }
+        // Statements before the first material have nothing to apply to
+        let Some(material) = materials.last_mut() else {
+            continue;
+        };
+        match keyword {
+            "Kd" => {
+                let color =
+                    floats::<3>(&mut tokens).ok_or_else(|| error("invalid diffuse color"))?;
+                material.base_color[..3].copy_from_slice(&color);
+            }
+            "d" => {
+                let [opacity] = floats::<1>(&mut tokens).ok_or_else(|| error("invalid opacity"))?;
+                material.base_color[3] = opacity;
+            }
+            "Tr" => {
+                let [transparency] =
+                    floats::<1>(&mut tokens).ok_or_else(|| error("invalid transparency"))?;
+                material.base_color[3] = 1. - transparency;
+            }
+            "map_Kd" => {
+                // Options like `-s 1 1 1` precede the file name
+                let file = tokens.last().ok_or_else(|| error("missing texture file"))?;
+                material
+                    .textures
+                    .push((TextureSlot::BaseColor, texture_path(directory, file)));
+            }
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
+
+/// Texture paths are relative to the library. Exporters on Windows write backslashes.
+fn texture_path(directory: &Path, file: &str) -> PathBuf {
+    directory.join(file.replace('\\', "/"))
+}
+
+fn floats<const N: usize>(tokens: &mut SplitWhitespace) -> Option<[f32; N]> {
+    let mut values = [0.; N];
+    for value in &mut values {
+        *value = tokens.next()?.parse().ok()?;
+    }
+    Some(values)
+}
+
+/// Names may contain spaces, so everything after the keyword is used
+fn rest(line: &str, keyword: &str) -> String {
+    line.trim_start()[keyword.len()..].trim().to_string()
+}
+
+fn name(line: &str, keyword: &str) -> Option<String> {
+    Some(rest(line, keyword)).filter(|name| !name.is_empty())
+}
+
+fn read_to_string(path: &Path) -> Result<String, AssetError> {
+    fs::read_to_string(path).map_err(|error| AssetError::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+fn parse_error(path: &Path, line: usize, message: &str) -> AssetError {
+    AssetError::Parse {
+        path: path.to_path_buf(),
+        message: format!("line {}: {}", line + 1, message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_obj(content: &str) -> Result<ObjFile, AssetError> {
+        parse_content(&std::env::temp_dir().join("memory.obj"), content)
+    }
+
+    fn indices(geometry: &RawGeometry) -> Vec<u32> {
+        geometry.indices.iter().map(|index| index.index).collect()
+    }
+
+    const SQUARE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+    #[test]
+    fn polygons_are_triangulated_as_fans() {
+        let file = parse_obj(&format!("{}f 1 2 3 4\n", SQUARE)).unwrap();
+        let geometry = &file.primitives[0].geometry;
+        assert_eq!(geometry.vertices.len(), 4);
+        assert_eq!(indices(geometry), [0, 1, 2, 0, 2, 3]);
+        assert!(!geometry.has_normals && !geometry.has_uvs);
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_last_attribute() {
+        let file = parse_obj(&format!("{}f -3 -2 -1\n", SQUARE)).unwrap();
+        let positions = file.primitives[0]
+            .geometry
+            .vertices
+            .iter()
+            .map(|vertex| vertex.position)
+            .collect::<Vec<_>>();
+        assert_eq!(positions, [[1., 0., 0.], [1., 1., 0.], [0., 1., 0.]]);
+    }
+
+    #[test]
+    fn corners_without_texture_coordinates_keep_their_normals() {
+        let file = parse_obj(&format!("{}vn 0 0 1\nf 1//1 2//1 3//1\n", SQUARE)).unwrap();
+        let geometry = &file.primitives[0].geometry;
+        assert!(geometry.has_normals);
+        assert!(!geometry.has_uvs);
+        assert!(
+            geometry
+                .vertices
+                .iter()
+                .all(|vertex| vertex.normal == [0., 0., 1.])
+        );
+    }
+
+    #[test]
+    fn corners_that_share_attributes_share_a_vertex() {
+        let file = parse_obj(&format!("{}f 1 2 3\nf 1 3 4\n", SQUARE)).unwrap();
+        let geometry = &file.primitives[0].geometry;
+        assert_eq!(geometry.vertices.len(), 4);
+        assert_eq!(indices(geometry), [0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn out_of_range_indices_are_errors() {
+        for face in ["f 1 2 5", "f 0 1 2", "f -5 1 2", "f 1/1 2/1 3/1", "f 1 2"] {
+            assert!(
+                matches!(
+                    parse_obj(&format!("{}{}\n", SQUARE, face)),
+                    Err(AssetError::Parse { .. })
+                ),
+                "{} was accepted",
+                face
+            );
+        }
+    }
+
+    #[test]
+    fn missing_material_libraries_use_the_default_material() {
+        let file = parse_obj(&format!(
+            "mtllib missing_library.mtl\n{}usemtl Red\nf 1 2 3\n",
+            SQUARE
+        ))
+        .unwrap();
+        assert_eq!(file.primitives.len(), 1);
+        assert!(file.primitives[0].material.is_none());
+    }
+
+    #[test]
+    fn every_library_of_a_statement_is_read() {
+        let names = ["red", "blue"].map(|name| format!("{}_{}.mtl", std::process::id(), name));
+        for (name, color) in names.iter().zip(["Red", "Blue"]) {
+            fs::write(
+                std::env::temp_dir().join(name),
+                format!("newmtl {}\n", color),
+            )
+            .unwrap();
+        }
+        let file = parse_obj(&format!(
+            "mtllib {} {}\n{}usemtl Red\nf 1 2 3\nusemtl Blue\nf 1 3 4\n",
+            names[0], names[1], SQUARE
+        ));
+        for name in &names {
+            fs::remove_file(std::env::temp_dir().join(name)).unwrap();
+        }
+        let file = file.unwrap();
+        assert_eq!(file.primitives.len(), 2);
+        assert!(
+            file.primitives
+                .iter()
+                .all(|primitive| primitive.material.is_some())
+        );
+    }
+
+    #[test]
+    fn groups_and_objects_become_primitives() {
+        let file = parse_obj(&format!(
+            "{}o First\nf 1 2 3\ng Second\nf 1 3 4\nusemtl Red\nf 2 3 4\ng\nf 1 2 4\ng Second\nf 1 2 3\n",
+            SQUARE
+        ))
+        .unwrap();
+        // The last face continues the group with the same material, so it joins its primitive
+        let counts = file
+            .primitives
+            .iter()
+            .map(|primitive| primitive.geometry.indices.len() / 3)
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [1, 1, 2, 1]);
+    }
+}
@@ -0,0 +1,457 @@
+use std::{fs, path::Path, str::SplitAsciiWhitespace};
+
+use crate::application::assets::{
+    asset_error::AssetError,
+    asset_traits::{Index, Vertex},
+    mesh_processing::RawGeometry,
+};
+
+const END_HEADER: &[u8] = b"end_header";
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Encoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    /// Bytes of the binary encoding
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+}
+
+enum PropertyType {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType },
+}
+
+struct Property {
+    name: String,
+    property_type: PropertyType,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    /// Bytes that a row of the element takes at least, an ASCII value takes at least one
+    fn min_row_size(&self, encoding: Encoding) -> usize {
+        self.properties
+            .iter()
+            .map(|property| match (encoding, &property.property_type) {
+                (Encoding::Ascii, _) => 1,
+                (_, PropertyType::Scalar(scalar)) => scalar.size(),
+                (_, PropertyType::List { count, .. }) => count.size(),
+            })
+            .sum()
+    }
+}
+
+/// Where a vertex property is stored in the vertex
+#[derive(Copy, Clone, PartialEq)]
+enum VertexSlot {
+    Position(usize),
+    Normal(usize),
+    TextureCoordinate(usize),
+    Ignored,
+}
+
+/// Reads the vertices and faces of a PLY file. Other elements like edges are skipped.
+pub fn parse(path: &Path) -> Result<RawGeometry, AssetError> {
+    let content = fs::read(path).map_err(|error| AssetError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    parse_content(path, &content)
+}
+
+/// The path only identifies the file in errors
+fn parse_content(path: &Path, content: &[u8]) -> Result<RawGeometry, AssetError> {
+    let error = |message: &str| AssetError::Parse {
+        path: path.to_path_buf(),
+        message: message.to_string(),
+    };
+
+    let header_end = content
+        .windows(END_HEADER.len())
+        .position(|window| window == END_HEADER)
+        .ok_or_else(|| error("missing end_header"))?;
+    // The body starts after the line break that ends the header
+    let body_start = content[header_end..]
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .map_or(content.len(), |offset| header_end + offset + 1);
+    let header = std::str::from_utf8(&content[..header_end])
+        .map_err(|_| error("the header is not valid text"))?;
+    let (encoding, elements) = parse_header(header).map_err(|message| error(&message))?;
+
+    let mut reader = match encoding {
+        Encoding::Ascii => Reader::Ascii(
+            std::str::from_utf8(&content[body_start..])
+                .map_err(|_| error("the ASCII body is not valid text"))?
+                .split_ascii_whitespace(),
+        ),
+        Encoding::BinaryLittleEndian | Encoding::BinaryBigEndian => Reader::Binary {
+            bytes: &content[body_start..],
+            big_endian: encoding == Encoding::BinaryBigEndian,
+        },
+    };
+
+    let mut geometry = RawGeometry {
+        vertices: Vec::new(),
+        indices: Vec::new(),
+        has_normals: false,
+        has_uvs: false,
+        has_tangents: false,
+        skin: None,
+    };
+    let truncated = || error("unexpected end of data");
+    let body_size = content.len() - body_start;
+    for element in &elements {
+        // Rows without data could repeat forever, whatever the file claims
+        let row_size = element.min_row_size(encoding);
+        if row_size == 0 && element.count > 0 {
+            return Err(error(&format!(
+                "element {} has no properties",
+                element.name
+            )));
+        }
+        let slots = element
+            .properties
+            .iter()
+            .map(|property| vertex_slot(&property.name))
+            .collect::<Vec<_>>();
+        if element.name == "vertex" {
+            geometry.has_normals = (0..3).all(|axis| slots.contains(&VertexSlot::Normal(axis)));
+            geometry.has_uvs =
+                (0..2).all(|axis| slots.contains(&VertexSlot::TextureCoordinate(axis)));
+            // The count comes from the file, so the reservation is limited to what the body can hold
+            geometry
+                .vertices
+                .reserve(element.count.min(body_size / row_size.max(1)));
+        }
+
+        for _ in 0..element.count {
+            let mut vertex = Vertex {
+                position: [0.; 3],
+                normal: [0.; 3],
+                tangent: [0.; 3],
+                texture_coordinates: [0.; 2],
+            };
+            for (property, slot) in element.properties.iter().zip(&slots) {
+                match property.property_type {
+                    PropertyType::Scalar(scalar) => {
+                        let value = reader.read(scalar).ok_or_else(truncated)? as f32;
+                        if element.name != "vertex" {
+                            continue;
+                        }
+                        match *slot {
+                            VertexSlot::Position(axis) => vertex.position[axis] = value,
+                            VertexSlot::Normal(axis) => vertex.normal[axis] = value,
+                            VertexSlot::TextureCoordinate(axis) => {
+                                vertex.texture_coordinates[axis] = value
+                            }
+                            VertexSlot::Ignored => {}
+                        }
+                    }
+                    PropertyType::List { count, item } => {
+                        let count = reader.read(count).ok_or_else(truncated)? as usize;
+                        let values = (0..count)
+                            .map(|_| reader.read(item).map(|value| value as u32))
+                            .collect::<Option<Vec<_>>>()
+                            .ok_or_else(truncated)?;
+                        let is_face = element.name == "face"
+                            && (property.name == "vertex_indices"
+                                || property.name == "vertex_index");
+                        if is_face && values.len() >= 3 {
+                            // Polygons are triangulated as a fan around their first corner
+                            for corner in 1..values.len() - 1 {
+                                geometry.indices.extend(
+                                    [values[0], values[corner], values[corner + 1]]
+                                        .map(|index| Index { index }),
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+            if element.name == "vertex" {
+                // Like OBJ, PLY puts the texture origin at the bottom left
+                if geometry.has_uvs {
+                    vertex.texture_coordinates[1] = 1. - vertex.texture_coordinates[1];
+                }
+                geometry.vertices.push(vertex);
+            }
+        }
+    }
+
+    let vertex_count = geometry.vertices.len() as u32;
+    if geometry
+        .indices
+        .iter()
+        .any(|index| index.index >= vertex_count)
+    {
+        return Err(error("a face references a vertex that does not exist"));
+    }
+    Ok(geometry)
+}
+
+fn parse_header(header: &str) -> Result<(Encoding, Vec<Element>), String> {
+    let mut lines = header.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err("not a PLY file".into());
+    }
+    let mut encoding = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        match tokens.as_slice() {
+            ["format", format, _version] => {
+                encoding = Some(match *format {
+                    "ascii" => Encoding::Ascii,
+                    "binary_little_endian" => Encoding::BinaryLittleEndian,
+                    "binary_big_endian" => Encoding::BinaryBigEndian,
+                    _ => return Err(format!("unknown format {}", format)),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("invalid element count {}", count))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or("property before the first element")?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    property_type: PropertyType::List {
+                        count: scalar_type(count)?,
+                        item: scalar_type(item)?,
+                    },
+                });
+            }
+            ["property", scalar, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or("property before the first element")?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    property_type: PropertyType::Scalar(scalar_type(scalar)?),
+                });
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(format!("unknown header line {}", line)),
+        }
+    }
+    Ok((encoding.ok_or("missing format")?, elements))
+}
+
+fn scalar_type(name: &str) -> Result<ScalarType, String> {
+    Ok(match name {
+        "char" | "int8" => ScalarType::I8,
+        "uchar" | "uint8" => ScalarType::U8,
+        "short" | "int16" => ScalarType::I16,
+        "ushort" | "uint16" => ScalarType::U16,
+        "int" | "int32" => ScalarType::I32,
+        "uint" | "uint32" => ScalarType::U32,
+        "float" | "float32" => ScalarType::F32,
+        "double" | "float64" => ScalarType::F64,
+        _ => return Err(format!("unknown property type {}", name)),
+    })
+}
+
+/// Exporters disagree on the names of texture coordinates
+fn vertex_slot(name: &str) -> VertexSlot {
+    match name {
+        "x" => VertexSlot::Position(0),
+        "y" => VertexSlot::Position(1),
+        "z" => VertexSlot::Position(2),
+        "nx" => VertexSlot::Normal(0),
+        "ny" => VertexSlot::Normal(1),
+        "nz" => VertexSlot::Normal(2),
+        "u" | "s" | "texture_u" | "texture_s" => VertexSlot::TextureCoordinate(0),
+        "v" | "t" | "texture_v" | "texture_t" => VertexSlot::TextureCoordinate(1),
+        _ => VertexSlot::Ignored,
+    }
+}
+
+enum Reader<'a> {
+    Ascii(SplitAsciiWhitespace<'a>),
+    Binary { bytes: &'a [u8], big_endian: bool },
+}
+
+impl Reader<'_> {
+    /// Returns None at the end of the data
+    fn read(&mut self, scalar: ScalarType) -> Option<f64> {
+        match self {
+            Reader::Ascii(tokens) => tokens.next()?.parse().ok(),
+            Reader::Binary { bytes, big_endian } => {
+                let size = scalar.size();
+                let (value, rest) = bytes.split_at_checked(size)?;
+                *bytes = rest;
+                let mut buffer = [0; 8];
+                buffer[..size].copy_from_slice(value);
+                if *big_endian {
+                    buffer[..size].reverse();
+                }
+                Some(match scalar {
+                    ScalarType::I8 => buffer[0] as i8 as f64,
+                    ScalarType::U8 => buffer[0] as f64,
+                    ScalarType::I16 => i16::from_le_bytes(prefix(&buffer)) as f64,
+                    ScalarType::U16 => u16::from_le_bytes(prefix(&buffer)) as f64,
+                    ScalarType::I32 => i32::from_le_bytes(prefix(&buffer)) as f64,
+                    ScalarType::U32 => u32::from_le_bytes(prefix(&buffer)) as f64,
+                    ScalarType::F32 => f32::from_le_bytes(prefix(&buffer)) as f64,
+                    ScalarType::F64 => f64::from_le_bytes(buffer),
+                })
+            }
+        }
+    }
+}
+
+fn prefix<const N: usize>(buffer: &[u8; 8]) -> [u8; N] {
+    *buffer.first_chunk().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "element vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+        property float u\nproperty float v\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n";
+    const POSITIONS: [[f32; 3]; 4] = [[0., 0., 0.], [1., 0., 0.], [1., 1., 0.], [0., 1., 0.]];
+
+    fn parse_ply(content: &[u8]) -> Result<RawGeometry, AssetError> {
+        parse_content(Path::new("memory.ply"), content)
+    }
+
+    /// The square of the ASCII tests in a binary encoding
+    fn binary_square(
+        format: &str,
+        to_bytes: fn(f32) -> [u8; 4],
+        index_bytes: fn(i32) -> [u8; 4],
+    ) -> Vec<u8> {
+        let mut content = format!("ply\nformat {} 1.0\n{}", format, HEADER).into_bytes();
+        for position in POSITIONS {
+            for value in position.into_iter().chain([position[0], position[1]]) {
+                content.extend(to_bytes(value));
+            }
+        }
+        content.push(4);
+        for index in 0..4 {
+            content.extend(index_bytes(index));
+        }
+        content
+    }
+
+    fn assert_square(geometry: &RawGeometry) {
+        let positions = geometry
+            .vertices
+            .iter()
+            .map(|vertex| vertex.position)
+            .collect::<Vec<_>>();
+        assert_eq!(positions, POSITIONS);
+        // Texture coordinates are flipped to a top left origin
+        assert_eq!(geometry.vertices[3].texture_coordinates, [0., 0.]);
+        assert_eq!(geometry.vertices[1].texture_coordinates, [1., 1.]);
+        let indices = geometry
+            .indices
+            .iter()
+            .map(|index| index.index)
+            .collect::<Vec<_>>();
+        assert_eq!(indices, [0, 1, 2, 0, 2, 3]);
+        assert!(geometry.has_uvs);
+        assert!(!geometry.has_normals);
+    }
+
+    #[test]
+    fn ascii_polygons_are_triangulated_as_fans() {
+        let content = format!(
+            "ply\nformat ascii 1.0\ncomment square\n{}0 0 0 0 0\n1 0 0 1 0\n1 1 0 1 1\n0 1 0 0 1\n4 0 1 2 3\n",
+            HEADER
+        );
+        assert_square(&parse_ply(content.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn binary_files_are_read_in_both_byte_orders() {
+        let little = binary_square("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+        assert_square(&parse_ply(&little).unwrap());
+        let big = binary_square("binary_big_endian", f32::to_be_bytes, i32::to_be_bytes);
+        assert_square(&parse_ply(&big).unwrap());
+    }
+
+    #[test]
+    fn truncated_binary_files_are_errors() {
+        let content = binary_square("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+        assert!(matches!(
+            parse_ply(&content[..content.len() - 2]),
+            Err(AssetError::Parse { .. })
+        ));
+    }
+
+    #[test]
+    fn out_of_range_indices_are_errors() {
+        let content = format!(
+            "ply\nformat ascii 1.0\n{}0 0 0 0 0\n1 0 0 1 0\n1 1 0 1 1\n0 1 0 0 1\n3 0 1 4\n",
+            HEADER
+        );
+        assert!(matches!(
+            parse_ply(content.as_bytes()),
+            Err(AssetError::Parse { .. })
+        ));
+    }
+
+    #[test]
+    fn huge_element_counts_are_truncation_errors() {
+        let content = format!(
+            "ply\nformat binary_little_endian 1.0\nelement vertex {}\nproperty float x\nend_header\n",
+            usize::MAX
+        );
+        let mut content = content.into_bytes();
+        content.extend(1f32.to_le_bytes());
+        assert!(matches!(parse_ply(&content), Err(AssetError::Parse { .. })));
+    }
+
+    #[test]
+    fn malformed_headers_are_errors() {
+        for content in [
+            "ply\nformat ascii 1.0\nelement vertex 0\n",
+            "obj\nformat ascii 1.0\nend_header\n",
+            "ply\nformat ascii 1.0\nproperty float x\nend_header\n",
+            "ply\nformat utf8 1.0\nend_header\n",
+            "ply\nformat ascii 1.0\nelement vertex 1000\nend_header\n",
+        ] {
+            assert!(
+                matches!(parse_ply(content.as_bytes()), Err(AssetError::Parse { .. })),
+                "{} was accepted",
+                content
+            );
+        }
+    }
+}
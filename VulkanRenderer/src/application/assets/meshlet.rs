@@ -0,0 +1,357 @@
+use std::collections::HashMap;
+
+use glam::Vec3;
+
+use crate::application::assets::{
+    asset_traits::{Index, MeshLod, Meshlet, Vertex},
+    mesh_processing::position_key,
+};
+
+/// Upper bound of the vertices referenced by a meshlet
+pub const MAX_MESHLET_VERTICES: usize = 64;
+/// Upper bound of the triangles of a meshlet. It keeps the triangle within a meshlet in 7 bits.
+pub const MAX_MESHLET_TRIANGLES: usize = 124;
+/// Cones whose normals spread further than this cosine from the axis are almost never back facing
+const MIN_CONE_COSINE: f32 = 0.1;
+
+/// Splits every level of detail into meshlets and sets the meshlet ranges of the levels.
+/// The triangles of each level are reordered so that the triangles of every meshlet are consecutive.
+pub fn build_meshlets(
+    vertices: &[Vertex],
+    indices: &mut [Index],
+    lods: &mut [MeshLod],
+) -> Vec<Meshlet> {
+    // Triangles that only touch at a position are neighbours as well, e.g. across flat shaded edges
+    let mut positions = HashMap::new();
+    let position_ids = vertices
+        .iter()
+        .map(|vertex| {
+            let next_id = positions.len() as u32;
+            *positions.entry(position_key(vertex)).or_insert(next_id)
+        })
+        .collect::<Vec<_>>();
+
+    let mut meshlets = Vec::new();
+    for lod in lods {
+        let range = lod.first_index as usize..(lod.first_index + lod.index_count) as usize;
+        let groups = partition(
+            vertices.len(),
+            &position_ids,
+            positions.len(),
+            &indices[range.clone()],
+        );
+
+        lod.first_meshlet = meshlets.len() as u32;
+        lod.meshlet_count = groups.len() as u32;
+        let mut first_triangle = 0;
+        let mut reordered = indices[range.clone()].chunks_exact_mut(3);
+        for triangles in groups {
+            meshlets.push(bounds(vertices, first_triangle, &triangles));
+            first_triangle += triangles.len() as u32;
+            for (triangle, target) in triangles.iter().zip(reordered.by_ref()) {
+                for (index, vertex) in target.iter_mut().zip(triangle) {
+                    index.index = *vertex;
+                }
+            }
+        }
+    }
+    meshlets
+}
+
+/// Grows meshlets greedily from the first unused triangle. The next triangle is the neighbour that adds the
+/// fewest vertices, which keeps the meshlets compact and thus their bounds tight.
+fn partition(
+    vertex_count: usize,
+    position_ids: &[u32],
+    position_count: usize,
+    indices: &[Index],
+) -> Vec<Vec<[u32; 3]>> {
+    let triangles = indices
+        .chunks_exact(3)
+        .map(|triangle| [triangle[0].index, triangle[1].index, triangle[2].index])
+        .collect::<Vec<_>>();
+
+    // Triangles around each position, in the triangles of offsets[position]..offsets[position + 1]
+    let mut offsets = vec![0usize; position_count + 1];
+    for vertex in triangles.iter().flatten() {
+        offsets[position_ids[*vertex as usize] as usize + 1] += 1;
+    }
+    for position in 0..position_count {
+        offsets[position + 1] += offsets[position];
+    }
+    let mut adjacent = vec![0; triangles.len() * 3];
+    let mut cursors = offsets.clone();
+    for (triangle, vertices) in triangles.iter().enumerate() {
+        for vertex in vertices {
+            let cursor = &mut cursors[position_ids[*vertex as usize] as usize];
+            adjacent[*cursor] = triangle;
+            *cursor += 1;
+        }
+    }
+
+    let mut used = vec![false; triangles.len()];
+    // The meshlet that last referenced each vertex
+    let mut owner = vec![usize::MAX; vertex_count];
+    let mut next_seed = 0;
+    let mut groups = Vec::new();
+    loop {
+        while next_seed < triangles.len() && used[next_seed] {
+            next_seed += 1;
+        }
+        if next_seed == triangles.len() {
+            break;
+        }
+
+        let id = groups.len();
+        let mut group = Vec::new();
+        let mut group_vertex_count = 0;
+        let mut candidates: Vec<usize> = Vec::new();
+        let mut next = Some(next_seed);
+        while let Some(triangle) = next {
+            used[triangle] = true;
+            group.push(triangles[triangle]);
+            for vertex in triangles[triangle] {
+                if owner[vertex as usize] != id {
+                    owner[vertex as usize] = id;
+                    group_vertex_count += 1;
+                }
+                let position = position_ids[vertex as usize] as usize;
+                candidates.extend(&adjacent[offsets[position]..offsets[position + 1]]);
+            }
+            if group.len() == MAX_MESHLET_TRIANGLES {
+                break;
+            }
+
+            candidates.retain(|candidate| !used[*candidate]);
+            let new_vertices = |triangle: usize| {
+                triangles[triangle]
+                    .iter()
+                    .filter(|vertex| owner[**vertex as usize] != id)
+                    .count()
+            };
+            let fits = |triangle: &usize| {
+                group_vertex_count + new_vertices(*triangle) <= MAX_MESHLET_VERTICES
+            };
+            next = candidates
+                .iter()
+                .copied()
+                .filter(fits)
+                .min_by_key(|candidate| new_vertices(*candidate));
+            // Disconnected pieces are merged in index order, which is local after the vertex cache optimization.
+            // Only small meshlets take them, as they may widen the bounds.
+            if next.is_none() && candidates.is_empty() && group.len() < MAX_MESHLET_TRIANGLES / 2 {
+                next = (next_seed..triangles.len()).find(|triangle| !used[*triangle]);
+                next = next.filter(fits);
+            }
+        }
+        groups.push(group);
+    }
+    groups
+}
+
+/// Bounding sphere and normal cone of the triangles of a meshlet
+fn bounds(vertices: &[Vertex], first_triangle: u32, triangles: &[[u32; 3]]) -> Meshlet {
+    let position = |vertex: u32| Vec3::from(vertices[vertex as usize].position);
+    let (min, max) = triangles
+        .iter()
+        .flatten()
+        .fold((Vec3::MAX, Vec3::MIN), |(min, max), vertex| {
+            (min.min(position(*vertex)), max.max(position(*vertex)))
+        });
+    let center = (min + max) * 0.5;
+    let radius = triangles
+        .iter()
+        .flatten()
+        .map(|vertex| position(*vertex).distance(center))
+        .fold(0., f32::max);
+
+    let normals = triangles
+        .iter()
+        .filter_map(|[a, b, c]| {
+            let (a, b, c) = (position(*a), position(*b), position(*c));
+            (b - a).cross(c - a).try_normalize()
+        })
+        .collect::<Vec<_>>();
+    let axis = normals
+        .iter()
+        .sum::<Vec3>()
+        .try_normalize()
+        .unwrap_or(Vec3::Z);
+    let min_cosine = normals
+        .iter()
+        .map(|normal| normal.dot(axis))
+        .fold(1., f32::min);
+    // The cutoff is the sine of the opening angle of the cone. A cutoff of one disables the test.
+    let cone_cutoff = if normals.is_empty() || min_cosine <= MIN_CONE_COSINE {
+        1.
+    } else {
+        (1. - min_cosine * min_cosine).sqrt()
+    };
+
+    Meshlet {
+        first_triangle,
+        triangle_count: triangles.len() as u32,
+        center: center.into(),
+        radius,
+        cone_axis: axis.into(),
+        cone_cutoff,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Grid in the XY plane facing +Z. Flat shaded grids have separate vertices for every triangle.
+    fn grid(size: u32, flat_shaded: bool) -> (Vec<Vertex>, Vec<Index>) {
+        let vertex = |x: u32, y: u32| Vertex {
+            position: [x as f32 / size as f32, y as f32 / size as f32, 0.],
+            normal: [0., 0., 1.],
+            tangent: [1., 0., 0.],
+            texture_coordinates: [0., 0.],
+        };
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                for triangle in [
+                    [(x, y), (x + 1, y), (x + 1, y + 1)],
+                    [(x, y), (x + 1, y + 1), (x, y + 1)],
+                ] {
+                    for (x, y) in triangle {
+                        let index = if flat_shaded {
+                            vertices.push(vertex(x, y));
+                            vertices.len() as u32 - 1
+                        } else {
+                            y * (size + 1) + x
+                        };
+                        indices.push(Index { index });
+                    }
+                }
+            }
+        }
+        if !flat_shaded {
+            for y in 0..=size {
+                for x in 0..=size {
+                    vertices.push(vertex(x, y));
+                }
+            }
+        }
+        (vertices, indices)
+    }
+
+    fn single_lod(indices: &[Index]) -> Vec<MeshLod> {
+        vec![MeshLod {
+            first_index: 0,
+            index_count: indices.len() as u32,
+            error: 0.,
+            first_meshlet: 0,
+            meshlet_count: 0,
+        }]
+    }
+
+    fn sorted_triangles(indices: &[Index]) -> Vec<[u32; 3]> {
+        let mut triangles = indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0].index, triangle[1].index, triangle[2].index])
+            .collect::<Vec<_>>();
+        triangles.sort_unstable();
+        triangles
+    }
+
+    #[test]
+    fn meshlets_cover_all_triangles_within_limits() {
+        let (vertices, mut indices) = grid(32, false);
+        let original = sorted_triangles(&indices);
+        let mut lods = single_lod(&indices);
+        let meshlets = build_meshlets(&vertices, &mut indices, &mut lods);
+
+        assert_eq!(sorted_triangles(&indices), original);
+        assert_eq!(lods[0].first_meshlet, 0);
+        assert_eq!(lods[0].meshlet_count as usize, meshlets.len());
+        let mut next_triangle = 0;
+        for meshlet in &meshlets {
+            assert_eq!(meshlet.first_triangle, next_triangle);
+            next_triangle += meshlet.triangle_count;
+            assert!(meshlet.triangle_count as usize <= MAX_MESHLET_TRIANGLES);
+            let first = meshlet.first_triangle as usize * 3;
+            let mut used = indices[first..first + meshlet.triangle_count as usize * 3]
+                .iter()
+                .map(|index| index.index)
+                .collect::<Vec<_>>();
+            used.sort_unstable();
+            used.dedup();
+            assert!(used.len() <= MAX_MESHLET_VERTICES);
+            // Every vertex is within the bounding sphere
+            for vertex in used {
+                let position = Vec3::from(vertices[vertex as usize].position);
+                assert!(position.distance(meshlet.center.into()) <= meshlet.radius + 1e-5);
+            }
+        }
+        assert_eq!(next_triangle as usize, indices.len() / 3);
+        // A regular grid fills most meshlets up to the vertex limit
+        assert!(meshlets.len() <= original.len() / 64);
+    }
+
+    #[test]
+    fn flat_shaded_triangles_are_grouped() {
+        let (vertices, mut indices) = grid(16, true);
+        let mut lods = single_lod(&indices);
+        let meshlets = build_meshlets(&vertices, &mut indices, &mut lods);
+
+        // Separate triangles bring three vertices each
+        let per_meshlet = MAX_MESHLET_VERTICES / 3;
+        assert!(meshlets.len() <= (indices.len() / 3).div_ceil(per_meshlet) + 2);
+    }
+
+    #[test]
+    fn cones_of_flat_meshlets_cull_from_behind() {
+        let (vertices, mut indices) = grid(16, false);
+        let mut lods = single_lod(&indices);
+        let meshlets = build_meshlets(&vertices, &mut indices, &mut lods);
+
+        // Same test as the cluster culling on the GPU
+        let back_facing = |meshlet: &Meshlet, viewer: Vec3| {
+            let to_center = Vec3::from(meshlet.center) - viewer;
+            to_center.dot(meshlet.cone_axis.into())
+                >= meshlet.cone_cutoff * to_center.length() + meshlet.radius
+        };
+        for meshlet in &meshlets {
+            assert!(Vec3::from(meshlet.cone_axis).abs_diff_eq(Vec3::Z, 1e-5));
+            assert!(meshlet.cone_cutoff < 1e-3);
+            assert!(back_facing(meshlet, Vec3::new(0.5, 0.5, -2.)));
+            assert!(!back_facing(meshlet, Vec3::new(0.5, 0.5, 2.)));
+        }
+    }
+
+    #[test]
+    fn levels_get_separate_meshlets() {
+        let (vertices, indices) = grid(8, false);
+        let mut all_indices = indices.clone();
+        all_indices.extend(indices.iter().take(indices.len() / 2));
+        let mut lods = single_lod(&indices);
+        lods.push(MeshLod {
+            first_index: indices.len() as u32,
+            index_count: indices.len() as u32 / 2,
+            error: 0.1,
+            first_meshlet: 0,
+            meshlet_count: 0,
+        });
+        let meshlets = build_meshlets(&vertices, &mut all_indices, &mut lods);
+
+        assert_eq!(lods[1].first_meshlet, lods[0].meshlet_count);
+        assert_eq!(
+            (lods[0].meshlet_count + lods[1].meshlet_count) as usize,
+            meshlets.len()
+        );
+        let second = &meshlets[lods[1].first_meshlet as usize..];
+        assert_eq!(second[0].first_triangle, 0);
+        assert_eq!(
+            second
+                .iter()
+                .map(|meshlet| meshlet.triangle_count)
+                .sum::<u32>(),
+            lods[1].index_count / 3
+        );
+    }
+}
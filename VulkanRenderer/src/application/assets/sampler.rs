@@ -0,0 +1,363 @@
+use std::{
+    collections::BTreeMap,
+    hash::{Hash, Hasher},
+};
+
+use asset_system::{Asset, assets::AssetMetadata};
+
+const MAG_FILTER_SETTING: &str = "sampler_mag_filter";
+const MIN_FILTER_SETTING: &str = "sampler_min_filter";
+const MIPMAP_MODE_SETTING: &str = "sampler_mipmap_mode";
+const ADDRESS_MODE_SETTING: &str = "sampler_address_mode";
+const MAX_ANISOTROPY_SETTING: &str = "sampler_max_anisotropy";
+const COMPARE_OP_SETTING: &str = "sampler_compare_op";
+const MIP_LOD_BIAS_SETTING: &str = "sampler_mip_lod_bias";
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum AddressMode {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+    ClampToBorder,
+}
+
+/// Comparison of depth samplers against the reference value
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum CompareOp {
+    Never,
+    Less,
+    Equal,
+    LessOrEqual,
+    Greater,
+    NotEqual,
+    GreaterOrEqual,
+    Always,
+}
+
+impl Filter {
+    fn name(self) -> &'static str {
+        match self {
+            Filter::Nearest => "nearest",
+            Filter::Linear => "linear",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [Filter::Nearest, Filter::Linear]
+            .into_iter()
+            .find(|filter| filter.name() == name)
+    }
+}
+
+impl AddressMode {
+    fn name(self) -> &'static str {
+        match self {
+            AddressMode::Repeat => "repeat",
+            AddressMode::MirroredRepeat => "mirrored_repeat",
+            AddressMode::ClampToEdge => "clamp_to_edge",
+            AddressMode::ClampToBorder => "clamp_to_border",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [
+            AddressMode::Repeat,
+            AddressMode::MirroredRepeat,
+            AddressMode::ClampToEdge,
+            AddressMode::ClampToBorder,
+        ]
+        .into_iter()
+        .find(|mode| mode.name() == name)
+    }
+}
+
+impl CompareOp {
+    fn name(self) -> &'static str {
+        match self {
+            CompareOp::Never => "never",
+            CompareOp::Less => "less",
+            CompareOp::Equal => "equal",
+            CompareOp::LessOrEqual => "less_or_equal",
+            CompareOp::Greater => "greater",
+            CompareOp::NotEqual => "not_equal",
+            CompareOp::GreaterOrEqual => "greater_or_equal",
+            CompareOp::Always => "always",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [
+            CompareOp::Never,
+            CompareOp::Less,
+            CompareOp::Equal,
+            CompareOp::LessOrEqual,
+            CompareOp::Greater,
+            CompareOp::NotEqual,
+            CompareOp::GreaterOrEqual,
+            CompareOp::Always,
+        ]
+        .into_iter()
+        .find(|op| op.name() == name)
+    }
+}
+
+/// How textures are sampled. Equal descriptions share one sampler on the GPU, see RHIResourceManager::sampler.
+#[derive(Copy, Clone, Debug)]
+pub struct SamplerDesc {
+    pub mag_filter: Filter,
+    pub min_filter: Filter,
+    /// Filter between mip levels
+    pub mipmap_mode: Filter,
+    /// Addressing of the U, V and W coordinates outside of [0, 1]
+    pub address_modes: [AddressMode; 3],
+    /// Largest anisotropy the sampler may use, 1 disables anisotropic filtering.
+    /// Limited further by the global anisotropy setting and the device.
+    pub max_anisotropy: f32,
+    pub compare_op: Option<CompareOp>,
+    /// Added to the mip level the sampler computes
+    pub mip_lod_bias: f32,
+}
+
+impl Default for SamplerDesc {
+    /// Trilinear, repeating and as anisotropic as the global setting allows
+    fn default() -> Self {
+        Self {
+            mag_filter: Filter::Linear,
+            min_filter: Filter::Linear,
+            mipmap_mode: Filter::Linear,
+            address_modes: [AddressMode::Repeat; 3],
+            max_anisotropy: 16.,
+            compare_op: None,
+            mip_lod_bias: 0.,
+        }
+    }
+}
+
+impl SamplerDesc {
+    /// Linear filtering without anisotropy, clamped to the edge
+    pub fn linear_clamp() -> Self {
+        Self {
+            address_modes: [AddressMode::ClampToEdge; 3],
+            max_anisotropy: 1.,
+            ..Self::default()
+        }
+    }
+
+    /// Point sampling clamped to the edge
+    pub fn nearest_clamp() -> Self {
+        Self {
+            mag_filter: Filter::Nearest,
+            min_filter: Filter::Nearest,
+            mipmap_mode: Filter::Nearest,
+            ..Self::linear_clamp()
+        }
+    }
+
+    pub fn with_address_mode(mut self, address_mode: AddressMode) -> Self {
+        self.address_modes = [address_mode; 3];
+        self
+    }
+
+    /// The floats are compared by their bits, so that equality and the hash agree
+    fn key(&self) -> impl Eq + Hash {
+        (
+            self.mag_filter,
+            self.min_filter,
+            self.mipmap_mode,
+            self.address_modes,
+            self.max_anisotropy.to_bits(),
+            self.compare_op,
+            self.mip_lod_bias.to_bits(),
+        )
+    }
+
+    /// Reads the description from a registry record. Missing settings keep their default, malformed ones return None.
+    pub fn from_settings(settings: &BTreeMap<String, String>) -> Option<Self> {
+        let mut desc = Self::default();
+        if let Some(filter) = settings.get(MAG_FILTER_SETTING) {
+            desc.mag_filter = Filter::from_name(filter)?;
+        }
+        if let Some(filter) = settings.get(MIN_FILTER_SETTING) {
+            desc.min_filter = Filter::from_name(filter)?;
+        }
+        if let Some(filter) = settings.get(MIPMAP_MODE_SETTING) {
+            desc.mipmap_mode = Filter::from_name(filter)?;
+        }
+        if let Some(modes) = settings.get(ADDRESS_MODE_SETTING) {
+            desc.address_modes = modes
+                .split(' ')
+                .map(AddressMode::from_name)
+                .collect::<Option<Vec<_>>>()?
+                .try_into()
+                .ok()?;
+        }
+        if let Some(anisotropy) = settings.get(MAX_ANISOTROPY_SETTING) {
+            desc.max_anisotropy = anisotropy.parse().ok()?;
+        }
+        if let Some(op) = settings.get(COMPARE_OP_SETTING) {
+            desc.compare_op = Some(CompareOp::from_name(op)?);
+        }
+        if let Some(bias) = settings.get(MIP_LOD_BIAS_SETTING) {
+            desc.mip_lod_bias = bias.parse().ok()?;
+        }
+        Some(desc)
+    }
+
+    /// Defaults are stored without a setting
+    pub fn to_settings(self) -> BTreeMap<String, String> {
+        let defaults = Self::default();
+        let mut settings = BTreeMap::new();
+        let mut insert = |key: &str, value: String, default: String| {
+            if value != default {
+                settings.insert(key.to_string(), value);
+            }
+        };
+        insert(
+            MAG_FILTER_SETTING,
+            self.mag_filter.name().into(),
+            defaults.mag_filter.name().into(),
+        );
+        insert(
+            MIN_FILTER_SETTING,
+            self.min_filter.name().into(),
+            defaults.min_filter.name().into(),
+        );
+        insert(
+            MIPMAP_MODE_SETTING,
+            self.mipmap_mode.name().into(),
+            defaults.mipmap_mode.name().into(),
+        );
+        let address_modes = |modes: [AddressMode; 3]| modes.map(AddressMode::name).join(" ");
+        insert(
+            ADDRESS_MODE_SETTING,
+            address_modes(self.address_modes),
+            address_modes(defaults.address_modes),
+        );
+        insert(
+            MAX_ANISOTROPY_SETTING,
+            self.max_anisotropy.to_string(),
+            defaults.max_anisotropy.to_string(),
+        );
+        if let Some(op) = self.compare_op {
+            insert(COMPARE_OP_SETTING, op.name().into(), String::new());
+        }
+        insert(
+            MIP_LOD_BIAS_SETTING,
+            self.mip_lod_bias.to_string(),
+            defaults.mip_lod_bias.to_string(),
+        );
+        settings
+    }
+}
+
+/// Sampler description that is registered as an asset, so that material instances can share it by its stable ID
+#[derive(Asset)]
+pub struct Sampler {
+    desc: SamplerDesc,
+    asset_metadata: AssetMetadata,
+}
+
+impl Sampler {
+    pub fn new(name: String, desc: SamplerDesc) -> Self {
+        Self {
+            desc,
+            asset_metadata: AssetMetadata::new(name),
+        }
+    }
+
+    pub fn desc(&self) -> SamplerDesc {
+        self.desc
+    }
+
+    pub fn set_desc(&mut self, desc: SamplerDesc) {
+        self.desc = desc;
+    }
+}
+
+impl PartialEq for SamplerDesc {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for SamplerDesc {}
+
+impl Hash for SamplerDesc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn defaults_are_stored_without_settings() {
+        assert!(SamplerDesc::default().to_settings().is_empty());
+        assert_eq!(
+            SamplerDesc::from_settings(&BTreeMap::new()),
+            Some(SamplerDesc::default())
+        );
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let desc = SamplerDesc {
+            mag_filter: Filter::Nearest,
+            mipmap_mode: Filter::Nearest,
+            address_modes: [
+                AddressMode::ClampToEdge,
+                AddressMode::MirroredRepeat,
+                AddressMode::Repeat,
+            ],
+            max_anisotropy: 4.,
+            compare_op: Some(CompareOp::LessOrEqual),
+            mip_lod_bias: -0.5,
+            ..SamplerDesc::default()
+        };
+        let settings = desc.to_settings();
+        assert_eq!(settings.len(), 6);
+        assert_eq!(
+            settings[ADDRESS_MODE_SETTING],
+            "clamp_to_edge mirrored_repeat repeat"
+        );
+        assert_eq!(SamplerDesc::from_settings(&settings), Some(desc));
+    }
+
+    #[test]
+    fn malformed_settings_are_rejected() {
+        for (key, value) in [
+            (MAG_FILTER_SETTING, "cubic"),
+            (ADDRESS_MODE_SETTING, "repeat repeat"),
+            (MAX_ANISOTROPY_SETTING, "many"),
+            (COMPARE_OP_SETTING, "less_than"),
+        ] {
+            let settings = BTreeMap::from([(key.to_string(), value.to_string())]);
+            assert_eq!(SamplerDesc::from_settings(&settings), None, "{key}");
+        }
+    }
+
+    #[test]
+    fn equal_descriptions_hash_equally() {
+        let descs = [
+            SamplerDesc::default(),
+            SamplerDesc::default(),
+            SamplerDesc::linear_clamp(),
+            SamplerDesc::default().with_address_mode(AddressMode::ClampToEdge),
+            SamplerDesc {
+                mip_lod_bias: 1.,
+                ..SamplerDesc::default()
+            },
+        ];
+        assert_eq!(descs.into_iter().collect::<HashSet<_>>().len(), 4);
+    }
+}
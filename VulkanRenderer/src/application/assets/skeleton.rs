@@ -0,0 +1,221 @@
+use asset_system::{Asset, assets::AssetMetadata};
+use glam::Mat4;
+
+use crate::application::scene::transform::Transform;
+
+#[derive(Clone)]
+pub struct Joint {
+    pub name: String,
+    pub parent: Option<usize>,
+    /// Transform relative to the parent when no animation moves the joint
+    pub rest: Transform,
+    /// Moves the vertices of the mesh from its bind pose into the space of the joint
+    pub inverse_bind_matrix: Mat4,
+}
+
+/// Transforms of all joints of a skeleton, relative to their parents
+#[derive(Clone)]
+pub struct Pose {
+    pub joints: Vec<Transform>,
+}
+
+impl Pose {
+    /// Moves every joint towards the other pose. A weight of one replaces the pose.
+    pub fn blend(&mut self, other: &Pose, weight: f32) {
+        for (joint, target) in self.joints.iter_mut().zip(&other.joints) {
+            joint.location = joint.location.lerp(target.location, weight);
+            joint.rotation = joint.rotation.slerp(target.rotation, weight);
+            joint.scale = joint.scale.lerp(target.scale, weight);
+        }
+    }
+}
+
+/// Joint hierarchy of a skin, as referenced by the joint indices of a skinned mesh
+#[derive(Asset)]
+pub struct Skeleton {
+    asset_metadata: AssetMetadata,
+    // Joints are imported from the source file and not edited field by field
+    #[asset(skip)]
+    joints: Vec<Joint>,
+    /// Places the root joints relative to the model, e.g. for nodes above the skeleton in the source file
+    #[asset(skip)]
+    root_transform: Mat4,
+    /// Joint indices with every parent before its children
+    #[asset(skip)]
+    order: Vec<usize>,
+}
+
+impl Skeleton {
+    /// Parents may come after their children. Joints whose ancestors form a cycle become roots.
+    pub fn new(name: String, mut joints: Vec<Joint>, root_transform: Mat4) -> Self {
+        let depth = |joints: &[Joint], joint: usize| {
+            let mut depth = 0;
+            let mut current = joints[joint].parent;
+            while let Some(parent) = current {
+                depth += 1;
+                if depth > joints.len() || parent >= joints.len() {
+                    return None;
+                }
+                current = joints[parent].parent;
+            }
+            Some(depth)
+        };
+        let depths = (0..joints.len())
+            .map(|joint| depth(&joints, joint))
+            .collect::<Vec<_>>();
+        for (joint, depth) in joints.iter_mut().zip(&depths) {
+            if depth.is_none() {
+                joint.parent = None;
+            }
+        }
+        let mut order = (0..joints.len()).collect::<Vec<_>>();
+        order.sort_by_key(|joint| depths[*joint].unwrap_or(0));
+        Self {
+            asset_metadata: AssetMetadata::new(name),
+            joints,
+            root_transform,
+            order,
+        }
+    }
+
+    pub fn joints(&self) -> &[Joint] {
+        &self.joints
+    }
+
+    pub fn rest_pose(&self) -> Pose {
+        Pose {
+            joints: self.joints.iter().map(|joint| joint.rest).collect(),
+        }
+    }
+
+    /// Transform of every joint relative to the model
+    pub fn joint_transforms(&self, pose: &Pose) -> Vec<Mat4> {
+        let mut transforms = vec![Mat4::IDENTITY; self.joints.len()];
+        for &joint in &self.order {
+            let parent = self.joints[joint]
+                .parent
+                .map_or(self.root_transform, |parent| transforms[parent]);
+            let local = pose
+                .joints
+                .get(joint)
+                .unwrap_or(&self.joints[joint].rest)
+                .matrix();
+            transforms[joint] = parent * local;
+        }
+        transforms
+    }
+
+    /// Matrices that move the vertices from the bind pose into the pose, relative to the model
+    pub fn joint_matrices(&self, pose: &Pose) -> Vec<Mat4> {
+        self.joint_transforms(pose)
+            .into_iter()
+            .zip(&self.joints)
+            .map(|(transform, joint)| transform * joint.inverse_bind_matrix)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Quat, Vec3};
+
+    use super::*;
+
+    fn assert_close(a: Mat4, b: Mat4) {
+        assert!(a.abs_diff_eq(b, 1e-5), "{} != {}", a, b);
+    }
+
+    /// A chain of joints one unit apart along Y, bound in their rest pose.
+    /// The joints are stored from the tip to the root.
+    fn chain(length: usize) -> Skeleton {
+        let joints = (0..length)
+            .map(|joint| Joint {
+                name: format!("Joint{}", joint),
+                parent: (joint + 1 < length).then_some(joint + 1),
+                rest: Transform {
+                    location: if joint + 1 < length {
+                        Vec3::Y
+                    } else {
+                        Vec3::ZERO
+                    },
+                    ..Transform::default()
+                },
+                inverse_bind_matrix: Mat4::from_translation(-Vec3::Y * (length - 1 - joint) as f32),
+            })
+            .collect();
+        Skeleton::new("Chain".into(), joints, Mat4::IDENTITY)
+    }
+
+    #[test]
+    fn the_rest_pose_keeps_the_bind_pose() {
+        let skeleton = chain(4);
+        for matrix in skeleton.joint_matrices(&skeleton.rest_pose()) {
+            assert_close(matrix, Mat4::IDENTITY);
+        }
+    }
+
+    #[test]
+    fn children_follow_their_parents() {
+        let skeleton = chain(3);
+        let mut pose = skeleton.rest_pose();
+        // Rotating the root bends the whole chain
+        pose.joints[2].rotation = Quat::from_rotation_z(std::f32::consts::FRAC_PI_2);
+        let transforms = skeleton.joint_transforms(&pose);
+        assert!(
+            transforms[0]
+                .transform_point3(Vec3::ZERO)
+                .abs_diff_eq(Vec3::new(-2., 0., 0.), 1e-5)
+        );
+        let matrices = skeleton.joint_matrices(&pose);
+        // The vertex at the tip in the bind pose moves with the tip
+        assert!(
+            matrices[0]
+                .transform_point3(Vec3::new(0., 2., 0.))
+                .abs_diff_eq(Vec3::new(-2., 0., 0.), 1e-5)
+        );
+    }
+
+    #[test]
+    fn the_root_transform_moves_every_joint() {
+        let mut skeleton = chain(2);
+        skeleton.root_transform = Mat4::from_translation(Vec3::X);
+        for matrix in skeleton.joint_matrices(&skeleton.rest_pose()) {
+            assert_close(matrix, Mat4::from_translation(Vec3::X));
+        }
+    }
+
+    #[test]
+    fn cyclic_parents_become_roots() {
+        let mut joints = chain(2).joints;
+        joints[1].parent = Some(0);
+        let skeleton = Skeleton::new("Cycle".into(), joints, Mat4::IDENTITY);
+        assert!(skeleton.joints().iter().all(|joint| joint.parent.is_none()));
+        assert_eq!(skeleton.joint_transforms(&skeleton.rest_pose()).len(), 2);
+    }
+
+    #[test]
+    fn blending_interpolates_between_poses() {
+        let skeleton = chain(2);
+        let mut pose = skeleton.rest_pose();
+        let mut target = skeleton.rest_pose();
+        target.joints[0].location = Vec3::new(0., 3., 0.);
+        target.joints[1].rotation = Quat::from_rotation_x(1.);
+        target.joints[1].scale = Vec3::splat(3.);
+
+        pose.blend(&target, 0.5);
+        assert!(
+            pose.joints[0]
+                .location
+                .abs_diff_eq(Vec3::new(0., 2., 0.), 1e-5)
+        );
+        assert!(
+            pose.joints[1]
+                .rotation
+                .abs_diff_eq(Quat::from_rotation_x(0.5), 1e-5)
+        );
+        assert!(pose.joints[1].scale.abs_diff_eq(Vec3::splat(2.), 1e-5));
+
+        pose.blend(&target, 1.);
+        assert_eq!(pose.joints[0].location, target.joints[0].location);
+    }
+}
@@ -1,26 +1,327 @@
-use std::path::Path;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
 
 use asset_system::{
-    assets::{Asset, AssetMetadata},
+    assets::{Asset, AssetMetadata, LoadState},
     resource_management::Resource,
 };
-use image::{DynamicImage, ImageError, ImageReader};
+use image::{
+    DynamicImage, ImageBuffer, ImageReader, Rgba, Rgba32FImage, RgbaImage, imageops::FilterType,
+};
+
+use crate::application::assets::{
+    asset_error::AssetError,
+    asset_traits::{BlockFormat, ColorSpace, PixelFormat, TextureInterface, TextureUsage},
+    compressed_image::CompressedImage,
+};
+
+const COLOR_SPACE_SETTING: &str = "color_space";
+const LINEAR_COLOR_SPACE: &str = "linear";
+const USAGE_SETTING: &str = "usage";
+const NORMAL_MAP_USAGE: &str = "normal_map";
+const SINGLE_CHANNEL_USAGE: &str = "single_channel";
+const COMPRESSION_SETTING: &str = "compression";
+const BLOCK_COMPRESSION: &str = "block";
+
+/// How a texture is imported
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct TextureSettings {
+    pub color_space: ColorSpace,
+    pub usage: TextureUsage,
+    /// Encode uncompressed sources into the block format of the usage.
+    /// DDS and KTX2 files keep the format they are stored in.
+    pub compress: bool,
+}
+
+impl TextureSettings {
+    /// Reads the settings from a registry record. Missing settings keep their default.
+    pub fn from_settings(settings: &BTreeMap<String, String>) -> Self {
+        let color_space = match settings.get(COLOR_SPACE_SETTING).map(String::as_str) {
+            Some(LINEAR_COLOR_SPACE) => ColorSpace::Linear,
+            _ => ColorSpace::Srgb,
+        };
+        let usage = match settings.get(USAGE_SETTING).map(String::as_str) {
+            Some(NORMAL_MAP_USAGE) => TextureUsage::NormalMap,
+            Some(SINGLE_CHANNEL_USAGE) => TextureUsage::SingleChannel,
+            _ => TextureUsage::Color,
+        };
+        Self {
+            color_space,
+            usage,
+            compress: settings.get(COMPRESSION_SETTING).map(String::as_str)
+                == Some(BLOCK_COMPRESSION),
+        }
+    }
+
+    /// Defaults are stored without a setting
+    pub fn to_settings(self) -> BTreeMap<String, String> {
+        let mut settings = BTreeMap::new();
+        if self.color_space == ColorSpace::Linear {
+            settings.insert(COLOR_SPACE_SETTING.into(), LINEAR_COLOR_SPACE.into());
+        }
+        match self.usage {
+            TextureUsage::Color => {}
+            TextureUsage::NormalMap => {
+                settings.insert(USAGE_SETTING.into(), NORMAL_MAP_USAGE.into());
+            }
+            TextureUsage::SingleChannel => {
+                settings.insert(USAGE_SETTING.into(), SINGLE_CHANNEL_USAGE.into());
+            }
+        }
+        if self.compress {
+            settings.insert(COMPRESSION_SETTING.into(), BLOCK_COMPRESSION.into());
+        }
+        settings
+    }
 
-use crate::application::assets::asset_traits::TextureInterface;
+    /// Identifies the import of a file in the registry, so that one file can be loaded in several color spaces
+    /// and usages. The compression is not part of it, as it only changes how the same texels are stored.
+    pub fn item(self) -> String {
+        Self {
+            compress: false,
+            ..self
+        }
+        .to_settings()
+        .into_values()
+        .collect::<Vec<_>>()
+        .join("/")
+    }
+}
+
+enum TextureData {
+    /// Uncompressed in one of the pixel formats, the mips are generated on upload
+    Image(DynamicImage),
+    Compressed(CompressedImage),
+}
 
 pub struct Texture {
-    image: DynamicImage,
+    data: TextureData,
+    settings: TextureSettings,
+    /// File the texture was imported from, empty for generated textures
+    path: PathBuf,
     metadata: AssetMetadata,
 }
 
 impl Texture {
-    pub fn new(filepath: impl AsRef<Path>, name: String) -> Result<Self, ImageError> {
-        let image = ImageReader::open(filepath)?.decode()?;
+    /// Reads DDS and KTX2 files with their block compressed mips, and decodes all other image formats.
+    pub fn new(
+        filepath: impl AsRef<Path>,
+        name: String,
+        settings: TextureSettings,
+    ) -> Result<Self, AssetError> {
+        let path = filepath.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        let data = match extension.as_deref() {
+            Some("dds") => TextureData::Compressed(CompressedImage::from_dds(path, &read(path)?)?),
+            Some("ktx2") => {
+                TextureData::Compressed(CompressedImage::from_ktx2(path, &read(path)?)?)
+            }
+            _ => return Self::from_image(path, decode(path)?, name, settings),
+        };
         Ok(Self {
-            image,
+            data,
+            settings,
+            path: path.to_path_buf(),
+            metadata: AssetMetadata::new(name),
+        })
+    }
+
+    /// Converts the texels of the image read from path to the pixel format they are uploaded in,
+    /// or encodes them if the settings ask for it
+    pub fn from_image(
+        path: &Path,
+        image: DynamicImage,
+        name: String,
+        settings: TextureSettings,
+    ) -> Result<Self, AssetError> {
+        let data = if settings.compress {
+            let format = settings.usage.block_format();
+            let compressed = CompressedImage::encode(&image, format).ok_or_else(|| {
+                AssetError::UnsupportedEncoding {
+                    path: path.to_path_buf(),
+                    format: format!("{:?}", format),
+                }
+            })?;
+            TextureData::Compressed(compressed)
+        } else {
+            TextureData::Image(Self::convert(image, settings.color_space))
+        };
+        Ok(Self {
+            data,
+            settings,
+            path: path.to_path_buf(),
             metadata: AssetMetadata::new(name),
         })
     }
+
+    /// 16 bit channels are only kept in linear textures because there is no 16 bit sRGB format.
+    /// Floating point texels are always linear.
+    fn convert(image: DynamicImage, color_space: ColorSpace) -> DynamicImage {
+        let linear = color_space == ColorSpace::Linear;
+        match image {
+            DynamicImage::ImageRgba8(_) | DynamicImage::ImageRgba32F(_) => image,
+            DynamicImage::ImageRgba16(_) if linear => image,
+            DynamicImage::ImageLuma16(_)
+            | DynamicImage::ImageLumaA16(_)
+            | DynamicImage::ImageRgb16(_)
+                if linear =>
+            {
+                DynamicImage::ImageRgba16(image.to_rgba16())
+            }
+            DynamicImage::ImageRgb32F(_) => DynamicImage::ImageRgba32F(image.to_rgba32f()),
+            _ => DynamicImage::ImageRgba8(image.to_rgba8()),
+        }
+    }
+
+    /// Single grey pixel that is shown while the texture is loaded in the background
+    pub fn placeholder(name: String, settings: TextureSettings) -> Self {
+        Self {
+            data: TextureData::Image(DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+                1,
+                1,
+                Rgba([128, 128, 128, 255]),
+            ))),
+            settings,
+            path: PathBuf::new(),
+            metadata: AssetMetadata::pending(name),
+        }
+    }
+
+    /// Magenta and black checkerboard that replaces textures that failed to load.
+    /// It is marked as failed with the cause.
+    pub fn error(name: String, cause: &AssetError) -> Self {
+        const SIZE: u32 = 64;
+        const TILE: u32 = 8;
+        let image = RgbaImage::from_fn(SIZE, SIZE, |x, y| {
+            if (x / TILE + y / TILE).is_multiple_of(2) {
+                Rgba([255, 0, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        });
+        let mut metadata = AssetMetadata::new(name);
+        metadata.set_load_state(LoadState::Failed(cause.to_string()));
+        Self {
+            data: TextureData::Image(DynamicImage::ImageRgba8(image)),
+            settings: TextureSettings::default(),
+            path: PathBuf::new(),
+            metadata,
+        }
+    }
+
+    pub fn settings(&self) -> TextureSettings {
+        self.settings
+    }
+}
+
+/// Number of mips down to a single texel
+pub fn mip_levels(width: u32, height: u32) -> u32 {
+    width.max(height).max(1).ilog2() + 1
+}
+
+/// The image followed by its mips, each half the size of the one before
+pub fn mip_chain(image: &DynamicImage) -> Vec<DynamicImage> {
+    let mut mips = Vec::with_capacity(mip_levels(image.width(), image.height()) as usize);
+    let mut level = image.clone();
+    while level.width() > 1 || level.height() > 1 {
+        let next = match &level {
+            DynamicImage::ImageRgba32F(float) => {
+                DynamicImage::ImageRgba32F(downsample_float(float))
+            }
+            _ => level.resize_exact(
+                (level.width() / 2).max(1),
+                (level.height() / 2).max(1),
+                FilterType::Triangle,
+            ),
+        };
+        mips.push(level);
+        level = next;
+    }
+    mips.push(level);
+    mips
+}
+
+/// Averages 2x2 texels. Resizing with the image crate would clamp HDR values to 1.
+fn downsample_float(image: &Rgba32FImage) -> Rgba32FImage {
+    let (width, height) = (image.width(), image.height());
+    Rgba32FImage::from_fn((width / 2).max(1), (height / 2).max(1), |x, y| {
+        let mut average = [0.; 4];
+        for row in [2 * y, (2 * y + 1).min(height - 1)] {
+            for column in [2 * x, (2 * x + 1).min(width - 1)] {
+                for (sum, value) in average.iter_mut().zip(image.get_pixel(column, row).0) {
+                    *sum += value / 4.;
+                }
+            }
+        }
+        Rgba(average)
+    })
+}
+
+/// Texels of all mips, tightly packed with the largest first.
+/// For formats the device cannot blit. Returns None if the pixels do not match the size.
+pub fn generate_mips(
+    format: PixelFormat,
+    width: u32,
+    height: u32,
+    pixels: &[u8],
+) -> Option<Vec<u8>> {
+    if pixels.len() != width as usize * height as usize * format.texel_size() {
+        return None;
+    }
+    // The texels are stored in native byte order
+    let image = match format {
+        PixelFormat::Rgba8 => {
+            DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, pixels.to_vec())?)
+        }
+        PixelFormat::Rgba16 => {
+            let channels = pixels
+                .chunks_exact(2)
+                .map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]))
+                .collect();
+            DynamicImage::ImageRgba16(ImageBuffer::from_raw(width, height, channels)?)
+        }
+        PixelFormat::Rgba32Float => {
+            let channels = pixels
+                .chunks_exact(4)
+                .map(|bytes| f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .collect();
+            DynamicImage::ImageRgba32F(ImageBuffer::from_raw(width, height, channels)?)
+        }
+    };
+    Some(
+        mip_chain(&image)
+            .iter()
+            .flat_map(|level| level.as_bytes())
+            .copied()
+            .collect(),
+    )
+}
+
+/// Decodes an image file in any format the image crate supports
+pub fn decode(path: &Path) -> Result<DynamicImage, AssetError> {
+    ImageReader::open(path)
+        .map_err(|error| AssetError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?
+        .decode()
+        .map_err(|error| AssetError::Image {
+            path: path.to_path_buf(),
+            error,
+        })
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, AssetError> {
+    fs::read(path).map_err(|error| AssetError::Io {
+        path: path.to_path_buf(),
+        error,
+    })
 }
 
 impl Resource for Texture {
@@ -33,13 +334,195 @@ impl Asset for Texture {
     fn asset_metadata(&self) -> &AssetMetadata {
         &self.metadata
     }
+
+    fn asset_metadata_mut(&mut self) -> &mut AssetMetadata {
+        &mut self.metadata
+    }
 }
 
 impl TextureInterface for Texture {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
     fn pixels(&self) -> &[u8] {
-        self.image.as_bytes()
+        match &self.data {
+            TextureData::Image(image) => image.as_bytes(),
+            TextureData::Compressed(compressed) => &compressed.mips[0],
+        }
     }
     fn size(&self) -> [u32; 3] {
-        [self.image.width(), self.image.height(), 1]
+        match &self.data {
+            TextureData::Image(image) => [image.width(), image.height(), 1],
+            TextureData::Compressed(compressed) => [compressed.width, compressed.height, 1],
+        }
+    }
+
+    fn color_space(&self) -> ColorSpace {
+        self.settings.color_space
+    }
+
+    fn pixel_format(&self) -> PixelFormat {
+        match &self.data {
+            TextureData::Image(DynamicImage::ImageRgba16(_)) => PixelFormat::Rgba16,
+            TextureData::Image(DynamicImage::ImageRgba32F(_)) => PixelFormat::Rgba32Float,
+            _ => PixelFormat::Rgba8,
+        }
+    }
+
+    fn block_format(&self) -> Option<BlockFormat> {
+        match &self.data {
+            TextureData::Image(_) => None,
+            TextureData::Compressed(compressed) => Some(compressed.format),
+        }
+    }
+
+    fn mips(&self) -> Vec<&[u8]> {
+        match &self.data {
+            TextureData::Image(image) => vec![image.as_bytes()],
+            TextureData::Compressed(compressed) => {
+                compressed.mips.iter().map(Vec::as_slice).collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Luma, Rgb, Rgb32FImage};
+
+    use super::*;
+
+    #[test]
+    fn texels_are_converted_to_rgba() {
+        let image = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(3, 2, Rgb([10, 20, 30])));
+        let texture = Texture::from_image(
+            Path::new("rgb.png"),
+            image,
+            "rgb".into(),
+            TextureSettings::default(),
+        )
+        .unwrap();
+        assert_eq!(texture.pixel_format(), PixelFormat::Rgba8);
+        assert_eq!(texture.pixels(), [10, 20, 30, 255].repeat(6));
+
+        let image = DynamicImage::ImageLuma8(ImageBuffer::from_pixel(2, 2, Luma([7])));
+        let texture = Texture::from_image(
+            Path::new("luma.png"),
+            image,
+            "luma".into(),
+            TextureSettings::default(),
+        )
+        .unwrap();
+        assert_eq!(texture.pixels(), [7, 7, 7, 255].repeat(4));
+    }
+
+    #[test]
+    fn sixteen_bit_channels_are_kept_in_linear_textures() {
+        let image = DynamicImage::ImageLuma16(ImageBuffer::from_pixel(2, 1, Luma([0x1234])));
+        let srgb = Texture::from_image(
+            Path::new("srgb.png"),
+            image.clone(),
+            "srgb".into(),
+            TextureSettings::default(),
+        )
+        .unwrap();
+        assert_eq!(srgb.pixel_format(), PixelFormat::Rgba8);
+        assert_eq!(srgb.pixels().len(), 2 * 4);
+
+        let settings = TextureSettings {
+            color_space: ColorSpace::Linear,
+            ..TextureSettings::default()
+        };
+        let linear =
+            Texture::from_image(Path::new("linear.png"), image, "linear".into(), settings).unwrap();
+        assert_eq!(linear.pixel_format(), PixelFormat::Rgba16);
+        let channel = u16::from_ne_bytes([linear.pixels()[0], linear.pixels()[1]]);
+        assert_eq!(channel, 0x1234);
+        assert_eq!(linear.pixels().len(), 2 * 8);
+    }
+
+    #[test]
+    fn float_texels_keep_their_precision() {
+        let image = DynamicImage::ImageRgb32F(Rgb32FImage::from_pixel(1, 1, Rgb([2.5, 0., 1.])));
+        let texture = Texture::from_image(
+            Path::new("hdr.png"),
+            image,
+            "hdr".into(),
+            TextureSettings::default(),
+        )
+        .unwrap();
+        assert_eq!(texture.pixel_format(), PixelFormat::Rgba32Float);
+        let channels = texture
+            .pixels()
+            .chunks_exact(4)
+            .map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(channels, [2.5, 0., 1., 1.]);
+    }
+
+    #[test]
+    fn items_distinguish_color_space_and_usage() {
+        let normal_map = TextureSettings {
+            color_space: ColorSpace::Linear,
+            usage: TextureUsage::NormalMap,
+            compress: false,
+        };
+        assert_eq!(TextureSettings::default().item(), "");
+        assert_eq!(normal_map.item(), "linear/normal_map");
+        assert_eq!(
+            TextureSettings {
+                compress: true,
+                ..normal_map
+            }
+            .item(),
+            normal_map.item()
+        );
+    }
+
+    #[test]
+    fn mip_chain_ends_at_a_single_texel() {
+        for (width, height) in [(1, 1), (8, 8), (5, 3), (1, 6)] {
+            let image = DynamicImage::ImageRgba8(RgbaImage::new(width, height));
+            let mips = mip_chain(&image);
+            assert_eq!(mips.len() as u32, mip_levels(width, height));
+            let last = mips.last().unwrap();
+            assert_eq!((last.width(), last.height()), (1, 1));
+        }
+        let sizes = mip_chain(&DynamicImage::ImageRgba8(RgbaImage::new(5, 3)))
+            .iter()
+            .map(|level| (level.width(), level.height()))
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, [(5, 3), (2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn generated_mips_are_packed_and_filtered() {
+        let pixels = [40, 80, 120, 255].repeat(4 * 2);
+        let mips = generate_mips(PixelFormat::Rgba8, 4, 2, &pixels).unwrap();
+        // 4x2, 2x1 and 1x1 texels of a constant color
+        assert_eq!(mips.len(), (8 + 2 + 1) * 4);
+        assert_eq!(mips[mips.len() - 4..], [40, 80, 120, 255]);
+
+        let checker = [0u16, 0, 0, 0, 1000, 1000, 1000, 1000]
+            .iter()
+            .flat_map(|channel| channel.to_ne_bytes())
+            .collect::<Vec<_>>();
+        let mips = generate_mips(PixelFormat::Rgba16, 2, 1, &checker).unwrap();
+        assert_eq!(mips.len(), (2 + 1) * 8);
+        let average = u16::from_ne_bytes([mips[16], mips[17]]);
+        assert!((400..=600).contains(&average), "{}", average);
+
+        assert!(generate_mips(PixelFormat::Rgba32Float, 2, 2, &[0; 16]).is_none());
+    }
+
+    #[test]
+    fn float_mips_keep_hdr_values() {
+        let image = Rgba32FImage::from_fn(2, 2, |x, _| Rgba([x as f32 * 100., 0.5, 0., 1.]));
+        let mips = mip_chain(&DynamicImage::ImageRgba32F(image));
+        assert_eq!(
+            mips[1].as_rgba32f().unwrap().get_pixel(0, 0).0,
+            [50., 0.5, 0., 1.]
+        );
     }
 }
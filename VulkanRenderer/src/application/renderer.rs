@@ -1,6 +1,7 @@
 mod full_screen_pass;
 mod post_processing;
 pub mod profiling;
+mod texture_streaming;
 mod visibility_buffer_data;
 mod visibility_buffer_generation;
 mod visibility_buffer_shading;
@@ -12,6 +13,8 @@ use std::{
     sync::{Arc, RwLock},
 };
 
+use egui_winit_vulkano::{egui, egui::Ui};
+use glam::Mat4;
 use vulkano::{
     Validated, VulkanError,
     buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
@@ -26,11 +29,17 @@ use vulkano::{
 use winit::dpi::PhysicalSize;
 
 use crate::application::{
-    assets::asset_traits::{RHICameraInterface, RHIResource, RHISceneInterface, RendererInterface},
+    assets::{
+        AssetManager::AssetChanges,
+        asset_traits::{
+            RHICameraInterface, RHIInterface, RHIResource, RHISceneInterface, RendererInterface,
+        },
+    },
     renderer::{
         full_screen_pass::FullScreenPass,
         post_processing::{PostProcessPass, PostProcessSettings},
         profiling::{Profiler, ProfilerStage},
+        texture_streaming::{StreamingSettings, TextureStreamer},
         visibility_buffer_data::{MutatingData, VisibilityBufferData, VisibilityBufferGlobalData},
         visibility_buffer_generation::{
             VisibilityBufferProcessingPass, VisibilityBufferRasterizer,
@@ -40,7 +49,7 @@ use crate::application::{
     rhi::{
         VKRHI,
         render_pass::RenderPassBuilder,
-        rhi_assets::{vulkan_material::VKMaterial, vulkan_scene::VKScene},
+        rhi_assets::{RHIResourceManager, vulkan_material::VKMaterial, vulkan_scene::VKScene},
         swapchain::Swapchain,
         swapchain_resources::{
             SwapchainFramebuffer, SwapchainFramebufferCreateInfo, SwapchainImage,
@@ -95,6 +104,14 @@ pub struct VKRenderer {
     profiler: Profiler,
     /// System to record data about the scene (e.g., number of visible materials)
     scene_statistics: RefCell<SceneStatistics>,
+    /// Settings of the level of detail selection
+    lod_settings: RefCell<LodSettings>,
+    /// Streams the mips of the textures the shading samples
+    texture_streamer: RefCell<TextureStreamer>,
+    /// Settings of the texture streaming
+    streaming_settings: RefCell<StreamingSettings>,
+    /// Settings of the texture samplers
+    filtering_settings: RefCell<FilteringSettings>,
 }
 
 /// Material compiler for forward rendering. Not currently used and to be considered deprecated.
@@ -183,8 +200,9 @@ impl VKRenderer {
             vis_buffer_global_data,
             color_render_target.clone(),
         ));
+        let texture_streamer = TextureStreamer::new(rhi.as_ref(), &vis_buffer_data.global_data);
         let vis_buffer_rasterizer =
-            VisibilityBufferRasterizer::new(rhi.clone(), &swapchain, vis_buffer_data.as_ref());
+            VisibilityBufferRasterizer::new(rhi.clone(), &swapchain, &vis_buffer_data);
         let vis_buffer_processing =
             VisibilityBufferProcessingPass::new(rhi.as_ref(), &vis_buffer_data);
         let vis_buffer_shade = VisibilityBufferShadePass::new(rhi.clone(), vis_buffer_data.clone());
@@ -213,6 +231,10 @@ impl VKRenderer {
             post_process,
             profiler,
             scene_statistics: RefCell::new(SceneStatistics::default()),
+            lod_settings: RefCell::new(LodSettings::default()),
+            texture_streamer: RefCell::new(texture_streamer),
+            streaming_settings: RefCell::new(StreamingSettings::default()),
+            filtering_settings: RefCell::new(FilteringSettings::default()),
         }
     }
 
@@ -236,6 +258,26 @@ impl VKRenderer {
         // Update scene statistics
         self.update_scene_statistics();
 
+        // Bind finished texture uploads and stream the mips that the previous frame asked for
+        self.texture_streamer.borrow_mut().update(
+            self.rhi.as_ref(),
+            &self.mutable_state_const().vis_buffer_data.global_data,
+            self.streaming_settings.borrow().budget(),
+        );
+
+        // Samplers pick up a new anisotropy limit when they are bound again
+        let max_anisotropy = self.filtering_settings.borrow().max_anisotropy as f32;
+        if self
+            .rhi
+            .resource_manager_mut()
+            .set_max_anisotropy(max_anisotropy)
+        {
+            self.mutable_state_const()
+                .vis_buffer_data
+                .global_data
+                .refresh_samplers(self.rhi.as_ref());
+        }
+
         // Recreate swapchain if needed
         if self.mutable_state_const().should_recreate_swapchain {
             self.mutable_state()
@@ -244,6 +286,15 @@ impl VKRenderer {
 
         // Update camera matrix and screen data
         self.update_mutating_data(scene);
+        // The previous frame is done with the poses and transforms, so they can be overwritten
+        self.mutable_state_const()
+            .vis_buffer_data
+            .global_data
+            .write_joint_matrices(scene);
+        self.mutable_state_const()
+            .vis_buffer_data
+            .global_data
+            .write_instance_transforms(scene);
 
         // Acquire swapchain image
         let acquire_image_result = self.mutable_state_const().swapchain.acquire_next_image();
@@ -652,6 +703,112 @@ impl VKRenderer {
         statistics.drawn_materials = *data.final_material_count_buffer.read().unwrap();
         statistics.fallback_pixels = *data.offset_accumulator_buffer.read().unwrap()
             - *data.no_fallback_texel_count_buffer.read().unwrap();
+        // The count keeps growing past the limit, the culling only stops writing draws
+        let cluster_draws = *data.global_data.cluster_draw_count.read().unwrap();
+        statistics.drawn_clusters =
+            cluster_draws.min(VisibilityBufferGlobalData::MAX_CLUSTER_DRAWS);
+        let dropped_clusters =
+            cluster_draws.saturating_sub(VisibilityBufferGlobalData::MAX_CLUSTER_DRAWS);
+        if dropped_clusters > 0 && statistics.dropped_clusters == 0 {
+            println!(
+                "{} visible clusters exceed the limit of {} cluster draws and are not drawn",
+                dropped_clusters,
+                VisibilityBufferGlobalData::MAX_CLUSTER_DRAWS
+            );
+        }
+        statistics.dropped_clusters = dropped_clusters;
+        statistics.resident_texture_bytes = self.texture_streamer.borrow().resident_size();
+    }
+
+    /// Swaps the RHI proxies of reloaded assets and recompiles changed materials.
+    /// Waits for the frame in flight first so that no replaced resource is in use.
+    pub fn reload_assets(&self, changes: &AssetChanges) {
+        if changes.is_empty() {
+            return;
+        }
+        if let Some(future) = self.mutable_state_const().in_flight_future.as_ref() {
+            future.wait(None).unwrap();
+        }
+
+        let state = self.mutable_state_const();
+        let global_data = &state.vis_buffer_data.global_data;
+
+        let (meshes, textures) = self.rhi.resource_manager_mut().reload(changes);
+        let material_instances = self
+            .rhi
+            .resource_manager_mut()
+            .update_samplers(&changes.samplers);
+        let resources = self.rhi.resource_manager();
+        for handle in meshes {
+            if let Some(vk_mesh) = handle.get(&resources) {
+                global_data.refresh_mesh(&self.rhi, handle.id(), vk_mesh);
+            }
+        }
+        for handle in textures {
+            if let (Some(index), Some(vk_texture)) =
+                (resources.index(handle.id()), handle.get(&resources))
+            {
+                global_data.refresh_texture(index, vk_texture.image_view());
+                self.texture_streamer
+                    .borrow_mut()
+                    .reset(&handle, vk_texture);
+            }
+        }
+        for handle in material_instances {
+            if let (Some(index), Some(vk_instance)) =
+                (resources.index(handle.id()), handle.get(&resources))
+            {
+                global_data.refresh_material_instance(&self.rhi, index, vk_instance);
+            }
+        }
+
+        if changes.materials.is_empty() {
+            return;
+        }
+        // Modules are cached per session, so a new session is needed to see the changed files
+        self.rhi.slang_compiler().reload();
+        let resources = self.rhi.resource_manager();
+        for material in &changes.materials {
+            let Some(handle) = resources.proxy_of::<VKMaterial, _>(material) else {
+                continue;
+            };
+            let (Some(index), Some(vk_material)) =
+                (resources.index(handle.id()), handle.get(&resources))
+            else {
+                continue;
+            };
+            match global_data.recompile_material(&self.rhi, index, vk_material) {
+                Ok(()) => println!("Recompiled material {}", vk_material.material_name()),
+                Err(error) => println!(
+                    "Failed to recompile material {}, keeping the previous version: {}",
+                    vk_material.material_name(),
+                    error
+                ),
+            }
+        }
+    }
+
+    /// Removes the RHI proxies of the assets that were unloaded since the last call and frees their GPU memory.
+    /// Waits for the frame in flight first, since it may still use them.
+    pub fn remove_unloaded_proxies(&self) {
+        let unloaded = self.rhi.resource_manager().asset_manager().drain_unloaded();
+        if unloaded.is_empty() {
+            return;
+        }
+        if let Some(future) = self.mutable_state_const().in_flight_future.as_ref() {
+            future.wait(None).unwrap();
+        }
+
+        let removed = self
+            .rhi
+            .resource_manager_mut()
+            .remove_unloaded_proxies(unloaded);
+        if removed.count > 0 {
+            self.mutable_state_const()
+                .vis_buffer_data
+                .global_data
+                .remove_proxies(&self.rhi, &removed);
+        }
     }
 
     pub fn compile_materials(&self) {
@@ -669,16 +826,40 @@ impl VKRenderer {
 
     /// Update camera and screen data
     fn update_mutating_data(&self, scene: &VKScene) {
+        let screen_size = self.mutable_state_const().swapchain.extent;
+        let view_projection = scene.camera().view_projection();
+        // The view is a rigid transform, so the second row only carries the focal length of the projection
+        let focal_length = view_projection.row(1).truncate().length();
         let data = MutatingData {
-            screen_size: self.mutable_state_const().swapchain.extent,
-            view_matrix: scene.camera().view_projection().to_cols_array_2d(),
+            screen_size,
+            view_matrix: view_projection.to_cols_array_2d(),
             view_position: scene.camera().location().into(),
+            lod_scale: focal_length * screen_size[1] as f32 * 0.5,
+            lod_error_threshold: self.lod_settings.borrow().error_threshold,
+            frustum_planes: Self::frustum_planes(view_projection),
         };
         let state = self.mutable_state_const();
         let mut write = state.mutating_data.write().unwrap();
         write.screen_size = data.screen_size;
         write.view_matrix = data.view_matrix;
         write.view_position = data.view_position;
+        write.lod_scale = data.lod_scale;
+        write.lod_error_threshold = data.lod_error_threshold;
+        write.frustum_planes = data.frustum_planes;
+    }
+
+    /// Extracts the planes of the view frustum from the view projection matrix (left, right, bottom, top, near, far)
+    fn frustum_planes(view_projection: Mat4) -> [[f32; 4]; 6] {
+        let row = |index| view_projection.row(index);
+        [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(2),
+            row(3) - row(2),
+        ]
+        .map(|plane| (plane / plane.truncate().length()).to_array())
     }
 
     pub fn post_process_settings(&self) -> RefMut<PostProcessSettings> {
@@ -693,6 +874,18 @@ impl VKRenderer {
         self.scene_statistics.borrow()
     }
 
+    pub fn lod_settings(&self) -> RefMut<LodSettings> {
+        self.lod_settings.borrow_mut()
+    }
+
+    pub fn streaming_settings(&self) -> RefMut<StreamingSettings> {
+        self.streaming_settings.borrow_mut()
+    }
+
+    pub fn filtering_settings(&self) -> RefMut<FilteringSettings> {
+        self.filtering_settings.borrow_mut()
+    }
+
     pub fn swapchain_extent(&self) -> [u32; 2] {
         self.mutable_state_const().swapchain.extent
     }
@@ -731,6 +924,48 @@ pub struct SceneStatistics {
     pub drawn_materials: u32,
     pub culled_materials: u32,
     pub fallback_pixels: u32,
+    pub drawn_clusters: u32,
+    /// Visible clusters beyond the limit of cluster draws, which are missing from the frame
+    pub dropped_clusters: u32,
+    pub resident_texture_bytes: u64,
+}
+
+pub struct LodSettings {
+    /// Largest error in pixels that a level of detail may have on screen
+    pub error_threshold: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            error_threshold: 1.,
+        }
+    }
+}
+
+impl LodSettings {
+    pub fn draw_gui(&mut self, gui: &mut Ui) {
+        gui.add(egui::Slider::new(&mut self.error_threshold, 0f32..=16f32).text("Error (pixels)"));
+    }
+}
+
+pub struct FilteringSettings {
+    /// Global limit of the anisotropic filtering of all samplers, 1 turns it off
+    pub max_anisotropy: u32,
+}
+
+impl Default for FilteringSettings {
+    fn default() -> Self {
+        Self {
+            max_anisotropy: RHIResourceManager::DEFAULT_MAX_ANISOTROPY as u32,
+        }
+    }
+}
+
+impl FilteringSettings {
+    pub fn draw_gui(&mut self, gui: &mut Ui) {
+        gui.add(egui::Slider::new(&mut self.max_anisotropy, 1..=16).text("Max Anisotropy"));
+    }
 }
 
 impl MaterialCompiler {
@@ -14,10 +14,7 @@ use vulkano::{
     },
     device::{Device, Queue},
     format::{ClearValue, Format},
-    image::{
-        ImageLayout,
-        sampler::{Sampler, SamplerCreateInfo},
-    },
+    image::{ImageLayout, sampler::Sampler},
     memory::allocator::{MemoryAllocator, MemoryTypeFilter},
     pipeline::{
         DynamicState, GraphicsPipeline, PipelineBindPoint,
@@ -35,16 +32,21 @@ use vulkano::{
     sync::{AccessFlags, PipelineStages},
 };
 
-use crate::application::rhi::{
-    VKRHI,
-    buffer::buffer_from_slice,
-    command_buffer::CommandBufferInterface,
-    pipeline::graphics_pipeline,
-    shader_cursor::ShaderCursor,
-    shader_object::{ShaderObject, ShaderObjectLayout},
-    shaders::SlangCompiler,
-    swapchain::Swapchain,
-    swapchain_resources::{SwapchainFramebuffer, SwapchainFramebufferCreateInfo, SwapchainImage},
+use crate::application::{
+    assets::{asset_traits::RHIInterface, sampler::SamplerDesc},
+    rhi::{
+        VKRHI,
+        buffer::buffer_from_slice,
+        command_buffer::CommandBufferInterface,
+        pipeline::graphics_pipeline,
+        shader_cursor::ShaderCursor,
+        shader_object::{ShaderObject, ShaderObjectLayout},
+        shaders::SlangCompiler,
+        swapchain::Swapchain,
+        swapchain_resources::{
+            SwapchainFramebuffer, SwapchainFramebufferCreateInfo, SwapchainImage,
+        },
+    },
 };
 
 /// Render pass that copies an image onto another image by using a fullscreen rectangle pass.
@@ -141,13 +143,9 @@ impl FullScreenPass {
             rhi.shader_object_update_queue().clone(),
         );
 
-        let sampler = Sampler::new(
-            rhi.device().clone(),
-            SamplerCreateInfo {
-                ..SamplerCreateInfo::default()
-            },
-        )
-        .unwrap();
+        let sampler = rhi
+            .resource_manager()
+            .sampler(&SamplerDesc::nearest_clamp());
 
         Self::write_descriptor_sets(shader_object.clone(), source_image, sampler.clone());
 
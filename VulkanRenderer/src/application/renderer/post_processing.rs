@@ -9,18 +9,24 @@ use shader_slang::ComponentType;
 use vulkano::{
     ValidationError,
     command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
-    image::sampler::{Sampler, SamplerCreateInfo},
+    image::sampler::Sampler,
     pipeline::{ComputePipeline, PipelineBindPoint},
     shader::{ShaderStages, spirv::bytes_to_words},
 };
 
-use crate::application::rhi::{
-    VKRHI,
-    pipeline::compute_pipeline,
-    shader_cursor::ShaderCursor,
-    shader_object::{ShaderObject, ShaderObjectLayout},
-    shaders::SlangCompiler,
-    swapchain_resources::SwapchainImage,
+use crate::application::{
+    assets::{
+        asset_traits::RHIInterface,
+        sampler::{AddressMode, SamplerDesc},
+    },
+    rhi::{
+        VKRHI,
+        pipeline::compute_pipeline,
+        shader_cursor::ShaderCursor,
+        shader_object::{ShaderObject, ShaderObjectLayout},
+        shaders::SlangCompiler,
+        swapchain_resources::SwapchainImage,
+    },
 };
 
 /// Post processing pass
@@ -74,11 +80,9 @@ impl PostProcessPass {
                 shader_object_layout.pipeline_layout().clone(),
             );
 
-        let sampler = Sampler::new(
-            rhi.device().clone(),
-            SamplerCreateInfo::simple_repeat_linear_no_mipmap(),
-        )
-        .unwrap();
+        let sampler = rhi
+            .resource_manager()
+            .sampler(&SamplerDesc::linear_clamp().with_address_mode(AddressMode::Repeat));
 
         Self::write_framebuffer_descriptors(shader_object.clone(), source, target, sampler.clone());
 
@@ -0,0 +1,286 @@
+mod residency;
+
+use std::{collections::HashMap, mem, sync::Arc};
+
+use asset_system::{assets::Asset, resource_management::ResourceId};
+use egui_winit_vulkano::{egui, egui::Ui};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::PrimaryCommandBufferAbstract,
+    image::view::ImageView,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
+    sync::{GpuFuture, future::FenceSignalFuture},
+};
+
+use crate::application::{
+    assets::{asset_traits::RHIInterface, texture::Texture},
+    renderer::{
+        texture_streaming::residency::{TextureResidency, plan_residency},
+        visibility_buffer_data::VisibilityBufferGlobalData,
+    },
+    rhi::{
+        VKRHI,
+        rhi_assets::{RHIHandle, RHIResourceManager, vulkan_texture::VKTexture},
+        shader_cursor::ShaderCursor,
+    },
+};
+
+/// Streams the mips of the bindless textures in and out of GPU memory.
+/// Textures start with the coarse levels their VKTexture uploads. The shading records the level it needs of every texture it samples,
+/// from which the streamer plans the residency of the next frames and uploads new images with the planned levels.
+/// Levels the bound image already holds are copied from it, only finer ones are read from the texture asset.
+/// An image replaces the previous one in the texture array only once its upload has finished, so sampling never sees missing levels.
+/// Textures are followed by their handles, so textures that are added or move to another index are picked up every frame.
+pub struct TextureStreamer {
+    /// Feedback of the shading for every texture of the texture array, see textures.slang
+    feedback: Subbuffer<[u32]>,
+    /// Streamed textures in the order of the texture array in the last frame
+    textures: Vec<StreamedTexture>,
+    /// Residency of every texture, parallel to the textures
+    residencies: Vec<TextureResidency>,
+    /// Uploads that have not finished yet. No new uploads are started until they have.
+    pending: Option<PendingUploads>,
+    frame: u64,
+}
+
+/// Texture with the image that is bound for it in the texture array
+struct StreamedTexture {
+    handle: RHIHandle<VKTexture>,
+    /// Holds the levels from the resident mip of the residency on
+    image: Arc<ImageView>,
+}
+
+/// Images in flight to the GPU with the id of their texture and their first level
+struct PendingUploads {
+    future: FenceSignalFuture<Box<dyn GpuFuture>>,
+    images: Vec<(ResourceId, u32, Arc<ImageView>)>,
+}
+
+impl TextureStreamer {
+    /// Bytes of new levels that are uploaded per frame at most, which keeps streaming from stalling the frame.
+    /// Evictions are not limited, since they free memory.
+    const UPLOAD_SIZE_PER_FRAME: u64 = 32 << 20;
+
+    pub fn new(rhi: &VKRHI, global_data: &VisibilityBufferGlobalData) -> Self {
+        let feedback = Buffer::from_iter(
+            rhi.buffer_allocator().clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..BufferCreateInfo::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..AllocationCreateInfo::default()
+            },
+            (0..VisibilityBufferGlobalData::MAX_TEXTURES).map(|_| 0u32),
+        )
+        .unwrap();
+        ShaderCursor::new(global_data.shader_object().clone())
+            .field("gTextureFeedback")
+            .unwrap()
+            .write_buffer(feedback.clone());
+
+        Self {
+            feedback,
+            textures: Vec::new(),
+            residencies: Vec::new(),
+            pending: None,
+            frame: 0,
+        }
+    }
+
+    fn residency(texture: &VKTexture) -> TextureResidency {
+        TextureResidency::new(texture.mip_sizes(), texture.size(), texture.first_mip())
+    }
+
+    /// Binds the uploads that have finished, reads the feedback of the previous frame and starts the uploads for the new residency.
+    /// The previous frame must be finished.
+    pub fn update(&mut self, rhi: &VKRHI, global_data: &VisibilityBufferGlobalData, budget: u64) {
+        self.frame += 1;
+        // The feedback refers to the indices of the previous frame
+        {
+            let mut feedback = self.feedback.write().unwrap();
+            for (index, value) in feedback.iter_mut().enumerate() {
+                if let Some(residency) = self.residencies.get_mut(index) {
+                    residency.request(*value, self.frame);
+                }
+                *value = 0;
+            }
+        }
+        let resources = rhi.resource_manager();
+        self.track_textures(&resources, global_data);
+
+        if let Some(pending) = &self.pending {
+            if !pending.future.is_signaled().unwrap() {
+                return;
+            }
+            // Dropping the uploads frees their staging buffers and scratch images
+            for (id, first_mip, image) in self.pending.take().unwrap().images {
+                let Some(index) = self.position(id) else {
+                    continue;
+                };
+                global_data.refresh_texture(index, &image);
+                self.residencies[index].resident_mip = first_mip;
+                self.textures[index].image = image;
+            }
+        }
+
+        let targets = plan_residency(&self.residencies, budget, self.frame);
+        let mut cb = rhi
+            .command_buffer_interface()
+            .primary_command_buffer(rhi.queue_family_indices().graphics_family);
+        let mut upload_size = 0;
+        let mut images = Vec::new();
+        for (index, first_mip) in targets.into_iter().enumerate() {
+            let residency = &mut self.residencies[index];
+            let streamed = &mut self.textures[index];
+            let Some(texture) = streamed.handle.get(&resources) else {
+                continue;
+            };
+            if first_mip == residency.resident_mip {
+                continue;
+            }
+            // The image of the texture holds the tail, so it can be bound again right away
+            if first_mip == texture.first_mip() {
+                global_data.refresh_texture(index, texture.image_view());
+                residency.resident_mip = first_mip;
+                streamed.image = texture.image_view().clone();
+                continue;
+            }
+            let size = residency.size(first_mip);
+            let raises = first_mip < residency.resident_mip;
+            if raises && upload_size > 0 && upload_size + size > Self::UPLOAD_SIZE_PER_FRAME {
+                continue;
+            }
+            let Some(source) = resources
+                .asset_of::<Texture, _>(&streamed.handle)
+                .and_then(|asset| resources.asset_manager().get(&asset))
+            else {
+                continue;
+            };
+            match texture.record_resident(
+                &*source,
+                &streamed.image,
+                residency.resident_mip,
+                first_mip,
+                rhi,
+                &mut cb,
+            ) {
+                Ok(image) => images.push((streamed.handle.id(), first_mip, image)),
+                Err(error) => {
+                    println!("{}. {} keeps its resident levels.", error, source.name());
+                    continue;
+                }
+            }
+            if raises {
+                upload_size += size;
+            }
+        }
+        if images.is_empty() {
+            return;
+        }
+
+        let future = cb
+            .build()
+            .unwrap()
+            .execute(rhi.queues().graphics_queue.clone())
+            .unwrap()
+            .boxed()
+            .then_signal_fence_and_flush()
+            .unwrap();
+        self.pending = Some(PendingUploads { future, images });
+    }
+
+    /// Follows the textures in the order of the texture array, which changes when textures are added or removed.
+    /// Textures that are new or moved to another index get their image bound there.
+    fn track_textures(
+        &mut self,
+        resources: &RHIResourceManager,
+        global_data: &VisibilityBufferGlobalData,
+    ) {
+        let previous_count = self.textures.len();
+        let mut previous = mem::take(&mut self.textures)
+            .into_iter()
+            .zip(mem::take(&mut self.residencies))
+            .enumerate()
+            .map(|(index, (texture, residency))| (texture.handle.id(), (index, texture, residency)))
+            .collect::<HashMap<_, _>>();
+        let current = resources
+            .handle_iterator::<VKTexture>()
+            .take(VisibilityBufferGlobalData::MAX_TEXTURES as usize)
+            .enumerate();
+        for (index, (handle, texture)) in current {
+            let (streamed, residency) = match previous.remove(&handle.id()) {
+                Some((previous_index, streamed, residency)) => {
+                    if previous_index != index {
+                        global_data.refresh_texture(index, &streamed.image);
+                    }
+                    (streamed, residency)
+                }
+                None => {
+                    global_data.refresh_texture(index, texture.image_view());
+                    let streamed = StreamedTexture {
+                        handle,
+                        image: texture.image_view().clone(),
+                    };
+                    (streamed, Self::residency(texture))
+                }
+            };
+            self.textures.push(streamed);
+            self.residencies.push(residency);
+        }
+        // The slots that removed textures leave at the end release their images
+        for index in self.textures.len()..previous_count {
+            global_data.refresh_texture(index, global_data.unbound_texture());
+        }
+    }
+
+    /// Index of the texture in the texture array
+    fn position(&self, id: ResourceId) -> Option<usize> {
+        self.textures
+            .iter()
+            .position(|texture| texture.handle.id() == id)
+    }
+
+    /// Starts a reloaded texture over with the image of its new proxy, which must already be bound
+    pub fn reset(&mut self, handle: &RHIHandle<VKTexture>, texture: &VKTexture) {
+        let Some(index) = self.position(handle.id()) else {
+            return;
+        };
+        self.residencies[index] = Self::residency(texture);
+        self.textures[index].image = texture.image_view().clone();
+        if let Some(pending) = &mut self.pending {
+            pending.images.retain(|(id, ..)| *id != handle.id());
+        }
+    }
+
+    /// Bytes of all images that are bound in the texture array
+    pub fn resident_size(&self) -> u64 {
+        self.residencies
+            .iter()
+            .map(TextureResidency::resident_size)
+            .sum()
+    }
+}
+
+pub struct StreamingSettings {
+    /// GPU memory that the streamed textures may take in MiB. The coarse levels of every texture stay even beyond it.
+    pub budget_mib: u32,
+}
+
+impl Default for StreamingSettings {
+    fn default() -> Self {
+        Self { budget_mib: 512 }
+    }
+}
+
+impl StreamingSettings {
+    pub fn budget(&self) -> u64 {
+        (self.budget_mib as u64) << 20
+    }
+
+    pub fn draw_gui(&mut self, gui: &mut Ui) {
+        gui.add(egui::Slider::new(&mut self.budget_mib, 16..=4096).text("Budget (MiB)"));
+    }
+}
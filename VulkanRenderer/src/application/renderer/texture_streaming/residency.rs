@@ -0,0 +1,180 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+/// Number of frames a texture keeps the detail it requested after it was last sampled.
+/// This keeps textures that flicker in and out of view from being streamed over and over.
+pub const RETAIN_FRAMES: u64 = 120;
+
+/// Mips of a streamed texture that are resident on the GPU and those the shading asked for
+#[derive(Clone, PartialEq, Debug)]
+pub struct TextureResidency {
+    /// Bytes of every level, largest first
+    mip_sizes: Vec<u64>,
+    /// Log2 of the texels along the larger axis of the top level
+    top_detail: u32,
+    /// The coarsest residency. These levels never leave the GPU.
+    tail_mip: u32,
+    /// First level of the resident image
+    pub resident_mip: u32,
+    /// Finest level the shading asked for when the texture was last sampled
+    requested_mip: u32,
+    last_used_frame: Option<u64>,
+}
+
+impl TextureResidency {
+    /// Texture of the size whose levels from tail_mip on are resident
+    pub fn new(mip_sizes: Vec<u64>, size: [u32; 2], tail_mip: u32) -> Self {
+        Self {
+            mip_sizes,
+            top_detail: size[0].max(size[1]).max(1).ilog2(),
+            tail_mip,
+            resident_mip: tail_mip,
+            requested_mip: tail_mip,
+            last_used_frame: None,
+        }
+    }
+
+    /// Bytes of an image with the levels from first_mip on
+    pub fn size(&self, first_mip: u32) -> u64 {
+        self.mip_sizes[first_mip as usize..].iter().sum()
+    }
+
+    pub fn resident_size(&self) -> u64 {
+        self.size(self.resident_mip)
+    }
+
+    /// Records the feedback of the shading, which holds the log2 of the texels needed along the larger axis plus one.
+    /// Zero means that the texture was not sampled.
+    pub fn request(&mut self, feedback: u32, frame: u64) {
+        if feedback == 0 {
+            return;
+        }
+        // Coarser levels than the top one round down to the next power of two, so they are picked conservatively
+        self.requested_mip = self.top_detail.saturating_sub(feedback - 1);
+        self.last_used_frame = Some(frame);
+    }
+
+    /// First level the texture should have without a budget
+    fn wanted_mip(&self, frame: u64) -> u32 {
+        match self.last_used_frame {
+            Some(used) if frame - used <= RETAIN_FRAMES => self.requested_mip.min(self.tail_mip),
+            _ => self.tail_mip,
+        }
+    }
+
+    /// Orders the textures that give up a level first: the longest unused, then the one that frees the most
+    fn eviction_priority(&self, first_mip: u32) -> (Reverse<Option<u64>>, u64) {
+        (
+            Reverse(self.last_used_frame),
+            self.mip_sizes[first_mip as usize],
+        )
+    }
+}
+
+/// First level every texture should have resident.
+/// Textures get the detail they asked for as long as the total fits into the budget.
+/// Beyond it, textures give up levels by their eviction priority. The tails stay even if they exceed the budget.
+pub fn plan_residency(textures: &[TextureResidency], budget: u64, frame: u64) -> Vec<u32> {
+    let mut targets = textures
+        .iter()
+        .map(|texture| texture.wanted_mip(frame))
+        .collect::<Vec<_>>();
+    let mut total = textures
+        .iter()
+        .zip(&targets)
+        .map(|(texture, first_mip)| texture.size(*first_mip))
+        .sum::<u64>();
+
+    let mut candidates = targets
+        .iter()
+        .enumerate()
+        .filter(|(index, first_mip)| **first_mip < textures[*index].tail_mip)
+        .map(|(index, first_mip)| (textures[index].eviction_priority(*first_mip), index))
+        .collect::<BinaryHeap<_>>();
+    while total > budget {
+        let Some((_, index)) = candidates.pop() else {
+            break;
+        };
+        let texture = &textures[index];
+        total -= texture.mip_sizes[targets[index] as usize];
+        targets[index] += 1;
+        if targets[index] < texture.tail_mip {
+            candidates.push((texture.eviction_priority(targets[index]), index));
+        }
+    }
+    targets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Square RGBA8 texture with all mips, whose tail starts at 64 texels
+    fn texture(size: u32) -> TextureResidency {
+        let mips = (0..=size.ilog2())
+            .map(|level| ((size >> level) as u64).pow(2) * 4)
+            .collect::<Vec<_>>();
+        let tail_mip = size.ilog2().saturating_sub(6);
+        TextureResidency::new(mips, [size, size], tail_mip)
+    }
+
+    #[test]
+    fn feedback_selects_the_level_with_enough_texels() {
+        let mut texture = texture(1024);
+        assert_eq!(texture.resident_mip, 4);
+        // 256 texels
+        texture.request(9, 0);
+        assert_eq!(plan_residency(&[texture.clone()], u64::MAX, 0), [2]);
+        // More texels than the top level has
+        texture.request(20, 1);
+        assert_eq!(plan_residency(&[texture.clone()], u64::MAX, 1), [0]);
+        // Never coarser than the tail
+        texture.request(1, 2);
+        assert_eq!(plan_residency(&[texture], u64::MAX, 2), [4]);
+    }
+
+    #[test]
+    fn unsampled_textures_fall_back_to_their_tail() {
+        let mut texture = texture(512);
+        texture.request(0, 0);
+        assert_eq!(plan_residency(&[texture.clone()], u64::MAX, 0), [3]);
+        texture.request(10, 0);
+        assert_eq!(
+            plan_residency(&[texture.clone()], u64::MAX, RETAIN_FRAMES),
+            [0]
+        );
+        assert_eq!(plan_residency(&[texture], u64::MAX, RETAIN_FRAMES + 1), [3]);
+    }
+
+    #[test]
+    fn the_budget_evicts_stale_textures_first() {
+        let mut stale = texture(256);
+        stale.request(9, 0);
+        let mut recent = texture(256);
+        recent.request(9, 10);
+        let textures = [stale, recent];
+        let full = textures[0].size(0);
+        assert_eq!(plan_residency(&textures, 2 * full, 10), [0, 0]);
+        // The stale texture gives up its top level before the recent one loses anything
+        assert_eq!(plan_residency(&textures, 2 * full - 1, 10), [1, 0]);
+        let budget = textures[0].size(2) + textures[1].size(1);
+        assert_eq!(plan_residency(&textures, budget, 10), [2, 1]);
+    }
+
+    #[test]
+    fn the_budget_evicts_the_largest_levels_first() {
+        let mut small = texture(128);
+        small.request(9, 0);
+        let mut large = texture(256);
+        large.request(9, 0);
+        let textures = [small, large];
+        let budget = textures[0].size(0) + textures[1].size(1);
+        assert_eq!(plan_residency(&textures, budget, 0), [0, 1]);
+    }
+
+    #[test]
+    fn tails_stay_resident_over_budget() {
+        let mut texture = texture(1024);
+        texture.request(11, 0);
+        assert_eq!(plan_residency(&[texture], 0, 0), [4]);
+    }
+}
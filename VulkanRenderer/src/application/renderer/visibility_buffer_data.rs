@@ -4,6 +4,8 @@ use std::{
     sync::{Arc, RwLock},
 };
 
+use asset_system::resource_management::ResourceId;
+use glam::Mat4;
 use shader_slang::{Blob, ComponentType, structs::specialization_arg::SpecializationArg};
 use vulkano::{
     DeviceAddress, DeviceSize, ValidationError,
@@ -14,7 +16,7 @@ use vulkano::{
     },
     device_generated_commands::{ComputePipelineIndirectBufferInfo, IndirectCommandsLayout},
     format::Format,
-    image::{ImageAspects, ImageUsage},
+    image::{ImageAspects, ImageUsage, sampler::Sampler, view::ImageView},
     memory::{
         DeviceAlignment,
         allocator::{AllocationCreateInfo, DeviceLayout, MemoryTypeFilter},
@@ -28,17 +30,28 @@ use vulkano::{
 };
 
 use crate::application::{
-    assets::asset_traits::{Index, RHIInterface, RHIModelInterface, Vertex},
+    assets::{
+        asset_traits::{
+            CompactVertex, Index, MeshLod, Meshlet, RHICubeTextureInterface, RHIInterface,
+            RHIModelInterface, RHITextureInterface, SkinVertex, Vertex, VertexFormat,
+        },
+        cube_texture::CubeTexture,
+        material_instance::TextureSlot,
+        sampler::SamplerDesc,
+        texture::{Texture, TextureSettings},
+    },
     renderer::visibility_buffer_generation::{
         ComputeDispatchParameter, PipelineBindParameter, VisBufferPushConstant,
     },
     rhi::{
         VKRHI,
-        buffer::buffer_from_slice,
+        buffer::{buffer_from_slice, copy_slice_to_buffer_staged},
         pipeline::compute_pipeline,
         rhi_assets::{
+            RHIHandle, RHIResourceManager, RemovedProxies, vulkan_cube_texture::VKCubeTexture,
             vulkan_material::VKMaterial, vulkan_material_instance::VKMaterialInstance,
-            vulkan_mesh::VKMesh, vulkan_model::VKModel,
+            vulkan_mesh::VKMesh, vulkan_model::VKModel, vulkan_scene::VKScene,
+            vulkan_texture::VKTexture,
         },
         shader_cursor::ShaderCursor,
         shader_object::{ShaderObject, ShaderObjectLayout},
@@ -119,39 +132,100 @@ pub struct VisibilityBufferData {
 /// This is essentially a GPU representation of the entire scene.
 #[derive(Clone)]
 pub struct VisibilityBufferGlobalData {
-    /// All instances in the scene, grouped by mesh to allow using multi draw indirect
+    /// All instances in the scene, grouped by mesh. The CPU writes the transforms of animated instances every frame.
     pub instances: Subbuffer<[InstanceData]>,
+    /// Model of each instance, parallel to the instance buffer
+    instance_models: Vec<RHIHandle<VKModel>>,
+    /// Level of detail that each instance is drawn with in the current frame
+    pub instance_lods: Subbuffer<[u32]>,
     /// All materials in the scene
     pub materials: Subbuffer<[MaterialData]>,
     /// All material instances in the scene
     pub material_instances: Subbuffer<[MaterialInstanceData]>,
-    /// All meshes in the scene
+    /// All levels of detail of all meshes in the scene. The levels of a mesh are consecutive, starting with the full resolution.
     pub meshes: Subbuffer<[MeshData]>,
     /// Global index buffer
     pub indices: Subbuffer<[u32]>,
     /// Global vertex buffer
     pub vertices: Subbuffer<[Vertex]>,
+    pub compact_vertices: Subbuffer<[CompactVertex]>,
+    /// Global meshlet buffer. Meshlets are the clusters in which meshes are culled and drawn.
+    pub clusters: Subbuffer<[Meshlet]>,
     /// Buffer with frequently changing data
     pub mutating_data: Subbuffer<MutatingData>,
-    /// Collection of all pipelines that are used for indirect shading
-    pub pipelines: Vec<Arc<ComputePipeline>>,
+    /// Collection of all pipelines that are used for indirect shading.
+    /// Pipelines are swapped when their material is recompiled.
+    pub pipelines: Arc<RwLock<Vec<Arc<ComputePipeline>>>>,
     /// Common shader object to all pipelines
     shader_object: Arc<ShaderObject>,
     /// Number of materials
     material_count: u32,
-    /// Buffer of draw indexed indirect commands to be used for rasterizing the visibility buffer with multi draw indirect
-    pub draw_indirect_commands: Subbuffer<[DrawIndexedIndirectCommand]>,
+    /// Buffer of draw indexed indirect commands to be used for rasterizing the visibility buffer with multi draw indirect.
+    /// The cluster culling writes one command per visible cluster.
+    pub cluster_draws: Subbuffer<[DrawIndexedIndirectCommand]>,
+    /// Number of cluster draws in the current frame. Draws beyond the capacity of the buffer are dropped.
+    pub cluster_draw_count: Subbuffer<u32>,
+    /// Instance and cluster of each cluster draw, bound as the instance buffer of the rasterizer
+    pub drawn_clusters: Subbuffer<[[u32; 2]]>,
+    /// Source and output ranges of every skinned instance. Holds one empty entry if there are none.
+    pub skinned_instances: Subbuffer<[SkinnedInstanceData]>,
+    /// Global skin buffer with the joints and weights of all skinned meshes
+    pub skin_vertices: Subbuffer<[SkinVertex]>,
+    /// Joint matrices of all skinned instances, written by the CPU every frame
+    pub joint_matrices: Subbuffer<[[[f32; 4]; 4]]>,
+    /// CPU side of the skinned instances, parallel to the skinned instance buffer
+    skinned: Vec<SkinnedInstance>,
+    /// Descriptions of the samplers in the bindless sampler array, the first one is the default
+    samplers: Arc<RwLock<Vec<SamplerDesc>>>,
+    /// For each mesh, the index of its first entry in the mesh buffer
+    mesh_entries: Vec<u32>,
+    /// Proxy of each mesh, parallel to mesh_entries. Indices into the resource manager change when meshes are removed.
+    mesh_ids: Vec<ResourceId>,
+    /// Number of instances in the scene
+    instance_count: u32,
+    /// Bound in the texture arrays where there is no texture
+    unbound_texture: Arc<ImageView>,
+    unbound_cube_texture: Arc<ImageView>,
 }
 
 #[derive(Copy, Clone, BufferContents)]
 #[repr(C)]
 pub struct InstanceData {
+    /// Entry of the full resolution level of the mesh in the mesh buffer
     pub mesh_index: u32,
     pub material_index: u32,
     pub model_transform: [[f32; 4]; 4],
     pub inverse_transpose_model_transform: [[f32; 4]; 4],
 }
 
+/// Instance whose mesh is skinned into its own range of the vertex buffer before rasterization
+#[derive(Clone)]
+struct SkinnedInstance {
+    model: RHIHandle<VKModel>,
+    mesh_index: usize,
+    /// Range of the vertex buffer the skinned vertices are written to
+    vertices: Subbuffer<[Vertex]>,
+    first_joint: u32,
+    joint_count: u32,
+    /// Entry of the full resolution level of the instance in the mesh buffer.
+    /// The levels of the instance use the index and cluster ranges of the mesh, but read the skinned vertices.
+    first_entry: u32,
+}
+
+#[derive(Copy, Clone, BufferContents)]
+#[repr(C)]
+pub struct SkinnedInstanceData {
+    /// Entry of the full resolution level of the mesh that is skinned
+    pub source_mesh: u32,
+    pub first_skin_vertex: u32,
+    /// Joint matrices of the instance in the joint matrix buffer
+    pub first_joint: u32,
+    pub joint_count: u32,
+    /// Range of the vertex buffer the skinned vertices are written to. Empty if the instance is not skinned.
+    pub first_output_vertex: u32,
+    pub vertex_count: u32,
+}
+
 #[derive(Copy, Clone, BufferContents)]
 #[repr(C)]
 pub struct MaterialData {
@@ -162,6 +236,20 @@ pub struct MaterialData {
 #[repr(C)]
 pub struct MaterialInstanceData {
     pub material_index: u32,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub normal_scale: f32,
+    /// Linear RGBA
+    pub base_color: [f32; 4],
+    /// Linear RGB radiance
+    pub emissive: [f32; 3],
+    pub occlusion_strength: f32,
+    /// Index of the texture of each TextureSlot in the bindless texture array, NO_TEXTURE if the slot is empty
+    pub textures: [u32; TextureSlot::ALL.len()],
+    /// Index in the bindless cube texture array, NO_TEXTURE without an environment map
+    pub environment_texture: u32,
+    /// Index in the bindless sampler array
+    pub sampler_index: u32,
 }
 
 #[derive(Copy, Clone, BufferContents)]
@@ -171,6 +259,23 @@ pub struct MeshData {
     pub primitive_count: u32,
     pub first_vertex: u32,
     pub vertex_count: u32,
+    /// Object space distance by which this level deviates from the full resolution
+    pub lod_error: f32,
+    /// Number of levels of the mesh
+    pub lod_count: u32,
+    /// Clusters of this level in the global meshlet buffer
+    pub first_cluster: u32,
+    pub cluster_count: u32,
+    /// Bounding sphere of the mesh in object space
+    pub bounds_center: [f32; 3],
+    pub bounds_radius: f32,
+    /// VertexFormat of the mesh, which selects the vertex buffer
+    pub vertex_format: u32,
+    /// Whether the vertices are skinned every frame, which makes the bounds unreliable
+    pub skinned: u32,
+    /// Decodes compact positions: offset + position * scale
+    pub position_offset: [f32; 3],
+    pub position_scale: [f32; 3],
 }
 
 #[derive(Copy, Clone, BufferContents)]
@@ -179,17 +284,26 @@ pub struct MutatingData {
     pub screen_size: [u32; 2],
     pub view_matrix: [[f32; 4]; 4],
     pub view_position: [f32; 3],
+    /// Converts object space errors at a distance of one into pixels
+    pub lod_scale: f32,
+    /// Largest error in pixels that a level of detail may have on screen
+    pub lod_error_threshold: f32,
+    /// Planes of the view frustum in world space, pointing inwards with normalized normals
+    pub frustum_planes: [[f32; 4]; 6],
 }
 
 #[derive(Copy, Clone, BufferContents)]
 #[repr(C)]
 pub struct VisBufferGlobalDataPointers {
     instances: DeviceAddress,
+    instance_lods: DeviceAddress,
     materials: DeviceAddress,
     material_instances: DeviceAddress,
     meshes: DeviceAddress,
     index_buffer: DeviceAddress,
     vertex_buffer: DeviceAddress,
+    compact_vertex_buffer: DeviceAddress,
+    clusters: DeviceAddress,
     mutating_data: DeviceAddress,
 }
 
@@ -393,52 +507,130 @@ impl VisibilityBufferData {
 }
 
 impl VisibilityBufferGlobalData {
+    /// Capacity of the cluster draws per frame
+    pub const MAX_CLUSTER_DRAWS: u32 = 1 << 20;
+    /// Size of the bindless texture array. Textures beyond it are not sampled.
+    pub const MAX_TEXTURES: u32 = 1024;
+    /// Size of the bindless cube texture array
+    pub const MAX_CUBE_TEXTURES: u32 = 64;
+    /// Size of the bindless sampler array. Material instances beyond it use the default sampler.
+    pub const MAX_SAMPLERS: u32 = 64;
+    /// Texture index of material instances without a texture in a slot
+    pub const NO_TEXTURE: u32 = u32::MAX;
+
     pub fn new(rhi: &VKRHI, mutating_data: Subbuffer<MutatingData>) -> Self {
+        let mut skinned_vertices = Self::allocate_skinned_vertices(rhi);
+        // Every element of the texture array must be written, the ones without a texture get this one
+        let unbound_texture = VKTexture::create(
+            &Texture::placeholder("Unbound Texture".into(), TextureSettings::default()),
+            rhi,
+            &mut rhi.resource_manager_mut(),
+        );
+        let unbound_cube_texture = VKCubeTexture::create(
+            &CubeTexture::placeholder("Unbound Cube Texture".into()),
+            rhi,
+            &mut rhi.resource_manager_mut(),
+        );
         let resources = rhi.resource_manager();
 
-        let mut instances = resources
-            .resource_iterator::<VKModel>()
-            .unwrap()
-            .map(|instance| InstanceData {
-                mesh_index: resources.index(instance.mesh().id()).unwrap() as u32,
-                material_index: resources.index(instance.material().id()).unwrap() as u32,
-                model_transform: instance.transform().to_cols_array_2d(),
-                inverse_transpose_model_transform: instance
-                    .transform()
-                    .inverse()
-                    .transpose()
-                    .to_cols_array_2d(),
+        let (mesh_ids, meshes): (Vec<_>, Vec<_>) = resources
+            .handle_iterator::<VKMesh>()
+            .map(|(handle, mesh)| (handle.id(), mesh))
+            .unzip();
+        let mesh_entries = meshes
+            .iter()
+            .scan(0u32, |entry, mesh| {
+                let first_entry = *entry;
+                *entry += mesh.lods().len() as u32;
+                Some(first_entry)
             })
             .collect::<Vec<_>>();
 
-        instances.sort_unstable_by_key(|instance| instance.mesh_index);
-
-        let draw_indirect_commands = instances
-            .chunk_by(|left, right| left.mesh_index == right.mesh_index)
-            .scan(0u32, |offset, slice| {
-                let mesh = resources
-                    .resource_iterator::<VKMesh>()
-                    .unwrap()
-                    .nth(slice[0].mesh_index as usize)
-                    .unwrap();
-                let first_instance = *offset;
-                *offset += slice.len() as u32;
-                Some(DrawIndexedIndirectCommand {
-                    index_count: mesh.index_size() as u32,
-                    instance_count: slice.len() as u32,
-                    first_index: mesh.index_offset() as u32,
-                    vertex_offset: mesh.vertex_offset() as u32,
-                    first_instance,
-                })
+        let mut mesh_data = meshes
+            .iter()
+            .flat_map(|mesh| Self::mesh_data(mesh, mesh.lods().len()))
+            .collect::<Vec<_>>();
+
+        // Skinned instances get their own entries after those of the meshes
+        let mut skinned = Vec::new();
+        let mut joint_count = 0;
+        let mut instances = resources
+            .handle_iterator::<VKModel>()
+            .map(|(handle, instance)| {
+                let mesh_index = resources.index(instance.mesh().id()).unwrap();
+                let mut mesh_entry = mesh_entries[mesh_index];
+                if let Some(vertices) = skinned_vertices.remove(&handle.id()) {
+                    let mesh = meshes[mesh_index];
+                    let skinned_instance = SkinnedInstance {
+                        model: handle.clone(),
+                        mesh_index,
+                        vertices,
+                        first_joint: joint_count,
+                        joint_count: mesh.joint_count() as u32,
+                        first_entry: mesh_data.len() as u32,
+                    };
+                    joint_count += skinned_instance.joint_count;
+                    mesh_data.extend(Self::skinned_mesh_data(
+                        mesh,
+                        mesh.lods().len(),
+                        &skinned_instance,
+                    ));
+                    mesh_entry = skinned_instance.first_entry;
+                    skinned.push(skinned_instance);
+                }
+                let data = InstanceData {
+                    mesh_index: mesh_entry,
+                    material_index: resources.index(instance.material().id()).unwrap() as u32,
+                    model_transform: instance.transform().to_cols_array_2d(),
+                    inverse_transpose_model_transform: instance
+                        .transform()
+                        .inverse()
+                        .transpose()
+                        .to_cols_array_2d(),
+                };
+                (handle, data)
             })
             .collect::<Vec<_>>();
 
+        instances.sort_unstable_by_key(|(_, instance)| instance.mesh_index);
+        let (instance_models, instances): (Vec<_>, Vec<_>) = instances.into_iter().unzip();
+
         let first_linked = Self::create_linked_program(
             rhi,
             resources.resource_iterator().unwrap().next().unwrap(),
-        );
+        )
+        .unwrap();
         let shader_object = Self::create_shader_object(rhi, first_linked);
 
+        let textures = resources
+            .resource_iterator::<VKTexture>()
+            .into_iter()
+            .flatten()
+            .map(|texture| texture.image_view().clone())
+            .collect::<Vec<_>>();
+        Self::write_textures(
+            &shader_object,
+            "gTextures",
+            Self::MAX_TEXTURES,
+            &textures,
+            unbound_texture.image_view(),
+            None,
+        );
+        let cube_textures = resources
+            .resource_iterator::<VKCubeTexture>()
+            .into_iter()
+            .flatten()
+            .map(|texture| texture.image_view().clone())
+            .collect::<Vec<_>>();
+        Self::write_textures(
+            &shader_object,
+            "gCubeTextures",
+            Self::MAX_CUBE_TEXTURES,
+            &cube_textures,
+            unbound_cube_texture.image_view(),
+            Some(&resources.sampler(&SamplerDesc::linear_clamp())),
+        );
+
         let pipelines = Self::compile_pipelines(rhi, shader_object.pipeline_layout());
 
         let materials = pipelines
@@ -453,49 +645,99 @@ impl VisibilityBufferGlobalData {
             })
             .collect::<Vec<_>>();
 
+        let mut samplers = vec![SamplerDesc::default()];
         let material_instances = resources
             .resource_iterator::<VKMaterialInstance>()
             .unwrap()
-            .map(|instance| MaterialInstanceData {
-                material_index: resources.index(instance.material().id()).unwrap() as u32,
-            })
+            .map(|instance| Self::material_instance_data(&resources, instance, &mut samplers))
             .collect::<Vec<_>>();
+        Self::write_samplers(&shader_object, &resources, &samplers);
 
-        let meshes = resources
-            .resource_iterator::<VKMesh>()
-            .unwrap()
-            .map(|mesh| MeshData {
-                first_primitive: mesh.index_offset() as u32 / 3,
-                primitive_count: mesh.index_size() as u32 / 3,
-                first_vertex: mesh.vertex_offset() as u32,
-                vertex_count: mesh.vertex_size() as u32,
+        // Buffers cannot be empty, so scenes without skinned instances get one entry that skins nothing
+        let mut skinned_instance_data = skinned
+            .iter()
+            .map(|instance| {
+                let mesh = meshes[instance.mesh_index];
+                Self::skinned_instance_data(mesh, mesh_entries[instance.mesh_index], instance)
             })
             .collect::<Vec<_>>();
+        if skinned_instance_data.is_empty() {
+            skinned_instance_data.push(SkinnedInstanceData {
+                source_mesh: 0,
+                first_skin_vertex: 0,
+                first_joint: 0,
+                joint_count: 0,
+                first_output_vertex: 0,
+                vertex_count: 0,
+            });
+        }
+        // Until the first pose is written, the instances are drawn in their bind pose
+        let joint_matrices = Buffer::from_iter(
+            rhi.buffer_allocator().clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::SHADER_DEVICE_ADDRESS | BufferUsage::STORAGE_BUFFER,
+                ..BufferCreateInfo::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..AllocationCreateInfo::default()
+            },
+            (0..joint_count.max(1)).map(|_| Mat4::IDENTITY.to_cols_array_2d()),
+        )
+        .unwrap();
 
         let material_count = materials.len() as u32;
 
-        let draw_indirect_commands = buffer_from_slice(
+        // Read back for the scene statistics
+        let cluster_draw_count = Buffer::new_sized(
             rhi.buffer_allocator().clone(),
-            rhi.command_buffer_interface(),
-            rhi.queues().compute_queue.clone(),
-            draw_indirect_commands.as_slice(),
-            BufferUsage::INDIRECT_BUFFER,
-            MemoryTypeFilter::PREFER_DEVICE,
+            BufferCreateInfo {
+                usage: BufferUsage::INDIRECT_BUFFER
+                    | BufferUsage::STORAGE_BUFFER
+                    | BufferUsage::TRANSFER_DST,
+                ..BufferCreateInfo::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..AllocationCreateInfo::default()
+            },
         )
         .unwrap();
 
+        let instance_count = instances.len() as u32;
         Self {
-            instances: Self::make_buffer(
+            instances: Buffer::from_iter(
+                rhi.buffer_allocator().clone(),
+                BufferCreateInfo {
+                    usage: BufferUsage::SHADER_DEVICE_ADDRESS | BufferUsage::STORAGE_BUFFER,
+                    ..BufferCreateInfo::default()
+                },
+                AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                        | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                    ..AllocationCreateInfo::default()
+                },
+                instances,
+            )
+            .unwrap(),
+            instance_models,
+            instance_lods: Self::make_buffer(
                 rhi,
-                instances.as_slice(),
-                BufferUsage::VERTEX_BUFFER | BufferUsage::SHADER_DEVICE_ADDRESS,
+                vec![0u32; instance_count as usize].as_slice(),
+                BufferUsage::SHADER_DEVICE_ADDRESS,
             ),
             materials: Self::make_buffer(
                 rhi,
                 materials.as_slice(),
                 BufferUsage::SHADER_DEVICE_ADDRESS,
             ),
-            meshes: Self::make_buffer(rhi, meshes.as_slice(), BufferUsage::SHADER_DEVICE_ADDRESS),
+            meshes: Self::make_buffer(
+                rhi,
+                mesh_data.as_slice(),
+                BufferUsage::SHADER_DEVICE_ADDRESS,
+            ),
             material_instances: Self::make_buffer(
                 rhi,
                 material_instances.as_slice(),
@@ -507,20 +749,485 @@ impl VisibilityBufferGlobalData {
                 .clone()
                 .reinterpret(),
             vertices: resources.shared_buffer().unwrap().clone(),
+            compact_vertices: resources.shared_buffer().unwrap().clone(),
+            clusters: resources.shared_buffer().unwrap().clone(),
             mutating_data,
-            pipelines,
+            pipelines: Arc::new(RwLock::new(pipelines)),
             shader_object,
             material_count,
-            draw_indirect_commands,
+            cluster_draws: VisibilityBufferData::create_slice_buffer(
+                rhi,
+                BufferUsage::INDIRECT_BUFFER | BufferUsage::STORAGE_BUFFER,
+                Self::MAX_CLUSTER_DRAWS,
+            ),
+            cluster_draw_count,
+            drawn_clusters: VisibilityBufferData::create_slice_buffer(
+                rhi,
+                BufferUsage::VERTEX_BUFFER | BufferUsage::STORAGE_BUFFER,
+                Self::MAX_CLUSTER_DRAWS,
+            ),
+            skinned_instances: Self::make_buffer(
+                rhi,
+                skinned_instance_data.as_slice(),
+                BufferUsage::SHADER_DEVICE_ADDRESS,
+            ),
+            skin_vertices: resources.shared_buffer().unwrap().clone(),
+            joint_matrices,
+            skinned,
+            samplers: Arc::new(RwLock::new(samplers)),
+            mesh_entries,
+            mesh_ids,
+            instance_count,
+            unbound_texture: unbound_texture.image_view().clone(),
+            unbound_cube_texture: unbound_cube_texture.image_view().clone(),
+        }
+    }
+
+    /// Requests a range of the vertex buffer for every instance of a skinned mesh.
+    /// Instances that do not fit anymore are drawn in their bind pose.
+    fn allocate_skinned_vertices(rhi: &VKRHI) -> HashMap<ResourceId, Subbuffer<[Vertex]>> {
+        let vertex_counts = {
+            let resources = rhi.resource_manager();
+            resources
+                .handle_iterator::<VKModel>()
+                .filter_map(|(handle, model)| {
+                    let mesh = model.mesh().get(&resources)?;
+                    mesh.skin().map(|_| (handle.id(), mesh.vertex_size()))
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut resources = rhi.resource_manager_mut();
+        vertex_counts
+            .into_iter()
+            .filter_map(|(model, vertex_count)| {
+                let vertices = resources.request_from_shared_buffer(vertex_count);
+                if vertices.is_none() {
+                    println!(
+                        "The vertex buffer is full, a skinned model is drawn in its bind pose"
+                    );
+                }
+                Some((model, vertices?))
+            })
+            .collect()
+    }
+
+    /// Entries of the levels of detail of a skinned instance. They read the skinned vertices in the full format.
+    /// If the skin of a reloaded mesh was removed or it has more vertices than the instance has room for,
+    /// the entries of the mesh are used unchanged.
+    fn skinned_mesh_data(
+        mesh: &VKMesh,
+        entry_count: usize,
+        instance: &SkinnedInstance,
+    ) -> Vec<MeshData> {
+        let mesh_data = Self::mesh_data(mesh, entry_count);
+        if !Self::fits_skinned_instance(mesh, instance) {
+            return mesh_data;
+        }
+        mesh_data
+            .into_iter()
+            .map(|data| MeshData {
+                first_vertex: (instance.vertices.offset() as usize / size_of::<Vertex>()) as u32,
+                vertex_format: VertexFormat::Full as u32,
+                skinned: 1,
+                ..data
+            })
+            .collect()
+    }
+
+    fn skinned_instance_data(
+        mesh: &VKMesh,
+        mesh_entry: u32,
+        instance: &SkinnedInstance,
+    ) -> SkinnedInstanceData {
+        SkinnedInstanceData {
+            source_mesh: mesh_entry,
+            first_skin_vertex: mesh.skin_offset().unwrap_or(0) as u32,
+            first_joint: instance.first_joint,
+            joint_count: instance.joint_count.min(mesh.joint_count() as u32),
+            first_output_vertex: (instance.vertices.offset() as usize / size_of::<Vertex>()) as u32,
+            vertex_count: if Self::fits_skinned_instance(mesh, instance) {
+                mesh.vertex_size() as u32
+            } else {
+                0
+            },
+        }
+    }
+
+    fn fits_skinned_instance(mesh: &VKMesh, instance: &SkinnedInstance) -> bool {
+        mesh.skin().is_some() && mesh.vertex_size() <= instance.vertices.len() as usize
+    }
+
+    /// Entries of the levels of detail of a mesh in the mesh buffer. If the mesh has fewer levels than entries,
+    /// its coarsest level fills the remaining ones.
+    fn mesh_data(mesh: &VKMesh, entry_count: usize) -> Vec<MeshData> {
+        let (bounds_center, bounds_radius) = mesh.bounding_sphere();
+        let (position_offset, position_scale) = mesh.position_quantization();
+        (0..entry_count)
+            .map(|entry| {
+                let lod = Self::lod_of_entry(mesh, entry);
+                MeshData {
+                    first_primitive: (mesh.index_offset() as u32 + lod.first_index) / 3,
+                    primitive_count: lod.index_count / 3,
+                    first_vertex: mesh.vertex_offset() as u32,
+                    vertex_count: mesh.vertex_size() as u32,
+                    lod_error: lod.error,
+                    lod_count: entry_count as u32,
+                    first_cluster: mesh.meshlet_offset() as u32 + lod.first_meshlet,
+                    cluster_count: lod.meshlet_count,
+                    bounds_center,
+                    bounds_radius,
+                    vertex_format: mesh.vertex_format() as u32,
+                    skinned: 0,
+                    position_offset,
+                    position_scale,
+                }
+            })
+            .collect()
+    }
+
+    fn lod_of_entry(mesh: &VKMesh, entry: usize) -> MeshLod {
+        mesh.lods()[entry.min(mesh.lods().len() - 1)]
+    }
+
+    /// Uploads the new buffer ranges of a reloaded mesh to all of its entries, including those of its skinned instances.
+    /// The mesh keeps the number of levels of detail it had when the visibility buffer data was created.
+    pub fn refresh_mesh(&self, rhi: &VKRHI, mesh_id: ResourceId, mesh: &VKMesh) {
+        let Some(mesh_index) = self.mesh_ids.iter().position(|id| *id == mesh_id) else {
+            return;
+        };
+        let first_entry = self.mesh_entries[mesh_index] as usize;
+        // Skinned instances have their entries after those of all meshes
+        let entry_count = self.mesh_entries.get(mesh_index + 1).map_or(
+            self.skinned
+                .first()
+                .map_or(self.meshes.len() as usize, |instance| {
+                    instance.first_entry as usize
+                }),
+            |next| *next as usize,
+        ) - first_entry;
+        for (entry, data) in Self::mesh_data(mesh, entry_count).into_iter().enumerate() {
+            Self::write_element(rhi, &self.meshes, first_entry + entry, data);
+        }
+        for (skinned_index, instance) in self.skinned.iter().enumerate() {
+            if instance.mesh_index != mesh_index {
+                continue;
+            }
+            let entries = Self::skinned_mesh_data(mesh, entry_count, instance);
+            for (entry, data) in entries.into_iter().enumerate() {
+                Self::write_element(
+                    rhi,
+                    &self.meshes,
+                    instance.first_entry as usize + entry,
+                    data,
+                );
+            }
+            Self::write_element(
+                rhi,
+                &self.skinned_instances,
+                skinned_index,
+                Self::skinned_instance_data(mesh, first_entry as u32, instance),
+            );
+        }
+    }
+
+    /// Updates the indices of the proxies that moved into the places of removed ones.
+    /// Textures are followed by the texture streamer. The GPU must not use the global data while this runs.
+    pub fn remove_proxies(&self, rhi: &VKRHI, removed: &RemovedProxies) {
+        {
+            let mut pipelines = self.pipelines.write().unwrap();
+            for &(from, to) in &removed.moved_materials {
+                if from == to || from >= self.material_count as usize {
+                    continue;
+                }
+                pipelines[to] = pipelines[from].clone();
+                if !cfg!(feature = "renderdoc_compatibility") {
+                    Self::write_element(
+                        rhi,
+                        &self.materials,
+                        to,
+                        MaterialData {
+                            pipeline_address: PipelineBindParameter::pipeline(&pipelines[to])
+                                .pipeline_address,
+                        },
+                    );
+                }
+            }
+        }
+
+        let resources = rhi.resource_manager();
+        let cube_textures = resources
+            .resource_iterator::<VKCubeTexture>()
+            .into_iter()
+            .flatten()
+            .map(|texture| texture.image_view().clone())
+            .collect::<Vec<_>>();
+        Self::write_textures(
+            &self.shader_object,
+            "gCubeTextures",
+            Self::MAX_CUBE_TEXTURES,
+            &cube_textures,
+            &self.unbound_cube_texture,
+            Some(&resources.sampler(&SamplerDesc::linear_clamp())),
+        );
+        // Material instances refer to textures and materials, instances to material instances by their indices
+        for (index, instance) in resources
+            .resource_iterator::<VKMaterialInstance>()
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            self.refresh_material_instance(rhi, index, instance);
+        }
+        let mut write = self.instances.write().unwrap();
+        for (instance, model) in write.iter_mut().zip(&self.instance_models) {
+            if let Some(index) = model
+                .get(&resources)
+                .and_then(|model| resources.index(model.material().id()))
+            {
+                instance.material_index = index as u32;
+            }
         }
     }
 
+    /// Binds a new image of a texture in place of the previous one, e.g., of a reloaded texture or with other mips resident
+    pub fn refresh_texture(&self, texture_index: usize, image_view: &Arc<ImageView>) {
+        if texture_index >= Self::MAX_TEXTURES as usize {
+            return;
+        }
+        ShaderCursor::new(self.shader_object.clone())
+            .field("gTextures")
+            .unwrap()
+            .at(texture_index as u32)
+            .unwrap()
+            .write_image_view(image_view.clone());
+    }
+
+    /// Fills a bindless texture array of the shading pipelines.
+    /// Arrays of combined image samplers get the sampler, those of plain textures are sampled with gSamplers.
+    fn write_textures(
+        shader_object: &Arc<ShaderObject>,
+        field: &str,
+        capacity: u32,
+        textures: &[Arc<ImageView>],
+        unbound_texture: &Arc<ImageView>,
+        sampler: Option<&Arc<Sampler>>,
+    ) {
+        let cursor = ShaderCursor::new(shader_object.clone())
+            .field(field)
+            .unwrap();
+        for index in 0..capacity {
+            let texture = textures
+                .get(index as usize)
+                .unwrap_or(unbound_texture)
+                .clone();
+            let mut element = cursor.at(index).unwrap();
+            match sampler {
+                Some(sampler) => element.write_image_view_sampler(texture, sampler.clone()),
+                None => element.write_image_view(texture),
+            }
+        }
+    }
+
+    /// Shader data of a material instance. Its sampler is added to the bindless sampler array if it is not in there yet.
+    fn material_instance_data(
+        resources: &RHIResourceManager,
+        instance: &VKMaterialInstance,
+        samplers: &mut Vec<SamplerDesc>,
+    ) -> MaterialInstanceData {
+        let parameters = instance.parameters();
+        MaterialInstanceData {
+            material_index: resources.index(instance.material().id()).unwrap() as u32,
+            metallic: parameters.metallic,
+            roughness: parameters.roughness,
+            normal_scale: parameters.normal_scale,
+            base_color: parameters.base_color,
+            emissive: parameters.emissive,
+            occlusion_strength: parameters.occlusion_strength,
+            textures: TextureSlot::ALL.map(|slot| {
+                instance
+                    .texture(slot)
+                    .and_then(|texture| resources.index(texture.id()))
+                    .filter(|index| *index < Self::MAX_TEXTURES as usize)
+                    .map_or(Self::NO_TEXTURE, |index| index as u32)
+            }),
+            environment_texture: instance
+                .environment_texture()
+                .and_then(|texture| resources.index(texture.id()))
+                .filter(|index| *index < Self::MAX_CUBE_TEXTURES as usize)
+                .map_or(Self::NO_TEXTURE, |index| index as u32),
+            sampler_index: Self::sampler_index(samplers, instance.sampler()),
+        }
+    }
+
+    /// Writes the data of a material instance again, e.g., after its shared sampler was edited.
+    /// A sampler that is not in the bindless sampler array yet is added to it.
+    pub fn refresh_material_instance(
+        &self,
+        rhi: &VKRHI,
+        instance_index: usize,
+        instance: &VKMaterialInstance,
+    ) {
+        if instance_index >= self.material_instances.len() as usize {
+            return;
+        }
+        let resources = rhi.resource_manager();
+        let mut samplers = self.samplers.write().unwrap();
+        let sampler_count = samplers.len();
+        let data = Self::material_instance_data(&resources, instance, &mut samplers);
+        if samplers.len() != sampler_count {
+            Self::write_samplers(&self.shader_object, &resources, &samplers);
+        }
+        Self::write_element(rhi, &self.material_instances, instance_index, data);
+    }
+
+    /// Index of the sampler in the bindless sampler array, which is added if it is not in there yet
+    fn sampler_index(samplers: &mut Vec<SamplerDesc>, desc: &SamplerDesc) -> u32 {
+        if let Some(index) = samplers.iter().position(|sampler| sampler == desc) {
+            return index as u32;
+        }
+        if samplers.len() >= Self::MAX_SAMPLERS as usize {
+            println!(
+                "The bindless sampler array is full with {} samplers. {:?} is replaced by the default sampler.",
+                Self::MAX_SAMPLERS,
+                desc
+            );
+            return 0;
+        }
+        samplers.push(*desc);
+        samplers.len() as u32 - 1
+    }
+
+    /// Fills the bindless sampler array, elements beyond the samplers get the default one
+    fn write_samplers(
+        shader_object: &Arc<ShaderObject>,
+        resources: &RHIResourceManager,
+        samplers: &[SamplerDesc],
+    ) {
+        let cursor = ShaderCursor::new(shader_object.clone())
+            .field("gSamplers")
+            .unwrap();
+        for index in 0..Self::MAX_SAMPLERS {
+            let desc = samplers.get(index as usize).unwrap_or(&samplers[0]);
+            cursor
+                .at(index)
+                .unwrap()
+                .write_sampler(resources.sampler(desc));
+        }
+    }
+
+    /// Binds the samplers again, e.g., after the global anisotropy limit changed
+    pub fn refresh_samplers(&self, rhi: &VKRHI) {
+        Self::write_samplers(
+            &self.shader_object,
+            &rhi.resource_manager(),
+            &self.samplers.read().unwrap(),
+        );
+    }
+
+    /// Copies the joint matrices of the skinned models from the scene.
+    /// Models without matrices keep their previous pose. Matrices beyond the joints of the mesh are ignored.
+    pub fn write_joint_matrices(&self, scene: &VKScene) {
+        if self.skinned.is_empty() {
+            return;
+        }
+        let mut write = self.joint_matrices.write().unwrap();
+        for instance in &self.skinned {
+            let Some(matrices) = scene.joint_matrices(&instance.model) else {
+                continue;
+            };
+            let first_joint = instance.first_joint as usize;
+            let joints = &mut write[first_joint..first_joint + instance.joint_count as usize];
+            for (joint, matrix) in joints.iter_mut().zip(matrices) {
+                *joint = matrix.to_cols_array_2d();
+            }
+        }
+    }
+
+    /// Copies the transforms of the animated models from the scene. Models without a transform keep theirs.
+    pub fn write_instance_transforms(&self, scene: &VKScene) {
+        if !scene.has_transforms() {
+            return;
+        }
+        let mut write = self.instances.write().unwrap();
+        for (instance, model) in write.iter_mut().zip(&self.instance_models) {
+            if let Some(transform) = scene.transform(model) {
+                instance.model_transform = transform.to_cols_array_2d();
+                instance.inverse_transpose_model_transform =
+                    transform.inverse().transpose().to_cols_array_2d();
+            }
+        }
+    }
+
+    /// Removes the cluster draws of the previous frame
+    pub fn reset_cluster_draws(
+        &self,
+        command_buffer: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<(), Box<ValidationError>> {
+        command_buffer
+            .fill_buffer(self.cluster_draw_count.clone().reinterpret(), 0)
+            .map(|_| ())
+    }
+
+    /// Recompiles the pipeline of a material and swaps it in. On failure, the previous pipeline is kept.
+    /// The GPU must not use the pipelines while this runs.
+    pub fn recompile_material(
+        &self,
+        rhi: &VKRHI,
+        material_index: usize,
+        material: &VKMaterial,
+    ) -> Result<(), String> {
+        if material_index >= self.material_count as usize {
+            return Err("The material was created after the visibility buffer data".into());
+        }
+        let spirv = Self::compile_material(rhi, material)?;
+        let create_info = Self::make_pipeline_create_info_from_spirv(
+            rhi,
+            &spirv,
+            self.shader_object.pipeline_layout().clone(),
+        );
+        let pipeline = Self::create_single_pipeline(rhi, create_info)?;
+        if !cfg!(feature = "renderdoc_compatibility") {
+            Self::write_element(
+                rhi,
+                &self.materials,
+                material_index,
+                MaterialData {
+                    pipeline_address: PipelineBindParameter::pipeline(&pipeline).pipeline_address,
+                },
+            );
+        }
+        self.pipelines.write().unwrap()[material_index] = pipeline;
+        Ok(())
+    }
+
+    fn write_element<T: BufferContents + Copy>(
+        rhi: &VKRHI,
+        buffer: &Subbuffer<[T]>,
+        index: usize,
+        value: T,
+    ) {
+        let index = index as DeviceSize;
+        copy_slice_to_buffer_staged(
+            &[value],
+            buffer.clone().slice(index..index + 1),
+            rhi.buffer_allocator().clone(),
+            rhi.command_buffer_interface(),
+            rhi.queues().compute_queue.clone(),
+        )
+        .unwrap();
+    }
+
     pub fn write_to_shader_cursor(&self, shader_cursor: &mut ShaderCursor) {
         shader_cursor
             .field("instances")
             .unwrap()
             //    .write_address(self.instances.device_address().unwrap());
             .write_buffer(self.instances.clone());
+        shader_cursor
+            .field("instanceLods")
+            .unwrap()
+            //    .write_address(self.instance_lods.device_address().unwrap());
+            .write_buffer(self.instance_lods.clone());
         shader_cursor
             .field("materials")
             .unwrap()
@@ -546,6 +1253,16 @@ impl VisibilityBufferGlobalData {
             .unwrap()
             //    .write_address(self.vertices.device_address().unwrap());
             .write_buffer(self.vertices.clone());
+        shader_cursor
+            .field("compactVertexBuffer")
+            .unwrap()
+            //    .write_address(self.compact_vertices.device_address().unwrap());
+            .write_buffer(self.compact_vertices.clone());
+        shader_cursor
+            .field("clusters")
+            .unwrap()
+            //    .write_address(self.clusters.device_address().unwrap());
+            .write_buffer(self.clusters.clone());
         shader_cursor
             .field("mutData")
             .unwrap()
@@ -556,11 +1273,14 @@ impl VisibilityBufferGlobalData {
     pub fn buffer_pointers(&self) -> VisBufferGlobalDataPointers {
         VisBufferGlobalDataPointers {
             instances: self.instances.device_address().unwrap().get(),
+            instance_lods: self.instance_lods.device_address().unwrap().get(),
             materials: self.materials.device_address().unwrap().get(),
             material_instances: self.material_instances.device_address().unwrap().get(),
             meshes: self.meshes.device_address().unwrap().get(),
             index_buffer: self.indices.device_address().unwrap().get(),
             vertex_buffer: self.vertices.device_address().unwrap().get(),
+            compact_vertex_buffer: self.compact_vertices.device_address().unwrap().get(),
+            clusters: self.clusters.device_address().unwrap().get(),
             mutating_data: self.mutating_data.device_address().unwrap().get(),
         }
     }
@@ -581,34 +1301,41 @@ impl VisibilityBufferGlobalData {
         .unwrap()
     }
 
-    fn create_linked_program(rhi: &VKRHI, material: &VKMaterial) -> ComponentType {
+    fn create_linked_program(rhi: &VKRHI, material: &VKMaterial) -> Result<ComponentType, String> {
+        let slang_error = |error: shader_slang::Error| format!("{:?}", error);
         let compiler = rhi.slang_compiler();
         let module = compiler
             .session()
             .load_module("Engine/VisibilityBuffer/visBufferComputeShade")
-            .unwrap();
+            .map_err(slang_error)?;
         let entry = module
             .find_entry_point_by_name(Self::shade_entry_point_name())
-            .unwrap();
+            .ok_or("Missing visibility buffer shading entry point")?;
         let module_component: ComponentType = module.into();
         let material_module = compiler
             .session()
             .load_module(material.module_name())
-            .unwrap();
+            .map_err(slang_error)?;
         let material_module_component: ComponentType = material_module.into();
         let material_reflection = material_module_component
             .layout(0)
-            .unwrap()
+            .map_err(slang_error)?
             .find_type_by_name(material.material_name())
-            .unwrap();
+            .ok_or_else(|| format!("Missing material type {}", material.material_name()))?;
         let composed = compiler
             .session()
             .create_composite_component_type(&[module_component, entry.into()])
-            .unwrap();
+            .map_err(slang_error)?;
         let specialized = composed
             .specialize(&[SpecializationArg::new(material_reflection)])
-            .unwrap();
-        specialized.link().unwrap()
+            .map_err(slang_error)?;
+        specialized.link().map_err(slang_error)
+    }
+
+    fn compile_material(rhi: &VKRHI, material: &VKMaterial) -> Result<Blob, String> {
+        Self::create_linked_program(rhi, material)?
+            .entry_point_code(0, 0)
+            .map_err(|error| format!("{:?}", error))
     }
 
     fn shade_entry_point_name() -> &'static str {
@@ -652,12 +1379,19 @@ impl VisibilityBufferGlobalData {
         let spirv = if let Some(spirv) = spirv_cache.get(&material_key) {
             spirv.clone()
         } else {
-            let linked = Self::create_linked_program(rhi, material);
-            let spirv = linked.entry_point_code(0, 0).unwrap();
+            let spirv = Self::compile_material(rhi, material).unwrap();
             spirv_cache.insert(material_key, spirv.clone());
             spirv
         };
 
+        Self::make_pipeline_create_info_from_spirv(rhi, &spirv, pipeline_layout)
+    }
+
+    fn make_pipeline_create_info_from_spirv(
+        rhi: &VKRHI,
+        spirv: &Blob,
+        pipeline_layout: Arc<PipelineLayout>,
+    ) -> ComputePipelineCreateInfo {
         compute_pipeline()
             .shader(
                 rhi.device().clone(),
@@ -705,6 +1439,72 @@ impl VisibilityBufferGlobalData {
         pipelines
     }
 
+    #[cfg(feature = "renderdoc_compatibility")]
+    fn create_single_pipeline(
+        rhi: &VKRHI,
+        create_info: ComputePipelineCreateInfo,
+    ) -> Result<Arc<ComputePipeline>, String> {
+        ComputePipeline::new(rhi.device().clone(), None, create_info)
+            .map_err(|error| format!("{:?}", error))
+    }
+
+    /// Creates an indirect bindable pipeline with its own indirect metadata buffer
+    #[cfg(not(feature = "renderdoc_compatibility"))]
+    fn create_single_pipeline(
+        rhi: &VKRHI,
+        create_info: ComputePipelineCreateInfo,
+    ) -> Result<Arc<ComputePipeline>, String> {
+        let layout = IndirectCommandsLayout::pipeline_indirect_memory_requirements(
+            rhi.device(),
+            &create_info,
+        )
+        .layout;
+        let indirect_metadata_buffer = Subbuffer::new(
+            Buffer::new(
+                rhi.buffer_allocator().clone(),
+                BufferCreateInfo {
+                    usage: BufferUsage::TRANSFER_DST
+                        | BufferUsage::INDIRECT_BUFFER
+                        | BufferUsage::SHADER_DEVICE_ADDRESS,
+                    ..BufferCreateInfo::default()
+                },
+                AllocationCreateInfo::default(),
+                layout,
+            )
+            .map_err(|error| format!("{:?}", error))?,
+        );
+        let pipeline = ComputePipeline::new(
+            rhi.device().clone(),
+            None,
+            ComputePipelineCreateInfo {
+                indirect_buffer_info: Some(ComputePipelineIndirectBufferInfo::buffer(
+                    indirect_metadata_buffer,
+                )),
+                ..create_info
+            },
+        )
+        .map_err(|error| format!("{:?}", error))?;
+
+        let mut command_buffer = rhi
+            .command_buffer_interface()
+            .primary_command_buffer(rhi.queue_family_indices().compute_family);
+        command_buffer
+            .update_pipeline_indirect_buffer(pipeline.clone())
+            .map_err(|error| format!("{:?}", error))?;
+        now(rhi.device().clone())
+            .then_execute(
+                rhi.queues().compute_queue.clone(),
+                command_buffer.build().unwrap(),
+            )
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        Ok(pipeline)
+    }
+
     #[cfg(not(feature = "renderdoc_compatibility"))]
     fn compile_pipelines(
         rhi: &VKRHI,
@@ -831,7 +1631,19 @@ impl VisibilityBufferGlobalData {
         &self.shader_object
     }
 
+    pub fn unbound_texture(&self) -> &Arc<ImageView> {
+        &self.unbound_texture
+    }
+
     pub fn num_materials(&self) -> u32 {
         self.material_count
     }
+
+    pub fn num_instances(&self) -> u32 {
+        self.instance_count
+    }
+
+    pub fn num_skinned_instances(&self) -> u32 {
+        self.skinned.len() as u32
+    }
 }
@@ -1,5 +1,4 @@
 use std::{
-    mem::offset_of,
     ops::Deref,
     rc::Rc,
     sync::{Arc, RwLock},
@@ -29,10 +28,10 @@ use vulkano::{
 };
 
 use crate::application::{
-    assets::asset_traits::{RHICameraInterface, RHISceneInterface, Vertex},
+    assets::asset_traits::{RHICameraInterface, RHISceneInterface},
     renderer::{
         profiling::{Profiler, ProfilerStage},
-        visibility_buffer_data::{InstanceData, VisibilityBufferData},
+        visibility_buffer_data::{VisibilityBufferData, VisibilityBufferGlobalData},
         visibility_buffer_shading::VisibilityBufferShadePass,
     },
     rhi::{
@@ -84,6 +83,14 @@ struct VisBufferStep {
 
 /// Rasterization step for the visibility buffer
 pub struct VisibilityBufferRasterizer {
+    /// Writes the vertices of the skinned instances in their current pose
+    skinning: VisBufferStep,
+    /// Selects the level of detail of each instance
+    lod_selection: VisBufferStep,
+    /// Culls the clusters of each instance and writes a draw command for every visible one
+    cluster_culling: VisBufferStep,
+    num_instances: u32,
+    num_skinned_instances: u32,
     shader_object: Arc<ShaderObject>,
     pipeline: Arc<GraphicsPipeline>,
     render_pass: Arc<RenderPass>,
@@ -768,7 +775,10 @@ impl VisBufferStep {
 }
 
 impl VisibilityBufferRasterizer {
-    pub fn new(rhi: Rc<VKRHI>, swapchain: &Swapchain, data: &VisibilityBufferData) -> Self {
+    /// Largest workgroup count of a dispatch dimension that every device supports
+    const MAX_DISPATCH_WIDTH: u32 = 65535;
+
+    pub fn new(rhi: Rc<VKRHI>, swapchain: &Swapchain, data: &Arc<VisibilityBufferData>) -> Self {
         let render_pass =
             RenderPassBuilder::build_default_render_pass(rhi.as_ref(), Format::R32G32B32A32_UINT)
                 .build();
@@ -798,6 +808,11 @@ impl VisibilityBufferRasterizer {
             rhi.in_flight_frames() as u32,
             rhi.shader_object_update_queue().clone(),
         );
+        data.global_data.write_to_shader_cursor(
+            &mut ShaderCursor::new(shader_object.clone())
+                .field("gGlobalData")
+                .unwrap(),
+        );
         // Global pipeline for the entire scene (as we do not support custom vertex materials yet)
         let pipeline = unsafe {
             graphics_pipeline()
@@ -809,13 +824,14 @@ impl VisibilityBufferRasterizer {
                         .deref(),
                 )
                 .vertex_buffer_description(&[
-                    // Use the global vertex buffer, but we only need the positions
+                    // The positions are read from the global vertex buffers in the format of each mesh.
+                    // Instance and cluster of each draw, the transforms are read from the instance buffer
                     VertexBufferDescription {
                         members: [(
-                            String::from("vertexInput.position"),
+                            String::from("instanceInput.cluster"),
                             VertexMemberInfo {
-                                offset: offset_of!(Vertex, position) as u32,
-                                format: Format::R32G32B32_SFLOAT,
+                                offset: 0,
+                                format: Format::R32G32_UINT,
                                 num_elements: 1,
                                 stride: 0,
                             },
@@ -823,24 +839,7 @@ impl VisibilityBufferRasterizer {
                         .iter()
                         .cloned()
                         .collect(),
-                        stride: size_of::<Vertex>() as u32,
-                        input_rate: VertexInputRate::Vertex,
-                    },
-                    // Use the global instance buffer, but we only need the transforms
-                    VertexBufferDescription {
-                        members: [(
-                            String::from("instanceInput.transform"),
-                            VertexMemberInfo {
-                                offset: offset_of!(InstanceData, model_transform) as u32,
-                                format: Format::R32G32B32A32_SFLOAT,
-                                num_elements: 4,
-                                stride: size_of::<[f32; 4]>() as u32,
-                            },
-                        )]
-                        .iter()
-                        .cloned()
-                        .collect(),
-                        stride: size_of::<InstanceData>() as u32,
+                        stride: size_of::<[u32; 2]>() as u32,
                         input_rate: VertexInputRate::Instance { divisor: 1 },
                     },
                 ])
@@ -877,6 +876,11 @@ impl VisibilityBufferRasterizer {
         );
 
         Self {
+            skinning: Self::skinning_shader(rhi.as_ref(), data),
+            lod_selection: Self::lod_selection_shader(rhi.as_ref(), data),
+            cluster_culling: Self::cluster_culling_shader(rhi.as_ref(), data),
+            num_instances: data.global_data.num_instances(),
+            num_skinned_instances: data.global_data.num_skinned_instances(),
             shader_object,
             pipeline,
             render_pass,
@@ -894,6 +898,38 @@ impl VisibilityBufferRasterizer {
         scene: &VKScene,
         data: &VisibilityBufferData,
     ) -> Result<(), Box<ValidationError>> {
+        // Skin the vertices first, one workgroup per skinned instance
+        if self.num_skinned_instances > 0 {
+            self.skinning.record_command_buffer(
+                command_buffer,
+                image_index,
+                [
+                    self.num_skinned_instances.min(Self::MAX_DISPATCH_WIDTH),
+                    self.num_skinned_instances
+                        .div_ceil(Self::MAX_DISPATCH_WIDTH),
+                    1,
+                ],
+            )?;
+        }
+
+        // Select the levels of detail and write the draws of the visible clusters
+        data.global_data.reset_cluster_draws(command_buffer)?;
+        self.lod_selection.record_command_buffer(
+            command_buffer,
+            image_index,
+            [self.num_instances / 64 + 1, 1, 1],
+        )?;
+        // One workgroup per instance, spread over two dimensions to stay below the dispatch limits
+        self.cluster_culling.record_command_buffer(
+            command_buffer,
+            image_index,
+            [
+                self.num_instances.clamp(1, Self::MAX_DISPATCH_WIDTH),
+                self.num_instances.div_ceil(Self::MAX_DISPATCH_WIDTH).max(1),
+                1,
+            ],
+        )?;
+
         // Begin the render pass (includes clear operations)
         command_buffer
             .begin_render_pass(
@@ -942,15 +978,18 @@ impl VisibilityBufferRasterizer {
                 self.shader_object.descriptor_sets()[image_index].clone(),
             )?;
 
-        // Bind vertex, index and instance buffers
+        // Bind the instance and index buffers
         command_buffer
-            .bind_vertex_buffers(0, data.global_data.vertices.clone())?
-            .bind_vertex_buffers(1, data.global_data.instances.clone())?
+            .bind_vertex_buffers(0, data.global_data.drawn_clusters.clone())?
             .bind_index_buffer(data.global_data.indices.clone())?;
 
-        // Do a single multi draw indirect to rasterize the entire scene
+        // Do a single multi draw indirect to rasterize the entire scene, with as many draws as visible clusters
         unsafe {
-            command_buffer.draw_indexed_indirect(data.global_data.draw_indirect_commands.clone())
+            command_buffer.draw_indexed_indirect_count(
+                data.global_data.cluster_draws.clone(),
+                data.global_data.cluster_draw_count.clone(),
+                VisibilityBufferGlobalData::MAX_CLUSTER_DRAWS,
+            )
         }?;
 
         // End the render pass
@@ -958,6 +997,93 @@ impl VisibilityBufferRasterizer {
             .end_render_pass(SubpassEndInfo::default())
             .map(|_| ())
     }
+
+    fn skinning_shader(rhi: &VKRHI, data: &Arc<VisibilityBufferData>) -> VisBufferStep {
+        let skinning = VisBufferStep::new(
+            rhi,
+            "Engine/VisibilityBuffer/visBufferSkin",
+            "skinVertices",
+            data.clone(),
+        );
+
+        let cursor = ShaderCursor::new(skinning.shader_object.clone());
+        let input_cursor = cursor.field("gInput").unwrap();
+        input_cursor
+            .field("skinnedInstances")
+            .unwrap()
+            .write_buffer(data.global_data.skinned_instances.clone());
+        input_cursor
+            .field("skinVertices")
+            .unwrap()
+            .write_buffer(data.global_data.skin_vertices.clone());
+        input_cursor
+            .field("jointMatrices")
+            .unwrap()
+            .write_buffer(data.global_data.joint_matrices.clone());
+        input_cursor
+            .field("skinnedVertices")
+            .unwrap()
+            .write_buffer(data.global_data.vertices.clone());
+
+        data.global_data
+            .write_to_shader_cursor(&mut cursor.field("gGlobalData").unwrap());
+
+        skinning
+    }
+
+    fn lod_selection_shader(rhi: &VKRHI, data: &Arc<VisibilityBufferData>) -> VisBufferStep {
+        let lod_selection = VisBufferStep::new(
+            rhi,
+            "Engine/VisibilityBuffer/visBufferSelectLod",
+            "selectLods",
+            data.clone(),
+        );
+
+        let cursor = ShaderCursor::new(lod_selection.shader_object.clone());
+        let input_cursor = cursor.field("gInput").unwrap();
+        input_cursor
+            .field("instanceLods")
+            .unwrap()
+            .write_buffer(data.global_data.instance_lods.clone());
+
+        data.global_data
+            .write_to_shader_cursor(&mut cursor.field("gGlobalData").unwrap());
+
+        lod_selection
+    }
+
+    fn cluster_culling_shader(rhi: &VKRHI, data: &Arc<VisibilityBufferData>) -> VisBufferStep {
+        let cluster_culling = VisBufferStep::new(
+            rhi,
+            "Engine/VisibilityBuffer/visBufferCullClusters",
+            "cullClusters",
+            data.clone(),
+        );
+
+        let cursor = ShaderCursor::new(cluster_culling.shader_object.clone());
+        let input_cursor = cursor.field("gInput").unwrap();
+        input_cursor
+            .field("clusterDraws")
+            .unwrap()
+            .write_buffer(data.global_data.cluster_draws.clone());
+        input_cursor
+            .field("clusterDrawCount")
+            .unwrap()
+            .write_buffer(data.global_data.cluster_draw_count.clone());
+        input_cursor
+            .field("drawnClusters")
+            .unwrap()
+            .write_buffer(data.global_data.drawn_clusters.clone());
+        input_cursor
+            .field("maxClusterDraws")
+            .unwrap()
+            .write(&VisibilityBufferGlobalData::MAX_CLUSTER_DRAWS);
+
+        data.global_data
+            .write_to_shader_cursor(&mut cursor.field("gGlobalData").unwrap());
+
+        cluster_culling
+    }
 }
 
 impl PipelineBindParameter {
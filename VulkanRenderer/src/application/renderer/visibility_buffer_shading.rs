@@ -16,16 +16,15 @@ use vulkano::{
     shader::ShaderStages,
 };
 
-use crate::application::assets::asset_traits::RHIInterface;
-use crate::application::rhi::rhi_assets::vulkan_material::VKMaterial;
 use crate::application::{
+    assets::asset_traits::RHIInterface,
     renderer::{
         visibility_buffer_data::VisibilityBufferData,
         visibility_buffer_generation::{
             ComputeDispatchParameter, PipelineBindParameter, VisBufferPushConstant,
         },
     },
-    rhi::{VKRHI, shader_cursor::ShaderCursor},
+    rhi::{VKRHI, rhi_assets::vulkan_material::VKMaterial, shader_cursor::ShaderCursor},
 };
 
 /// Shading step of the visibility buffer
@@ -249,6 +248,8 @@ impl VisibilityBufferShadePass {
         self.data
             .global_data
             .pipelines
+            .read()
+            .unwrap()
             .iter()
             .enumerate()
             .for_each(|(index, pipeline)| {
@@ -56,7 +56,7 @@ use winit::{event_loop::ActiveEventLoop, window::Window};
 
 use super::assets::asset_traits::RHIInterface;
 use crate::application::{
-    assets::AssetManager::AssetManager,
+    assets::AssetManager::{AssetManager, SHADER_DIRECTORY},
     rhi::{
         rhi_assets::{
             RHIResourceManager, vulkan_camera::VKCamera, vulkan_material::VKMaterial,
@@ -93,10 +93,7 @@ pub struct VKRHI {
 }
 
 impl VKRHI {
-    pub fn new(
-        event_loop: &ActiveEventLoop,
-        asset_manager: Arc<RefCell<AssetManager>>,
-    ) -> Rc<Self> {
+    pub fn new(event_loop: &ActiveEventLoop, asset_manager: Arc<AssetManager>) -> Rc<Self> {
         let window = Self::create_window(event_loop);
         let instance = Self::create_instance(&Surface::required_extensions(event_loop).unwrap());
         let debug_messenger = Self::create_debug_messenger(instance.clone());
@@ -124,7 +121,7 @@ impl VKRHI {
                 ..GuiConfig::default()
             },
         ));
-        let slang_compiler = SlangCompiler::new("resources/assets/materials/shaders".as_ref());
+        let slang_compiler = SlangCompiler::new(SHADER_DIRECTORY.as_ref());
         let buffer_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
         let descriptor_allocator = Arc::new(StandardDescriptorSetAllocator::new(
             device.clone(),
@@ -5,7 +5,10 @@ use vulkano::{
     buffer::{
         AllocateBufferError, Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer,
     },
-    command_buffer::{CopyBufferInfoTyped, PrimaryCommandBufferAbstract},
+    command_buffer::{
+        AutoCommandBufferBuilder, CopyBufferInfoTyped, PrimaryAutoCommandBuffer,
+        PrimaryCommandBufferAbstract,
+    },
     device::Queue,
     memory::allocator::{AllocationCreateInfo, MemoryAllocator, MemoryTypeFilter},
     sync::{GpuFuture, Sharing},
@@ -93,3 +96,28 @@ pub fn copy_slice_to_buffer_staged<T: BufferContents + Copy>(
     .unwrap();
     copy_buffer_to_buffer(staging_buffer, dst_buffer, command_buffer_interface, queue)
 }
+
+/// Records the staged copy into an existing command buffer, so that several uploads share one submission
+pub fn record_slice_to_buffer_staged<T: BufferContents + Copy>(
+    src_slice: &[T],
+    dst_buffer: Subbuffer<[T]>,
+    allocator: Arc<dyn MemoryAllocator>,
+    cb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+) -> Result<(), Validated<VulkanError>> {
+    let staging_buffer = Buffer::from_iter(
+        allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_SRC,
+            ..BufferCreateInfo::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..AllocationCreateInfo::default()
+        },
+        src_slice.iter().copied(),
+    )
+    .unwrap();
+    cb.copy_buffer(CopyBufferInfoTyped::buffers(staging_buffer, dst_buffer))?;
+    Ok(())
+}
@@ -43,7 +43,13 @@ pub fn create_logical_device(
         robust_buffer_access: true,
         robust_buffer_access2: true,
         multi_draw_indirect: true,
+        draw_indirect_count: true,
         host_query_reset: true,
+        // Material instances index the bindless texture array, neighbouring texels may use different textures
+        shader_sampled_image_array_dynamic_indexing: true,
+        shader_sampled_image_array_non_uniform_indexing: true,
+        // Optional, block compressed textures are decoded on the CPU without it
+        texture_compression_bc: physical_device.supported_features().texture_compression_bc,
         ..DeviceFeatures::default()
     };
     let device_create_info = DeviceCreateInfo {
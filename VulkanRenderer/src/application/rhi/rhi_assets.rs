@@ -1,38 +1,59 @@
 use std::{
     any::TypeId,
-    cell::{Ref, RefCell},
+    cell::RefCell,
     collections::HashMap,
     marker::PhantomData,
     rc::{Rc, Weak},
     sync::Arc,
 };
 
-use asset_system::{assets::AssetHandle, resource_management::ResourceManager};
+use asset_system::{
+    assets::{Asset, AssetHandle},
+    resource_management::{Resource, ResourceId, ResourceManager},
+};
 use vulkano::{
     DeviceSize,
     buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::PrimaryCommandBufferAbstract,
+    image::sampler::{
+        Filter as VKFilter, LOD_CLAMP_NONE, Sampler, SamplerAddressMode, SamplerCreateInfo,
+        SamplerMipmapMode,
+    },
     memory::allocator::{AllocationCreateInfo, MemoryAllocator},
+    pipeline::graphics::depth_stencil::CompareOp as VKCompareOp,
+    sync::GpuFuture,
 };
 
 use crate::application::{
     assets::{
-        AssetManager::AssetManager,
+        AssetManager::{AssetChanges, AssetManager},
         asset_traits::{
-            MaterialInstanceInterface, MaterialInterface, MeshInterface, ModelInterface,
-            RHIMaterialInstanceInterface, RHIMaterialInterface, RHIMeshInterface,
-            RHIModelInterface, RHIResource, RHITextureInterface, TextureInterface,
+            CubeTextureInterface, MaterialInstanceInterface, MaterialInterface, MeshInterface,
+            ModelInterface, RHICubeTextureInterface, RHIMaterialInstanceInterface,
+            RHIMaterialInterface, RHIMeshInterface, RHIModelInterface, RHIResource,
+            RHITextureInterface, TextureInterface,
         },
+        mesh::Mesh,
+        sampler::{AddressMode, CompareOp, Filter, Sampler as SamplerAsset, SamplerDesc},
+        texture::Texture,
     },
     rhi::{
         VKRHI,
         rhi_assets::{
-            vulkan_material::VKMaterial, vulkan_material_instance::VKMaterialInstance,
-            vulkan_mesh::VKMesh, vulkan_model::VKModel, vulkan_texture::VKTexture,
+            buffer_ranges::BufferRanges,
+            vulkan_cube_texture::VKCubeTexture,
+            vulkan_material::VKMaterial,
+            vulkan_material_instance::VKMaterialInstance,
+            vulkan_mesh::{VKMesh, VKVertices},
+            vulkan_model::VKModel,
+            vulkan_texture::VKTexture,
         },
     },
 };
 
+pub mod buffer_ranges;
 pub mod vulkan_camera;
+pub mod vulkan_cube_texture;
 pub mod vulkan_material;
 pub mod vulkan_material_instance;
 pub mod vulkan_mesh;
@@ -42,19 +63,31 @@ pub mod vulkan_texture;
 
 pub struct RHIResourceManager {
     resources: ResourceManager,
-    asset_to_rhi: HashMap<usize, usize>,
-    asset_manager: Arc<RefCell<AssetManager>>,
+    asset_to_rhi: HashMap<ResourceId, ResourceId>,
+    asset_manager: Arc<AssetManager>,
     rhi: Option<Weak<VKRHI>>,
     shared_buffers: HashMap<TypeId, SharedBuffer>,
+    /// Samplers by their description, created on first use
+    samplers: RefCell<HashMap<SamplerDesc, Arc<Sampler>>>,
+    /// Global limit of the anisotropy of all samplers
+    max_anisotropy: f32,
 }
 
 struct SharedBuffer {
     buffer: Subbuffer<[u8]>,
-    next_index: DeviceSize,
+    ranges: BufferRanges,
+}
+
+/// Proxies that were removed by [`RHIResourceManager::remove_unloaded_proxies`]
+#[derive(Default)]
+pub struct RemovedProxies {
+    pub count: usize,
+    /// Materials that moved from the first index into the place of a removed one at the second index, in the order of the removals
+    pub moved_materials: Vec<(usize, usize)>,
 }
 
 pub struct RHIHandle<T: RHIResource + 'static> {
-    uuid: usize,
+    id: ResourceId,
     _phantom: PhantomData<T>,
 }
 
@@ -64,14 +97,19 @@ macro_rules! implement_rhi_resource {
             &mut self,
             source: AssetHandle<T>,
         ) -> RHIHandle<$rhi_type> {
-            let asset_manager_arc = self.asset_manager.clone();
-            let asset_manager = asset_manager_arc.borrow();
-            let source_data = source.get(asset_manager.resource_manager()).unwrap();
-            let asset_id = source_data.asset_metadata().uuid();
-            if let Some(id) = self.asset_to_rhi.get(&asset_id) {
-                RHIHandle::<$rhi_type>::new(id.clone())
+            // Only assets of type T stay locked while the proxy and the proxies it depends on are created
+            let asset_manager = self.asset_manager.clone();
+            let source_data = asset_manager.get(&source).unwrap();
+            let asset_id = source.id;
+            // Proxies that were removed in the meantime are recreated
+            if let Some(id) = self
+                .asset_to_rhi
+                .get(&asset_id)
+                .filter(|id| self.resources.contains::<$rhi_type>(**id))
+            {
+                RHIHandle::<$rhi_type>::new(*id)
             } else {
-                let new_rhi = $rhi_type::create(source_data, self.rhi().as_ref(), self);
+                let new_rhi = $rhi_type::create(&*source_data, self.rhi().as_ref(), self);
                 let id = self.resources.add(new_rhi);
                 self.asset_to_rhi.insert(asset_id, id);
                 RHIHandle::<$rhi_type>::new(id)
@@ -81,13 +119,17 @@ macro_rules! implement_rhi_resource {
 }
 
 impl RHIResourceManager {
-    pub fn new(asset_manager: Arc<RefCell<AssetManager>>) -> Self {
+    pub const DEFAULT_MAX_ANISOTROPY: f32 = 16.;
+
+    pub fn new(asset_manager: Arc<AssetManager>) -> Self {
         Self {
             resources: ResourceManager::new(),
             asset_to_rhi: HashMap::new(),
             asset_manager,
             rhi: None,
             shared_buffers: HashMap::new(),
+            samplers: RefCell::new(HashMap::new()),
+            max_anisotropy: Self::DEFAULT_MAX_ANISOTROPY,
         }
     }
 
@@ -96,6 +138,7 @@ impl RHIResourceManager {
     }
 
     implement_rhi_resource!(create_texture, VKTexture, TextureInterface);
+    implement_rhi_resource!(create_cube_texture, VKCubeTexture, CubeTextureInterface);
     implement_rhi_resource!(create_mesh, VKMesh, MeshInterface);
     implement_rhi_resource!(create_material, VKMaterial, MaterialInterface);
     implement_rhi_resource!(
@@ -109,16 +152,269 @@ impl RHIResourceManager {
         self.rhi.as_ref().unwrap().upgrade().unwrap()
     }
 
-    fn asset_manager(&self) -> Ref<AssetManager> {
-        self.asset_manager.borrow()
+    pub fn asset_manager(&self) -> &AssetManager {
+        &self.asset_manager
     }
 
     pub fn resource_iterator<T: RHIResource + 'static>(&self) -> Option<impl Iterator<Item = &T>> {
         self.resources.get_iter()
     }
 
-    pub fn index(&self, uuid: usize) -> Option<usize> {
-        self.resources.index(uuid)
+    /// Iterates over all resources of type T together with a handle to each of them
+    pub fn handle_iterator<T: RHIResource + 'static>(
+        &self,
+    ) -> impl Iterator<Item = (RHIHandle<T>, &T)> {
+        self.resources
+            .iter::<T>()
+            .map(|(id, resource)| (RHIHandle::new(id), resource))
+    }
+
+    pub fn index(&self, id: ResourceId) -> Option<usize> {
+        self.resources.index(id)
+    }
+
+    /// Removes the RHI resource and returns it. Returns None if the handle is stale.
+    pub fn take<T: RHIResource + 'static>(&mut self, handle: &RHIHandle<T>) -> Option<T> {
+        let resource = self.resources.take(handle.id)?;
+        self.asset_to_rhi.retain(|_, id| *id != handle.id);
+        Some(resource)
+    }
+
+    /// Removes the RHI resource. All handles to it become invalid.
+    pub fn remove<T: RHIResource + 'static>(&mut self, handle: &RHIHandle<T>) -> bool {
+        self.take(handle).is_some()
+    }
+
+    /// Removes the proxies of assets the asset manager unloaded, see [`AssetManager::drain_unloaded`].
+    /// Mesh ranges in the shared buffers are released for reuse.
+    /// The last proxy of each type moves into the place of a removed one, so indices change.
+    pub fn remove_unloaded_proxies(&mut self, unloaded: Vec<ResourceId>) -> RemovedProxies {
+        let mut removed = RemovedProxies::default();
+        for asset_id in unloaded {
+            let Some(id) = self.asset_to_rhi.remove(&asset_id) else {
+                continue;
+            };
+            removed.count += 1;
+            if let Some(index) = self
+                .resources
+                .index(id)
+                .filter(|_| self.resources.contains::<VKMaterial>(id))
+            {
+                let last = self.resources.count::<VKMaterial>() - 1;
+                removed.moved_materials.push((last, index));
+            }
+            if let Some(mesh) = self.resources.take::<VKMesh>(id) {
+                self.release_vertices(mesh.vertex());
+                self.release_to_shared_buffer(mesh.index());
+                self.release_to_shared_buffer(mesh.meshlet());
+                if let Some(skin) = mesh.skin() {
+                    self.release_to_shared_buffer(skin);
+                }
+            } else {
+                self.resources.remove_any(id);
+            }
+        }
+        removed
+    }
+
+    /// Returns the proxy of an asset if one was created
+    pub fn proxy_of<T: RHIResource + 'static, A: Asset + 'static>(
+        &self,
+        asset: &AssetHandle<A>,
+    ) -> Option<RHIHandle<T>> {
+        self.asset_to_rhi
+            .get(&asset.id)
+            .copied()
+            .filter(|id| self.resources.contains::<T>(*id))
+            .map(RHIHandle::new)
+    }
+
+    /// Returns the asset a proxy was created from, if it is still loaded
+    pub fn asset_of<A: Asset + 'static, T: RHIResource + 'static>(
+        &self,
+        proxy: &RHIHandle<T>,
+    ) -> Option<AssetHandle<A>> {
+        self.asset_to_rhi
+            .iter()
+            .find(|(_, id)| **id == proxy.id)
+            .map(|(asset_id, _)| AssetHandle::new(*asset_id))
+    }
+
+    /// Recreates the proxies of reloaded meshes and textures. Proxies keep their handles and thus their indices.
+    /// All uploads are recorded into one command buffer and submitted together.
+    /// The GPU must not use the old data anymore. Returns the reloaded mesh and texture proxies.
+    pub fn reload(
+        &mut self,
+        changes: &AssetChanges,
+    ) -> (Vec<RHIHandle<VKMesh>>, Vec<RHIHandle<VKTexture>>) {
+        let asset_manager = self.asset_manager.clone();
+        let meshes = changes
+            .meshes
+            .iter()
+            .filter_map(|mesh| Some((self.proxy_of::<VKMesh, Mesh>(mesh)?, mesh)))
+            .collect::<Vec<_>>();
+        let textures = changes
+            .textures
+            .iter()
+            .filter_map(|texture| Some((self.proxy_of::<VKTexture, Texture>(texture)?, texture)))
+            .collect::<Vec<_>>();
+        if meshes.is_empty() && textures.is_empty() {
+            return (Vec::new(), Vec::new());
+        }
+
+        let rhi = self.rhi();
+        let mut cb = rhi
+            .command_buffer_interface
+            .primary_command_buffer(rhi.queues.graphics_queue.queue_family_index());
+        // The new ranges are allocated while the old ones are still in use, so a mesh that does not fit keeps its old proxy
+        let new_meshes = meshes
+            .into_iter()
+            .filter_map(|(handle, mesh)| {
+                // Assets are locked one at a time while their upload is recorded
+                let source = asset_manager.get(mesh)?;
+                let Some(mesh) =
+                    VKMesh::record_preallocated(&*source, self, &rhi.buffer_allocator, &mut cb)
+                else {
+                    println!(
+                        "The shared buffers are full. {} is not reloaded, keeping the previous version.",
+                        source.name()
+                    );
+                    return None;
+                };
+                Some((handle, mesh))
+            })
+            .collect::<Vec<_>>();
+        let new_textures = textures
+            .into_iter()
+            .filter_map(|(handle, texture)| {
+                let source = asset_manager.get(texture)?;
+                match VKTexture::record(&*source, rhi.as_ref(), &mut cb) {
+                    Ok(texture) => Some((handle, texture)),
+                    Err(error) => {
+                        println!(
+                            "{}. {} is not reloaded, keeping the previous version.",
+                            error,
+                            source.name()
+                        );
+                        None
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        cb.build()
+            .unwrap()
+            .execute(rhi.queues.graphics_queue.clone())
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        let textures = new_textures
+            .into_iter()
+            .map(|(handle, texture)| {
+                self.replace(&handle, texture);
+                handle
+            })
+            .collect();
+        let meshes = new_meshes
+            .into_iter()
+            .map(|(handle, mesh)| {
+                let old = handle.get(self).unwrap();
+                let (vertex, index, meshlet, skin) = (
+                    old.vertex().clone(),
+                    old.index().clone(),
+                    old.meshlet().clone(),
+                    old.skin().cloned(),
+                );
+                self.release_vertices(&vertex);
+                self.release_to_shared_buffer(&index);
+                self.release_to_shared_buffer(&meshlet);
+                if let Some(skin) = skin {
+                    self.release_to_shared_buffer(&skin);
+                }
+                self.replace(&handle, mesh);
+                handle
+            })
+            .collect();
+        (meshes, textures)
+    }
+
+    /// Resolves the sampler descriptions of all material instances that share one of the edited samplers.
+    /// Returns the updated material instances, their sampler indices have to be recomputed.
+    pub fn update_samplers(
+        &mut self,
+        samplers: &[AssetHandle<SamplerAsset>],
+    ) -> Vec<RHIHandle<VKMaterialInstance>> {
+        let asset_manager = self.asset_manager.clone();
+        let instances = self
+            .resources
+            .iter::<VKMaterialInstance>()
+            .filter(|(_, instance)| {
+                instance
+                    .sampler_asset()
+                    .is_some_and(|sampler| samplers.contains(sampler))
+            })
+            .map(|(id, _)| RHIHandle::new(id))
+            .collect::<Vec<_>>();
+        for instance in &instances {
+            if let Some(instance) = self.resources.get_mut::<VKMaterialInstance>(instance.id) {
+                instance.refresh_sampler(&asset_manager);
+            }
+        }
+        instances
+    }
+
+    fn replace<T: RHIResource + 'static>(&mut self, handle: &RHIHandle<T>, mut resource: T) {
+        if let Some(current) = self.resources.get_mut::<T>(handle.id) {
+            Resource::set_uuid(&mut resource, handle.id.uuid);
+            *current = resource;
+        }
+    }
+
+    /// Removes the RHI proxy of an asset if one exists
+    pub fn remove_proxy_of<T: RHIResource + 'static, A: Asset + 'static>(
+        &mut self,
+        asset: &AssetHandle<A>,
+    ) -> bool {
+        self.asset_to_rhi
+            .get(&asset.id)
+            .copied()
+            .is_some_and(|id| self.remove(&RHIHandle::<T>::new(id)))
+    }
+
+    /// Returns the sampler of the description. Equal descriptions share one sampler.
+    /// Its anisotropy is limited by the global limit and the device.
+    pub fn sampler(&self, desc: &SamplerDesc) -> Arc<Sampler> {
+        self.samplers
+            .borrow_mut()
+            .entry(*desc)
+            .or_insert_with(|| {
+                let rhi = self.rhi();
+                let max_anisotropy = desc.max_anisotropy.min(self.max_anisotropy).min(
+                    rhi.device
+                        .physical_device()
+                        .properties()
+                        .max_sampler_anisotropy,
+                );
+                Sampler::new(
+                    rhi.device.clone(),
+                    sampler_create_info(desc, max_anisotropy),
+                )
+                .unwrap()
+            })
+            .clone()
+    }
+
+    /// Sets the global limit of the anisotropy. Returns whether it changed.
+    /// Samplers that were handed out keep their anisotropy, so users need to request them again.
+    pub fn set_max_anisotropy(&mut self, max_anisotropy: f32) -> bool {
+        if max_anisotropy == self.max_anisotropy {
+            return false;
+        }
+        self.max_anisotropy = max_anisotropy;
+        self.samplers.get_mut().clear();
+        true
     }
 
     pub fn request_from_shared_buffer<T: BufferContents>(
@@ -127,17 +423,23 @@ impl RHIResourceManager {
     ) -> Option<Subbuffer<[T]>> {
         let buffer = self.shared_buffers.get_mut(&TypeId::of::<T>())?;
         let bytes_required = (num * size_of::<T>()) as DeviceSize;
-        let new_index = buffer.next_index + bytes_required;
-        if buffer.buffer.size() < new_index {
-            None
-        } else {
-            let result = buffer
-                .buffer
-                .clone()
-                .slice(buffer.next_index..new_index)
-                .reinterpret::<[T]>();
-            buffer.next_index = new_index;
-            Some(result)
+        let range = buffer.ranges.allocate(bytes_required)?;
+        Some(buffer.buffer.clone().slice(range).reinterpret::<[T]>())
+    }
+
+    /// Returns a range that was handed out by request_from_shared_buffer
+    pub fn release_to_shared_buffer<T: BufferContents>(&mut self, subbuffer: &Subbuffer<[T]>) {
+        if let Some(buffer) = self.shared_buffers.get_mut(&TypeId::of::<T>()) {
+            let start = subbuffer.offset() - buffer.buffer.offset();
+            buffer.ranges.release(start..start + subbuffer.size());
+        }
+    }
+
+    /// Returns the vertices of a mesh to the shared buffer of their format
+    fn release_vertices(&mut self, vertices: &VKVertices) {
+        match vertices {
+            VKVertices::Full(buffer) => self.release_to_shared_buffer(buffer),
+            VKVertices::Compact(buffer) => self.release_to_shared_buffer(buffer),
         }
     }
 
@@ -169,26 +471,27 @@ impl RHIResourceManager {
 }
 
 impl<T: RHIResource + 'static> RHIHandle<T> {
-    fn new(uuid: usize) -> Self {
+    fn new(id: ResourceId) -> Self {
         Self {
-            uuid,
+            id,
             _phantom: PhantomData,
         }
     }
 
+    /// Returns None if the resource was removed, even if its slot has been reused since
     pub fn get<'a>(&self, manager: &'a RHIResourceManager) -> Option<&'a T> {
-        manager.resources.get::<T>(self.uuid)
+        manager.resources.get::<T>(self.id)
     }
 
-    pub fn id(&self) -> usize {
-        self.uuid
+    pub fn id(&self) -> ResourceId {
+        self.id
     }
 }
 
 impl<T: RHIResource> Clone for RHIHandle<T> {
     fn clone(&self) -> Self {
         Self {
-            uuid: self.uuid,
+            id: self.id,
             _phantom: PhantomData,
         }
     }
@@ -208,7 +511,45 @@ impl SharedBuffer {
         .unwrap();
         Self {
             buffer,
-            next_index: 0,
+            ranges: BufferRanges::new(size),
         }
     }
 }
+
+fn sampler_create_info(desc: &SamplerDesc, max_anisotropy: f32) -> SamplerCreateInfo {
+    let filter = |filter| match filter {
+        Filter::Nearest => VKFilter::Nearest,
+        Filter::Linear => VKFilter::Linear,
+    };
+    let address_mode = |mode| match mode {
+        AddressMode::Repeat => SamplerAddressMode::Repeat,
+        AddressMode::MirroredRepeat => SamplerAddressMode::MirroredRepeat,
+        AddressMode::ClampToEdge => SamplerAddressMode::ClampToEdge,
+        AddressMode::ClampToBorder => SamplerAddressMode::ClampToBorder,
+    };
+    let compare_op = |op| match op {
+        CompareOp::Never => VKCompareOp::Never,
+        CompareOp::Less => VKCompareOp::Less,
+        CompareOp::Equal => VKCompareOp::Equal,
+        CompareOp::LessOrEqual => VKCompareOp::LessOrEqual,
+        CompareOp::Greater => VKCompareOp::Greater,
+        CompareOp::NotEqual => VKCompareOp::NotEqual,
+        CompareOp::GreaterOrEqual => VKCompareOp::GreaterOrEqual,
+        CompareOp::Always => VKCompareOp::Always,
+    };
+    SamplerCreateInfo {
+        mag_filter: filter(desc.mag_filter),
+        min_filter: filter(desc.min_filter),
+        mipmap_mode: match desc.mipmap_mode {
+            Filter::Nearest => SamplerMipmapMode::Nearest,
+            Filter::Linear => SamplerMipmapMode::Linear,
+        },
+        address_mode: desc.address_modes.map(address_mode),
+        mip_lod_bias: desc.mip_lod_bias,
+        // Anisotropy of 1 samples like a sampler without it
+        anisotropy: (max_anisotropy > 1.).then_some(max_anisotropy),
+        compare: desc.compare_op.map(compare_op),
+        lod: 0.0..=LOD_CLAMP_NONE,
+        ..SamplerCreateInfo::default()
+    }
+}
@@ -0,0 +1,116 @@
+use std::ops::Range;
+
+use vulkano::DeviceSize;
+
+/// Byte ranges handed out from a shared buffer. Released ranges are reused before the buffer grows.
+pub struct BufferRanges {
+    size: DeviceSize,
+    next_index: DeviceSize,
+    /// Byte ranges below next_index that were released and can be handed out again
+    free_ranges: Vec<Range<DeviceSize>>,
+}
+
+impl BufferRanges {
+    pub fn new(size: DeviceSize) -> Self {
+        Self {
+            size,
+            next_index: 0,
+            free_ranges: Vec::new(),
+        }
+    }
+
+    /// First fit allocation from the released ranges, falling back to the end of the buffer
+    pub fn allocate(&mut self, size: DeviceSize) -> Option<Range<DeviceSize>> {
+        if let Some(position) = self
+            .free_ranges
+            .iter()
+            .position(|range| range.end - range.start >= size)
+        {
+            let range = &mut self.free_ranges[position];
+            let result = range.start..range.start + size;
+            range.start += size;
+            if range.is_empty() {
+                self.free_ranges.remove(position);
+            }
+            return Some(result);
+        }
+
+        let new_index = self.next_index + size;
+        if self.size < new_index {
+            None
+        } else {
+            let result = self.next_index..new_index;
+            self.next_index = new_index;
+            Some(result)
+        }
+    }
+
+    pub fn release(&mut self, range: Range<DeviceSize>) {
+        if range.is_empty() {
+            return;
+        }
+        // Keep the free ranges sorted and merge neighbours to limit fragmentation
+        let position = self
+            .free_ranges
+            .partition_point(|free| free.start < range.start);
+        self.free_ranges.insert(position, range);
+        if position + 1 < self.free_ranges.len()
+            && self.free_ranges[position].end == self.free_ranges[position + 1].start
+        {
+            let next = self.free_ranges.remove(position + 1);
+            self.free_ranges[position].end = next.end;
+        }
+        if position > 0 && self.free_ranges[position - 1].end == self.free_ranges[position].start {
+            let current = self.free_ranges.remove(position);
+            self.free_ranges[position - 1].end = current.end;
+        }
+        // A free range at the end of the used space is given back to the bump allocator
+        if self
+            .free_ranges
+            .last()
+            .is_some_and(|last| last.end == self.next_index)
+        {
+            self.next_index = self.free_ranges.pop().unwrap().start;
+        }
+    }
+
+    /// Bytes that are currently handed out
+    pub fn used(&self) -> DeviceSize {
+        self.next_index
+            - self
+                .free_ranges
+                .iter()
+                .map(|range| range.end - range.start)
+                .sum::<DeviceSize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn released_ranges_are_reused_first() {
+        let mut ranges = BufferRanges::new(100);
+        let first = ranges.allocate(10).unwrap();
+        let second = ranges.allocate(20).unwrap();
+        ranges.allocate(30).unwrap();
+        ranges.release(first.clone());
+        ranges.release(second);
+        // The neighbouring ranges were merged into one
+        assert_eq!(ranges.allocate(25), Some(0..25));
+        assert_eq!(ranges.used(), 55);
+        assert_eq!(ranges.allocate(50), None);
+    }
+
+    #[test]
+    fn releasing_everything_empties_the_buffer() {
+        let mut ranges = BufferRanges::new(64);
+        let allocated = [8, 16, 4].map(|size| ranges.allocate(size).unwrap());
+        for range in allocated {
+            ranges.release(range);
+        }
+        assert_eq!(ranges.used(), 0);
+        assert_eq!(ranges.allocate(64), Some(0..64));
+    }
+}
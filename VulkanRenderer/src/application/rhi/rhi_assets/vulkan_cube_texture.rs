@@ -0,0 +1,144 @@
+use std::sync::Arc;
+
+use asset_system::resource_management::Resource;
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage},
+    command_buffer::{
+        AutoCommandBufferBuilder, PrimaryAutoCommandBuffer, PrimaryCommandBufferAbstract,
+    },
+    format::Format,
+    image::{
+        Image, ImageAspects, ImageCreateFlags, ImageCreateInfo, ImageLayout, ImageSubresourceRange,
+        ImageTiling, ImageType, ImageUsage, SampleCount,
+        view::{ImageView, ImageViewCreateInfo, ImageViewType},
+    },
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    sync::{GpuFuture, Sharing},
+};
+
+use crate::application::{
+    assets::asset_traits::{CubeTextureInterface, RHICubeTextureInterface, RHIResource},
+    rhi::{
+        VKRHI,
+        rhi_assets::{RHIResourceManager, vulkan_texture::VKTexture},
+    },
+};
+
+pub struct VKCubeTexture {
+    image: Arc<ImageView>,
+    uuid: usize,
+}
+
+impl Resource for VKCubeTexture {
+    fn set_uuid(&mut self, uuid: usize) {
+        self.uuid = uuid;
+    }
+}
+
+impl RHIResource for VKCubeTexture {
+    fn uuid_mut(&mut self) -> &mut usize {
+        &mut self.uuid
+    }
+}
+
+impl RHICubeTextureInterface for VKCubeTexture {
+    type RHI = VKRHI;
+
+    fn create<T: CubeTextureInterface>(
+        source: &T,
+        rhi: &Self::RHI,
+        _: &mut RHIResourceManager,
+    ) -> Self {
+        let mut cb = rhi
+            .command_buffer_interface
+            .primary_command_buffer(rhi.queues.graphics_queue.queue_family_index());
+
+        let texture = Self::record(source, rhi, &mut cb);
+
+        cb.build()
+            .unwrap()
+            .execute(rhi.queues.graphics_queue.clone())
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        texture
+    }
+}
+
+impl VKCubeTexture {
+    /// Half floats can be filtered on every device, unlike 32 bit floats
+    const FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
+    /// Creates the cube image and records the upload of all its mips into cb instead of submitting it
+    pub fn record<T: CubeTextureInterface>(
+        source: &T,
+        rhi: &VKRHI,
+        cb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Self {
+        let mip_levels = source.mips().len() as u32;
+        let array_layers = VKTexture::layer_count(ImageViewType::Cube);
+        let image_create_info = ImageCreateInfo {
+            flags: ImageCreateFlags::CUBE_COMPATIBLE,
+            image_type: ImageType::Dim2d,
+            format: Self::FORMAT,
+            extent: [source.face_size(), source.face_size(), 1],
+            array_layers,
+            mip_levels,
+            samples: SampleCount::Sample1,
+            tiling: ImageTiling::Optimal,
+            usage: ImageUsage::SAMPLED | ImageUsage::TRANSFER_DST,
+            sharing: Sharing::Exclusive,
+            initial_layout: ImageLayout::Undefined,
+            ..ImageCreateInfo::default()
+        };
+        let allocation_info = AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+            ..AllocationCreateInfo::default()
+        };
+        let image_view_create_info = ImageViewCreateInfo {
+            view_type: ImageViewType::Cube,
+            format: Self::FORMAT,
+            subresource_range: ImageSubresourceRange {
+                aspects: ImageAspects::COLOR,
+                mip_levels: 0..mip_levels,
+                array_layers: 0..array_layers,
+            },
+            ..ImageViewCreateInfo::default()
+        };
+        let alloc = Arc::new(StandardMemoryAllocator::new_default(rhi.device.clone()));
+
+        let staging_buffer = Buffer::from_iter(
+            alloc.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_SRC,
+                sharing: Sharing::Exclusive,
+                ..BufferCreateInfo::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..AllocationCreateInfo::default()
+            },
+            source.mips().concat(),
+        )
+        .unwrap();
+
+        let image = Image::new(alloc, image_create_info, allocation_info).unwrap();
+
+        VKTexture::copy_buffer_to_image(staging_buffer, &image, mip_levels, cb).unwrap();
+
+        let image_view = ImageView::new(image, image_view_create_info).unwrap();
+
+        Self {
+            image: image_view,
+            uuid: 0,
+        }
+    }
+
+    pub fn image_view(&self) -> &Arc<ImageView> {
+        &self.image
+    }
+}
@@ -1,16 +1,27 @@
 use std::{cell::RefCell, ops::Deref, sync::Arc};
 
-use asset_system::resource_management::Resource;
+use asset_system::{assets::AssetHandle, resource_management::Resource};
 use vulkano::{
     descriptor_set::{DescriptorSet, allocator::DescriptorSetAllocator},
     memory::allocator::MemoryAllocator,
 };
 
 use crate::application::{
-    assets::asset_traits::{MaterialInstanceInterface, RHIMaterialInstanceInterface, RHIResource},
+    assets::{
+        AssetManager::AssetManager,
+        asset_traits::{
+            MaterialInstanceInterface, MaterialParameters, RHIMaterialInstanceInterface,
+            RHIResource,
+        },
+        material_instance::TextureSlot,
+        sampler::{Sampler, SamplerDesc},
+    },
     rhi::{
         VKRHI,
-        rhi_assets::{RHIHandle, RHIResourceManager, vulkan_material::VKMaterial},
+        rhi_assets::{
+            RHIHandle, RHIResourceManager, vulkan_cube_texture::VKCubeTexture,
+            vulkan_material::VKMaterial, vulkan_texture::VKTexture,
+        },
         shader_cursor::ShaderCursor,
         shader_object::ShaderObjectQueue,
     },
@@ -19,12 +30,21 @@ use crate::application::{
 pub struct VKMaterialInstance {
     //shader_object: Arc<ShaderObject>,
     material: RHIHandle<VKMaterial>,
+    parameters: MaterialParameters,
+    /// Texture of each slot, in the order of TextureSlot::ALL
+    textures: [Option<RHIHandle<VKTexture>>; TextureSlot::ALL.len()],
+    /// Shared sampler asset, None for the default sampler
+    sampler_asset: Option<AssetHandle<Sampler>>,
+    /// Description of the shared sampler, resolved to a sampler by the RHIResourceManager
+    sampler: SamplerDesc,
+    environment_texture: Option<RHIHandle<VKCubeTexture>>,
     uuid: usize,
 }
 
 impl VKMaterialInstance {
     fn new(
         material: RHIHandle<VKMaterial>,
+        parameters: MaterialParameters,
         descriptor_allocator: &Arc<dyn DescriptorSetAllocator>,
         buffer_allocator: &Arc<dyn MemoryAllocator>,
         in_flight_frames: usize,
@@ -45,14 +65,75 @@ impl VKMaterialInstance {
         Self {
             //shader_object,
             material,
+            parameters,
+            textures: Default::default(),
+            sampler_asset: None,
+            sampler: SamplerDesc::default(),
+            environment_texture: None,
             uuid: 0,
         }
     }
 
+    fn with_textures(
+        mut self,
+        textures: [Option<RHIHandle<VKTexture>>; TextureSlot::ALL.len()],
+    ) -> Self {
+        self.textures = textures;
+        self
+    }
+
+    fn with_sampler(
+        mut self,
+        sampler_asset: Option<AssetHandle<Sampler>>,
+        asset_manager: &AssetManager,
+    ) -> Self {
+        self.sampler_asset = sampler_asset;
+        self.refresh_sampler(asset_manager);
+        self
+    }
+
+    fn with_environment_texture(
+        mut self,
+        environment_texture: Option<RHIHandle<VKCubeTexture>>,
+    ) -> Self {
+        self.environment_texture = environment_texture;
+        self
+    }
+
     pub fn material(&self) -> RHIHandle<VKMaterial> {
         self.material.clone()
     }
 
+    pub fn parameters(&self) -> &MaterialParameters {
+        &self.parameters
+    }
+
+    pub fn texture(&self, slot: TextureSlot) -> Option<&RHIHandle<VKTexture>> {
+        self.textures[slot as usize].as_ref()
+    }
+
+    pub fn sampler(&self) -> &SamplerDesc {
+        &self.sampler
+    }
+
+    pub fn sampler_asset(&self) -> Option<&AssetHandle<Sampler>> {
+        self.sampler_asset.as_ref()
+    }
+
+    /// Reads the description of the shared sampler again after it was edited.
+    /// Falls back to the default sampler if the asset is gone.
+    pub fn refresh_sampler(&mut self, asset_manager: &AssetManager) {
+        self.sampler = self
+            .sampler_asset
+            .as_ref()
+            .and_then(|sampler| asset_manager.get(sampler).map(|sampler| sampler.desc()))
+            .unwrap_or_default();
+    }
+
+    pub fn environment_texture(&self) -> Option<&RHIHandle<VKCubeTexture>> {
+        self.environment_texture.as_ref()
+    }
+
     pub fn shader_cursor(&self) -> ShaderCursor {
         //ShaderCursor::new(self.shader_object.clone())
         unimplemented!()
@@ -84,13 +165,26 @@ impl RHIMaterialInstanceInterface for VKMaterialInstance {
         rhi: &Self::RHI,
         resource_manager: &mut RHIResourceManager,
     ) -> Self {
+        // Textures are uploaded together with the first material instance that uses them
+        let textures = TextureSlot::ALL.map(|slot| {
+            source
+                .texture(slot)
+                .map(|texture| resource_manager.create_texture(texture))
+        });
+        let environment_texture = source
+            .environment_texture()
+            .map(|texture| resource_manager.create_cube_texture(texture));
         VKMaterialInstance::new(
             resource_manager.create_material(source.material()),
+            source.parameters(),
             &rhi.descriptor_allocator,
             &rhi.buffer_allocator,
             rhi.frames_in_flight,
             resource_manager.deref(),
             rhi.shader_object_update_queue().clone(),
         )
+        .with_textures(textures)
+        .with_sampler(source.sampler(), resource_manager.asset_manager())
+        .with_environment_texture(environment_texture)
     }
 }
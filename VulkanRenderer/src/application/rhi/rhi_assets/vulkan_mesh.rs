@@ -1,25 +1,50 @@
 use std::sync::Arc;
 
 use asset_system::resource_management::Resource;
+use glam::Vec3;
 use vulkano::{
     buffer::{BufferUsage, Subbuffer},
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
     device::Queue,
     memory::allocator::{MemoryAllocator, MemoryTypeFilter},
 };
 
 use crate::application::{
-    assets::asset_traits::{Index, MeshInterface, RHIMeshInterface, RHIResource, Vertex},
+    assets::{
+        asset_traits::{
+            CompactVertex, Index, MeshInterface, MeshLod, Meshlet, RHIMeshInterface, RHIResource,
+            SkinVertex, Vertex, VertexFormat,
+        },
+        compact_vertex,
+    },
     rhi::{
         VKRHI,
-        buffer::{buffer_from_slice, copy_slice_to_buffer_staged},
+        buffer::{buffer_from_slice, copy_slice_to_buffer_staged, record_slice_to_buffer_staged},
         command_buffer::CommandBufferInterface,
         rhi_assets::RHIResourceManager,
     },
 };
 
+/// Vertices in the format the mesh selected. Each format has its own shared buffer.
+#[derive(Clone)]
+pub enum VKVertices {
+    Full(Subbuffer<[Vertex]>),
+    Compact(Subbuffer<[CompactVertex]>),
+}
+
 pub struct VKMesh {
-    vertex_buffer: Subbuffer<[Vertex]>,
+    vertex_buffer: VKVertices,
     index_buffer: Subbuffer<[Index]>,
+    meshlet_buffer: Subbuffer<[Meshlet]>,
+    /// Joints and weights of skinned meshes, parallel to the vertices
+    skin_buffer: Option<Subbuffer<[SkinVertex]>>,
+    /// Number of joints the skin references
+    joint_count: usize,
+    lods: Vec<MeshLod>,
+    /// Center and radius in object space
+    bounding_sphere: ([f32; 3], f32),
+    /// Offset and scale that decode compact positions
+    position_quantization: ([f32; 3], [f32; 3]),
     uuid: usize,
 }
 
@@ -30,28 +55,69 @@ impl VKMesh {
         command_buffer_interface: &CommandBufferInterface,
         queue: &Arc<Queue>,
     ) -> Self {
-        let vertex_buffer = buffer_from_slice(
+        let vertex_buffer = match mesh.vertex_format() {
+            VertexFormat::Full => VKVertices::Full(
+                buffer_from_slice(
+                    allocator.clone(),
+                    command_buffer_interface,
+                    queue.clone(),
+                    mesh.vertices(),
+                    BufferUsage::STORAGE_BUFFER,
+                    MemoryTypeFilter::PREFER_DEVICE,
+                )
+                .unwrap(),
+            ),
+            VertexFormat::Compact => VKVertices::Compact(
+                buffer_from_slice(
+                    allocator.clone(),
+                    command_buffer_interface,
+                    queue.clone(),
+                    &compact_vertex::compress(mesh.vertices(), mesh.bounds()),
+                    BufferUsage::STORAGE_BUFFER,
+                    MemoryTypeFilter::PREFER_DEVICE,
+                )
+                .unwrap(),
+            ),
+        };
+        let index_buffer = buffer_from_slice(
             allocator.clone(),
             command_buffer_interface,
             queue.clone(),
-            mesh.vertices(),
-            BufferUsage::VERTEX_BUFFER,
+            mesh.indices(),
+            BufferUsage::INDEX_BUFFER,
             MemoryTypeFilter::PREFER_DEVICE,
         )
         .unwrap();
-        let index_buffer = buffer_from_slice(
+        let meshlet_buffer = buffer_from_slice(
             allocator.clone(),
             command_buffer_interface,
             queue.clone(),
-            mesh.indices(),
-            BufferUsage::INDEX_BUFFER,
+            mesh.meshlets(),
+            BufferUsage::STORAGE_BUFFER,
             MemoryTypeFilter::PREFER_DEVICE,
         )
         .unwrap();
+        let skin_buffer = mesh.skin().map(|skin| {
+            buffer_from_slice(
+                allocator.clone(),
+                command_buffer_interface,
+                queue.clone(),
+                skin,
+                BufferUsage::STORAGE_BUFFER,
+                MemoryTypeFilter::PREFER_DEVICE,
+            )
+            .unwrap()
+        });
 
         Self {
             vertex_buffer,
             index_buffer,
+            meshlet_buffer,
+            skin_buffer,
+            joint_count: Self::joint_count_of(mesh),
+            lods: mesh.lods().to_vec(),
+            bounding_sphere: Self::bounding_sphere_of(mesh),
+            position_quantization: compact_vertex::position_quantization(mesh.bounds()),
             uuid: 0,
         }
     }
@@ -63,36 +129,187 @@ impl VKMesh {
         command_buffer_interface: &CommandBufferInterface,
         queue: &Arc<Queue>,
     ) -> Self {
-        let vertex_buffer = resource_manager
-            .request_from_shared_buffer(mesh.vertices().len())
+        let vertex_buffer = match mesh.vertex_format() {
+            VertexFormat::Full => {
+                let buffer = resource_manager
+                    .request_from_shared_buffer(mesh.vertices().len())
+                    .unwrap();
+                copy_slice_to_buffer_staged(
+                    mesh.vertices(),
+                    buffer.clone(),
+                    allocator.clone(),
+                    command_buffer_interface,
+                    queue.clone(),
+                )
+                .unwrap();
+                VKVertices::Full(buffer)
+            }
+            VertexFormat::Compact => {
+                let vertices = compact_vertex::compress(mesh.vertices(), mesh.bounds());
+                let buffer = resource_manager
+                    .request_from_shared_buffer(vertices.len())
+                    .unwrap();
+                copy_slice_to_buffer_staged(
+                    &vertices,
+                    buffer.clone(),
+                    allocator.clone(),
+                    command_buffer_interface,
+                    queue.clone(),
+                )
+                .unwrap();
+                VKVertices::Compact(buffer)
+            }
+        };
+        let index_buffer = resource_manager
+            .request_from_shared_buffer(mesh.indices().len())
             .unwrap();
         copy_slice_to_buffer_staged(
-            mesh.vertices(),
-            vertex_buffer.clone(),
+            mesh.indices(),
+            index_buffer.clone(),
             allocator.clone(),
             command_buffer_interface,
             queue.clone(),
         )
         .unwrap();
-        let index_buffer = resource_manager
-            .request_from_shared_buffer(mesh.indices().len())
+        let meshlet_buffer = resource_manager
+            .request_from_shared_buffer(mesh.meshlets().len())
             .unwrap();
         copy_slice_to_buffer_staged(
-            mesh.indices(),
-            index_buffer.clone(),
+            mesh.meshlets(),
+            meshlet_buffer.clone(),
             allocator.clone(),
             command_buffer_interface,
             queue.clone(),
         )
         .unwrap();
+        let skin_buffer = mesh.skin().map(|skin| {
+            let buffer = resource_manager
+                .request_from_shared_buffer(skin.len())
+                .unwrap();
+            copy_slice_to_buffer_staged(
+                skin,
+                buffer.clone(),
+                allocator.clone(),
+                command_buffer_interface,
+                queue.clone(),
+            )
+            .unwrap();
+            buffer
+        });
         Self {
             vertex_buffer,
             index_buffer,
+            meshlet_buffer,
+            skin_buffer,
+            joint_count: Self::joint_count_of(mesh),
+            lods: mesh.lods().to_vec(),
+            bounding_sphere: Self::bounding_sphere_of(mesh),
+            position_quantization: compact_vertex::position_quantization(mesh.bounds()),
             uuid: 0,
         }
     }
 
-    pub fn vertex(&self) -> &Subbuffer<[Vertex]> {
+    /// Allocates the mesh from the shared buffers and records its upload into cb instead of submitting it.
+    /// Returns None without keeping any range if a shared buffer is full.
+    pub fn record_preallocated<Mesh: MeshInterface>(
+        mesh: &Mesh,
+        resource_manager: &mut RHIResourceManager,
+        allocator: &Arc<dyn MemoryAllocator>,
+        cb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Option<Self> {
+        let compact_vertices = (mesh.vertex_format() == VertexFormat::Compact)
+            .then(|| compact_vertex::compress(mesh.vertices(), mesh.bounds()));
+        let vertex_buffer = match &compact_vertices {
+            None => resource_manager
+                .request_from_shared_buffer(mesh.vertices().len())
+                .map(VKVertices::Full),
+            Some(vertices) => resource_manager
+                .request_from_shared_buffer(vertices.len())
+                .map(VKVertices::Compact),
+        };
+        let index_buffer = resource_manager.request_from_shared_buffer(mesh.indices().len());
+        let meshlet_buffer = resource_manager.request_from_shared_buffer(mesh.meshlets().len());
+        let skin_buffer = mesh
+            .skin()
+            .map(|skin| resource_manager.request_from_shared_buffer(skin.len()))
+            .transpose();
+        let (Some(vertex_buffer), Some(index_buffer), Some(meshlet_buffer), Some(skin_buffer)) = (
+            vertex_buffer.clone(),
+            index_buffer.clone(),
+            meshlet_buffer.clone(),
+            skin_buffer.clone(),
+        ) else {
+            if let Some(vertices) = &vertex_buffer {
+                resource_manager.release_vertices(vertices);
+            }
+            if let Some(buffer) = &index_buffer {
+                resource_manager.release_to_shared_buffer(buffer);
+            }
+            if let Some(buffer) = &meshlet_buffer {
+                resource_manager.release_to_shared_buffer(buffer);
+            }
+            if let Some(Some(buffer)) = &skin_buffer {
+                resource_manager.release_to_shared_buffer(buffer);
+            }
+            return None;
+        };
+
+        match &vertex_buffer {
+            VKVertices::Full(buffer) => record_slice_to_buffer_staged(
+                mesh.vertices(),
+                buffer.clone(),
+                allocator.clone(),
+                cb,
+            ),
+            VKVertices::Compact(buffer) => record_slice_to_buffer_staged(
+                compact_vertices.as_deref().unwrap(),
+                buffer.clone(),
+                allocator.clone(),
+                cb,
+            ),
+        }
+        .unwrap();
+        record_slice_to_buffer_staged(mesh.indices(), index_buffer.clone(), allocator.clone(), cb)
+            .unwrap();
+        record_slice_to_buffer_staged(
+            mesh.meshlets(),
+            meshlet_buffer.clone(),
+            allocator.clone(),
+            cb,
+        )
+        .unwrap();
+        if let (Some(skin), Some(buffer)) = (mesh.skin(), &skin_buffer) {
+            record_slice_to_buffer_staged(skin, buffer.clone(), allocator.clone(), cb).unwrap();
+        }
+        Some(Self {
+            vertex_buffer,
+            index_buffer,
+            meshlet_buffer,
+            skin_buffer,
+            joint_count: Self::joint_count_of(mesh),
+            lods: mesh.lods().to_vec(),
+            bounding_sphere: Self::bounding_sphere_of(mesh),
+            position_quantization: compact_vertex::position_quantization(mesh.bounds()),
+            uuid: 0,
+        })
+    }
+
+    fn bounding_sphere_of<Mesh: MeshInterface>(mesh: &Mesh) -> ([f32; 3], f32) {
+        let (min, max) = mesh.bounds();
+        let (min, max) = (Vec3::from(min), Vec3::from(max));
+        (((min + max) * 0.5).into(), (max - min).length() * 0.5)
+    }
+
+    fn joint_count_of<Mesh: MeshInterface>(mesh: &Mesh) -> usize {
+        mesh.skin().map_or(0, |skin| {
+            skin.iter()
+                .flat_map(|vertex| vertex.joints)
+                .max()
+                .map_or(0, |joint| joint as usize + 1)
+        })
+    }
+
+    pub fn vertex(&self) -> &VKVertices {
         &self.vertex_buffer
     }
 
@@ -100,17 +317,63 @@ impl VKMesh {
         &self.index_buffer
     }
 
+    pub fn meshlet(&self) -> &Subbuffer<[Meshlet]> {
+        &self.meshlet_buffer
+    }
+
     pub fn index_offset(&self) -> usize {
         self.index_buffer.offset() as usize / size_of::<Index>()
     }
     pub fn index_size(&self) -> usize {
         self.index_buffer.len() as usize
     }
+    /// Offset in the shared buffer of the vertex format
     pub fn vertex_offset(&self) -> usize {
-        self.vertex_buffer.offset() as usize / size_of::<Vertex>()
+        match &self.vertex_buffer {
+            VKVertices::Full(buffer) => buffer.offset() as usize / size_of::<Vertex>(),
+            VKVertices::Compact(buffer) => buffer.offset() as usize / size_of::<CompactVertex>(),
+        }
     }
     pub fn vertex_size(&self) -> usize {
-        self.vertex_buffer.len() as usize
+        match &self.vertex_buffer {
+            VKVertices::Full(buffer) => buffer.len() as usize,
+            VKVertices::Compact(buffer) => buffer.len() as usize,
+        }
+    }
+    pub fn vertex_format(&self) -> VertexFormat {
+        match &self.vertex_buffer {
+            VKVertices::Full(_) => VertexFormat::Full,
+            VKVertices::Compact(_) => VertexFormat::Compact,
+        }
+    }
+    pub fn meshlet_offset(&self) -> usize {
+        self.meshlet_buffer.offset() as usize / size_of::<Meshlet>()
+    }
+
+    pub fn skin(&self) -> Option<&Subbuffer<[SkinVertex]>> {
+        self.skin_buffer.as_ref()
+    }
+    /// Offset in the shared skin buffer. None if the mesh is not skinned.
+    pub fn skin_offset(&self) -> Option<usize> {
+        self.skin_buffer
+            .as_ref()
+            .map(|buffer| buffer.offset() as usize / size_of::<SkinVertex>())
+    }
+    pub fn joint_count(&self) -> usize {
+        self.joint_count
+    }
+
+    /// Levels of detail, their first indices are relative to index_offset and their first meshlets to meshlet_offset
+    pub fn lods(&self) -> &[MeshLod] {
+        &self.lods
+    }
+
+    pub fn bounding_sphere(&self) -> ([f32; 3], f32) {
+        self.bounding_sphere
+    }
+
+    pub fn position_quantization(&self) -> ([f32; 3], [f32; 3]) {
+        self.position_quantization
     }
 }
 
@@ -1,3 +1,8 @@
+use std::collections::HashMap;
+
+use asset_system::resource_management::ResourceId;
+use glam::Mat4;
+
 use crate::application::{
     assets::asset_traits::{CameraInterface, RHISceneInterface, SceneInterface},
     rhi::{
@@ -11,6 +16,38 @@ use crate::application::{
 pub struct VKScene {
     models: Vec<RHIHandle<VKModel>>,
     camera: VKCamera,
+    /// Skinning matrices of the joints of each skinned model in its current pose
+    joint_matrices: HashMap<ResourceId, Vec<Mat4>>,
+    /// Transforms of the animated models in the current frame
+    transforms: HashMap<ResourceId, Mat4>,
+}
+
+impl VKScene {
+    /// Sets the pose of the model at the index in the scene. Models without a pose are drawn in their bind pose.
+    pub fn set_joint_matrices(&mut self, model_index: usize, matrices: Vec<Mat4>) {
+        if let Some(model) = self.models.get(model_index) {
+            self.joint_matrices.insert(model.id(), matrices);
+        }
+    }
+
+    pub fn joint_matrices(&self, model: &RHIHandle<VKModel>) -> Option<&[Mat4]> {
+        self.joint_matrices.get(&model.id()).map(Vec::as_slice)
+    }
+
+    /// Moves the model at the index in the scene. Models without a transform keep the one they were created with.
+    pub fn set_transform(&mut self, model_index: usize, transform: Mat4) {
+        if let Some(model) = self.models.get(model_index) {
+            self.transforms.insert(model.id(), transform);
+        }
+    }
+
+    pub fn transform(&self, model: &RHIHandle<VKModel>) -> Option<Mat4> {
+        self.transforms.get(&model.id()).copied()
+    }
+
+    pub fn has_transforms(&self) -> bool {
+        !self.transforms.is_empty()
+    }
 }
 
 impl RHISceneInterface for VKScene {
@@ -29,6 +66,8 @@ impl RHISceneInterface for VKScene {
         Self {
             models,
             camera: source.camera().rhi(rhi),
+            joint_matrices: HashMap::new(),
+            transforms: HashMap::new(),
         }
     }
 
@@ -1,4 +1,4 @@
-use std::{cmp::max, sync::Arc};
+use std::{cmp::max, ops::Range, sync::Arc};
 
 use asset_system::resource_management::Resource;
 use smallvec::smallvec;
@@ -6,30 +6,61 @@ use vulkano::{
     Validated, ValidationError, VulkanError,
     buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
     command_buffer::{
-        AutoCommandBufferBuilder, BlitImageInfo, CopyBufferToImageInfo, ImageBlit,
-        PrimaryAutoCommandBuffer, PrimaryCommandBufferAbstract,
+        AutoCommandBufferBuilder, BlitImageInfo, BufferImageCopy, CopyBufferToImageInfo,
+        CopyImageInfo, ImageBlit, ImageCopy, PrimaryAutoCommandBuffer,
+        PrimaryCommandBufferAbstract,
     },
-    format::Format,
+    format::{Format, FormatFeatures},
     image::{
         Image, ImageAspects, ImageCreateInfo, ImageLayout, ImageSubresourceLayers,
         ImageSubresourceRange, ImageTiling, ImageType, ImageUsage, SampleCount,
         sampler::Filter,
         view::{ImageView, ImageViewCreateInfo, ImageViewType},
     },
-    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
     sync::{GpuFuture, Sharing},
 };
 
 use crate::application::{
-    assets::asset_traits::{RHIResource, RHITextureInterface, TextureInterface},
+    assets::{
+        asset_error::AssetError,
+        asset_traits::{
+            BlockFormat, ColorSpace, PixelFormat, RHIResource, RHITextureInterface,
+            TextureInterface,
+        },
+        block_compression,
+        texture::{self, Texture},
+    },
     rhi::{VKRHI, rhi_assets::RHIResourceManager},
 };
 
 pub struct VKTexture {
+    /// Image with the levels from first_mip on
     image: Arc<ImageView>,
+    format: Format,
+    /// Size of the top level
+    size: [u32; 2],
+    mip_levels: u32,
+    /// How the texels of levels that are streamed in are read from the asset
+    level_source: LevelSource,
+    first_mip: u32,
     uuid: usize,
 }
 
+/// How the texels of the levels are produced from the texture asset.
+/// Levels are read from the asset whenever they are uploaded, so no texels stay on the host.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum LevelSource {
+    /// The block compressed mips of the asset
+    Stored,
+    /// The block compressed mips of the asset, decoded on the CPU since the device cannot sample them
+    Decoded(BlockFormat),
+    /// The top level of the asset, whose mips are blitted on the GPU
+    Blitted,
+    /// The top level of the asset, whose mips are generated on the CPU since the format cannot be blitted
+    Generated,
+}
+
 impl Resource for VKTexture {
     fn set_uuid(&mut self, uuid: usize) {
         self.uuid = uuid;
@@ -50,15 +81,219 @@ impl RHITextureInterface for VKTexture {
         rhi: &Self::RHI,
         _: &mut RHIResourceManager,
     ) -> Self {
+        let mut cb = rhi
+            .command_buffer_interface
+            .primary_command_buffer(rhi.queues.graphics_queue.queue_family_index());
+
+        let texture = Self::record(source, rhi, &mut cb).unwrap_or_else(|error| {
+            println!(
+                "{}. {} is replaced by the fallback asset.",
+                error,
+                source.name()
+            );
+            Self::record(&Texture::error(source.name().clone(), &error), rhi, &mut cb).unwrap()
+        });
+
+        cb.build()
+            .unwrap()
+            .execute(rhi.queues.graphics_queue.clone())
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        texture
+    }
+}
+
+impl VKTexture {
+    /// Largest size of the level that is uploaded when the texture is created.
+    /// The finer levels are streamed in once the shading samples them.
+    const TAIL_SIZE: u32 = 64;
+
+    /// Creates the image with the coarse levels of the texture and records their upload into cb instead of submitting it.
+    /// The finer levels are read from the source again when they are streamed in, see record_resident.
+    /// Block compressed textures in a format the device cannot sample are decoded on the CPU.
+    /// Fails if the blocks cannot be decoded or the texels do not match the size.
+    pub fn record<T: TextureInterface>(
+        source: &T,
+        rhi: &VKRHI,
+        cb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<Self, AssetError> {
+        let color_space = source.color_space();
+        let [width, height, _] = source.size();
+        let (format, level_source, mip_levels) = match source.block_format() {
+            // Uncompressed textures only store the top level, so the mips are generated on upload
+            None => {
+                let format = Self::format(source.pixel_format(), color_space);
+                let level_source = if Self::can_blit(rhi, format) {
+                    LevelSource::Blitted
+                } else {
+                    LevelSource::Generated
+                };
+                (format, level_source, texture::mip_levels(width, height))
+            }
+            Some(block_format) => {
+                let format = Self::block_format(block_format, color_space);
+                let mip_levels = source.mips().len() as u32;
+                if Self::is_supported(rhi, format) {
+                    (format, LevelSource::Stored, mip_levels)
+                } else {
+                    (
+                        Self::format(PixelFormat::Rgba8, color_space),
+                        LevelSource::Decoded(block_format),
+                        mip_levels,
+                    )
+                }
+            }
+        };
+        let size = [width, height];
+        let first_mip = (0..mip_levels)
+            .find(|level| width.max(height) >> level <= Self::TAIL_SIZE)
+            .unwrap_or(mip_levels - 1);
+        let image = Self::create_image(rhi, format, size, first_mip..mip_levels);
+        let texture = Self {
+            image: Self::create_view(image.clone()),
+            format,
+            size,
+            mip_levels,
+            level_source,
+            first_mip,
+            uuid: 0,
+        };
+        texture.record_source_levels(source, first_mip..mip_levels, &image, first_mip, rhi, cb)?;
+        Ok(texture)
+    }
+
+    /// Creates a new image with the levels from first_mip on and records their upload into cb.
+    /// The levels the resident image holds from resident_mip on are copied from it, only finer ones are read from the source.
+    /// The image of the texture itself keeps its levels.
+    pub fn record_resident<T: TextureInterface>(
+        &self,
+        source: &T,
+        resident: &Arc<ImageView>,
+        resident_mip: u32,
+        first_mip: u32,
+        rhi: &VKRHI,
+        cb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<Arc<ImageView>, AssetError> {
+        let image = Self::create_image(rhi, self.format, self.size, first_mip..self.mip_levels);
+        if first_mip < resident_mip {
+            self.record_source_levels(source, first_mip..resident_mip, &image, first_mip, rhi, cb)?;
+        }
+        Self::copy_levels(
+            resident.image(),
+            resident_mip,
+            &image,
+            first_mip,
+            first_mip.max(resident_mip)..self.mip_levels,
+            cb,
+        )
+        .unwrap();
+        Ok(Self::create_view(image))
+    }
+
+    /// Records the upload of the levels of the source into the image, whose first level is first_mip.
+    /// The texels only live in staging memory until the upload finished.
+    fn record_source_levels<T: TextureInterface>(
+        &self,
+        source: &T,
+        levels: Range<u32>,
+        image: &Arc<Image>,
+        first_mip: u32,
+        rhi: &VKRHI,
+        cb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<(), AssetError> {
+        let texels = match self.level_source {
+            LevelSource::Stored => {
+                source.mips()[levels.start as usize..levels.end as usize].concat()
+            }
+            LevelSource::Decoded(block_format) => {
+                Self::decode(source, block_format, levels.clone()).ok_or_else(|| {
+                    AssetError::UnsupportedTextureFormat {
+                        path: source.path().to_path_buf(),
+                        format: format!("{:?}", block_format),
+                    }
+                })?
+            }
+            LevelSource::Generated => {
+                let [width, height] = self.size;
+                let mips =
+                    texture::generate_mips(source.pixel_format(), width, height, source.pixels())
+                        .ok_or_else(|| AssetError::Parse {
+                        path: source.path().to_path_buf(),
+                        message: "The texels do not match the size of the texture".into(),
+                    })?;
+                let start = self.levels_size(0..levels.start) as usize;
+                let end = self.levels_size(0..levels.end) as usize;
+                mips[start..end].to_vec()
+            }
+            // The top level is uploaded into a scratch image, which the command buffer keeps alive until it finished
+            LevelSource::Blitted => {
+                let scratch = Self::create_scratch_image(rhi, self.format, self.size, levels.end);
+                let staging_buffer = Self::staging_buffer(rhi, source.pixels().to_vec());
+                Self::copy_buffer_to_levels(staging_buffer, &scratch, 0..1, cb).unwrap();
+                Self::generate_mips(&scratch, self.size, levels.end, 1, cb).unwrap();
+                Self::copy_levels(&scratch, 0, image, first_mip, levels, cb).unwrap();
+                return Ok(());
+            }
+        };
+        let staging_buffer = Self::staging_buffer(rhi, texels);
+        Self::copy_buffer_to_levels(
+            staging_buffer,
+            image,
+            levels.start - first_mip..levels.end - first_mip,
+            cb,
+        )
+        .unwrap();
+        Ok(())
+    }
+
+    /// Creates an image for the levels of a texture in the range.
+    /// Its levels can be copied into the next image with other levels resident.
+    fn create_image(rhi: &VKRHI, format: Format, size: [u32; 2], levels: Range<u32>) -> Arc<Image> {
+        Self::new_image(
+            rhi,
+            format,
+            Self::level_extent(size, levels.start),
+            levels.len() as u32,
+            ImageUsage::SAMPLED | ImageUsage::TRANSFER_DST | ImageUsage::TRANSFER_SRC,
+        )
+    }
+
+    /// Creates an image for the levels of a texture down to mip_levels, in which the mips are blitted
+    fn create_scratch_image(
+        rhi: &VKRHI,
+        format: Format,
+        size: [u32; 2],
+        mip_levels: u32,
+    ) -> Arc<Image> {
+        Self::new_image(
+            rhi,
+            format,
+            Self::level_extent(size, 0),
+            mip_levels,
+            ImageUsage::TRANSFER_DST | ImageUsage::TRANSFER_SRC,
+        )
+    }
+
+    fn new_image(
+        rhi: &VKRHI,
+        format: Format,
+        extent: [u32; 3],
+        mip_levels: u32,
+        usage: ImageUsage,
+    ) -> Arc<Image> {
         let image_create_info = ImageCreateInfo {
             image_type: ImageType::Dim2d,
-            format: Format::B8G8R8A8_SRGB,
-            extent: source.size(),
+            format,
+            extent,
             array_layers: 1,
-            mip_levels: 1,
+            mip_levels,
             samples: SampleCount::Sample1,
             tiling: ImageTiling::Optimal,
-            usage: ImageUsage::SAMPLED | ImageUsage::TRANSFER_DST,
+            usage,
             sharing: Sharing::Exclusive,
             initial_layout: ImageLayout::Undefined,
             ..ImageCreateInfo::default()
@@ -67,20 +302,32 @@ impl RHITextureInterface for VKTexture {
             memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
             ..AllocationCreateInfo::default()
         };
+        // Streamed images come and go, so they share the allocator instead of each holding its own blocks
+        Image::new(
+            rhi.buffer_allocator.clone(),
+            image_create_info,
+            allocation_info,
+        )
+        .unwrap()
+    }
+
+    fn create_view(image: Arc<Image>) -> Arc<ImageView> {
         let image_view_create_info = ImageViewCreateInfo {
             view_type: ImageViewType::Dim2d,
-            format: Format::B8G8R8A8_SRGB,
+            format: image.format(),
             subresource_range: ImageSubresourceRange {
                 aspects: ImageAspects::COLOR,
-                mip_levels: 0..1,
+                mip_levels: 0..image.mip_levels(),
                 array_layers: 0..1,
             },
             ..ImageViewCreateInfo::default()
         };
-        let alloc = Arc::new(StandardMemoryAllocator::new_default(rhi.device.clone()));
+        ImageView::new(image, image_view_create_info).unwrap()
+    }
 
-        let staging_buffer = Buffer::from_iter(
-            alloc.clone(),
+    fn staging_buffer(rhi: &VKRHI, texels: Vec<u8>) -> Subbuffer<[u8]> {
+        Buffer::from_iter(
+            rhi.buffer_allocator.clone(),
             BufferCreateInfo {
                 usage: BufferUsage::TRANSFER_SRC,
                 sharing: Sharing::Exclusive,
@@ -91,63 +338,177 @@ impl RHITextureInterface for VKTexture {
                     | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
                 ..AllocationCreateInfo::default()
             },
-            source.pixels().iter().copied(),
+            texels,
         )
-        .unwrap();
+        .unwrap()
+    }
 
-        let image = Image::new(alloc, image_create_info, allocation_info).unwrap();
+    /// Extent of a level of a texture with the size
+    fn level_extent([width, height]: [u32; 2], level: u32) -> [u32; 3] {
+        [(width >> level).max(1), (height >> level).max(1), 1]
+    }
 
-        let mut cb = rhi
-            .command_buffer_interface
-            .primary_command_buffer(rhi.queues.graphics_queue.queue_family_index());
+    /// Bytes of the levels in the range
+    fn levels_size(&self, levels: Range<u32>) -> u64 {
+        levels
+            .map(|level| Self::level_size(self.format, Self::level_extent(self.size, level)))
+            .sum()
+    }
 
-        Self::copy_buffer_to_image(staging_buffer, &image, &mut cb).unwrap();
+    /// Bytes of one layer of a level with the extent
+    fn level_size(format: Format, extent: [u32; 3]) -> u64 {
+        format.block_size()
+            * extent[0].div_ceil(format.block_extent()[0]) as u64
+            * extent[1].div_ceil(format.block_extent()[1]) as u64
+    }
 
-        let mip_levels = ((max(source.size()[0], source.size()[1]) as f32)
-            .log2()
-            .floor() as u32)
-            + 1;
-        Self::generate_mips(
-            &image,
-            [source.size()[0], source.size()[1]],
-            mip_levels,
-            1,
-            &mut cb,
-        )
-        .unwrap();
+    /// Only 8 bit texels can be decoded from sRGB
+    fn format(pixel_format: PixelFormat, color_space: ColorSpace) -> Format {
+        match (pixel_format, color_space) {
+            (PixelFormat::Rgba8, ColorSpace::Srgb) => Format::R8G8B8A8_SRGB,
+            (PixelFormat::Rgba8, ColorSpace::Linear) => Format::R8G8B8A8_UNORM,
+            (PixelFormat::Rgba16, _) => Format::R16G16B16A16_UNORM,
+            (PixelFormat::Rgba32Float, _) => Format::R32G32B32A32_SFLOAT,
+        }
+    }
 
-        let image_view = ImageView::new(image, image_view_create_info).unwrap();
+    fn block_format(block_format: BlockFormat, color_space: ColorSpace) -> Format {
+        let srgb = color_space == ColorSpace::Srgb;
+        match block_format {
+            BlockFormat::Bc1 if srgb => Format::BC1_RGBA_SRGB_BLOCK,
+            BlockFormat::Bc1 => Format::BC1_RGBA_UNORM_BLOCK,
+            BlockFormat::Bc2 if srgb => Format::BC2_SRGB_BLOCK,
+            BlockFormat::Bc2 => Format::BC2_UNORM_BLOCK,
+            BlockFormat::Bc3 if srgb => Format::BC3_SRGB_BLOCK,
+            BlockFormat::Bc3 => Format::BC3_UNORM_BLOCK,
+            BlockFormat::Bc4 => Format::BC4_UNORM_BLOCK,
+            BlockFormat::Bc5 => Format::BC5_UNORM_BLOCK,
+            BlockFormat::Bc6hUfloat => Format::BC6H_UFLOAT_BLOCK,
+            BlockFormat::Bc6hSfloat => Format::BC6H_SFLOAT_BLOCK,
+            BlockFormat::Bc7 if srgb => Format::BC7_SRGB_BLOCK,
+            BlockFormat::Bc7 => Format::BC7_UNORM_BLOCK,
+        }
+    }
 
-        cb.build()
-            .unwrap()
-            .execute(rhi.queues.graphics_queue.clone())
-            .unwrap()
-            .then_signal_fence_and_flush()
-            .unwrap()
-            .wait(None)
-            .unwrap();
+    /// Whether the device can sample and filter images of the format
+    fn is_supported(rhi: &VKRHI, format: Format) -> bool {
+        let features = FormatFeatures::SAMPLED_IMAGE | FormatFeatures::SAMPLED_IMAGE_FILTER_LINEAR;
+        // Block compressed formats can only be used with the feature enabled
+        rhi.device.enabled_features().texture_compression_bc
+            && rhi
+                .physical_device
+                .format_properties(format)
+                .is_ok_and(|properties| properties.optimal_tiling_features.contains(features))
+    }
 
-        Self {
-            image: image_view,
-            uuid: 0,
+    /// Whether mips of the format can be generated with linear blits
+    fn can_blit(rhi: &VKRHI, format: Format) -> bool {
+        let features = FormatFeatures::BLIT_SRC
+            | FormatFeatures::BLIT_DST
+            | FormatFeatures::SAMPLED_IMAGE_FILTER_LINEAR;
+        rhi.physical_device
+            .format_properties(format)
+            .is_ok_and(|properties| properties.optimal_tiling_features.contains(features))
+    }
+
+    /// RGBA texels of the stored mips in the range, or None if there is no decoder for the blocks
+    fn decode<T: TextureInterface>(
+        source: &T,
+        block_format: BlockFormat,
+        levels: Range<u32>,
+    ) -> Option<Vec<u8>> {
+        let [width, height, _] = source.size();
+        let mips = source.mips();
+        let mut texels = Vec::new();
+        for level in levels {
+            let [level_width, level_height, _] = Self::level_extent([width, height], level);
+            let data = mips.get(level as usize)?;
+            let image = block_compression::decode(block_format, data, level_width, level_height)?;
+            texels.extend(image.into_raw());
         }
+        Some(texels)
     }
-}
 
-impl VKTexture {
+    /// Copies the first mip_levels levels of the image, see copy_buffer_to_levels
     pub fn copy_buffer_to_image(
         src_buffer: Subbuffer<[u8]>,
         dst_image: &Arc<Image>,
+        mip_levels: u32,
         cb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
     ) -> Result<(), Validated<VulkanError>> {
-        cb.copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(
-            src_buffer,
-            dst_image.clone(),
-        ))?;
+        Self::copy_buffer_to_levels(src_buffer, dst_image, 0..mip_levels, cb)
+    }
+
+    /// Copies mip levels that follow each other tightly packed in the buffer.
+    /// Each level holds all array layers of the image.
+    fn copy_buffer_to_levels(
+        src_buffer: Subbuffer<[u8]>,
+        dst_image: &Arc<Image>,
+        mip_levels: Range<u32>,
+        cb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<(), Validated<VulkanError>> {
+        let [width, height, _] = dst_image.extent();
+        let format = dst_image.format();
+        let array_layers = dst_image.array_layers();
+        let mut buffer_offset = 0;
+        let regions = mip_levels
+            .map(|mip_level| {
+                let extent = [(width >> mip_level).max(1), (height >> mip_level).max(1), 1];
+                let region = BufferImageCopy {
+                    buffer_offset,
+                    image_subresource: ImageSubresourceLayers {
+                        aspects: ImageAspects::COLOR,
+                        mip_level,
+                        array_layers: 0..array_layers,
+                    },
+                    image_extent: extent,
+                    ..BufferImageCopy::default()
+                };
+                buffer_offset += array_layers as u64 * Self::level_size(format, extent);
+                region
+            })
+            .collect();
+        cb.copy_buffer_to_image(CopyBufferToImageInfo {
+            regions,
+            ..CopyBufferToImageInfo::buffer_image(src_buffer, dst_image.clone())
+        })?;
+        Ok(())
+    }
+
+    /// Copies the levels between two images of the same texture, which hold its levels from src_first_mip and dst_first_mip on
+    fn copy_levels(
+        src_image: &Arc<Image>,
+        src_first_mip: u32,
+        dst_image: &Arc<Image>,
+        dst_first_mip: u32,
+        levels: Range<u32>,
+        cb: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<(), Box<ValidationError>> {
+        let [width, height, _] = dst_image.extent();
+        let regions = levels
+            .map(|level| ImageCopy {
+                src_subresource: ImageSubresourceLayers {
+                    aspects: ImageAspects::COLOR,
+                    mip_level: level - src_first_mip,
+                    array_layers: 0..1,
+                },
+                dst_subresource: ImageSubresourceLayers {
+                    aspects: ImageAspects::COLOR,
+                    mip_level: level - dst_first_mip,
+                    array_layers: 0..1,
+                },
+                extent: Self::level_extent([width, height], level - dst_first_mip),
+                ..ImageCopy::default()
+            })
+            .collect();
+        cb.copy_image(CopyImageInfo {
+            regions,
+            ..CopyImageInfo::images(src_image.clone(), dst_image.clone())
+        })?;
         Ok(())
     }
 
-    // TODO: There is a safety check missing regarding image Format
+    /// The format must support linear blits
     fn generate_mips(
         image: &Arc<Image>,
         size: [u32; 2],
@@ -157,7 +518,7 @@ impl VKTexture {
     ) -> Result<(), Box<ValidationError>> {
         let mut width = size[0];
         let mut height = size[1];
-        for mip in 0..mip_levels {
+        for mip in 1..mip_levels {
             let next_width = max(width / 2, 1);
             let next_height = max(height / 2, 1);
 
@@ -264,4 +625,20 @@ impl VKTexture {
     pub fn image_view(&self) -> &Arc<ImageView> {
         &self.image
     }
+
+    /// First level of the image of the texture
+    pub fn first_mip(&self) -> u32 {
+        self.first_mip
+    }
+
+    pub fn size(&self) -> [u32; 2] {
+        self.size
+    }
+
+    /// Bytes of every level, largest first
+    pub fn mip_sizes(&self) -> Vec<u64> {
+        (0..self.mip_levels)
+            .map(|level| self.levels_size(level..level + 1))
+            .collect()
+    }
 }
@@ -409,18 +409,23 @@ impl ShaderObject {
     }
 
     pub fn write_image_view(self: &Arc<Self>, offset: ShaderOffset, view: Arc<ImageView>) {
-        let write = WriteDescriptorSet::image_view_with_layout(
+        let write = WriteDescriptorSet::image_view_with_layout_array(
             offset.binding_offset,
-            DescriptorImageViewInfo {
+            offset.binding_array_element,
+            [DescriptorImageViewInfo {
                 image_view: view,
                 image_layout: ImageLayout::ShaderReadOnlyOptimal, // TODO: Is this always correct?
-            },
+            }],
         );
         self.queue_descriptor_writes([write].into_iter());
     }
 
     pub fn write_sampler(self: &Arc<Self>, offset: ShaderOffset, sampler: Arc<Sampler>) {
-        let write = WriteDescriptorSet::sampler(offset.binding_offset, sampler);
+        let write = WriteDescriptorSet::sampler_array(
+            offset.binding_offset,
+            offset.binding_array_element,
+            [sampler],
+        );
         self.queue_descriptor_writes([write].into_iter());
     }
 
@@ -430,13 +435,16 @@ impl ShaderObject {
         view: Arc<ImageView>,
         sampler: Arc<Sampler>,
     ) {
-        let write = WriteDescriptorSet::image_view_with_layout_sampler(
+        let write = WriteDescriptorSet::image_view_with_layout_sampler_array(
             offset.binding_offset,
-            DescriptorImageViewInfo {
-                image_view: view,
-                image_layout: ImageLayout::ShaderReadOnlyOptimal, // TODO: Is this always correct?
-            },
-            sampler,
+            offset.binding_array_element,
+            [(
+                DescriptorImageViewInfo {
+                    image_view: view,
+                    image_layout: ImageLayout::ShaderReadOnlyOptimal, // TODO: Is this always correct?
+                },
+                sampler,
+            )],
         );
         self.queue_descriptor_writes([write].into_iter());
     }
@@ -1,4 +1,8 @@
-use std::path::Path;
+use std::{
+    cell::{Ref, RefCell},
+    ffi::CString,
+    path::Path,
+};
 
 use shader_slang::{
     CompileTarget, CompilerOptions, GlobalSession, OptimizationLevel, Session, SessionDesc,
@@ -6,7 +10,9 @@ use shader_slang::{
 };
 
 pub struct SlangCompiler {
-    session: Session,
+    global_session: GlobalSession,
+    search_path: CString,
+    session: RefCell<Session>,
 }
 
 impl SlangCompiler {
@@ -16,10 +22,20 @@ impl SlangCompiler {
             "Using slang compiler version {}",
             global_session.build_tag_string()
         );
+        let search_path = CString::new(shader_base_path.to_str().unwrap()).unwrap();
+        let session = Self::create_session(&global_session, &search_path);
+        Self {
+            global_session,
+            search_path,
+            session: RefCell::new(session),
+        }
+    }
+
+    fn create_session(global_session: &GlobalSession, search_path: &CString) -> Session {
         let targets = [TargetDesc::default()
             .format(CompileTarget::Spirv)
             .profile(global_session.find_profile("spirv_1_6"))];
-        let search_paths = [shader_base_path.to_str().unwrap().as_ptr() as *const i8];
+        let search_paths = [search_path.as_ptr() as *const i8];
         let options = CompilerOptions::default()
             .optimization(OptimizationLevel::High)
             .emit_spirv_directly(true)
@@ -29,11 +45,15 @@ impl SlangCompiler {
             .targets(&targets)
             .search_paths(search_paths.as_slice())
             .options(&options);
-        let session = global_session.create_session(&session_description).unwrap();
-        Self { session }
+        global_session.create_session(&session_description).unwrap()
+    }
+
+    pub fn session(&self) -> Ref<Session> {
+        self.session.borrow()
     }
 
-    pub fn session(&self) -> &Session {
-        &self.session
+    /// Replaces the session so that modules are loaded from disk again instead of the session's module cache
+    pub fn reload(&self) {
+        *self.session.borrow_mut() = Self::create_session(&self.global_session, &self.search_path);
     }
 }
@@ -2,9 +2,10 @@ use asset_system::assets::AssetHandle;
 use egui_winit_vulkano::egui::Ui;
 use model::Model;
 
-use super::assets::asset_traits::SceneInterface;
-use crate::application::scene::camera::Camera;
+use super::assets::{AssetManager::AssetManager, asset_traits::SceneInterface};
+use crate::application::scene::{animation::ModelAnimation, camera::Camera};
 
+pub mod animation;
 pub mod camera;
 pub mod model;
 pub mod transform;
@@ -12,6 +13,11 @@ pub mod transform;
 pub struct Scene {
     pub models: Vec<AssetHandle<Model>>,
     pub camera: Camera,
+    /// Keyframe animations that move the models
+    pub animations: Vec<ModelAnimation>,
+    /// Indices of the models with a skeleton, the only ones with poses to animate.
+    /// Models that get a skeleton after they are added have to be listed as well.
+    pub skinned_models: Vec<usize>,
 }
 
 impl Scene {
@@ -19,13 +25,40 @@ impl Scene {
         Self {
             models: vec![],
             camera: Camera::default(),
+            animations: vec![],
+            skinned_models: vec![],
+        }
+    }
+
+    /// Advances the animations and moves their models to the new positions.
+    /// Paused models are moved as well, so scrubbing the time in the GUI takes effect.
+    /// The animation layers of the skinned models are advanced, their poses are sampled when they are drawn.
+    pub fn animate(&mut self, delta_time: f32, asset_manager: &AssetManager) {
+        for model in &self.skinned_models {
+            if let Some(mut model) = self
+                .models
+                .get(*model)
+                .and_then(|model| asset_manager.get_mut(model))
+            {
+                model.advance_animations(delta_time);
+            }
+        }
+        for animation in &mut self.animations {
+            animation.advance(delta_time);
+            if let Some(mut model) = self
+                .models
+                .get(animation.model)
+                .and_then(|model| asset_manager.get_mut(model))
+            {
+                model.transform = animation.transform();
+            }
         }
     }
 
     pub fn draw_gui(&mut self, gui: &mut Ui) {
-        /*        self.models.iter_mut().for_each(|model| {
-            model.draw_gui(gui);
-        })*/
+        for (index, animation) in self.animations.iter_mut().enumerate() {
+            gui.push_id(index, |gui| animation.draw_gui(gui));
+        }
     }
 }
 
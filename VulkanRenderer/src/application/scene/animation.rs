@@ -0,0 +1,193 @@
+use egui_winit_vulkano::{egui, egui::Ui};
+use glam::Mat4;
+
+use crate::application::{assets::animation_clip::ChannelKeyframes, scene::transform::Transform};
+
+/// Playback position and settings of an animation
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Playback {
+    pub playing: bool,
+    /// Starts over at the end, otherwise the last keyframe holds
+    pub looping: bool,
+    /// Negative speeds play backwards
+    pub speed: f32,
+    /// Position in seconds
+    pub time: f32,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self {
+            playing: true,
+            looping: true,
+            speed: 1.,
+            time: 0.,
+        }
+    }
+}
+
+impl Playback {
+    /// Moves the position of a playing animation. It wraps around if looping and stops at either end otherwise.
+    pub fn advance(&mut self, delta_time: f32, duration: f32) {
+        if !self.playing {
+            return;
+        }
+        self.time += delta_time * self.speed;
+        if self.looping && duration > 0. {
+            self.time = self.time.rem_euclid(duration);
+        } else if self.time < 0. || self.time > duration {
+            self.time = self.time.clamp(0., duration);
+            self.playing = false;
+        }
+    }
+}
+
+/// Keyframed translation, rotation and scale tracks that move a model of the scene
+pub struct ModelAnimation {
+    pub name: String,
+    /// Index into the models of the scene
+    pub model: usize,
+    /// The tracks animate the transform relative to the parent, like the nodes of a glTF file
+    pub parent: Mat4,
+    /// Holds for the properties that no track animates
+    pub rest: Transform,
+    tracks: Vec<ChannelKeyframes>,
+    /// Time of the last keyframe of all tracks in seconds
+    duration: f32,
+    pub playback: Playback,
+}
+
+impl ModelAnimation {
+    pub fn new(
+        name: String,
+        model: usize,
+        parent: Mat4,
+        rest: Transform,
+        tracks: Vec<ChannelKeyframes>,
+    ) -> Self {
+        let duration = tracks
+            .iter()
+            .map(ChannelKeyframes::duration)
+            .fold(0., f32::max);
+        Self {
+            name,
+            model,
+            parent,
+            rest,
+            tracks,
+            duration,
+            playback: Playback::default(),
+        }
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    pub fn advance(&mut self, delta_time: f32) {
+        self.playback.advance(delta_time, self.duration);
+    }
+
+    /// Transform of the model at the current playback position
+    pub fn transform(&self) -> Transform {
+        let mut local = self.rest;
+        for track in &self.tracks {
+            track.apply(self.playback.time, &mut local);
+        }
+        Transform::from_matrix(self.parent * local.matrix())
+    }
+
+    pub fn draw_gui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            let label = if self.playback.playing {
+                "Pause"
+            } else {
+                "Play"
+            };
+            if ui.button(label).clicked() {
+                // Animations that stopped at their end start over
+                if !self.playback.playing && !self.playback.looping {
+                    if self.playback.speed >= 0. && self.playback.time >= self.duration {
+                        self.playback.time = 0.;
+                    } else if self.playback.speed < 0. && self.playback.time <= 0. {
+                        self.playback.time = self.duration;
+                    }
+                }
+                self.playback.playing = !self.playback.playing;
+            }
+            ui.checkbox(&mut self.playback.looping, "Loop");
+            ui.label(&self.name);
+        });
+        ui.add(egui::Slider::new(&mut self.playback.time, 0f32..=self.duration).text("Time (s)"));
+        ui.add(egui::Slider::new(&mut self.playback.speed, -2f32..=2f32).text("Speed"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Quat, Vec3};
+
+    use super::*;
+    use crate::application::assets::animation_clip::{Interpolation, Keyframes};
+
+    fn slide() -> ModelAnimation {
+        let track = Keyframes::new(
+            vec![0., 2.],
+            vec![Vec3::ZERO, Vec3::new(4., 0., 0.)],
+            Interpolation::Linear,
+        )
+        .unwrap();
+        ModelAnimation::new(
+            "Slide".into(),
+            0,
+            Mat4::IDENTITY,
+            Transform::default(),
+            vec![ChannelKeyframes::Translation(track)],
+        )
+    }
+
+    #[test]
+    fn looping_playback_wraps_around() {
+        let mut playback = Playback::default();
+        playback.advance(2.5, 2.);
+        assert!((playback.time - 0.5).abs() < 1e-5);
+        assert!(playback.playing);
+
+        playback.speed = -1.;
+        playback.advance(1., 2.);
+        assert!((playback.time - 1.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn playback_without_looping_stops_at_the_end() {
+        let mut playback = Playback {
+            looping: false,
+            speed: 2.,
+            ..Playback::default()
+        };
+        playback.advance(1.5, 2.);
+        assert_eq!(playback.time, 2.);
+        assert!(!playback.playing);
+
+        // Paused animations keep their position
+        playback.advance(1., 2.);
+        assert_eq!(playback.time, 2.);
+    }
+
+    #[test]
+    fn tracks_move_the_model_relative_to_its_parent() {
+        let mut animation = slide();
+        animation.rest.rotation = Quat::from_rotation_y(1.);
+        animation.parent = Mat4::from_translation(Vec3::Y);
+        animation.advance(1.);
+        let transform = animation.transform();
+        assert!(transform.location.abs_diff_eq(Vec3::new(2., 1., 0.), 1e-5));
+        // Properties without a track keep their rest value
+        assert!(
+            transform
+                .rotation
+                .abs_diff_eq(Quat::from_rotation_y(1.), 1e-5)
+        );
+        assert_eq!(animation.duration(), 2.);
+    }
+}
@@ -1,19 +1,60 @@
 use asset_system::{
     Asset,
     assets::{AssetHandle, AssetMetadata},
+    reflection::AssetHandles,
+    resource_management::ResourceId,
 };
 use egui_winit_vulkano::egui::Ui;
+use glam::Mat4;
 
 use super::transform::Transform;
 use crate::application::assets::{
-    asset_traits::ModelInterface, material_instance::MaterialInstance, mesh::Mesh,
+    AssetManager::AssetManager, animation_clip::AnimationClip, asset_traits::ModelInterface,
+    material_instance::MaterialInstance, mesh::Mesh, skeleton::Skeleton,
 };
 
+/// Plays an animation clip on the skeleton of a model
+#[derive(Clone)]
+pub struct AnimationLayer {
+    pub clip: AssetHandle<AnimationClip>,
+    /// Playback position in seconds
+    pub time: f32,
+    pub speed: f32,
+    /// How much the layer replaces the pose of the layers before it
+    pub weight: f32,
+    /// Starts over at the end of the clip, otherwise the last keyframe holds
+    pub looping: bool,
+}
+
+impl AnimationLayer {
+    pub fn new(clip: AssetHandle<AnimationClip>) -> Self {
+        Self {
+            clip,
+            time: 0.,
+            speed: 1.,
+            weight: 1.,
+            looping: true,
+        }
+    }
+}
+
+impl AssetHandles for AnimationLayer {
+    fn collect_ids(&self, ids: &mut Vec<ResourceId>) {
+        self.clip.collect_ids(ids);
+    }
+}
+
 #[derive(Asset)]
 pub struct Model {
     pub transform: Transform,
     pub mesh: AssetHandle<Mesh>,
     pub material: AssetHandle<MaterialInstance>,
+    /// Deforms a skinned mesh. Without it, skinned meshes are drawn in their bind pose.
+    #[asset(handle)]
+    pub skeleton: Option<AssetHandle<Skeleton>>,
+    /// Blended in order on top of the rest pose of the skeleton
+    #[asset(handle)]
+    pub animations: Vec<AnimationLayer>,
     asset_metadata: AssetMetadata,
 }
 
@@ -32,9 +73,40 @@ impl Model {
             transform,
             mesh,
             material,
+            skeleton: None,
+            animations: Vec::new(),
             asset_metadata: AssetMetadata::new(name),
         }
     }
+
+    /// Moves the playback position of all animation layers
+    pub fn advance_animations(&mut self, delta_time: f32) {
+        for layer in &mut self.animations {
+            layer.time += delta_time * layer.speed;
+        }
+    }
+
+    /// Samples the animation layers and returns the skinning matrix of every joint.
+    /// None if the model has no skeleton or it is not loaded. Clips that are not loaded are skipped.
+    pub fn joint_matrices(&self, asset_manager: &AssetManager) -> Option<Vec<Mat4>> {
+        let skeleton = asset_manager.get(self.skeleton.as_ref()?)?;
+        let mut pose = skeleton.rest_pose();
+        for layer in &self.animations {
+            let Some(clip) = asset_manager.get(&layer.clip) else {
+                continue;
+            };
+            let time = if layer.looping && clip.duration() > 0. {
+                layer.time.rem_euclid(clip.duration())
+            } else {
+                layer.time.clamp(0., clip.duration())
+            };
+            // Channels only overwrite the joints they animate, the others keep their rest transform
+            let mut layer_pose = skeleton.rest_pose();
+            clip.sample(time, &mut layer_pose);
+            pose.blend(&layer_pose, layer.weight);
+        }
+        Some(skeleton.joint_matrices(&pose))
+    }
 }
 
 impl ModelInterface for Model {
@@ -19,6 +19,16 @@ impl Default for Transform {
 }
 
 impl Transform {
+    /// Decomposes an affine matrix without shear
+    pub fn from_matrix(matrix: Mat4) -> Self {
+        let (scale, rotation, location) = matrix.to_scale_rotation_translation();
+        Self {
+            location,
+            rotation,
+            scale,
+        }
+    }
+
     pub fn matrix(&self) -> Mat4 {
         //Mat4::from_translation(self.location) * Mat4::from_quat(self.rotation) * Mat4::from_scale(self.scale)
         Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.location)
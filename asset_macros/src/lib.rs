@@ -1,24 +1,98 @@
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{DeriveInput, parse_macro_input};
+use quote::{ToTokens, quote};
+use syn::{DeriveInput, Expr, Field, Ident, Type, parse_macro_input};
 
-#[proc_macro_derive(Asset)]
+/// Reflection settings of a single field, read from its `#[asset(...)]` attributes
+struct ReflectedField {
+    ident: Ident,
+    ty: Type,
+    range: Option<(Expr, Expr)>,
+    handle: bool,
+}
+
+#[proc_macro_derive(Asset, attributes(asset))]
 pub fn derive_asset(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
 
     let name = input.ident;
 
-    /*if let syn::Data::Struct(data) = input.data {
-        if let syn::Fields::Named(fields) = data.fields {
+    let mut fields = Vec::new();
+    if let syn::Data::Struct(data) = input.data
+        && let syn::Fields::Named(named) = data.fields
+    {
+        for field in named.named {
+            match reflect_field(field) {
+                Ok(Some(field)) => fields.push(field),
+                Ok(None) => {}
+                Err(error) => return error.to_compile_error().into(),
+            }
+        }
+    }
 
+    let field_infos = fields.iter().map(|field| {
+        let field_name = field.ident.to_string();
+        let type_name = type_name(&field.ty);
+        let range = match &field.range {
+            Some((start, end)) => quote! {
+                Some(::core::ops::Range { start: (#start) as f64, end: (#end) as f64 })
+            },
+            None => quote! { None },
+        };
+        let handle = field.handle;
+        quote! {
+            asset_system::reflection::FieldInfo {
+                name: #field_name,
+                type_name: #type_name,
+                range: #range,
+                handle: #handle,
+            }
         }
-    }*/
+    });
+    let field_names = fields
+        .iter()
+        .map(|field| field.ident.to_string())
+        .collect::<Vec<_>>();
+    let field_idents = fields.iter().map(|field| &field.ident).collect::<Vec<_>>();
+    // Every handle field is a dependency of the asset
+    let handle_fields = fields
+        .iter()
+        .filter(|field| field.handle)
+        .map(|field| &field.ident);
 
     quote! {
         impl asset_system::assets::Asset for #name {
-            fn asset_metadata(&self) -> &AssetMetadata {
+            fn asset_metadata(&self) -> &asset_system::assets::AssetMetadata {
                 &self.asset_metadata
             }
+
+            fn asset_metadata_mut(&mut self) -> &mut asset_system::assets::AssetMetadata {
+                &mut self.asset_metadata
+            }
+
+            fn dependencies(&self) -> Vec<asset_system::resource_management::ResourceId> {
+                let mut ids = Vec::new();
+                #(asset_system::reflection::AssetHandles::collect_ids(&self.#handle_fields, &mut ids);)*
+                ids
+            }
+
+            fn fields() -> &'static [asset_system::reflection::FieldInfo] {
+                const FIELDS: &[asset_system::reflection::FieldInfo] = &[#(#field_infos),*];
+                FIELDS
+            }
+
+            fn field(&self, name: &str) -> Option<&dyn ::core::any::Any> {
+                match name {
+                    #(#field_names => Some(&self.#field_idents),)*
+                    _ => None,
+                }
+            }
+
+            fn field_mut(&mut self, name: &str) -> Option<&mut dyn ::core::any::Any> {
+                match name {
+                    #(#field_names => Some(&mut self.#field_idents),)*
+                    _ => None,
+                }
+            }
         }
 
         impl asset_system::resource_management::Resource for #name {
@@ -29,3 +103,84 @@ pub fn derive_asset(input: TokenStream) -> TokenStream {
     }
     .into()
 }
+
+/// Returns None for fields that are not reflected: the asset metadata and fields marked with `#[asset(skip)]`
+fn reflect_field(field: Field) -> syn::Result<Option<ReflectedField>> {
+    let Some(ident) = field.ident else {
+        return Ok(None);
+    };
+    if ident == "asset_metadata" {
+        return Ok(None);
+    }
+
+    let mut skip = false;
+    let mut range = None;
+    let mut handle = is_asset_handle(&field.ty);
+    for attribute in field
+        .attrs
+        .iter()
+        .filter(|attribute| attribute.path().is_ident("asset"))
+    {
+        attribute.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                skip = true;
+            } else if meta.path.is_ident("handle") {
+                handle = true;
+            } else if meta.path.is_ident("range") {
+                range = Some(parse_range(meta.value()?.parse()?)?);
+            } else {
+                return Err(meta.error("expected `skip`, `handle` or `range = min..max`"));
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok((!skip).then_some(ReflectedField {
+        ident,
+        ty: field.ty,
+        range,
+        handle,
+    }))
+}
+
+fn parse_range(expr: Expr) -> syn::Result<(Expr, Expr)> {
+    if let Expr::Range(range) = &expr
+        && let syn::RangeLimits::HalfOpen(_) = range.limits
+        && let (Some(start), Some(end)) = (&range.start, &range.end)
+    {
+        return Ok((start.as_ref().clone(), end.as_ref().clone()));
+    }
+    Err(syn::Error::new_spanned(
+        expr,
+        "expected a range with both bounds like `0.0..1.0`",
+    ))
+}
+
+/// Token streams are printed with spaces between all tokens. Only the spaces between two words are kept,
+/// so that `&'static str` and `dyn Trait` keep theirs while `Vec < u8 >` becomes `Vec<u8>`.
+fn type_name(ty: &Type) -> String {
+    let printed = ty.to_token_stream().to_string();
+    let is_word = |character: Option<&char>| {
+        character.is_some_and(|character| character.is_alphanumeric() || *character == '_')
+    };
+    let mut name = String::with_capacity(printed.len());
+    let mut characters = printed.chars().peekable();
+    while let Some(character) = characters.next() {
+        if character != ' ' || (is_word(name.chars().last().as_ref()) && is_word(characters.peek()))
+        {
+            name.push(character);
+        }
+    }
+    name
+}
+
+fn is_asset_handle(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "AssetHandle"),
+        _ => false,
+    }
+}
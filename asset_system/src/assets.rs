@@ -1,10 +1,20 @@
-use std::marker::PhantomData;
+use std::{
+    any::Any,
+    marker::PhantomData,
+    sync::{Arc, Weak},
+};
 
-use crate::resource_management::{Resource, ResourceManager};
+use crate::{
+    reflection::FieldInfo,
+    resource_management::{Resource, ResourceId, ResourceManager},
+};
 
-pub trait Asset: Resource + Sized {
+/// Assets are shared between importer threads and the render thread, so they must be Send and Sync
+pub trait Asset: Resource + Sized + Send + Sync {
     fn asset_metadata(&self) -> &AssetMetadata;
 
+    fn asset_metadata_mut(&mut self) -> &mut AssetMetadata;
+
     fn uuid(&self) -> usize {
         self.asset_metadata().uuid
     }
@@ -12,39 +22,193 @@ pub trait Asset: Resource + Sized {
     fn name(&self) -> &String {
         &self.asset_metadata().name
     }
+
+    fn load_state(&self) -> &LoadState {
+        &self.asset_metadata().load_state
+    }
+
+    /// Assets this asset holds handles to. They are kept loaded as long as this asset exists.
+    fn dependencies(&self) -> Vec<ResourceId> {
+        Vec::new()
+    }
+
+    /// Reflection data of all fields that are not marked with `#[asset(skip)]`
+    fn fields() -> &'static [FieldInfo] {
+        &[]
+    }
+
+    /// Returns None if the field is unknown or skipped
+    fn field(&self, _name: &str) -> Option<&dyn Any> {
+        None
+    }
+
+    fn field_mut(&mut self, _name: &str) -> Option<&mut dyn Any> {
+        None
+    }
+}
+
+/// Progress of a background load. Until the asset is ready, it holds placeholder data.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum LoadState {
+    Pending,
+    Ready,
+    Failed(String),
 }
 
 pub struct AssetMetadata {
     pub uuid: usize,
     name: String,
+    load_state: LoadState,
 }
 
 impl AssetMetadata {
     pub fn new(name: String) -> Self {
-        Self { uuid: 0, name }
+        Self {
+            uuid: 0,
+            name,
+            load_state: LoadState::Ready,
+        }
+    }
+
+    /// Metadata of a placeholder asset whose data is still being loaded
+    pub fn pending(name: String) -> Self {
+        Self {
+            load_state: LoadState::Pending,
+            ..Self::new(name)
+        }
     }
 
     pub fn uuid(&self) -> usize {
         self.uuid
     }
+
+    pub fn load_state(&self) -> &LoadState {
+        &self.load_state
+    }
+
+    pub fn set_load_state(&mut self, load_state: LoadState) {
+        self.load_state = load_state;
+    }
 }
 
 pub struct AssetHandle<T: Asset> {
-    pub uuid: usize,
+    pub id: ResourceId,
     pub _phantom: PhantomData<T>,
 }
 
 impl<T: Asset + 'static> AssetHandle<T> {
+    pub fn new(id: ResourceId) -> Self {
+        Self {
+            id,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns None if the asset was removed, even if its slot has been reused since
     pub fn get<'a>(&self, manager: &'a ResourceManager) -> Option<&'a T> {
-        manager.get(self.uuid)
+        manager.get(self.id)
+    }
+
+    pub fn get_mut<'a>(&self, manager: &'a mut ResourceManager) -> Option<&'a mut T> {
+        manager.get_mut(self.id)
+    }
+
+    pub fn is_valid(&self, manager: &ResourceManager) -> bool {
+        manager.contains::<T>(self.id)
+    }
+
+    /// Returns None if the asset was removed
+    pub fn load_state<'a>(&self, manager: &'a ResourceManager) -> Option<&'a LoadState> {
+        Some(self.get(manager)?.load_state())
+    }
+
+    pub fn is_ready(&self, manager: &ResourceManager) -> bool {
+        self.load_state(manager) == Some(&LoadState::Ready)
+    }
+
+    pub fn uuid(&self) -> usize {
+        self.id.uuid
     }
 }
 
 impl<T: Asset> Clone for AssetHandle<T> {
     fn clone(&self) -> Self {
         Self {
-            uuid: self.uuid,
+            id: self.id,
             _phantom: PhantomData,
         }
     }
 }
+
+impl<T: Asset> PartialEq for AssetHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T: Asset> Eq for AssetHandle<T> {}
+
+/// Handle that keeps the asset loaded while it exists
+pub struct StrongAssetHandle<T: Asset> {
+    handle: AssetHandle<T>,
+    _counter: Arc<()>,
+}
+
+/// Handle that tracks an asset without keeping it loaded
+pub struct WeakAssetHandle<T: Asset> {
+    handle: AssetHandle<T>,
+    counter: Weak<()>,
+}
+
+impl<T: Asset + 'static> StrongAssetHandle<T> {
+    pub(crate) fn new(handle: AssetHandle<T>, counter: Arc<()>) -> Self {
+        Self {
+            handle,
+            _counter: counter,
+        }
+    }
+
+    pub fn handle(&self) -> &AssetHandle<T> {
+        &self.handle
+    }
+
+    pub fn downgrade(&self) -> WeakAssetHandle<T> {
+        WeakAssetHandle::new(self.handle.clone(), Arc::downgrade(&self._counter))
+    }
+}
+
+impl<T: Asset + 'static> WeakAssetHandle<T> {
+    pub(crate) fn new(handle: AssetHandle<T>, counter: Weak<()>) -> Self {
+        Self { handle, counter }
+    }
+
+    /// Returns None if the asset has been unloaded
+    pub fn upgrade(&self) -> Option<StrongAssetHandle<T>> {
+        Some(StrongAssetHandle::new(
+            self.handle.clone(),
+            self.counter.upgrade()?,
+        ))
+    }
+
+    pub fn handle(&self) -> &AssetHandle<T> {
+        &self.handle
+    }
+}
+
+impl<T: Asset> Clone for StrongAssetHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            handle: self.handle.clone(),
+            _counter: self._counter.clone(),
+        }
+    }
+}
+
+impl<T: Asset> Clone for WeakAssetHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            handle: self.handle.clone(),
+            counter: self.counter.clone(),
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use crate::{
+    assets::{Asset, AssetHandle, StrongAssetHandle, WeakAssetHandle},
+    resource_management::ResourceId,
+};
+
+struct DependencyNode {
+    dependencies: Vec<ResourceId>,
+    dependents: HashSet<ResourceId>,
+    /// Strong and weak handles hold clones of this counter
+    handle_counter: Arc<()>,
+    /// Whether the asset is unloaded once nothing references it anymore
+    collectable: bool,
+}
+
+/// Tracks which assets reference each other and how many handles keep them alive.
+/// An asset is kept alive by the assets depending on it and by its strong handles, which are counted separately.
+#[derive(Default)]
+pub struct DependencyGraph {
+    nodes: HashMap<ResourceId, DependencyNode>,
+}
+
+impl DependencyGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, id: ResourceId, dependencies: Vec<ResourceId>, collectable: bool) {
+        for dependency in &dependencies {
+            if let Some(node) = self.nodes.get_mut(dependency) {
+                node.dependents.insert(id);
+            }
+        }
+        self.nodes.insert(
+            id,
+            DependencyNode {
+                dependencies,
+                dependents: HashSet::new(),
+                handle_counter: Arc::new(()),
+                collectable,
+            },
+        );
+    }
+
+    /// Removes the asset from the graph and returns its dependencies.
+    /// Outstanding weak handles to it fail to upgrade afterwards.
+    pub fn remove(&mut self, id: ResourceId) -> Vec<ResourceId> {
+        let Some(node) = self.nodes.remove(&id) else {
+            return Vec::new();
+        };
+        for dependency in &node.dependencies {
+            if let Some(dependency) = self.nodes.get_mut(dependency) {
+                dependency.dependents.remove(&id);
+            }
+        }
+        node.dependencies
+    }
+
+    /// Replaces the dependencies of an asset and returns the previous ones
+    pub fn set_dependencies(
+        &mut self,
+        id: ResourceId,
+        dependencies: Vec<ResourceId>,
+    ) -> Vec<ResourceId> {
+        let Some(node) = self.nodes.get_mut(&id) else {
+            return Vec::new();
+        };
+        let previous = std::mem::replace(&mut node.dependencies, dependencies.clone());
+        for dependency in &previous {
+            if let Some(node) = self.nodes.get_mut(dependency) {
+                node.dependents.remove(&id);
+            }
+        }
+        for dependency in &dependencies {
+            if let Some(node) = self.nodes.get_mut(dependency) {
+                node.dependents.insert(id);
+            }
+        }
+        previous
+    }
+
+    pub fn contains(&self, id: ResourceId) -> bool {
+        self.nodes.contains_key(&id)
+    }
+
+    /// Assets that hold a handle to this asset
+    pub fn dependents(&self, id: ResourceId) -> impl Iterator<Item = ResourceId> {
+        self.nodes
+            .get(&id)
+            .into_iter()
+            .flat_map(|node| node.dependents.iter().copied())
+    }
+
+    pub fn dependencies(&self, id: ResourceId) -> &[ResourceId] {
+        self.nodes
+            .get(&id)
+            .map(|node| node.dependencies.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Number of strong handles. The graph itself holds the counter, which is not counted.
+    pub fn strong_count(&self, id: ResourceId) -> usize {
+        self.nodes
+            .get(&id)
+            .map(|node| Arc::strong_count(&node.handle_counter) - 1)
+            .unwrap_or(0)
+    }
+
+    /// Number of assets that hold a handle to this asset
+    pub fn dependent_count(&self, id: ResourceId) -> usize {
+        self.nodes
+            .get(&id)
+            .map(|node| node.dependents.len())
+            .unwrap_or(0)
+    }
+
+    pub fn weak_count(&self, id: ResourceId) -> usize {
+        self.nodes
+            .get(&id)
+            .map(|node| Arc::weak_count(&node.handle_counter))
+            .unwrap_or(0)
+    }
+
+    /// Whether the asset can be unloaded because nothing keeps it alive
+    pub fn is_unused(&self, id: ResourceId) -> bool {
+        self.nodes.get(&id).is_some_and(|node| {
+            node.collectable && node.dependents.is_empty() && self.strong_count(id) == 0
+        })
+    }
+
+    /// All collectable assets that are not referenced anymore
+    pub fn unused(&self) -> Vec<ResourceId> {
+        self.nodes
+            .keys()
+            .copied()
+            .filter(|id| self.is_unused(*id))
+            .collect()
+    }
+
+    pub fn strong<T: Asset + 'static>(
+        &self,
+        handle: &AssetHandle<T>,
+    ) -> Option<StrongAssetHandle<T>> {
+        let node = self.nodes.get(&handle.id)?;
+        Some(StrongAssetHandle::new(
+            handle.clone(),
+            node.handle_counter.clone(),
+        ))
+    }
+
+    pub fn weak<T: Asset + 'static>(&self, handle: &AssetHandle<T>) -> Option<WeakAssetHandle<T>> {
+        let node = self.nodes.get(&handle.id)?;
+        Some(WeakAssetHandle::new(
+            handle.clone(),
+            Arc::downgrade(&node.handle_counter),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assets::AssetMetadata, resource_management::Resource};
+
+    struct Texture {
+        metadata: AssetMetadata,
+    }
+
+    impl Resource for Texture {
+        fn set_uuid(&mut self, uuid: usize) {
+            self.metadata.uuid = uuid;
+        }
+    }
+
+    impl Asset for Texture {
+        fn asset_metadata(&self) -> &AssetMetadata {
+            &self.metadata
+        }
+
+        fn asset_metadata_mut(&mut self) -> &mut AssetMetadata {
+            &mut self.metadata
+        }
+    }
+
+    fn id(uuid: usize) -> ResourceId {
+        ResourceId {
+            uuid,
+            generation: 0,
+        }
+    }
+
+    /// Removes the candidates that are unused and continues with their dependencies, like the asset manager does
+    fn release(graph: &mut DependencyGraph, candidates: Vec<ResourceId>) -> Vec<ResourceId> {
+        let mut released = Vec::new();
+        for candidate in candidates {
+            if graph.is_unused(candidate) {
+                released.push(candidate);
+                let dependencies = graph.remove(candidate);
+                released.extend(release(graph, dependencies));
+            }
+        }
+        released
+    }
+
+    #[test]
+    fn dependents_and_handles_are_counted_separately() {
+        let mut graph = DependencyGraph::new();
+        let (texture, material) = (id(1), id(2));
+        graph.insert(texture, Vec::new(), true);
+        graph.insert(material, vec![texture], true);
+        assert_eq!(graph.strong_count(texture), 0);
+        assert_eq!(graph.dependent_count(texture), 1);
+        assert!(!graph.is_unused(texture));
+
+        let handle = graph.strong(&AssetHandle::<Texture>::new(texture)).unwrap();
+        assert_eq!(graph.strong_count(texture), 1);
+        graph.set_dependencies(material, Vec::new());
+        assert_eq!(graph.dependent_count(texture), 0);
+        assert!(!graph.is_unused(texture));
+        drop(handle);
+        assert_eq!(graph.strong_count(texture), 0);
+        assert!(graph.is_unused(texture));
+    }
+
+    #[test]
+    fn releasing_an_asset_releases_its_unused_dependencies() {
+        let mut graph = DependencyGraph::new();
+        let (texture, material, instance, shared) = (id(1), id(2), id(3), id(4));
+        graph.insert(texture, Vec::new(), true);
+        graph.insert(shared, Vec::new(), true);
+        graph.insert(material, vec![texture, shared], true);
+        graph.insert(instance, vec![material], true);
+        // Another asset still uses the shared texture
+        graph.insert(id(5), vec![shared], false);
+
+        let handle = graph
+            .strong(&AssetHandle::<Texture>::new(instance))
+            .unwrap();
+        assert!(graph.unused().is_empty());
+        drop(handle);
+        assert_eq!(graph.unused(), vec![instance]);
+
+        let unused = graph.unused();
+        let released = release(&mut graph, unused);
+        assert_eq!(released, vec![instance, material, texture]);
+        assert!(graph.contains(shared));
+        assert_eq!(graph.dependent_count(shared), 1);
+    }
+
+    #[test]
+    fn uncollectable_assets_are_kept() {
+        let mut graph = DependencyGraph::new();
+        graph.insert(id(1), Vec::new(), false);
+        assert!(!graph.is_unused(id(1)));
+        assert!(graph.unused().is_empty());
+    }
+
+    #[test]
+    fn weak_handles_fail_to_upgrade_after_collection() {
+        let mut graph = DependencyGraph::new();
+        let texture = id(1);
+        graph.insert(texture, Vec::new(), true);
+        let weak = graph.weak(&AssetHandle::<Texture>::new(texture)).unwrap();
+        assert_eq!(graph.weak_count(texture), 1);
+        assert!(graph.is_unused(texture));
+
+        let strong = weak.upgrade().unwrap();
+        assert_eq!(graph.strong_count(texture), 1);
+        drop(strong);
+
+        let unused = graph.unused();
+        assert_eq!(release(&mut graph, unused), vec![texture]);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(weak.handle().id, texture);
+        assert!(graph.weak(&AssetHandle::<Texture>::new(texture)).is_none());
+    }
+}
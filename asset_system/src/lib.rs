@@ -1,4 +1,9 @@
 pub mod assets;
+pub mod dependencies;
+pub mod loader;
+pub mod reflection;
+pub mod registry;
 pub mod resource_management;
+pub mod watcher;
 
 pub use asset_macros::*;
@@ -0,0 +1,56 @@
+use std::ops::Range;
+
+use crate::{
+    assets::{Asset, AssetHandle},
+    resource_management::ResourceId,
+};
+
+/// Reflection data of a single asset field, generated by the Asset derive
+#[derive(Clone, PartialEq, Debug)]
+pub struct FieldInfo {
+    pub name: &'static str,
+    /// The type as written in the struct definition
+    pub type_name: &'static str,
+    /// Valid values of a numeric field, set with `#[asset(range = min..max)]`
+    pub range: Option<Range<f64>>,
+    /// Whether the field holds handles to other assets. Set for `AssetHandle` fields and with `#[asset(handle)]`.
+    pub handle: bool,
+}
+
+impl FieldInfo {
+    /// Returns None if the field is unknown or skipped
+    pub fn find(fields: &'static [FieldInfo], name: &str) -> Option<&'static FieldInfo> {
+        fields.iter().find(|field| field.name == name)
+    }
+}
+
+/// Types that can be marked with `#[asset(handle)]`. Collects the ids of all contained handles.
+pub trait AssetHandles {
+    fn collect_ids(&self, ids: &mut Vec<ResourceId>);
+}
+
+impl<T: Asset> AssetHandles for AssetHandle<T> {
+    fn collect_ids(&self, ids: &mut Vec<ResourceId>) {
+        ids.push(self.id);
+    }
+}
+
+impl<T: AssetHandles> AssetHandles for Option<T> {
+    fn collect_ids(&self, ids: &mut Vec<ResourceId>) {
+        if let Some(handles) = self {
+            handles.collect_ids(ids);
+        }
+    }
+}
+
+impl<T: AssetHandles> AssetHandles for Vec<T> {
+    fn collect_ids(&self, ids: &mut Vec<ResourceId>) {
+        self.iter().for_each(|handles| handles.collect_ids(ids));
+    }
+}
+
+impl<T: AssetHandles, const N: usize> AssetHandles for [T; N] {
+    fn collect_ids(&self, ids: &mut Vec<ResourceId>) {
+        self.iter().for_each(|handles| handles.collect_ids(ids));
+    }
+}
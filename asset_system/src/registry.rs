@@ -0,0 +1,411 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{Display, Formatter},
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+
+/// Stable 128-bit identifier of an asset. Unlike the runtime uuid, this is persisted in the registry manifest
+/// and stays the same across sessions.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct AssetId(pub u128);
+
+impl AssetId {
+    pub fn random() -> Self {
+        Self(rand::random())
+    }
+}
+
+impl Display for AssetId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+impl FromStr for AssetId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u128::from_str_radix(s, 16).map(Self)
+    }
+}
+
+// IDs are written as hex strings to keep the manifest readable and independent of 128-bit integer support
+impl Serialize for AssetId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for AssetId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let string = String::deserialize(deserializer)?;
+        string.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Persistent description of a single asset
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AssetRecord {
+    pub id: AssetId,
+    /// Name of the asset type, e.g. "Mesh" or "Material"
+    pub asset_type: String,
+    /// Display name, which can change without changing the ID
+    pub name: String,
+    /// Source file relative to the working directory, if the asset is imported from a file
+    #[serde(default)]
+    pub source: Option<PathBuf>,
+    /// What the asset is within its source, e.g. the primitive of a mesh file. Empty for whole files.
+    /// Assets without a source are identified by the item alone.
+    /// Manifests written before items existed identify their assets by name.
+    #[serde(default)]
+    pub item: String,
+    /// Type specific settings that are needed to import the asset again
+    #[serde(default)]
+    pub settings: BTreeMap<String, String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Manifest {
+    assets: Vec<AssetRecord>,
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Io(io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+}
+
+impl Display for RegistryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistryError::Io(error) => write!(f, "Asset registry IO error: {}", error),
+            RegistryError::Parse(error) => write!(f, "Asset registry parse error: {}", error),
+            RegistryError::Serialize(error) => {
+                write!(f, "Asset registry serialization error: {}", error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+/// What identifies an asset across sessions, independent of its display name
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+struct AssetKey {
+    asset_type: String,
+    source: Option<PathBuf>,
+    item: String,
+}
+
+impl AssetKey {
+    fn of(record: &AssetRecord) -> Self {
+        Self {
+            asset_type: record.asset_type.clone(),
+            source: record.source.clone(),
+            item: record.item.clone(),
+        }
+    }
+}
+
+/// Registry that maps stable asset IDs to the information required to load the asset.
+/// It is backed by a RON manifest file.
+pub struct AssetRegistry {
+    path: PathBuf,
+    records: BTreeMap<AssetId, AssetRecord>,
+    lookup: HashMap<AssetKey, AssetId>,
+    dirty: bool,
+}
+
+impl AssetRegistry {
+    /// An empty registry that is written to path on save
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            records: BTreeMap::new(),
+            lookup: HashMap::new(),
+            dirty: false,
+        }
+    }
+
+    /// Loads the manifest at path. A missing manifest results in an empty registry that is created on save.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RegistryError> {
+        let path = path.as_ref().to_path_buf();
+        let manifest = match fs::read_to_string(&path) {
+            Ok(content) => ron::from_str::<Manifest>(&content).map_err(RegistryError::Parse)?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Manifest::default(),
+            Err(error) => return Err(RegistryError::Io(error)),
+        };
+
+        let mut registry = Self::new(path);
+        for mut record in manifest.assets {
+            if record.source.is_none() && record.item.is_empty() {
+                record.item = record.name.clone();
+                registry.dirty = true;
+            }
+            registry.insert(record);
+        }
+        Ok(registry)
+    }
+
+    /// Writes the manifest back to disk if anything changed since loading
+    pub fn save(&mut self) -> Result<(), RegistryError> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let manifest = Manifest {
+            assets: self.records.values().cloned().collect(),
+        };
+        let content = ron::ser::to_string_pretty(&manifest, ron::ser::PrettyConfig::default())
+            .map_err(RegistryError::Serialize)?;
+        fs::write(&self.path, content).map_err(RegistryError::Io)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn insert(&mut self, record: AssetRecord) {
+        self.lookup.insert(AssetKey::of(&record), record.id);
+        self.records.insert(record.id, record);
+    }
+
+    /// Returns the stable ID of the asset of the given type that is the item of the source, registering it if it is unknown.
+    /// Assets without a source are identified by the item alone, which their creator must keep unique.
+    /// Name and settings of known assets are updated if they changed.
+    pub fn register(
+        &mut self,
+        asset_type: &str,
+        name: &str,
+        source: Option<&Path>,
+        item: &str,
+        settings: BTreeMap<String, String>,
+    ) -> AssetId {
+        if let Some(record) = self
+            .find(asset_type, source, item)
+            .and_then(|id| self.records.get_mut(&id))
+        {
+            if record.name != name || record.settings != settings {
+                record.name = name.into();
+                record.settings = settings;
+                self.dirty = true;
+            }
+            return record.id;
+        }
+
+        let mut id = AssetId::random();
+        while self.records.contains_key(&id) {
+            id = AssetId::random();
+        }
+        self.insert(AssetRecord {
+            id,
+            asset_type: asset_type.into(),
+            name: name.into(),
+            source: source.map(Path::to_path_buf),
+            item: item.into(),
+            settings,
+        });
+        self.dirty = true;
+        id
+    }
+
+    pub fn find(&self, asset_type: &str, source: Option<&Path>, item: &str) -> Option<AssetId> {
+        self.lookup
+            .get(&AssetKey {
+                asset_type: asset_type.into(),
+                source: source.map(Path::to_path_buf),
+                item: item.into(),
+            })
+            .copied()
+    }
+
+    /// Changes the display name of an asset. Its ID stays the same.
+    pub fn rename(&mut self, id: AssetId, name: &str) -> bool {
+        let Some(record) = self.records.get_mut(&id) else {
+            return false;
+        };
+        if record.name != name {
+            record.name = name.into();
+            self.dirty = true;
+        }
+        true
+    }
+
+    pub fn get(&self, id: AssetId) -> Option<&AssetRecord> {
+        self.records.get(&id)
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &AssetRecord> {
+        self.records.values()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Manifest in the temporary directory that is removed again when the test ends
+    struct TempManifest(PathBuf);
+
+    impl TempManifest {
+        fn new(test: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "asset_registry_{}_{}.ron",
+                test,
+                std::process::id()
+            ));
+            let _ = fs::remove_file(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempManifest {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn settings(key: &str, value: &str) -> BTreeMap<String, String> {
+        BTreeMap::from([(key.to_string(), value.to_string())])
+    }
+
+    #[test]
+    fn missing_manifests_load_empty() {
+        let manifest = TempManifest::new("missing");
+        let registry = AssetRegistry::load(&manifest.0).unwrap();
+        assert_eq!(registry.records().count(), 0);
+        assert_eq!(registry.path(), manifest.0);
+    }
+
+    #[test]
+    fn records_survive_save_and_load() {
+        let manifest = TempManifest::new("round_trip");
+        let mut registry = AssetRegistry::load(&manifest.0).unwrap();
+        let mesh = registry.register(
+            "Mesh",
+            "Sphere",
+            Some(Path::new("meshes/sphere.glb")),
+            "0/1",
+            settings("vertex_format", "compact"),
+        );
+        let material = registry.register("Material", "Red", None, "Red", BTreeMap::new());
+        registry.save().unwrap();
+
+        let loaded = AssetRegistry::load(&manifest.0).unwrap();
+        assert_eq!(loaded.records().count(), 2);
+        assert_eq!(
+            loaded.find("Mesh", Some(Path::new("meshes/sphere.glb")), "0/1"),
+            Some(mesh)
+        );
+        assert_eq!(loaded.find("Material", None, "Red"), Some(material));
+        let record = loaded.get(mesh).unwrap();
+        assert_eq!(record.name, "Sphere");
+        assert_eq!(record.settings, settings("vertex_format", "compact"));
+    }
+
+    #[test]
+    fn registering_again_keeps_the_id() {
+        let mut registry = AssetRegistry::new("unused.ron");
+        let source = Some(Path::new("textures/a.png"));
+        let first = registry.register("Texture", "A", source, "", BTreeMap::new());
+        let second = registry.register("Texture", "A", source, "", settings("usage", "normal_map"));
+        assert_eq!(first, second);
+        assert_eq!(registry.records().count(), 1);
+        assert_eq!(
+            registry.get(first).unwrap().settings,
+            settings("usage", "normal_map")
+        );
+    }
+
+    #[test]
+    fn equal_names_do_not_collide() {
+        let mut registry = AssetRegistry::new("unused.ron");
+        let a = registry.register(
+            "Texture",
+            "albedo",
+            Some(Path::new("a.png")),
+            "",
+            BTreeMap::new(),
+        );
+        let b = registry.register(
+            "Texture",
+            "albedo",
+            Some(Path::new("b.png")),
+            "",
+            BTreeMap::new(),
+        );
+        let primitive = registry.register(
+            "Texture",
+            "albedo",
+            Some(Path::new("a.png")),
+            "1",
+            BTreeMap::new(),
+        );
+        let mesh = registry.register(
+            "Mesh",
+            "albedo",
+            Some(Path::new("a.png")),
+            "",
+            BTreeMap::new(),
+        );
+        assert_eq!(
+            [a, b, primitive, mesh]
+                .into_iter()
+                .collect::<std::collections::BTreeSet<_>>()
+                .len(),
+            4
+        );
+        assert_eq!(
+            registry.get(a).unwrap().source.as_deref(),
+            Some(Path::new("a.png"))
+        );
+        assert_eq!(
+            registry.get(b).unwrap().source.as_deref(),
+            Some(Path::new("b.png"))
+        );
+    }
+
+    #[test]
+    fn renaming_keeps_the_id() {
+        let mut registry = AssetRegistry::new("unused.ron");
+        let source = Some(Path::new("meshes/monkey.glb"));
+        let id = registry.register("Mesh", "Monkey", source, "", BTreeMap::new());
+        assert!(registry.rename(id, "Suzanne"));
+        assert_eq!(registry.get(id).unwrap().name, "Suzanne");
+        assert_eq!(registry.find("Mesh", source, ""), Some(id));
+        // Importing the file again under another name finds the same asset
+        assert_eq!(
+            registry.register("Mesh", "Ape", source, "", BTreeMap::new()),
+            id
+        );
+        assert!(!registry.rename(AssetId(0), "Nothing"));
+    }
+
+    #[test]
+    fn legacy_records_are_identified_by_name() {
+        let manifest = TempManifest::new("legacy");
+        fs::write(
+            &manifest.0,
+            r#"(assets: [(id: "00000000000000000000000000000001", asset_type: "Material", name: "Red")])"#,
+        )
+        .unwrap();
+        let registry = AssetRegistry::load(&manifest.0).unwrap();
+        assert_eq!(registry.find("Material", None, "Red"), Some(AssetId(1)));
+    }
+
+    #[test]
+    fn malformed_manifests_fail_to_load() {
+        let manifest = TempManifest::new("malformed");
+        fs::write(&manifest.0, "(assets: [(id: 7)])").unwrap();
+        assert!(matches!(
+            AssetRegistry::load(&manifest.0),
+            Err(RegistryError::Parse(_))
+        ));
+    }
+}
@@ -1,19 +1,136 @@
 use std::{
     any::{Any, TypeId},
     collections::HashMap,
+    ops::{Deref, DerefMut},
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
 };
 
+#[derive(Copy, Clone)]
 struct ResourcePosition {
     type_id: TypeId,
     index: usize,
 }
 
-trait VecLike {
+/// A slot in the id table. The generation is bumped every time the slot is freed,
+/// so that ids handed out before the removal no longer resolve.
+struct ResourceSlot {
+    generation: u32,
+    position: Option<ResourcePosition>,
+}
+
+/// Generation checked identifier of a resource inside a [`ResourceManager`]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct ResourceId {
+    pub uuid: usize,
+    pub generation: u32,
+}
+
+/// Positions of the resources of all types, addressed by the uuid of their ids
+struct SlotTable {
+    slots: Vec<ResourceSlot>,
+    free_ids: Vec<usize>,
+}
+
+impl SlotTable {
+    fn new() -> Self {
+        Self {
+            // Slot 0 is reserved so that a zero uuid always means "not registered"
+            slots: vec![ResourceSlot {
+                generation: 0,
+                position: None,
+            }],
+            free_ids: Vec::new(),
+        }
+    }
+
+    /// Returns None if the id is stale
+    fn position(&self, id: ResourceId) -> Option<ResourcePosition> {
+        let slot = self.slots.get(id.uuid)?;
+        if slot.generation == id.generation {
+            slot.position
+        } else {
+            None
+        }
+    }
+
+    /// Returns None if the id is stale or refers to a resource of another type
+    fn index_of<T: 'static>(&self, id: ResourceId) -> Option<usize> {
+        self.position(id)
+            .filter(|position| position.type_id == TypeId::of::<T>())
+            .map(|position| position.index)
+    }
+
+    fn allocate(&mut self, type_id: TypeId, index: usize) -> ResourceId {
+        let uuid = self.free_ids.pop().unwrap_or_else(|| {
+            self.slots.push(ResourceSlot {
+                generation: 0,
+                position: None,
+            });
+            self.slots.len() - 1
+        });
+        let slot = &mut self.slots[uuid];
+        slot.position = Some(ResourcePosition { type_id, index });
+        ResourceId {
+            uuid,
+            generation: slot.generation,
+        }
+    }
+
+    /// Frees the slot of a removed resource and moves the resource that took its index
+    fn free(&mut self, id: ResourceId, index: usize, moved: Option<ResourceId>) {
+        if let Some(moved) = moved {
+            self.slots[moved.uuid].position.as_mut().unwrap().index = index;
+        }
+
+        let slot = &mut self.slots[id.uuid];
+        slot.position = None;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_ids.push(id.uuid);
+    }
+}
+
+trait VecLike: Send + Sync {
     fn size(&self) -> usize;
 
     fn as_any(&self) -> &dyn Any;
 
     fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    /// Drops the item at index and moves the last item into its place.
+    /// Returns the id of the moved item.
+    fn swap_remove(&mut self, index: usize) -> Option<ResourceId>;
+}
+
+/// Densely packed storage of a single resource type.
+/// `ids` holds the id of the resource at the same index in `items`.
+struct DenseStorage<T> {
+    items: Vec<T>,
+    ids: Vec<ResourceId>,
+}
+
+impl<T> DenseStorage<T> {
+    fn new() -> Self {
+        Self {
+            items: Vec::new(),
+            ids: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, item: T, id: ResourceId) {
+        self.items.push(item);
+        self.ids.push(id);
+    }
+
+    /// Returns the removed item and the id of the item that was moved into its place
+    fn take(&mut self, index: usize) -> (T, Option<ResourceId>) {
+        let removed = self.items.swap_remove(index);
+        self.ids.swap_remove(index);
+        (removed, self.ids.get(index).copied())
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (ResourceId, &T)> {
+        self.ids.iter().copied().zip(self.items.iter())
+    }
 }
 
 struct TypedMultiMap {
@@ -34,24 +151,22 @@ impl TypedMultiMap {
             .unwrap_or(0)
     }
 
-    fn get_vec<T: 'static>(&self) -> Option<&Vec<T>> {
+    fn get_vec<T: 'static>(&self) -> Option<&DenseStorage<T>> {
         self.map
             .get(&Self::type_id::<T>())
-            .map(|v| v.as_any().downcast_ref::<Vec<T>>())
-            .flatten()
+            .and_then(|v| v.as_any().downcast_ref::<DenseStorage<T>>())
     }
 
-    fn get_vec_mut<T: 'static>(&mut self) -> Option<&mut Vec<T>> {
+    fn get_vec_mut<T: 'static>(&mut self) -> Option<&mut DenseStorage<T>> {
         self.map
             .get_mut(&Self::type_id::<T>())
-            .map(|v| v.as_any_mut().downcast_mut::<Vec<T>>())
-            .flatten()
+            .and_then(|v| v.as_any_mut().downcast_mut::<DenseStorage<T>>())
     }
 
-    fn get_add_vec<T: 'static>(&mut self) -> &mut Vec<T> {
+    fn get_add_vec<T: Send + Sync + 'static>(&mut self) -> &mut DenseStorage<T> {
         if self.get_vec::<T>().is_none() {
             self.map
-                .insert(Self::type_id::<T>(), Box::new(Vec::<T>::new()));
+                .insert(Self::type_id::<T>(), Box::new(DenseStorage::<T>::new()));
         }
 
         self.get_vec_mut::<T>().unwrap()
@@ -62,9 +177,9 @@ impl TypedMultiMap {
     }
 }
 
-impl<T: 'static> VecLike for Vec<T> {
+impl<T: Send + Sync + 'static> VecLike for DenseStorage<T> {
     fn size(&self) -> usize {
-        self.len()
+        self.items.len()
     }
 
     fn as_any(&self) -> &dyn Any {
@@ -74,6 +189,10 @@ impl<T: 'static> VecLike for Vec<T> {
     fn as_any_mut(&mut self) -> &mut dyn Any {
         self
     }
+
+    fn swap_remove(&mut self, index: usize) -> Option<ResourceId> {
+        self.take(index).1
+    }
 }
 
 pub trait Resource {
@@ -82,61 +201,440 @@ pub trait Resource {
 
 pub struct ResourceManager {
     data: TypedMultiMap,
-    id_pos_map: HashMap<usize, ResourcePosition>,
-    next_id: usize,
+    slots: SlotTable,
+}
+
+impl Default for ResourceManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ResourceManager {
     pub fn new() -> Self {
         Self {
             data: TypedMultiMap::new(),
-            id_pos_map: HashMap::new(),
-            next_id: 1,
+            slots: SlotTable::new(),
         }
     }
 
-    pub fn get<T: Resource + 'static>(&self, uuid: usize) -> Option<&T> {
-        let pos = self.id_pos_map.get(&uuid)?;
-        if pos.type_id == TypedMultiMap::type_id::<T>() {
-            self.data.get_vec::<T>()?.get(pos.index)
-        } else {
-            None
-        }
+    pub fn get<T: Resource + 'static>(&self, id: ResourceId) -> Option<&T> {
+        let index = self.slots.index_of::<T>(id)?;
+        self.data.get_vec::<T>()?.items.get(index)
     }
 
-    pub fn get_mut<T: Resource + 'static>(&mut self, uuid: usize) -> Option<&mut T> {
-        let pos = self.id_pos_map.get(&uuid)?;
-        if pos.type_id == TypedMultiMap::type_id::<T>() {
-            self.data.get_vec_mut::<T>()?.get_mut(pos.index)
-        } else {
-            None
-        }
+    pub fn get_mut<T: Resource + 'static>(&mut self, id: ResourceId) -> Option<&mut T> {
+        let index = self.slots.index_of::<T>(id)?;
+        self.data.get_vec_mut::<T>()?.items.get_mut(index)
+    }
+
+    pub fn contains<T: Resource + 'static>(&self, id: ResourceId) -> bool {
+        self.slots.index_of::<T>(id).is_some()
     }
 
     pub fn get_iter<T: Resource + 'static>(&self) -> Option<impl Iterator<Item = &T>> {
-        Some(self.data.get_vec::<T>()?.iter())
+        Some(self.data.get_vec::<T>()?.items.iter())
+    }
+
+    /// Iterates over all resources of type T together with their ids
+    pub fn iter<T: Resource + 'static>(&self) -> impl Iterator<Item = (ResourceId, &T)> {
+        self.data
+            .get_vec::<T>()
+            .into_iter()
+            .flat_map(DenseStorage::iter)
+    }
+
+    pub fn count<T: Resource + 'static>(&self) -> usize {
+        self.data.size::<T>()
+    }
+
+    /// Resources must be Send and Sync so that the manager can be shared between threads behind a lock
+    pub fn add<T: Resource + Send + Sync + 'static>(&mut self, mut data: T) -> ResourceId {
+        let storage = self.data.get_add_vec::<T>();
+        let id = self.slots.allocate(TypeId::of::<T>(), storage.items.len());
+        data.set_uuid(id.uuid);
+        storage.push(data, id);
+        id
+    }
+
+    /// Removes the resource and returns it. The last resource of the same type is moved into the freed position.
+    /// Returns None if the id is stale or refers to a resource of another type.
+    pub fn take<T: Resource + 'static>(&mut self, id: ResourceId) -> Option<T> {
+        let index = self.slots.index_of::<T>(id)?;
+        let (removed, moved) = self.data.get_vec_mut::<T>()?.take(index);
+        self.slots.free(id, index, moved);
+        Some(removed)
+    }
+
+    /// Removes the resource without knowing its type. Returns false if the id is stale.
+    pub fn remove_any(&mut self, id: ResourceId) -> bool {
+        let Some(ResourcePosition { type_id, index }) = self.slots.position(id) else {
+            return false;
+        };
+        let Some(storage) = self.data.map.get_mut(&type_id) else {
+            return false;
+        };
+        let moved = storage.swap_remove(index);
+        self.slots.free(id, index, moved);
+        true
+    }
+
+    /// Removes the resource. Returns false if the id is stale or refers to a resource of another type.
+    pub fn remove<T: Resource + 'static>(&mut self, id: ResourceId) -> bool {
+        self.take::<T>(id).is_some()
+    }
+
+    /// The index of the resource inside the densely packed storage of its type.
+    /// This is only stable until the next removal of a resource of the same type.
+    pub fn index(&self, id: ResourceId) -> Option<usize> {
+        Some(self.slots.position(id)?.index)
+    }
+}
+
+/// Type erased storage of a [`SharedResourceManager`], so that resources can be removed without knowing their type
+trait SharedStorage: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+
+    /// Locks the storage for writing while `f` runs
+    fn with_write(&self, f: &mut dyn FnMut(&mut dyn VecLike));
+}
+
+impl<T: Send + Sync + 'static> SharedStorage for RwLock<DenseStorage<T>> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn with_write(&self, f: &mut dyn FnMut(&mut dyn VecLike)) {
+        f(&mut *self.write().unwrap())
+    }
+}
+
+/// Resource manager that is shared between threads without an outer lock.
+/// Every resource type is stored behind its own lock, so readers of one type never wait for writers of another.
+/// The id table has a separate lock that is only held briefly and always after the lock of a type.
+/// The types are fixed on construction through [`Self::with_type`].
+pub struct SharedResourceManager {
+    storages: HashMap<TypeId, Box<dyn SharedStorage>>,
+    slots: RwLock<SlotTable>,
+}
+
+/// Read access to a single resource. The resources of its type cannot be changed while it exists.
+pub struct ResourceRef<'a, T> {
+    storage: RwLockReadGuard<'a, DenseStorage<T>>,
+    index: usize,
+}
+
+/// Write access to a single resource. The resources of its type cannot be accessed otherwise while it exists.
+pub struct ResourceMut<'a, T> {
+    storage: RwLockWriteGuard<'a, DenseStorage<T>>,
+    index: usize,
+}
+
+/// Read access to all resources of a type
+pub struct ResourceView<'a, T> {
+    storage: RwLockReadGuard<'a, DenseStorage<T>>,
+}
+
+impl<T> Deref for ResourceRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.storage.items[self.index]
+    }
+}
+
+impl<T> Deref for ResourceMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.storage.items[self.index]
     }
+}
 
-    pub fn add<T: Resource + 'static>(&mut self, mut data: T) -> usize {
-        let id = self.next_id;
-        data.set_uuid(id);
-        self.next_id += 1;
+impl<T> DerefMut for ResourceMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.storage.items[self.index]
+    }
+}
 
-        let vec = self.data.get_add_vec::<T>();
-        let index = vec.len();
-        vec.push(data);
+impl<T> ResourceView<'_, T> {
+    /// Iterates over all resources together with their ids
+    pub fn iter(&self) -> impl Iterator<Item = (ResourceId, &T)> {
+        self.storage.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.storage.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.storage.items.is_empty()
+    }
+}
 
-        self.id_pos_map.insert(
-            id,
-            ResourcePosition {
-                type_id: TypeId::of::<T>(),
-                index,
-            },
+impl Default for SharedResourceManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SharedResourceManager {
+    pub fn new() -> Self {
+        Self {
+            storages: HashMap::new(),
+            slots: RwLock::new(SlotTable::new()),
+        }
+    }
+
+    /// Adds a storage for resources of type T
+    pub fn with_type<T: Resource + Send + Sync + 'static>(mut self) -> Self {
+        self.storages.insert(
+            TypeId::of::<T>(),
+            Box::new(RwLock::new(DenseStorage::<T>::new())),
         );
+        self
+    }
+
+    fn storage<T: 'static>(&self) -> Option<&RwLock<DenseStorage<T>>> {
+        self.storages
+            .get(&TypeId::of::<T>())
+            .and_then(|storage| storage.as_any().downcast_ref())
+    }
+
+    /// Panics if the type was not added through [`Self::with_type`]
+    pub fn add<T: Resource + Send + Sync + 'static>(&self, mut data: T) -> ResourceId {
+        let mut storage = self
+            .storage::<T>()
+            .unwrap_or_else(|| panic!("{} is not a resource type", std::any::type_name::<T>()))
+            .write()
+            .unwrap();
+        let id = self
+            .slots
+            .write()
+            .unwrap()
+            .allocate(TypeId::of::<T>(), storage.items.len());
+        data.set_uuid(id.uuid);
+        storage.push(data, id);
         id
     }
 
-    pub fn index(&self, uuid: usize) -> Option<usize> {
-        Some(self.id_pos_map.get(&uuid)?.index)
+    pub fn get<T: Resource + 'static>(&self, id: ResourceId) -> Option<ResourceRef<'_, T>> {
+        let storage = self.storage::<T>()?.read().unwrap();
+        // Positions of type T only change while its storage is locked for writing
+        let index = self.slots.read().unwrap().index_of::<T>(id)?;
+        Some(ResourceRef { storage, index })
+    }
+
+    pub fn get_mut<T: Resource + 'static>(&self, id: ResourceId) -> Option<ResourceMut<'_, T>> {
+        let storage = self.storage::<T>()?.write().unwrap();
+        let index = self.slots.read().unwrap().index_of::<T>(id)?;
+        Some(ResourceMut { storage, index })
+    }
+
+    pub fn contains<T: Resource + 'static>(&self, id: ResourceId) -> bool {
+        self.slots.read().unwrap().index_of::<T>(id).is_some()
+    }
+
+    /// Locks all resources of type T for reading
+    pub fn view<T: Resource + 'static>(&self) -> Option<ResourceView<'_, T>> {
+        Some(ResourceView {
+            storage: self.storage::<T>()?.read().unwrap(),
+        })
+    }
+
+    pub fn count<T: Resource + 'static>(&self) -> usize {
+        self.view::<T>().map_or(0, |view| view.len())
+    }
+
+    /// Removes the resource and returns it. The last resource of the same type is moved into the freed position.
+    /// Returns None if the id is stale or refers to a resource of another type.
+    pub fn take<T: Resource + 'static>(&self, id: ResourceId) -> Option<T> {
+        let mut storage = self.storage::<T>()?.write().unwrap();
+        let mut slots = self.slots.write().unwrap();
+        let index = slots.index_of::<T>(id)?;
+        let (removed, moved) = storage.take(index);
+        slots.free(id, index, moved);
+        Some(removed)
+    }
+
+    /// Removes the resource. Returns false if the id is stale or refers to a resource of another type.
+    pub fn remove<T: Resource + 'static>(&self, id: ResourceId) -> bool {
+        self.take::<T>(id).is_some()
+    }
+
+    /// Removes the resource without knowing its type. Returns false if the id is stale.
+    pub fn remove_any(&self, id: ResourceId) -> bool {
+        let Some(type_id) = self
+            .slots
+            .read()
+            .unwrap()
+            .position(id)
+            .map(|position| position.type_id)
+        else {
+            return false;
+        };
+        let Some(storage) = self.storages.get(&type_id) else {
+            return false;
+        };
+        let mut removed = false;
+        storage.with_write(&mut |storage| {
+            let mut slots = self.slots.write().unwrap();
+            // The resource may have been removed before the storage was locked
+            let Some(ResourcePosition { index, .. }) = slots.position(id) else {
+                return;
+            };
+            let moved = storage.swap_remove(index);
+            slots.free(id, index, moved);
+            removed = true;
+        });
+        removed
+    }
+
+    /// The index of the resource inside the densely packed storage of its type.
+    /// This is only stable until the next removal of a resource of the same type.
+    pub fn index(&self, id: ResourceId) -> Option<usize> {
+        Some(self.slots.read().unwrap().position(id)?.index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(PartialEq, Debug)]
+    struct Mesh {
+        uuid: usize,
+        name: &'static str,
+    }
+
+    impl Mesh {
+        fn new(name: &'static str) -> Self {
+            Self { uuid: 0, name }
+        }
+    }
+
+    impl Resource for Mesh {
+        fn set_uuid(&mut self, uuid: usize) {
+            self.uuid = uuid;
+        }
+    }
+
+    struct Texture;
+
+    impl Resource for Texture {
+        fn set_uuid(&mut self, _uuid: usize) {}
+    }
+
+    #[test]
+    fn added_resources_know_their_uuid() {
+        let mut resources = ResourceManager::new();
+        let id = resources.add(Mesh::new("a"));
+        assert_ne!(id.uuid, 0);
+        assert_eq!(resources.get::<Mesh>(id).unwrap().uuid, id.uuid);
+    }
+
+    #[test]
+    fn removed_ids_are_stale() {
+        let mut resources = ResourceManager::new();
+        let id = resources.add(Mesh::new("a"));
+        assert_eq!(resources.take::<Mesh>(id).unwrap().name, "a");
+        assert!(resources.get::<Mesh>(id).is_none());
+        assert!(!resources.contains::<Mesh>(id));
+        assert_eq!(resources.index(id), None);
+        assert!(!resources.remove::<Mesh>(id));
+        assert!(!resources.remove_any(id));
+        assert_eq!(resources.count::<Mesh>(), 0);
+    }
+
+    #[test]
+    fn reused_uuids_get_a_new_generation() {
+        let mut resources = ResourceManager::new();
+        let old = resources.add(Mesh::new("old"));
+        resources.remove::<Mesh>(old);
+        let new = resources.add(Mesh::new("new"));
+        assert_eq!(new.uuid, old.uuid);
+        assert_ne!(new.generation, old.generation);
+        // The stale id must not resolve to the resource that took over its slot
+        assert!(resources.get::<Mesh>(old).is_none());
+        assert_eq!(resources.get::<Mesh>(new).unwrap().name, "new");
+    }
+
+    #[test]
+    fn ids_of_another_type_do_not_resolve() {
+        let mut resources = ResourceManager::new();
+        let mesh = resources.add(Mesh::new("a"));
+        let texture = resources.add(Texture);
+        assert!(resources.get::<Texture>(mesh).is_none());
+        assert!(!resources.remove::<Texture>(mesh));
+        assert!(resources.contains::<Texture>(texture));
+        assert!(resources.contains::<Mesh>(mesh));
+    }
+
+    #[test]
+    fn removal_moves_the_last_resource_into_the_gap() {
+        let mut resources = ResourceManager::new();
+        let ids = ["a", "b", "c"].map(|name| resources.add(Mesh::new(name)));
+        assert!(resources.remove::<Mesh>(ids[0]));
+        // "c" took the index of "a", "b" kept its own
+        assert_eq!(resources.index(ids[2]), Some(0));
+        assert_eq!(resources.index(ids[1]), Some(1));
+        assert_eq!(resources.get::<Mesh>(ids[2]).unwrap().name, "c");
+        let order = resources
+            .iter::<Mesh>()
+            .map(|(id, mesh)| (id, mesh.name))
+            .collect::<Vec<_>>();
+        assert_eq!(order, [(ids[2], "c"), (ids[1], "b")]);
+
+        // The same fix-up applies without knowing the type
+        assert!(resources.remove_any(ids[2]));
+        assert_eq!(resources.index(ids[1]), Some(0));
+        assert_eq!(resources.get::<Mesh>(ids[1]).unwrap().name, "b");
+    }
+
+    #[test]
+    fn removing_the_last_resource_moves_nothing() {
+        let mut resources = ResourceManager::new();
+        let ids = ["a", "b"].map(|name| resources.add(Mesh::new(name)));
+        assert!(resources.remove::<Mesh>(ids[1]));
+        assert_eq!(resources.index(ids[0]), Some(0));
+        assert_eq!(resources.count::<Mesh>(), 1);
+    }
+
+    #[test]
+    fn shared_removal_moves_the_last_resource_into_the_gap() {
+        let resources = SharedResourceManager::new().with_type::<Mesh>();
+        let ids = ["a", "b", "c"].map(|name| resources.add(Mesh::new(name)));
+        assert!(resources.remove::<Mesh>(ids[0]));
+        assert_eq!(resources.index(ids[2]), Some(0));
+        assert_eq!(resources.get::<Mesh>(ids[2]).unwrap().name, "c");
+        assert!(resources.get::<Mesh>(ids[0]).is_none());
+
+        assert!(resources.remove_any(ids[2]));
+        assert!(!resources.remove_any(ids[2]));
+        assert_eq!(resources.index(ids[1]), Some(0));
+        let view = resources.view::<Mesh>().unwrap();
+        assert_eq!(
+            view.iter().map(|(id, mesh)| (id, mesh.name)).collect::<Vec<_>>(),
+            [(ids[1], "b")]
+        );
+    }
+
+    #[test]
+    fn shared_resources_are_changed_through_their_guard() {
+        let resources = SharedResourceManager::new()
+            .with_type::<Mesh>()
+            .with_type::<Texture>();
+        let mesh = resources.add(Mesh::new("a"));
+        resources.get_mut::<Mesh>(mesh).unwrap().name = "b";
+        assert_eq!(resources.get::<Mesh>(mesh).unwrap().name, "b");
+        assert!(resources.get::<Texture>(mesh).is_none());
+        assert!(!resources.contains::<Texture>(mesh));
+        assert_eq!(resources.count::<Texture>(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn shared_resources_of_unknown_types_are_rejected() {
+        SharedResourceManager::new().add(Texture);
     }
 }
@@ -0,0 +1,65 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Polls a directory tree for modified files.
+/// Polling avoids platform specific notification APIs and is cheap enough for the asset folder.
+pub struct FileWatcher {
+    root: PathBuf,
+    interval: Duration,
+    last_poll: Instant,
+    timestamps: HashMap<PathBuf, SystemTime>,
+}
+
+impl FileWatcher {
+    pub fn new(root: impl AsRef<Path>, interval: Duration) -> Self {
+        let root = root.as_ref().to_path_buf();
+        let mut timestamps = HashMap::new();
+        Self::scan(&root, &mut timestamps);
+        Self {
+            root,
+            interval,
+            last_poll: Instant::now(),
+            timestamps,
+        }
+    }
+
+    /// Returns all files that were created or modified since the last poll.
+    /// Does nothing until the poll interval has passed.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        if self.last_poll.elapsed() < self.interval {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+
+        let mut timestamps = HashMap::with_capacity(self.timestamps.len());
+        Self::scan(&self.root, &mut timestamps);
+        let changed = timestamps
+            .iter()
+            .filter(|(path, modified)| self.timestamps.get(*path) != Some(*modified))
+            .map(|(path, _)| path.clone())
+            .collect();
+        self.timestamps = timestamps;
+        changed
+    }
+
+    fn scan(directory: &Path, timestamps: &mut HashMap<PathBuf, SystemTime>) {
+        let Ok(entries) = fs::read_dir(directory) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                Self::scan(&path, timestamps);
+            } else if let Ok(modified) = metadata.modified() {
+                timestamps.insert(path, modified);
+            }
+        }
+    }
+}
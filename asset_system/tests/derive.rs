@@ -0,0 +1,154 @@
+use asset_system::{
+    Asset,
+    assets::{Asset as _, AssetHandle, AssetMetadata},
+    reflection::FieldInfo,
+    resource_management::{ResourceId, ResourceManager},
+};
+
+#[derive(Asset)]
+struct Target {
+    asset_metadata: AssetMetadata,
+}
+
+#[derive(Asset)]
+struct Reflected {
+    asset_metadata: AssetMetadata,
+    label: String,
+    #[asset(range = 0.0..1.0)]
+    roughness: f32,
+    #[asset(range = 1..16)]
+    samples: u32,
+    #[asset(skip)]
+    cache: Vec<u8>,
+    target: AssetHandle<Target>,
+    #[asset(handle)]
+    optional_target: Option<AssetHandle<Target>>,
+    #[asset(handle)]
+    targets: Vec<AssetHandle<Target>>,
+    #[asset(skip)]
+    skipped_target: AssetHandle<Target>,
+    callback: Option<Box<dyn Fn(u32) -> u32 + Send + Sync>>,
+    category: &'static str,
+}
+
+fn target(manager: &mut ResourceManager) -> AssetHandle<Target> {
+    AssetHandle::new(manager.add(Target {
+        asset_metadata: AssetMetadata::new("Target".into()),
+    }))
+}
+
+fn reflected(manager: &mut ResourceManager) -> Reflected {
+    Reflected {
+        asset_metadata: AssetMetadata::new("Reflected".into()),
+        label: "label".into(),
+        roughness: 0.5,
+        samples: 4,
+        cache: vec![1, 2, 3],
+        target: target(manager),
+        optional_target: Some(target(manager)),
+        targets: vec![target(manager), target(manager)],
+        skipped_target: target(manager),
+        callback: None,
+        category: "category",
+    }
+}
+
+fn field_names() -> Vec<&'static str> {
+    Reflected::fields().iter().map(|field| field.name).collect()
+}
+
+#[test]
+fn fields_are_reflected_in_declaration_order() {
+    assert_eq!(
+        field_names(),
+        [
+            "label",
+            "roughness",
+            "samples",
+            "target",
+            "optional_target",
+            "targets",
+            "callback",
+            "category"
+        ]
+    );
+    let label = &Reflected::fields()[0];
+    assert_eq!(label.type_name, "String");
+    assert_eq!(label.range, None);
+    assert!(!label.handle);
+    assert_eq!(Reflected::fields()[3].type_name, "AssetHandle<Target>");
+}
+
+#[test]
+fn type_names_keep_the_spaces_between_words() {
+    let category = FieldInfo::find(Reflected::fields(), "category").unwrap();
+    assert_eq!(category.type_name, "&'static str");
+    let callback = FieldInfo::find(Reflected::fields(), "callback").unwrap();
+    assert_eq!(
+        callback.type_name,
+        "Option<Box<dyn Fn(u32)->u32+Send+Sync>>"
+    );
+}
+
+#[test]
+fn asset_metadata_is_not_reflected() {
+    assert!(Target::fields().is_empty());
+    let mut manager = ResourceManager::new();
+    let asset = reflected(&mut manager);
+    assert!(asset.field("asset_metadata").is_none());
+}
+
+#[test]
+fn skip_hides_the_field() {
+    let mut manager = ResourceManager::new();
+    let mut asset = reflected(&mut manager);
+    assert!(!field_names().contains(&"cache"));
+    assert_eq!(asset.cache.len(), 3);
+    assert!(asset.field("cache").is_none());
+    assert!(asset.field_mut("cache").is_none());
+    // Skipped handles are not dependencies either
+    assert!(!asset.dependencies().contains(&asset.skipped_target.id));
+}
+
+#[test]
+fn range_is_converted_to_f64() {
+    let roughness = FieldInfo::find(Reflected::fields(), "roughness").unwrap();
+    assert_eq!(roughness.range, Some(0.0..1.0));
+    let samples = FieldInfo::find(Reflected::fields(), "samples").unwrap();
+    assert_eq!(samples.range, Some(1.0..16.0));
+}
+
+#[test]
+fn handle_fields_are_dependencies() {
+    let mut manager = ResourceManager::new();
+    let asset = reflected(&mut manager);
+    let handles = Reflected::fields()
+        .iter()
+        .filter(|field| field.handle)
+        .map(|field| field.name)
+        .collect::<Vec<_>>();
+    // AssetHandle fields are detected without the attribute
+    assert_eq!(handles, ["target", "optional_target", "targets"]);
+
+    let mut expected: Vec<ResourceId> = vec![asset.target.id];
+    expected.extend(asset.optional_target.as_ref().map(|handle| handle.id));
+    expected.extend(asset.targets.iter().map(|handle| handle.id));
+    assert_eq!(asset.dependencies(), expected);
+}
+
+#[test]
+fn field_values_can_be_read_and_written() {
+    let mut manager = ResourceManager::new();
+    let mut asset = reflected(&mut manager);
+    assert_eq!(
+        asset.field("label").unwrap().downcast_ref::<String>(),
+        Some(&"label".to_string())
+    );
+    *asset
+        .field_mut("roughness")
+        .unwrap()
+        .downcast_mut::<f32>()
+        .unwrap() = 0.25;
+    assert_eq!(asset.roughness, 0.25);
+    assert!(asset.field("unknown").is_none());
+}
@@ -0,0 +1,167 @@
+use std::{
+    sync::{Arc, mpsc},
+    thread,
+    time::Duration,
+};
+
+use asset_system::{
+    Asset,
+    assets::{Asset as _, AssetMetadata},
+    resource_management::{ResourceId, SharedResourceManager},
+};
+
+#[derive(Asset)]
+struct Shared {
+    asset_metadata: AssetMetadata,
+    value: u32,
+}
+
+#[derive(Asset)]
+struct Other {
+    asset_metadata: AssetMetadata,
+    value: u32,
+}
+
+fn shared(value: u32) -> Shared {
+    Shared {
+        asset_metadata: AssetMetadata::new(format!("Shared{}", value)),
+        value,
+    }
+}
+
+fn manager() -> Arc<SharedResourceManager> {
+    Arc::new(
+        SharedResourceManager::new()
+            .with_type::<Shared>()
+            .with_type::<Other>(),
+    )
+}
+
+#[test]
+fn assets_can_be_added_from_importer_threads() {
+    let manager = manager();
+    let importers = (0..4)
+        .map(|thread| {
+            let manager = manager.clone();
+            thread::spawn(move || {
+                (0..100)
+                    .map(|value| {
+                        let value = thread * 100 + value;
+                        (manager.add(shared(value)), value)
+                    })
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect::<Vec<_>>();
+    let added = importers
+        .into_iter()
+        .flat_map(|importer| importer.join().unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(manager.count::<Shared>(), 400);
+    for (id, value) in added {
+        let asset = manager.get::<Shared>(id).unwrap();
+        assert_eq!(asset.value, value);
+        assert_eq!(asset.name(), &format!("Shared{}", value));
+    }
+}
+
+#[test]
+fn assets_stay_readable_while_others_are_removed() {
+    let manager = manager();
+    let ids = (0..200)
+        .map(|value| manager.add(shared(value)))
+        .collect::<Vec<_>>();
+    let (removed, kept): (Vec<_>, Vec<_>) = ids
+        .iter()
+        .copied()
+        .enumerate()
+        .partition(|(value, _)| value % 2 == 0);
+
+    let remover = {
+        let manager = manager.clone();
+        // Every removal moves the last asset into the gap
+        thread::spawn(move || {
+            for (_, id) in removed {
+                assert!(manager.remove::<Shared>(id));
+            }
+        })
+    };
+    let readers = (0..4)
+        .map(|_| {
+            let manager = manager.clone();
+            let kept = kept.clone();
+            thread::spawn(move || {
+                for _ in 0..20 {
+                    for (value, id) in &kept {
+                        assert_eq!(manager.get::<Shared>(*id).unwrap().value, *value as u32);
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    remover.join().unwrap();
+    readers
+        .into_iter()
+        .for_each(|reader| reader.join().unwrap());
+
+    assert_eq!(manager.count::<Shared>(), 100);
+    assert!(
+        ids.iter()
+            .step_by(2)
+            .all(|id| manager.get::<Shared>(*id).is_none())
+    );
+    let view = manager.view::<Shared>().unwrap();
+    for (index, (id, asset)) in view.iter().enumerate() {
+        assert_eq!(manager.index(id), Some(index));
+        assert_eq!(ids[asset.value as usize], id);
+    }
+}
+
+#[test]
+fn concurrent_removals_of_the_same_asset_succeed_once() {
+    let manager = manager();
+    let ids = (0..100)
+        .map(|value| manager.add(shared(value)))
+        .collect::<Vec<ResourceId>>();
+    let removers = (0..4)
+        .map(|_| {
+            let manager = manager.clone();
+            let ids = ids.clone();
+            thread::spawn(move || ids.iter().filter(|id| manager.remove_any(**id)).count())
+        })
+        .collect::<Vec<_>>();
+    let removed = removers
+        .into_iter()
+        .map(|remover| remover.join().unwrap())
+        .sum::<usize>();
+    assert_eq!(removed, 100);
+    assert_eq!(manager.count::<Shared>(), 0);
+}
+
+#[test]
+fn readers_of_one_type_do_not_block_writers_of_another() {
+    let manager = manager();
+    let id = manager.add(shared(1));
+    let reading = manager.get::<Shared>(id).unwrap();
+
+    let (done, finished) = mpsc::channel();
+    let writer = {
+        let manager = manager.clone();
+        thread::spawn(move || {
+            let id = manager.add(Other {
+                asset_metadata: AssetMetadata::new("Other".into()),
+                value: 1,
+            });
+            manager.get_mut::<Other>(id).unwrap().value = 2;
+            done.send(id).unwrap();
+        })
+    };
+    let other = finished
+        .recv_timeout(Duration::from_secs(10))
+        .expect("the writer waited for the reader of another type");
+    assert_eq!(reading.value, 1);
+    drop(reading);
+    writer.join().unwrap();
+    assert_eq!(manager.get::<Other>(other).unwrap().value, 2);
+}